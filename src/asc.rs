@@ -0,0 +1,557 @@
+//! Import and export of Vector ASC traces
+//!
+//! Classic and CAN FD message events are supported, all other events (statistics,
+//! error frames, comments, ...) are skipped when reading:
+//!
+//! ```text
+//! date Mon Oct 19 10:00:00.000 am 2026
+//! base hex  timestamps absolute
+//! Begin Triggerblock Mon Oct 19 10:00:00.000 am 2026
+//!    0.000000 Start of measurement
+//!    0.010000 1  7E0             Tx   d 8 03 22 F1 90 00 00 00 00
+//!    0.012000 1  18DAF110x       Rx   d 8 10 14 62 F1 90 31 32 33
+//!    0.020000 CANFD   1 Rx        7E8                                   1 0 d 32 ...
+//! End TriggerBlock
+//! ```
+//!
+//! Timestamps are relative to the `date` header, which is interpreted as UTC (or to
+//! the UNIX epoch if the date is localized and cannot be parsed). The
+//! frames can be fed into a [Reassembler](crate::frame::Reassembler) to extract the
+//! PDUs of a trace without a CAN interface, and the PDUs into
+//! [exchanges](crate::frame::exchanges) to pair UDS requests with their responses.
+
+use crate::frame::{CanFrame, CANFD_DATA_LENGTHS};
+use crate::{ExtendedId, Id, StandardId, SFF_MASK};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+/// Direction of a logged frame, seen from the logging tool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AscDirection {
+    Rx,
+    Tx,
+}
+
+/// Frame of an ASC trace together with its channel and direction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AscEntry {
+    pub channel: u8,
+    pub direction: AscDirection,
+    pub frame: CanFrame,
+}
+
+/// Read all frames of an ASC trace
+pub fn read_asc(reader: impl Read) -> io::Result<Vec<AscEntry>> {
+    let mut entries = Vec::new();
+    let mut start = UNIX_EPOCH;
+    let mut hex = true;
+    let mut relative = false;
+    let mut last = Duration::ZERO;
+
+    for (number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        let invalid = |message: String| invalid_data(format!("line {}: {message}", number + 1));
+
+        if let Some(date) = line.strip_prefix("date ") {
+            // localized dates cannot be parsed, the timestamps stay relative then
+            start = parse_date(date).unwrap_or(UNIX_EPOCH);
+            continue;
+        }
+        if let Some(base) = line.strip_prefix("base ") {
+            let mut tokens = base.split_whitespace();
+            hex = tokens.next() != Some("dec");
+            relative = tokens.nth(1) == Some("relative");
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let Some(time) = tokens.next().and_then(|token| token.parse::<f64>().ok()) else {
+            // header, comment or trigger block marker
+            continue;
+        };
+        let time = Duration::try_from_secs_f64(time)
+            .map_err(|_| invalid(format!("invalid timestamp {time}")))?;
+        let time = if relative {
+            last.checked_add(time)
+                .ok_or_else(|| invalid("timestamp overflow".to_string()))?
+        } else {
+            time
+        };
+        last = time;
+        let timestamp = start
+            .checked_add(time)
+            .ok_or_else(|| invalid("timestamp overflow".to_string()))?;
+
+        let tokens: Vec<&str> = tokens.collect();
+        let entry = match tokens.first() {
+            Some(&"CANFD") => parse_fd(&tokens[1..], hex),
+            Some(channel) if channel.parse::<u8>().is_ok() => parse_classic(&tokens, hex),
+            _ => continue,
+        };
+        if let Some((channel, direction, id, data, fd)) = entry.map_err(invalid)? {
+            entries.push(AscEntry {
+                channel,
+                direction,
+                frame: CanFrame {
+                    timestamp,
+                    id,
+                    data,
+                    fd,
+                },
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Read all frames of an ASC trace file
+pub fn load_asc(path: impl AsRef<Path>) -> io::Result<Vec<AscEntry>> {
+    read_asc(File::open(path)?)
+}
+
+type ParsedFrame = (u8, AscDirection, Id, Vec<u8>, bool);
+
+/// `<channel> <id>[x] <Rx|Tx> d <dlc> <data...> [...]`
+fn parse_classic(tokens: &[&str], hex: bool) -> Result<Option<ParsedFrame>, String> {
+    let channel = tokens[0].parse().map_err(|_| "invalid channel")?;
+    let Some(id) = tokens.get(1).and_then(|id| parse_id(id, hex)) else {
+        // error frames and other channel events
+        return Ok(None);
+    };
+    let direction = match tokens.get(2) {
+        Some(&"Rx") => AscDirection::Rx,
+        Some(&"Tx") => AscDirection::Tx,
+        _ => return Ok(None),
+    };
+    if tokens.get(3) != Some(&"d") {
+        // remote frame
+        return Ok(None);
+    }
+    let dlc: usize = tokens
+        .get(4)
+        .and_then(|dlc| usize::from_str_radix(dlc, 16).ok())
+        .ok_or("invalid dlc")?;
+    let data = parse_data(tokens.get(5..5 + dlc.min(8)).ok_or("missing data")?, hex)?;
+    Ok(Some((channel, direction, id, data, false)))
+}
+
+/// `<channel> <Rx|Tx> <id>[x] [name] <brs> <esi> <dlc> <length> <data...> [...]`
+fn parse_fd(tokens: &[&str], hex: bool) -> Result<Option<ParsedFrame>, String> {
+    let channel = tokens
+        .first()
+        .and_then(|channel| channel.parse().ok())
+        .ok_or("invalid channel")?;
+    let direction = match tokens.get(1) {
+        Some(&"Rx") => AscDirection::Rx,
+        Some(&"Tx") => AscDirection::Tx,
+        _ => return Ok(None),
+    };
+    let Some(id) = tokens.get(2).and_then(|id| parse_id(id, hex)) else {
+        return Ok(None);
+    };
+    // the symbolic name is optional, BRS and ESI are always 0 or 1
+    let mut index = 3;
+    if !matches!(tokens.get(index), Some(&"0") | Some(&"1")) {
+        index += 1;
+    }
+    let length: usize = tokens
+        .get(index + 3)
+        .and_then(|length| length.parse().ok())
+        .ok_or("invalid data length")?;
+    let data = tokens
+        .get(index + 4..index + 4 + length)
+        .ok_or("missing data")?;
+    Ok(Some((channel, direction, id, parse_data(data, hex)?, true)))
+}
+
+fn parse_id(text: &str, hex: bool) -> Option<Id> {
+    let radix = if hex { 16 } else { 10 };
+    match text.strip_suffix('x') {
+        Some(id) => {
+            let raw = u32::from_str_radix(id, radix).ok()?;
+            ExtendedId::new(raw).map(Id::Extended)
+        }
+        None => {
+            let raw = u32::from_str_radix(text, radix).ok()?;
+            if raw > SFF_MASK {
+                return None;
+            }
+            StandardId::new(raw as u16).map(Id::Standard)
+        }
+    }
+}
+
+fn parse_data(tokens: &[&str], hex: bool) -> Result<Vec<u8>, String> {
+    let radix = if hex { 16 } else { 10 };
+    tokens
+        .iter()
+        .map(|byte| u8::from_str_radix(byte, radix).map_err(|_| format!("invalid byte {byte:?}")))
+        .collect()
+}
+
+/// Parse the date of the header, e.g. `Mon Oct 19 10:00:00.000 am 2026`
+fn parse_date(text: &str) -> Option<SystemTime> {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let month_name = *tokens.get(1)?;
+    let month = MONTHS.iter().position(|&month| month == month_name)? as u32 + 1;
+    let day: u32 = tokens.get(2)?.parse().ok()?;
+    let (clock, meridiem, year) = match tokens.len() {
+        5 => (tokens[3], None, tokens[4]),
+        6 => (tokens[3], Some(tokens[4]), tokens[5]),
+        _ => return None,
+    };
+    let year: i64 = year.parse().ok()?;
+
+    let mut clock = clock.split(':');
+    let mut hour: u64 = clock.next()?.parse().ok()?;
+    let minute: u64 = clock.next()?.parse().ok()?;
+    let second: f64 = clock.next()?.parse().ok()?;
+    match meridiem {
+        Some("am") if hour == 12 => hour = 0,
+        Some("pm") if hour < 12 => hour += 12,
+        _ => {}
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let seconds = days
+        .checked_mul(86400)?
+        .checked_add(hour.checked_mul(3600)?)?
+        .checked_add(minute.checked_mul(60)?)?;
+    let since_epoch =
+        Duration::from_secs(seconds).checked_add(Duration::try_from_secs_f64(second).ok()?)?;
+    UNIX_EPOCH.checked_add(since_epoch)
+}
+
+fn format_date(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let days = since_epoch.as_secs() / 86400;
+    let seconds_of_day = since_epoch.as_secs() % 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let hour = seconds_of_day / 3600;
+    let (hour12, meridiem) = match hour {
+        0 => (12, "am"),
+        1..=11 => (hour, "am"),
+        12 => (12, "pm"),
+        _ => (hour - 12, "pm"),
+    };
+    format!(
+        "{} {} {:02} {:02}:{:02}:{:02}.{:03} {} {}",
+        WEEKDAYS[(days % 7) as usize],
+        MONTHS[month as usize - 1],
+        day,
+        hour12,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis(),
+        meridiem,
+        year
+    )
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5
+        + i64::from(day)
+        - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Proleptic Gregorian date of the days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Writes frames as a Vector ASC trace with hex values and absolute timestamps
+pub struct AscWriter<W: Write> {
+    writer: W,
+    start: SystemTime,
+}
+
+impl AscWriter<BufWriter<File>> {
+    /// Create (or truncate) a trace file, timestamps are relative to `start`
+    pub fn create(path: impl AsRef<Path>, start: SystemTime) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), start)
+    }
+}
+
+impl<W: Write> AscWriter<W> {
+    /// Start a trace on the given writer, timestamps are relative to `start`
+    pub fn new(mut writer: W, start: SystemTime) -> io::Result<Self> {
+        let date = format_date(start);
+        writeln!(writer, "date {date}")?;
+        writeln!(writer, "base hex  timestamps absolute")?;
+        writeln!(writer, "internal events logged")?;
+        writeln!(writer, "Begin Triggerblock {date}")?;
+        writeln!(writer, "   0.000000 Start of measurement")?;
+        Ok(Self { writer, start })
+    }
+
+    /// Append one frame
+    pub fn write_frame(
+        &mut self,
+        channel: u8,
+        direction: AscDirection,
+        frame: &CanFrame,
+    ) -> io::Result<()> {
+        let time = frame
+            .timestamp
+            .duration_since(self.start)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let id = match frame.id {
+            Id::Standard(id) => format!("{:X}", id.as_raw()),
+            Id::Extended(id) => format!("{:X}x", id.as_raw()),
+        };
+        let direction = match direction {
+            AscDirection::Rx => "Rx",
+            AscDirection::Tx => "Tx",
+        };
+        let data = frame
+            .data
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if frame.fd {
+            let dlc = match frame.data.len() {
+                len @ 0..=8 => len,
+                len => {
+                    8 + CANFD_DATA_LENGTHS
+                        .iter()
+                        .position(|&dl| dl >= len)
+                        .unwrap_or(CANFD_DATA_LENGTHS.len() - 1)
+                }
+            };
+            writeln!(
+                self.writer,
+                "{time:>11.6} CANFD {channel:>3} {direction:<4} {id:>8} {:>32} 0 0 {dlc:x} {:>2} {data} {:>8} {:>4} {:>8X} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                "", frame.data.len(), 0, 0, 0, 0, 0, 0, 0, 0
+            )
+        } else {
+            writeln!(
+                self.writer,
+                "{time:>11.6} {channel}  {id:<15} {direction:<4} d {} {data}",
+                frame.data.len()
+            )
+        }
+    }
+
+    /// Write the end of the trigger block and flush
+    pub fn finish(mut self) -> io::Result<W> {
+        writeln!(self.writer, "End TriggerBlock")?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE: &str = "\
+date Mon Oct 19 10:00:00.000 am 2026
+base hex  timestamps absolute
+internal events logged
+// version 13.0.0
+Begin Triggerblock Mon Oct 19 10:00:00.000 am 2026
+   0.000000 Start of measurement
+   0.010000 1  7E0             Tx   d 8 03 22 F1 90 00 00 00 00
+   0.011000 1  ErrorFrame
+   0.012000 1  18DAF110x       Rx   d 8 10 14 62 F1 90 31 32 33
+   0.013000 2  7E0             Rx   r
+   0.020000 CANFD   1 Rx        7E8  EngineResponse  1 0 9 12 01 02 03 04 05 06 07 08 09 0A 0B 0C   123456 130 1000 0 0 0 0 0
+End TriggerBlock
+";
+
+    fn date(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn reads_trace() {
+        let start = date(1792404000);
+        let entries = read_asc(TRACE.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0],
+            AscEntry {
+                channel: 1,
+                direction: AscDirection::Tx,
+                frame: CanFrame::new(
+                    start + Duration::from_millis(10),
+                    StandardId::new(0x7E0).unwrap(),
+                    vec![0x03, 0x22, 0xF1, 0x90, 0, 0, 0, 0],
+                ),
+            }
+        );
+        assert_eq!(entries[1].direction, AscDirection::Rx);
+        assert_eq!(
+            entries[1].frame.id,
+            Id::Extended(ExtendedId::new(0x18DAF110).unwrap())
+        );
+        assert!(entries[2].frame.fd);
+        assert_eq!(entries[2].frame.data, (1..=12).collect::<Vec<u8>>());
+        assert_eq!(
+            entries[2].frame.timestamp,
+            start + Duration::from_millis(20)
+        );
+    }
+
+    #[test]
+    fn decimal_and_relative_timestamps() {
+        let trace = "\
+date Mon Oct 19 10:30:05.000 pm 2026
+base dec  timestamps relative
+   0.500000 1  2016            Tx   d 3 2 16 240
+   0.250000 1  2024            Rx   d 2 255 1
+";
+        let entries = read_asc(trace.as_bytes()).unwrap();
+        let start = date(1792449005);
+        assert_eq!(
+            entries[0].frame.id,
+            Id::Standard(StandardId::new(0x7E0).unwrap())
+        );
+        assert_eq!(entries[0].frame.data, [0x02, 0x10, 0xF0]);
+        assert_eq!(
+            entries[0].frame.timestamp,
+            start + Duration::from_millis(500)
+        );
+        assert_eq!(
+            entries[1].frame.timestamp,
+            start + Duration::from_millis(750)
+        );
+    }
+
+    #[test]
+    fn localized_date_falls_back_to_epoch() {
+        let trace = "\
+date Mo 19. Okt 10:00:00.000 2026
+   1.000000 1  7E0             Tx   d 1 01
+";
+        let entries = read_asc(trace.as_bytes()).unwrap();
+        assert_eq!(entries[0].frame.timestamp, date(1));
+    }
+
+    #[test]
+    fn invalid_timestamps() {
+        for time in ["-1.0", "NaN", "inf", "1e300"] {
+            let trace = format!("{time} 1  7E0             Tx   d 1 01\n");
+            let error = read_asc(trace.as_bytes()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{time}");
+        }
+        assert_eq!(parse_date("Mon Oct 19 10:00:-1.0 am 2026"), None);
+        assert_eq!(parse_date("Mon Oct 19 10:00:NaN am 2026"), None);
+        assert_eq!(parse_date("Mon Oct 19 10:00:00 am 1969"), None);
+    }
+
+    #[test]
+    fn invalid_frames() {
+        for line in [
+            "0.1 1  7E0             Tx   d 8 01 02",
+            "0.1 1  7E0             Tx   d 2 01 XY",
+            "0.1 CANFD   1 Rx        7E8  1 0 9 12 01 02",
+        ] {
+            let error = read_asc(line.as_bytes()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{line}");
+        }
+    }
+
+    #[test]
+    fn dates() {
+        for seconds in [0, 1792404000, 1792449005, 951782400] {
+            let text = format_date(date(seconds));
+            assert_eq!(parse_date(&text), Some(date(seconds)), "{text}");
+        }
+        assert_eq!(
+            format_date(date(1792404000)),
+            "Mon Oct 19 10:00:00.000 am 2026"
+        );
+        assert_eq!(
+            format_date(date(1792449005)),
+            "Mon Oct 19 10:30:05.000 pm 2026"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let start = date(1792404000);
+        let frames = vec![
+            (
+                1,
+                AscDirection::Tx,
+                CanFrame::new(
+                    start + Duration::from_millis(10),
+                    StandardId::new(0x7E0).unwrap(),
+                    vec![0x02, 0x10, 0x03],
+                ),
+            ),
+            (
+                2,
+                AscDirection::Rx,
+                CanFrame::new(
+                    start + Duration::from_micros(12_345),
+                    ExtendedId::new(0x18DAF110).unwrap(),
+                    vec![],
+                ),
+            ),
+            (
+                1,
+                AscDirection::Rx,
+                CanFrame::new(
+                    start + Duration::from_secs(3),
+                    StandardId::new(0x7E8).unwrap(),
+                    (0..48).collect(),
+                ),
+            ),
+            (
+                1,
+                AscDirection::Tx,
+                CanFrame {
+                    timestamp: start + Duration::from_secs(4),
+                    id: Id::Standard(StandardId::new(0x7E0).unwrap()),
+                    data: vec![0x01],
+                    fd: true,
+                },
+            ),
+        ];
+        let mut writer = AscWriter::new(Vec::new(), start).unwrap();
+        for (channel, direction, frame) in &frames {
+            writer.write_frame(*channel, *direction, frame).unwrap();
+        }
+        let trace = writer.finish().unwrap();
+        let entries = read_asc(trace.as_slice()).unwrap();
+        let read: Vec<(u8, AscDirection, CanFrame)> = entries
+            .into_iter()
+            .map(|entry| (entry.channel, entry.direction, entry.frame))
+            .collect();
+        assert_eq!(read, frames);
+    }
+}
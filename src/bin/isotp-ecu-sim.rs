@@ -0,0 +1,56 @@
+//! Simulation of the ECUs described in a TOML or YAML model, see `uds::sim`
+
+use clap::Parser;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tokio_socketcan_isotp::uds::sim::SimulationModel;
+
+/// Serve simulated UDS ECUs on SocketCAN interfaces
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Model file, `.yaml`/`.yml` are read as YAML and all others as TOML
+    model: PathBuf,
+    /// CAN device of all ECUs, replacing the ones of the model
+    #[arg(short, long)]
+    interface: Option<String>,
+    /// Only check the model
+    #[arg(long)]
+    check: bool,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    let model = match SimulationModel::load(&args.model) {
+        Ok(model) => model,
+        Err(err) => {
+            eprintln!("{}: {err}", args.model.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    for ecu in &model.ecus {
+        let interface = args
+            .interface
+            .as_deref()
+            .or(ecu.interface.as_deref())
+            .unwrap_or(&model.interface);
+        println!(
+            "{}: {} -> {} on {interface}",
+            ecu.name, ecu.request_id, ecu.response_id
+        );
+    }
+    if args.check {
+        return ExitCode::SUCCESS;
+    }
+    tokio::select! {
+        result = model.serve(args.interface.as_deref()) => match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{err}");
+                ExitCode::FAILURE
+            }
+        },
+        _ = tokio::signal::ctrl_c() => ExitCode::SUCCESS,
+    }
+}
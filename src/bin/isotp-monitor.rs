@@ -0,0 +1,570 @@
+//! Terminal UI of the ISO-TP conversations on a CAN device, built on `sniffer`
+
+use clap::Parser;
+use ratatui::crossterm::event::{
+    self, Event as TerminalEvent, KeyCode, KeyEventKind, KeyModifiers,
+};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::io;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio_socketcan_isotp::sniffer::{IsoTpSniffer, SniffedPdu};
+use tokio_socketcan_isotp::text::{format_ascii, format_bytes, format_id, parse_byte, parse_id};
+use tokio_socketcan_isotp::uds::service::{
+    service_name, COMMUNICATION_CONTROL, CONTROL_DTC_SETTING, DIAGNOSTIC_SESSION_CONTROL,
+    ECU_RESET, INPUT_OUTPUT_CONTROL_BY_IDENTIFIER, NEGATIVE_RESPONSE, POSITIVE_RESPONSE_OFFSET,
+    READ_DATA_BY_IDENTIFIER, READ_DTC_INFORMATION, ROUTINE_CONTROL, SECURITY_ACCESS,
+    TESTER_PRESENT, WRITE_DATA_BY_IDENTIFIER,
+};
+use tokio_socketcan_isotp::uds::NegativeResponseCode;
+use tokio_socketcan_isotp::{Id, IsoTpBehaviour, IsoTpOptions, LinkLayerOptions, TxFlags};
+
+/// Interval of the redraw refreshing the ages
+const REFRESH: Duration = Duration::from_millis(250);
+
+const KEYS: &str = " q quit  ↑↓ select  Enter PDUs  Esc back  c clear ";
+
+/// Live view of the ISO-TP conversations on a CAN device
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// CAN device such as vcan0
+    interface: String,
+    /// Ids of a conversation as TESTER:ECU, e.g. 7E0:7E8, the tester sends the requests
+    #[arg(short = 'c', long = "conversation", required = true, value_parser = parse_conversation)]
+    conversations: Vec<(Id, Id)>,
+    /// Extended addressing, address in front of the frames of the tester
+    #[arg(short = 'x', long, value_parser = parse_byte)]
+    ext_address: Option<u8>,
+    /// Address in front of the frames of the ECU, the one of the tester if not given
+    #[arg(long, value_parser = parse_byte, requires = "ext_address")]
+    rx_ext_address: Option<u8>,
+    /// Receive CAN FD frames
+    #[arg(long)]
+    fd: bool,
+    /// PDUs kept per conversation
+    #[arg(long, default_value_t = 1000)]
+    keep: usize,
+}
+
+impl Args {
+    fn isotp_options(&self) -> Option<IsoTpOptions> {
+        let address = self.ext_address?;
+        let mut options = IsoTpOptions::default();
+        let mut flags = IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR;
+        options.set_ext_address(address);
+        if let Some(address) = self.rx_ext_address {
+            flags |= IsoTpBehaviour::CAN_ISOTP_RX_EXT_ADDR;
+            options.set_rx_ext_address(address);
+        }
+        options.set_flags(flags);
+        Some(options)
+    }
+
+    fn link_layer_options(&self) -> Option<LinkLayerOptions> {
+        self.fd
+            .then(|| LinkLayerOptions::can_fd(64, TxFlags::empty()))
+    }
+}
+
+enum Event {
+    Pdu(usize, SniffedPdu),
+    Error(usize, io::Error),
+    Terminal(TerminalEvent),
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    let (events, mut received) = mpsc::unbounded_channel();
+    // open all sockets before the terminal is taken over, so errors are readable
+    for (index, (tester, ecu)) in args.conversations.iter().enumerate() {
+        let sniffer = match IsoTpSniffer::open_with_opts(
+            &args.interface,
+            *tester,
+            *ecu,
+            args.isotp_options(),
+            args.link_layer_options(),
+        ) {
+            Ok(sniffer) => sniffer,
+            Err(err) => {
+                eprintln!("isotp-monitor: {}: {err}", args.interface);
+                return ExitCode::FAILURE;
+            }
+        };
+        let events = events.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = match sniffer.recv().await {
+                    Ok(pdu) => Event::Pdu(index, pdu),
+                    Err(err) => {
+                        // protocol errors concern a single PDU, a device gone down is
+                        // not polled at full speed
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        Event::Error(index, err)
+                    }
+                };
+                if events.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    // crossterm reads blocking, the sniffers run on the workers
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if events.send(Event::Terminal(event)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut app = App::new(&args);
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal, &mut received).await;
+    ratatui::restore();
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("isotp-monitor: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Response times of the requests of a conversation
+#[derive(Default)]
+struct Timing {
+    count: u32,
+    last: Duration,
+    min: Duration,
+    max: Duration,
+    total: Duration,
+}
+
+impl Timing {
+    fn add(&mut self, time: Duration) {
+        if self.count == 0 || time < self.min {
+            self.min = time;
+        }
+        self.max = self.max.max(time);
+        self.last = time;
+        self.total += time;
+        self.count += 1;
+    }
+
+    fn average(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count)
+    }
+}
+
+/// Statistics and the latest PDUs of the conversation of a tester and an ECU
+struct Conversation {
+    tester: Id,
+    ecu: Id,
+    pdus: VecDeque<SniffedPdu>,
+    /// Time of the first PDU, the PDUs are shown relative to it
+    started: Option<SystemTime>,
+    requests: u64,
+    responses: u64,
+    /// Response pending (NRC 0x78) sent by the ECU
+    pending: u64,
+    errors: u64,
+    last_error: Option<String>,
+    /// Time of the request the final response is still expected for
+    outstanding: Option<SystemTime>,
+    timing: Timing,
+    /// PDU selected in the detail view
+    selected: TableState,
+}
+
+impl Conversation {
+    fn new(tester: Id, ecu: Id) -> Self {
+        Self {
+            tester,
+            ecu,
+            pdus: VecDeque::new(),
+            started: None,
+            requests: 0,
+            responses: 0,
+            pending: 0,
+            errors: 0,
+            last_error: None,
+            outstanding: None,
+            timing: Timing::default(),
+            selected: TableState::default(),
+        }
+    }
+
+    fn add(&mut self, pdu: SniffedPdu, keep: usize) {
+        if pdu.tx_id == self.tester {
+            self.requests += 1;
+            self.outstanding = Some(pdu.timestamp);
+        } else {
+            self.responses += 1;
+            if is_response_pending(&pdu.data) {
+                self.pending += 1;
+            } else if let Some(request) = self.outstanding.take() {
+                self.timing
+                    .add(pdu.timestamp.duration_since(request).unwrap_or_default());
+            }
+        }
+        self.started.get_or_insert(pdu.timestamp);
+        self.pdus.push_back(pdu);
+        if self.pdus.len() > keep {
+            self.pdus.pop_front();
+            // keep the selection on the same PDU
+            if let Some(selected) = self.selected.selected() {
+                self.selected.select(Some(selected.saturating_sub(1)));
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = Self::new(self.tester, self.ecu);
+    }
+
+    fn name(&self) -> String {
+        format!("{} <-> {}", format_id(self.tester), format_id(self.ecu))
+    }
+}
+
+enum View {
+    Conversations,
+    Pdus(usize),
+}
+
+struct App {
+    interface: String,
+    keep: usize,
+    conversations: Vec<Conversation>,
+    selected: TableState,
+    view: View,
+}
+
+impl App {
+    fn new(args: &Args) -> Self {
+        Self {
+            interface: args.interface.clone(),
+            keep: args.keep.max(1),
+            conversations: args
+                .conversations
+                .iter()
+                .map(|(tester, ecu)| Conversation::new(*tester, *ecu))
+                .collect(),
+            selected: TableState::default().with_selected(Some(0)),
+            view: View::Conversations,
+        }
+    }
+
+    async fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        events: &mut mpsc::UnboundedReceiver<Event>,
+    ) -> io::Result<()> {
+        let mut refresh = tokio::time::interval(REFRESH);
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(Event::Pdu(index, pdu)) => self.conversations[index].add(pdu, self.keep),
+                    Some(Event::Error(index, err)) => {
+                        let conversation = &mut self.conversations[index];
+                        conversation.errors += 1;
+                        conversation.last_error = Some(err.to_string());
+                    }
+                    Some(Event::Terminal(event)) => {
+                        if !self.handle(event) {
+                            return Ok(());
+                        }
+                    }
+                    None => return Ok(()),
+                },
+                _ = refresh.tick() => {}
+            }
+        }
+    }
+
+    /// Apply a key, false to quit
+    fn handle(&mut self, event: TerminalEvent) -> bool {
+        let TerminalEvent::Key(key) = event else {
+            return true;
+        };
+        if key.kind != KeyEventKind::Press {
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char('c') => match self.view {
+                View::Conversations => self.conversations.iter_mut().for_each(Conversation::clear),
+                View::Pdus(index) => self.conversations[index].clear(),
+            },
+            KeyCode::Enter => {
+                if let (View::Conversations, Some(index)) = (&self.view, self.selected.selected()) {
+                    let conversation = &mut self.conversations[index];
+                    if conversation.selected.selected().is_none() {
+                        conversation
+                            .selected
+                            .select(conversation.pdus.len().checked_sub(1));
+                    }
+                    self.view = View::Pdus(index);
+                }
+            }
+            KeyCode::Esc | KeyCode::Backspace | KeyCode::Left => {
+                self.view = View::Conversations;
+            }
+            KeyCode::Up => self.step(-1),
+            KeyCode::Down => self.step(1),
+            KeyCode::PageUp => self.step(-20),
+            KeyCode::PageDown => self.step(20),
+            KeyCode::Home => self.step(isize::MIN),
+            KeyCode::End => self.step(isize::MAX),
+            _ => {}
+        }
+        true
+    }
+
+    /// Move the selection of the table shown
+    fn step(&mut self, by: isize) {
+        let (state, len) = match self.view {
+            View::Conversations => (&mut self.selected, self.conversations.len()),
+            View::Pdus(index) => {
+                let conversation = &mut self.conversations[index];
+                (&mut conversation.selected, conversation.pdus.len())
+            }
+        };
+        if len == 0 {
+            return;
+        }
+        let current = state.selected().unwrap_or(0);
+        let next = current.saturating_add_signed(by).min(len - 1);
+        state.select(Some(next));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [body, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        match self.view {
+            View::Conversations => self.draw_conversations(frame, body),
+            View::Pdus(index) => self.draw_pdus(frame, body, index),
+        }
+        frame.render_widget(
+            Line::from(vec![
+                format!(" {} ", self.interface).bold().reversed(),
+                KEYS.into(),
+            ]),
+            status,
+        );
+    }
+
+    fn draw_conversations(&mut self, frame: &mut Frame, area: Rect) {
+        let now = SystemTime::now();
+        let rows = self.conversations.iter().map(|conversation| {
+            let last = conversation.pdus.back();
+            let timing = &conversation.timing;
+            Row::new(vec![
+                conversation.name(),
+                conversation.requests.to_string(),
+                conversation.responses.to_string(),
+                conversation.pending.to_string(),
+                conversation.errors.to_string(),
+                last.map(|pdu| format_age(now, pdu.timestamp))
+                    .unwrap_or_default(),
+                last.map(|pdu| describe(&pdu.data)).unwrap_or_default(),
+                match timing.average() {
+                    Some(average) => format!(
+                        "{} / {} / {} / {}",
+                        format_millis(timing.last),
+                        format_millis(timing.min),
+                        format_millis(average),
+                        format_millis(timing.max)
+                    ),
+                    None => String::new(),
+                },
+                last.map(|pdu| format_bytes(&pdu.data)).unwrap_or_default(),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(22),
+                Constraint::Length(9),
+                Constraint::Length(10),
+                Constraint::Length(8),
+                Constraint::Length(7),
+                Constraint::Length(8),
+                Constraint::Length(34),
+                Constraint::Length(30),
+                Constraint::Min(10),
+            ],
+        )
+        .header(
+            Row::new(vec![
+                "Conversation",
+                "Requests",
+                "Responses",
+                "Pending",
+                "Errors",
+                "Last",
+                "Service",
+                "Response ms last/min/avg/max",
+                "Last payload",
+            ])
+            .bold(),
+        )
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(" ISO-TP conversations "));
+        frame.render_stateful_widget(table, area, &mut self.selected);
+    }
+
+    fn draw_pdus(&mut self, frame: &mut Frame, area: Rect, index: usize) {
+        let conversation = &mut self.conversations[index];
+        let [list, detail] =
+            Layout::vertical([Constraint::Min(5), Constraint::Length(10)]).areas(area);
+        let start = conversation.started;
+        let rows = conversation.pdus.iter().map(|pdu| {
+            let time = start
+                .and_then(|start| pdu.timestamp.duration_since(start).ok())
+                .unwrap_or_default();
+            Row::new(vec![
+                format!("{:.3}", time.as_secs_f64()),
+                if pdu.tx_id == conversation.tester {
+                    "->".to_owned()
+                } else {
+                    "<-".to_owned()
+                },
+                pdu.data.len().to_string(),
+                describe(&pdu.data),
+                format_bytes(&pdu.data),
+            ])
+        });
+        let title = match &conversation.last_error {
+            Some(err) => format!(" {} - last error: {err} ", conversation.name()),
+            None => format!(" {} ", conversation.name()),
+        };
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(10),
+                Constraint::Length(3),
+                Constraint::Length(6),
+                Constraint::Length(34),
+                Constraint::Min(10),
+            ],
+        )
+        .header(Row::new(vec!["Time s", "Dir", "Length", "Service", "Data"]).bold())
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(title));
+        frame.render_stateful_widget(table, list, &mut conversation.selected);
+
+        let selected = conversation
+            .selected
+            .selected()
+            .and_then(|selected| conversation.pdus.get(selected));
+        let text: Vec<Line> = match selected {
+            Some(pdu) => pdu
+                .data
+                .chunks(16)
+                .enumerate()
+                .map(|(line, chunk)| {
+                    Line::from(format!(
+                        "{:04X}  {:<47}  {}",
+                        line * 16,
+                        format_bytes(chunk),
+                        format_ascii(chunk)
+                    ))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let title = selected
+            .map(|pdu| format!(" {} ", describe(&pdu.data)))
+            .unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(text)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(title)),
+            detail,
+        );
+    }
+}
+
+fn is_response_pending(data: &[u8]) -> bool {
+    matches!(data, [NEGATIVE_RESPONSE, _, code, ..]
+        if NegativeResponseCode::from(*code) == NegativeResponseCode::RequestCorrectlyReceivedResponsePending)
+}
+
+/// UDS service of a request or response with its DID, routine or sub-function
+fn describe(data: &[u8]) -> String {
+    let name = |sid: u8| service_name(sid).map_or_else(|| format!("0x{sid:02X}"), str::to_owned);
+    match data {
+        [] => String::new(),
+        [NEGATIVE_RESPONSE, sid, code, ..] => format!(
+            "{} NRC {}",
+            name(*sid),
+            NegativeResponseCode::from(*code).description()
+        ),
+        [sid, rest @ ..] => {
+            let (service, suffix) = match service_name(*sid) {
+                Some(_) => (*sid, ""),
+                None => match sid.checked_sub(POSITIVE_RESPONSE_OFFSET) {
+                    Some(service) if service_name(service).is_some() => (service, " +"),
+                    _ => return format!("0x{sid:02X}"),
+                },
+            };
+            let parameter = match (service, rest) {
+                (
+                    READ_DATA_BY_IDENTIFIER
+                    | WRITE_DATA_BY_IDENTIFIER
+                    | INPUT_OUTPUT_CONTROL_BY_IDENTIFIER,
+                    [high, low, ..],
+                ) => format!(" {high:02X}{low:02X}"),
+                (ROUTINE_CONTROL, [sub_function, high, low, ..]) => {
+                    format!(" {sub_function:02X} {high:02X}{low:02X}")
+                }
+                (
+                    DIAGNOSTIC_SESSION_CONTROL
+                    | ECU_RESET
+                    | READ_DTC_INFORMATION
+                    | SECURITY_ACCESS
+                    | COMMUNICATION_CONTROL
+                    | TESTER_PRESENT
+                    | CONTROL_DTC_SETTING,
+                    [sub_function, ..],
+                ) => {
+                    format!(" {sub_function:02X}")
+                }
+                _ => String::new(),
+            };
+            format!("{}{parameter}{suffix}", name(service))
+        }
+    }
+}
+
+fn format_age(now: SystemTime, then: SystemTime) -> String {
+    let age = now.duration_since(then).unwrap_or_default();
+    if age < Duration::from_secs(60) {
+        format!("{:.1} s", age.as_secs_f64())
+    } else {
+        format!("{} min", age.as_secs() / 60)
+    }
+}
+
+fn format_millis(time: Duration) -> String {
+    format!("{:.1}", time.as_secs_f64() * 1000.0)
+}
+
+fn parse_conversation(text: &str) -> Result<(Id, Id), String> {
+    let (tester, ecu) = text
+        .split_once(':')
+        .ok_or_else(|| format!("expected TESTER:ECU ids, got {text:?}"))?;
+    let parse = |text| parse_id(text).map_err(|err| err.to_string());
+    Ok((parse(tester)?, parse(ecu)?))
+}
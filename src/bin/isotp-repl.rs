@@ -0,0 +1,528 @@
+//! Interactive UDS shell for bench work
+
+use clap::Parser;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_socketcan_isotp::text::{decode_hex, format_ascii, format_bytes, parse_byte, parse_id};
+use tokio_socketcan_isotp::trace::{RecordingTransport, TraceFormat, TraceWriter};
+use tokio_socketcan_isotp::uds::did::DidRegistry;
+use tokio_socketcan_isotp::uds::service::{service_name, NEGATIVE_RESPONSE};
+#[cfg(feature = "seed-key-library")]
+use tokio_socketcan_isotp::uds::SharedLibraryAlgorithm;
+use tokio_socketcan_isotp::uds::{
+    DiagnosticSession, Dtc, DtcList, DtcStatus, Error, ResetType, RoutineControlType,
+    SecurityAccessOutcome, SeedKeyAlgorithm, SessionParameters, UdsClient,
+    DEFAULT_TESTER_PRESENT_INTERVAL, S3_SERVER,
+};
+use tokio_socketcan_isotp::{Id, IsoTpBehaviour, IsoTpOptions, IsoTpSocket, IsoTpTransport};
+
+const COMMANDS: &str = "Commands, numbers are hex with or without 0x:
+  session <default|programming|extended|safety|NN>  DiagnosticSessionControl
+  read <did>...                                    ReadDataByIdentifier, decoded with --dids
+  write <did> <data>                               WriteDataByIdentifier
+  unlock <level>                                   SecurityAccess, the key is asked for without --seed-key-library
+  reset [hard|keyoffon|soft|NN]                    ECUReset
+  dtc list [mask]                                  reportDTCByStatusMask, all DTCs by default
+  dtc count [mask]                                 reportNumberOfDTCByStatusMask
+  dtc supported                                    reportSupportedDTC
+  dtc clear [group]                                ClearDiagnosticInformation, e.g. P0301-00, all by default
+  routine <start|stop|result> <id> [data]          RoutineControl
+  tp [on|off]                                      send TesterPresent, or switch the one sent in the background
+  <data>                                           raw request, e.g. 22 F1 90
+  help
+  quit";
+
+/// Interactive UDS shell on an ISO-TP socket
+#[derive(Parser)]
+#[command(version, after_help = COMMANDS)]
+struct Args {
+    /// CAN device such as vcan0
+    interface: String,
+    /// CAN id the requests are sent on, 29 bit if longer than 3 hex digits
+    #[arg(short = 's', long, value_parser = parse_id)]
+    tx_id: Id,
+    /// CAN id the responses are received on, 29 bit if longer than 3 hex digits
+    #[arg(short = 'd', long, value_parser = parse_id)]
+    rx_id: Id,
+    /// Extended addressing, address in front of the frames sent and received
+    #[arg(short = 'x', long, value_parser = parse_byte)]
+    ext_address: Option<u8>,
+    /// Pad the frames sent with this byte
+    #[arg(short = 'p', long, value_parser = parse_byte)]
+    padding: Option<u8>,
+    /// DID registry in TOML or YAML decoding the data read
+    #[arg(long)]
+    dids: Option<PathBuf>,
+    /// Record the PDUs of the session, `.jsonl` as JSON Lines and all others binary
+    #[arg(long)]
+    trace: Option<PathBuf>,
+    /// History file, `~/.isotp_repl_history` by default
+    #[arg(long)]
+    history: Option<PathBuf>,
+    /// Milliseconds between the TesterPresent sent in non-default sessions, below S3server (5000)
+    #[arg(
+        long,
+        default_value_t = DEFAULT_TESTER_PRESENT_INTERVAL.as_millis() as u64,
+        value_parser = clap::value_parser!(u64).range(1..S3_SERVER.as_millis() as u64)
+    )]
+    tester_present: u64,
+    /// Shared library exporting GenerateKeyEx computing the SecurityAccess keys
+    #[cfg(feature = "seed-key-library")]
+    #[arg(long)]
+    seed_key_library: Option<PathBuf>,
+    /// Variant passed to the library
+    #[cfg(feature = "seed-key-library")]
+    #[arg(long, requires = "seed_key_library")]
+    variant: Option<String>,
+}
+
+impl Args {
+    fn isotp_options(&self) -> IsoTpOptions {
+        let mut options = IsoTpOptions::default();
+        let mut flags = IsoTpBehaviour::empty();
+        if let Some(address) = self.ext_address {
+            flags |= IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR;
+            options.set_ext_address(address);
+        }
+        if let Some(padding) = self.padding {
+            flags |= IsoTpBehaviour::CAN_ISOTP_TX_PADDING;
+            options.set_txpad_content(padding);
+        }
+        options.set_flags(flags);
+        options
+    }
+
+    fn history_path(&self) -> Option<PathBuf> {
+        self.history.clone().or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".isotp_repl_history"))
+        })
+    }
+
+    #[cfg(feature = "seed-key-library")]
+    fn algorithm(&self) -> Result<Option<Box<dyn SeedKeyAlgorithm>>, String> {
+        let Some(path) = &self.seed_key_library else {
+            return Ok(None);
+        };
+        // the library is given by the user, who vouches for its interface
+        let algorithm = unsafe { SharedLibraryAlgorithm::load(path) }
+            .and_then(|algorithm| match &self.variant {
+                Some(variant) => algorithm.with_variant(variant),
+                None => Ok(algorithm),
+            })
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        Ok(Some(Box::new(algorithm)))
+    }
+
+    #[cfg(not(feature = "seed-key-library"))]
+    fn algorithm(&self) -> Result<Option<Box<dyn SeedKeyAlgorithm>>, String> {
+        Ok(None)
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Args::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("isotp-repl: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let dids = args
+        .dids
+        .as_ref()
+        .map(|path| DidRegistry::load(path).map_err(|err| format!("{}: {err}", path.display())))
+        .transpose()?;
+    let algorithm = args.algorithm()?;
+    let socket = IsoTpSocket::open_with_opts(
+        &args.interface,
+        args.rx_id,
+        args.tx_id,
+        Some(args.isotp_options()),
+        None,
+        None,
+    )
+    .map_err(|err| format!("{}: {err}", args.interface))?;
+    let mut shell = Shell {
+        editor: DefaultEditor::new().map_err(|err| err.to_string())?,
+        history: args.history_path(),
+        dids,
+        algorithm,
+        tester_present: Arc::new(AtomicBool::new(true)),
+    };
+    if let Some(path) = &shell.history {
+        // a missing history is normal on the first start
+        shell.editor.load_history(path).ok();
+    }
+    let interval = Duration::from_millis(args.tester_present);
+    match &args.trace {
+        Some(path) => {
+            let writer = TraceWriter::create(path, TraceFormat::from_path(path))
+                .map_err(|err| format!("{}: {err}", path.display()))?;
+            let writer = Arc::new(writer);
+            let transport = RecordingTransport::new(socket, writer.clone());
+            shell.run(UdsClient::new(transport), interval).await;
+            writer
+                .flush()
+                .map_err(|err| format!("{}: {err}", path.display()))?;
+        }
+        None => shell.run(UdsClient::new(socket), interval).await,
+    }
+    if let Some(path) = &shell.history {
+        shell
+            .editor
+            .save_history(path)
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+    }
+    Ok(())
+}
+
+struct Shell {
+    editor: DefaultEditor,
+    history: Option<PathBuf>,
+    dids: Option<DidRegistry>,
+    algorithm: Option<Box<dyn SeedKeyAlgorithm>>,
+    /// Whether TesterPresent is sent in non-default sessions
+    tester_present: Arc<AtomicBool>,
+}
+
+impl Shell {
+    async fn run<T: IsoTpTransport + Send + Sync + 'static>(
+        &mut self,
+        client: UdsClient<T>,
+        interval: Duration,
+    ) {
+        let client = Arc::new(client);
+        let keepalive = self.keepalive(&client, interval);
+        println!("type help for the commands");
+        loop {
+            // the editor blocks the thread of main only, the keepalive runs on the workers
+            let line = match self.editor.readline(&prompt(&client)) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(err) => {
+                    eprintln!("{err}");
+                    break;
+                }
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            self.editor.add_history_entry(line).ok();
+            if matches!(line, "quit" | "exit") {
+                break;
+            }
+            if let Err(err) = self.execute(&client, line).await {
+                println!("error: {err}");
+            }
+        }
+        keepalive.abort();
+        if client.timing().session != DiagnosticSession::Default {
+            // leave the ECU the way it was found, S3 ends the session otherwise
+            client
+                .diagnostic_session_control(DiagnosticSession::Default)
+                .await
+                .ok();
+        }
+    }
+
+    /// TesterPresent in the background while a non-default session is active
+    ///
+    /// A [SessionGuard](tokio_socketcan_isotp::uds::SessionGuard) returns to the default
+    /// session when replaced, the shell switches between sessions directly instead.
+    fn keepalive<T: IsoTpTransport + Send + Sync + 'static>(
+        &self,
+        client: &Arc<UdsClient<T>>,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let client = Arc::clone(client);
+        let enabled = Arc::clone(&self.tester_present);
+        tokio::spawn(async move {
+            let active = || {
+                enabled.load(Ordering::Relaxed)
+                    && client.timing().session != DiagnosticSession::Default
+            };
+            // the interval is checked by the argument parser, so only the transport fails
+            while let Err(err) = client.keep_alive(interval, active).await {
+                eprintln!("TesterPresent failed: {err}");
+            }
+        })
+    }
+
+    async fn execute<T: IsoTpTransport>(
+        &self,
+        client: &UdsClient<T>,
+        line: &str,
+    ) -> Result<(), String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["help"] => println!("{COMMANDS}"),
+            ["session", session] => {
+                let parameters = client
+                    .diagnostic_session_control(parse_session(session)?)
+                    .await
+                    .map_err(describe)?;
+                println!(
+                    "{}, P2 {} ms, P2* {} ms",
+                    parameters.session,
+                    parameters.p2.as_millis(),
+                    parameters.p2_star.as_millis()
+                );
+            }
+            ["read", ref dids @ ..] if !dids.is_empty() => {
+                for did in dids {
+                    let did = parse_hex_number(did)?;
+                    let data = client
+                        .read_data_by_identifier(did)
+                        .await
+                        .map_err(describe)?;
+                    println!("{did:04X} = {}", self.format_did(did, &data));
+                }
+            }
+            ["write", did, ref data @ ..] if !data.is_empty() => {
+                let did = parse_hex_number(did)?;
+                client
+                    .write_data_by_identifier(did, &parse_data(&data.concat())?)
+                    .await
+                    .map_err(describe)?;
+                println!("{did:04X} written");
+            }
+            ["unlock", level] => {
+                let level = parse_hex_number(level)?;
+                let outcome = match &self.algorithm {
+                    Some(algorithm) => client.security_access(level, algorithm.as_ref()).await,
+                    None => client.security_access(level, &KeyPrompt).await,
+                }
+                .map_err(describe)?;
+                match outcome {
+                    SecurityAccessOutcome::Unlocked => println!("level 0x{level:02X} unlocked"),
+                    SecurityAccessOutcome::AlreadyUnlocked => {
+                        println!("level 0x{level:02X} was unlocked already")
+                    }
+                }
+            }
+            ["reset"] | ["reset", _] => {
+                let reset_type = match words.get(1) {
+                    Some(reset_type) => parse_reset_type(reset_type)?,
+                    None => ResetType::Hard,
+                };
+                let power_down_time = client.ecu_reset(reset_type).await.map_err(describe)?;
+                // the ECU starts in the default session, which also stops the keepalive
+                let mut config = client.config();
+                config.timing = SessionParameters::default();
+                client.set_config(config);
+                match power_down_time {
+                    Some(seconds) => println!("{reset_type}, power down time {seconds} s"),
+                    None => println!("{reset_type}"),
+                }
+            }
+            ["dtc", "list"] | ["dtc", "list", _] => {
+                let mask = parse_mask(words.get(2))?;
+                let list = client
+                    .report_dtc_by_status_mask(mask)
+                    .await
+                    .map_err(describe)?;
+                print_dtcs(&list);
+            }
+            ["dtc", "count"] | ["dtc", "count", _] => {
+                let mask = parse_mask(words.get(2))?;
+                let count = client
+                    .report_number_of_dtc_by_status_mask(mask)
+                    .await
+                    .map_err(describe)?;
+                println!(
+                    "{} DTCs, {}, availability mask {}",
+                    count.count, count.format, count.availability_mask
+                );
+            }
+            ["dtc", "supported"] => {
+                let list = client.report_supported_dtc().await.map_err(describe)?;
+                print_dtcs(&list);
+            }
+            ["dtc", "clear"] | ["dtc", "clear", _] => {
+                let group = match words.get(2) {
+                    Some(group) => Dtc::from_str(group)
+                        .or_else(|_| parse_hex_number(group).map(Dtc::new))
+                        .map_err(|_| format!("invalid DTC group {group:?}"))?,
+                    None => Dtc::ALL,
+                };
+                client
+                    .clear_diagnostic_information(group)
+                    .await
+                    .map_err(describe)?;
+                println!("DTCs cleared");
+            }
+            ["routine", control_type, routine, ref data @ ..] => {
+                let control_type = match control_type {
+                    "start" => RoutineControlType::Start,
+                    "stop" => RoutineControlType::Stop,
+                    "result" | "results" => RoutineControlType::RequestResults,
+                    other => return Err(format!("unknown routine control {other:?}")),
+                };
+                let routine = parse_hex_number(routine)?;
+                let option_record = if data.is_empty() {
+                    Vec::new()
+                } else {
+                    parse_data(&data.concat())?
+                };
+                let status = client
+                    .routine_control(control_type, routine, &option_record)
+                    .await
+                    .map_err(describe)?;
+                println!("{routine:04X} {control_type}: {}", format_bytes(&status));
+            }
+            ["tp"] => {
+                client.tester_present(false).await.map_err(describe)?;
+                println!("TesterPresent answered");
+            }
+            ["tp", "on"] | ["tp", "off"] => {
+                let enabled = words[1] == "on";
+                self.tester_present.store(enabled, Ordering::Relaxed);
+                println!(
+                    "background TesterPresent {}",
+                    if enabled { "on" } else { "off" }
+                );
+            }
+            _ => {
+                let request = parse_data(&words.concat())
+                    .map_err(|_| format!("unknown command {line:?}, type help for the commands"))?;
+                let response = client.request(&request).await.map_err(describe)?;
+                println!("{}", format_response(&response));
+            }
+        }
+        Ok(())
+    }
+
+    /// Data decoded by the registry, or in hex with the printable text
+    fn format_did(&self, did: u16, data: &[u8]) -> String {
+        if let Some(value) = self
+            .dids
+            .as_ref()
+            .filter(|dids| dids.get(did).is_some())
+            .map(|dids| dids.decode(did, data))
+        {
+            return match value {
+                Ok(value) => value.to_string(),
+                Err(err) => format!("{} ({err})", format_bytes(data)),
+            };
+        }
+        format!("{} |{}|", format_bytes(data), format_ascii(data))
+    }
+}
+
+/// Key typed in by the user, for algorithms only available in other tools
+struct KeyPrompt;
+
+impl SeedKeyAlgorithm for KeyPrompt {
+    fn compute_key(&self, level: u8, seed: &[u8]) -> Result<Vec<u8>, Error> {
+        let key_error = |reason: String| Error::KeyComputation { reason };
+        print!("seed of level 0x{level:02X}: {}\nkey> ", format_bytes(seed));
+        io::stdout()
+            .flush()
+            .map_err(|err| key_error(err.to_string()))?;
+        let mut key = String::new();
+        io::stdin()
+            .read_line(&mut key)
+            .map_err(|err| key_error(err.to_string()))?;
+        parse_data(&key.split_whitespace().collect::<String>()).map_err(key_error)
+    }
+}
+
+/// Prompt showing the active session
+fn prompt<T: IsoTpTransport>(client: &UdsClient<T>) -> String {
+    let session = match client.timing().session {
+        DiagnosticSession::Default => "default".to_owned(),
+        DiagnosticSession::Programming => "programming".to_owned(),
+        DiagnosticSession::Extended => "extended".to_owned(),
+        DiagnosticSession::SafetySystem => "safety".to_owned(),
+        DiagnosticSession::Other(session) => format!("{session:02X}"),
+    };
+    format!("uds {session}> ")
+}
+
+/// Error with the description of the NRC, IO errors without their debug output
+fn describe(err: Error) -> String {
+    match err {
+        Error::Io { source } => source.to_string(),
+        err => err.to_string(),
+    }
+}
+
+fn print_dtcs(list: &DtcList) {
+    println!(
+        "{} DTCs, availability mask {}",
+        list.records.len(),
+        list.availability_mask
+    );
+    for record in &list.records {
+        println!("  {} {}", record.dtc, record.status);
+    }
+}
+
+/// Positive response with the name of the service
+fn format_response(response: &[u8]) -> String {
+    let service = response
+        .first()
+        .filter(|sid| **sid != NEGATIVE_RESPONSE)
+        .and_then(|sid| service_name(sid.wrapping_sub(0x40)))
+        .unwrap_or("positive response");
+    format!(
+        "{service}: {} |{}|",
+        format_bytes(response),
+        format_ascii(response)
+    )
+}
+
+fn parse_session(text: &str) -> Result<DiagnosticSession, String> {
+    Ok(match text {
+        "default" => DiagnosticSession::Default,
+        "programming" => DiagnosticSession::Programming,
+        "extended" => DiagnosticSession::Extended,
+        "safety" => DiagnosticSession::SafetySystem,
+        other => DiagnosticSession::from(parse_hex_number::<u8>(other)?),
+    })
+}
+
+fn parse_reset_type(text: &str) -> Result<ResetType, String> {
+    Ok(match text {
+        "hard" => ResetType::Hard,
+        "keyoffon" => ResetType::KeyOffOn,
+        "soft" => ResetType::Soft,
+        other => ResetType::from(parse_hex_number::<u8>(other)?),
+    })
+}
+
+fn parse_mask(text: Option<&&str>) -> Result<DtcStatus, String> {
+    text.map_or(Ok(DtcStatus::ALL), |mask| {
+        parse_hex_number(mask).map(DtcStatus)
+    })
+}
+
+/// Hex number with or without 0x, checked against the range of the type
+fn parse_hex_number<N: TryFrom<u32>>(text: &str) -> Result<N, String> {
+    u32::from_str_radix(text.trim_start_matches("0x"), 16)
+        .ok()
+        .and_then(|number| N::try_from(number).ok())
+        .ok_or_else(|| format!("invalid number {text:?}"))
+}
+
+/// Hex bytes without separators
+fn parse_data(text: &str) -> Result<Vec<u8>, String> {
+    decode_hex(text)
+        .ok()
+        .filter(|data| !data.is_empty())
+        .ok_or_else(|| format!("invalid data {text:?}"))
+}
@@ -0,0 +1,525 @@
+//! Asynchronous counterparts of isotpsend, isotprecv and isotpdump of the can-utils
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::fmt;
+use std::io;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio_socketcan_isotp::frame::CANFD_DATA_LENGTHS;
+use tokio_socketcan_isotp::sniffer::{IsoTpSniffer, SniffedPdu};
+use tokio_socketcan_isotp::text::{
+    encode_hex, format_ascii, format_bytes, format_id, parse_byte, parse_hex, parse_id,
+};
+use tokio_socketcan_isotp::{
+    FlowControlOptions, Id, IsoTpBehaviour, IsoTpOptions, IsoTpSocket, LinkLayerOptions,
+    StMinOptions, TxFlags,
+};
+
+const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  other error
+  2  invalid command line
+  3  CAN device missing or down
+  4  timeout, no PDU or no flow control or consecutive frame in time (N_Bs, N_Cr)
+  5  protocol error, wrong sequence number, invalid flow control or frame
+  6  overflow, flow control overflow or PDU too long
+  7  invalid input data";
+
+/// Send, receive and observe ISO-TP PDUs on SocketCAN
+#[derive(Parser)]
+#[command(version, after_help = EXIT_CODES)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Send PDUs, the one given or one per line of stdin
+    Send {
+        #[command(flatten)]
+        socket: SocketArgs,
+        #[arg(short = 'f', long, value_enum, default_value_t)]
+        input: Format,
+        /// PDU, read from stdin if not given
+        data: Option<String>,
+    },
+    /// Print the PDUs received
+    Recv {
+        #[command(flatten)]
+        socket: SocketArgs,
+        #[arg(short = 'o', long, value_enum, default_value_t)]
+        output: Format,
+        /// Exit after this many PDUs
+        #[arg(short = 'n', long)]
+        count: Option<u64>,
+        /// Fail if no PDU is received for this many milliseconds
+        #[arg(short = 't', long)]
+        timeout: Option<u64>,
+    },
+    /// Print the PDUs of both directions without sending flow control
+    Dump {
+        #[command(flatten)]
+        socket: SocketArgs,
+        #[arg(short = 'o', long, value_enum, default_value_t)]
+        output: Format,
+        /// Exit after this many PDUs
+        #[arg(short = 'n', long)]
+        count: Option<u64>,
+        /// Fail if no PDU is received for this many milliseconds
+        #[arg(short = 't', long)]
+        timeout: Option<u64>,
+    },
+    /// Send a request and print the one response
+    Req {
+        #[command(flatten)]
+        socket: SocketArgs,
+        #[arg(short = 'f', long, value_enum, default_value_t)]
+        input: Format,
+        #[arg(short = 'o', long, value_enum, default_value_t)]
+        output: Format,
+        /// Milliseconds to wait for the response
+        #[arg(short = 't', long, default_value_t = 1000)]
+        timeout: u64,
+        /// Request, read from stdin if not given
+        data: Option<String>,
+    },
+}
+
+/// Socket options shared by all subcommands
+#[derive(Args)]
+struct SocketArgs {
+    /// CAN device such as vcan0
+    interface: String,
+    /// CAN id the PDUs are sent on, 29 bit if longer than 3 hex digits
+    #[arg(short = 's', long, value_parser = parse_id)]
+    tx_id: Id,
+    /// CAN id the PDUs are received on, 29 bit if longer than 3 hex digits
+    #[arg(short = 'd', long, value_parser = parse_id)]
+    rx_id: Id,
+    /// Extended addressing, address in front of the frames sent
+    #[arg(short = 'x', long, value_parser = parse_byte)]
+    ext_address: Option<u8>,
+    /// Address in front of the frames received, the one sent if not given
+    #[arg(long, value_parser = parse_byte, requires = "ext_address")]
+    rx_ext_address: Option<u8>,
+    /// Pad the frames sent with this byte
+    #[arg(short = 'p', long, value_parser = parse_byte)]
+    padding: Option<u8>,
+    /// Padding byte expected in the frames received
+    #[arg(short = 'q', long, value_parser = parse_byte)]
+    rx_padding: Option<u8>,
+    /// Reject received frames which are not padded to full length
+    #[arg(long, requires = "rx_padding")]
+    check_padding_len: bool,
+    /// Reject received frames with other padding bytes than --rx-padding
+    #[arg(long, requires = "rx_padding")]
+    check_padding_data: bool,
+    /// Block size of the flow control sent, 0 for no limit
+    #[arg(short = 'b', long, default_value_t = 0)]
+    block_size: u8,
+    /// STmin of the flow control sent in hex, 00-7F ms or F1-F9 100-900 us
+    #[arg(short = 'm', long, value_parser = parse_byte, default_value = "00")]
+    st_min: u8,
+    /// Wait frames accepted, 0 to not expect any
+    #[arg(short = 'w', long, default_value_t = 0)]
+    wft_max: u8,
+    /// Frame transmission time (N_As/N_Ar) in nanoseconds
+    #[arg(long, default_value_t = 0)]
+    frame_txtime: u32,
+    /// Nanoseconds between the consecutive frames sent, ignoring the STmin of the receiver
+    #[arg(long, value_name = "NS")]
+    force_tx_stmin: Option<u32>,
+    /// Ignore consecutive frames received less than this many nanoseconds apart
+    #[arg(long, value_name = "NS")]
+    force_rx_stmin: Option<u32>,
+    /// Receive without sending flow control
+    #[arg(short = 'l', long)]
+    listen_mode: bool,
+    /// Half duplex error state handling
+    #[arg(long)]
+    half_duplex: bool,
+    /// Send single frames to many receivers, nothing is received
+    #[arg(long)]
+    sf_broadcast: bool,
+    /// Send CAN FD frames
+    #[arg(long)]
+    fd: bool,
+    /// Payload length of CAN FD frames sent: 8, 12, 16, 20, 24, 32, 48 or 64
+    #[arg(long, default_value_t = 64, requires = "fd", value_parser = parse_tx_dl)]
+    tx_dl: u8,
+    /// Bit rate switch of CAN FD frames sent
+    #[arg(long, requires = "fd")]
+    brs: bool,
+}
+
+/// Representation of PDUs on stdin and stdout
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum Format {
+    /// Hex bytes, separated by spaces or not
+    #[default]
+    Hex,
+    /// Text, unprintable bytes are output as '.'
+    Ascii,
+    /// One object per line with the data in hex, `{"data":"22F190"}` as input
+    Json,
+}
+
+impl SocketArgs {
+    fn isotp_options(&self) -> IsoTpOptions {
+        let mut options = IsoTpOptions::default();
+        let mut flags = IsoTpBehaviour::empty();
+        if let Some(address) = self.ext_address {
+            flags |= IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR;
+            options.set_ext_address(address);
+        }
+        if let Some(address) = self.rx_ext_address {
+            flags |= IsoTpBehaviour::CAN_ISOTP_RX_EXT_ADDR;
+            options.set_rx_ext_address(address);
+        }
+        if let Some(padding) = self.padding {
+            flags |= IsoTpBehaviour::CAN_ISOTP_TX_PADDING;
+            options.set_txpad_content(padding);
+        }
+        if let Some(padding) = self.rx_padding {
+            flags |= IsoTpBehaviour::CAN_ISOTP_RX_PADDING;
+            options.set_rxpad_content(padding);
+        }
+        if self.check_padding_len {
+            flags |= IsoTpBehaviour::CAN_ISOTP_CHK_PAD_LEN;
+        }
+        if self.check_padding_data {
+            flags |= IsoTpBehaviour::CAN_ISOTP_CHK_PAD_DATA;
+        }
+        if self.listen_mode {
+            flags |= IsoTpBehaviour::CAN_ISOTP_LISTEN_MODE;
+        }
+        if self.half_duplex {
+            flags |= IsoTpBehaviour::CAN_ISOTP_HALF_DUPLEX;
+        }
+        if self.sf_broadcast {
+            flags |= IsoTpBehaviour::CAN_ISOTP_SF_BROADCAST;
+        }
+        if self.force_tx_stmin.is_some() {
+            flags |= IsoTpBehaviour::CAN_ISOTP_FORCE_TXSTMIN;
+        }
+        if self.force_rx_stmin.is_some() {
+            flags |= IsoTpBehaviour::CAN_ISOTP_FORCE_RXSTMIN;
+        }
+        options.set_flags(flags);
+        options
+            .set_frame_txtime(Duration::from_nanos(self.frame_txtime.into()))
+            .expect("nanoseconds of a u32");
+        options
+    }
+
+    /// CAN FD link layer if `--fd` is given, kernel defaults otherwise
+    fn link_layer_options(&self) -> Option<LinkLayerOptions> {
+        if !self.fd {
+            return None;
+        }
+        let flags = if self.brs {
+            TxFlags::CANFD_BRS
+        } else {
+            TxFlags::empty()
+        };
+        Some(LinkLayerOptions::can_fd(self.tx_dl, flags))
+    }
+
+    /// Separation times of `--force-tx-stmin` and `--force-rx-stmin`
+    fn stmin_options(&self) -> StMinOptions {
+        StMinOptions {
+            tx_stmin: self
+                .force_tx_stmin
+                .map(|nanos| Duration::from_nanos(nanos.into())),
+            rx_stmin: self
+                .force_rx_stmin
+                .map(|nanos| Duration::from_nanos(nanos.into())),
+        }
+    }
+
+    fn open(&self) -> Result<IsoTpSocket, CliError> {
+        IsoTpSocket::open_with_stmin(
+            &self.interface,
+            self.rx_id,
+            self.tx_id,
+            Some(self.isotp_options()),
+            Some(FlowControlOptions::new(
+                self.block_size,
+                self.st_min,
+                self.wft_max,
+            )),
+            self.link_layer_options(),
+            self.stmin_options(),
+        )
+        .map_err(|err| CliError::open(&self.interface, err))
+    }
+
+    /// Sniffer of the conversation, the node sending on the tx id is the one described
+    fn sniffer(&self) -> Result<IsoTpSniffer, CliError> {
+        IsoTpSniffer::open_with_opts(
+            &self.interface,
+            self.tx_id,
+            self.rx_id,
+            Some(self.isotp_options()),
+            self.link_layer_options(),
+        )
+        .map_err(|err| CliError::open(&self.interface, err))
+    }
+}
+
+/// Error with the class its exit code reports
+#[derive(Debug)]
+struct CliError {
+    code: u8,
+    message: String,
+}
+
+impl CliError {
+    const FAILURE: u8 = 1;
+    const INTERFACE: u8 = 3;
+    const TIMEOUT: u8 = 4;
+    const PROTOCOL: u8 = 5;
+    const OVERFLOW: u8 = 6;
+    const INPUT: u8 = 7;
+
+    fn new(code: u8, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn open(interface: &str, err: tokio_socketcan_isotp::Error) -> Self {
+        match err {
+            tokio_socketcan_isotp::Error::Lookup { source } => {
+                Self::new(Self::INTERFACE, format!("{interface}: {source}"))
+            }
+            tokio_socketcan_isotp::Error::Io { source } => {
+                let err = Self::from(source);
+                Self::new(err.code, format!("{interface}: {}", err.message))
+            }
+        }
+    }
+
+    fn timeout() -> Self {
+        Self::new(Self::TIMEOUT, "timeout")
+    }
+}
+
+impl From<io::Error> for CliError {
+    /// Classify by the errno the ISO-TP socket reports
+    fn from(err: io::Error) -> Self {
+        let code = match err.raw_os_error() {
+            Some(libc::ENODEV | libc::ENXIO | libc::ENETDOWN | libc::EADDRNOTAVAIL) => {
+                Self::INTERFACE
+            }
+            // ECOMM is reported when the flow control (N_Bs) or a consecutive frame (N_Cr)
+            // is missing
+            Some(libc::ETIMEDOUT | libc::ECOMM) => Self::TIMEOUT,
+            Some(libc::EILSEQ | libc::EBADMSG | libc::EPROTO) => Self::PROTOCOL,
+            Some(libc::EMSGSIZE | libc::EOVERFLOW) => Self::OVERFLOW,
+            _ if err.kind() == io::ErrorKind::InvalidData => Self::INPUT,
+            _ => Self::FAILURE,
+        };
+        Self::new(code, err.to_string())
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = tokio::select! {
+        result = run(cli.command) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("tokio-isotp: {err}");
+            ExitCode::from(err.code)
+        }
+    }
+}
+
+async fn run(command: Command) -> Result<(), CliError> {
+    match command {
+        Command::Send {
+            socket,
+            input,
+            data,
+        } => {
+            let pdus = read_input(input, data).await?;
+            let socket = socket.open()?;
+            for pdu in pdus {
+                socket.write_packet(&pdu).await?;
+            }
+            Ok(())
+        }
+        Command::Recv {
+            socket,
+            output,
+            count,
+            timeout,
+        } => {
+            let socket = socket.open()?;
+            let mut received = 0;
+            while count.is_none_or(|count| received < count) {
+                let data = with_timeout(timeout, socket.read_packet()).await??;
+                println!(
+                    "{}",
+                    format_pdu(output, &received_pdu(&socket, data), false)
+                );
+                received += 1;
+            }
+            Ok(())
+        }
+        Command::Dump {
+            socket,
+            output,
+            count,
+            timeout,
+        } => {
+            let sniffer = socket.sniffer()?;
+            let mut received = 0;
+            while count.is_none_or(|count| received < count) {
+                let pdu = with_timeout(timeout, sniffer.recv()).await??;
+                println!("{}", format_pdu(output, &pdu, true));
+                received += 1;
+            }
+            Ok(())
+        }
+        Command::Req {
+            socket,
+            input,
+            output,
+            timeout,
+            data,
+        } => {
+            let mut pdus = read_input(input, data).await?;
+            if pdus.len() != 1 {
+                return Err(CliError::new(CliError::INPUT, "expected one request"));
+            }
+            let request = pdus.remove(0);
+            let socket = socket.open()?;
+            socket.write_packet(&request).await?;
+            let response = with_timeout(Some(timeout), socket.read_packet()).await??;
+            println!(
+                "{}",
+                format_pdu(output, &received_pdu(&socket, response), false)
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Option<u64>,
+    future: impl std::future::Future<Output = T>,
+) -> Result<T, CliError> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(Duration::from_millis(timeout), future)
+            .await
+            .map_err(|_| CliError::timeout()),
+        None => Ok(future.await),
+    }
+}
+
+/// The PDU given on the command line or one PDU per non-empty line of stdin
+async fn read_input(format: Format, data: Option<String>) -> Result<Vec<Vec<u8>>, CliError> {
+    if let Some(data) = data {
+        return Ok(vec![parse_pdu(format, &data)?]);
+    }
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut pdus = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        if !line.trim().is_empty() {
+            pdus.push(parse_pdu(format, &line)?);
+        }
+    }
+    Ok(pdus)
+}
+
+fn parse_pdu(format: Format, text: &str) -> Result<Vec<u8>, CliError> {
+    let pdu = match format {
+        Format::Hex => parse_hex(text).ok(),
+        Format::Ascii => Some(text.as_bytes().to_vec()),
+        Format::Json => match serde_json::from_str::<serde_json::Value>(text) {
+            Ok(serde_json::Value::Object(object)) => match object.get("data") {
+                Some(serde_json::Value::String(data)) => parse_hex(data).ok(),
+                _ => None,
+            },
+            _ => None,
+        },
+    };
+    match pdu {
+        Some(pdu) if !pdu.is_empty() => Ok(pdu),
+        _ => Err(CliError::new(
+            CliError::INPUT,
+            format!("invalid PDU {text:?}"),
+        )),
+    }
+}
+
+/// PDU just received on a socket
+fn received_pdu(socket: &IsoTpSocket, data: Vec<u8>) -> SniffedPdu {
+    SniffedPdu {
+        timestamp: SystemTime::now(),
+        tx_id: socket.rx_id(),
+        rx_id: socket.tx_id(),
+        data,
+    }
+}
+
+/// The data only, or with time and ids as isotpdump prints them
+fn format_pdu(format: Format, pdu: &SniffedPdu, with_ids: bool) -> String {
+    let text = match format {
+        Format::Hex => format_bytes(&pdu.data),
+        Format::Ascii => format_ascii(&pdu.data),
+        Format::Json => {
+            let since_epoch = pdu.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+            return serde_json::json!({
+                "timestamp": since_epoch.as_secs_f64(),
+                "tx_id": format_id(pdu.tx_id),
+                "rx_id": format_id(pdu.rx_id),
+                "data": encode_hex(&pdu.data),
+            })
+            .to_string();
+        }
+    };
+    if with_ids {
+        format!(
+            "{} {} -> {} [{}] {text}",
+            format_time(pdu.timestamp),
+            format_id(pdu.tx_id),
+            format_id(pdu.rx_id),
+            pdu.data.len()
+        )
+    } else {
+        text
+    }
+}
+
+/// Seconds since the epoch the way candump prints them
+fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "({}.{:06})",
+        since_epoch.as_secs(),
+        since_epoch.subsec_micros()
+    )
+}
+
+fn parse_tx_dl(text: &str) -> Result<u8, String> {
+    text.parse()
+        .ok()
+        .filter(|length| CANFD_DATA_LENGTHS.contains(&usize::from(*length)))
+        .ok_or_else(|| format!("invalid CAN FD data length {text:?}"))
+}
@@ -0,0 +1,285 @@
+//! Import and export of `candump -l` log files
+//!
+//! Each line holds one frame, e.g.
+//!
+//! ```text
+//! (1436509052.249713) vcan0 7E0#0322F190
+//! (1436509052.250127) vcan0 18DAF110#100A62F190313233
+//! (1436509052.251001) can1 7E8##1023E00...
+//! ```
+//!
+//! Extended ids are written with 8 hex digits, CAN FD frames use `##` followed by the
+//! flags nibble. The frames can be fed into a [Reassembler](crate::frame::Reassembler)
+//! to extract the PDUs of a log without a CAN interface, and the PDUs into
+//! [exchanges](crate::frame::exchanges) to pair UDS requests with their responses.
+
+use crate::frame::{CanFrame, CANFD_MAX_DLEN};
+use crate::text::{decode_hex, encode_hex, format_id};
+use crate::{Id, CAN_MAX_DLEN, EFF_MASK, SFF_MASK};
+use crate::{ExtendedId, StandardId};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Frame of a candump log together with the interface it was logged on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandumpEntry {
+    pub interface: String,
+    pub frame: CanFrame,
+}
+
+/// Read all frames of a candump log
+///
+/// Empty lines and remote or error frames are skipped.
+pub fn read_candump(reader: impl Read) -> io::Result<Vec<CandumpEntry>> {
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(entry) = parse_line(&line)
+            .map_err(|message| invalid_data(format!("line {}: {message}", number + 1)))?
+        {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Read all frames of a candump log file
+pub fn load_candump(path: impl AsRef<Path>) -> io::Result<Vec<CandumpEntry>> {
+    read_candump(File::open(path)?)
+}
+
+fn parse_line(line: &str) -> Result<Option<CandumpEntry>, String> {
+    let mut tokens = line.split_whitespace();
+    let timestamp = tokens
+        .next()
+        .and_then(|token| token.strip_prefix('('))
+        .and_then(|token| token.strip_suffix(')'))
+        .ok_or("missing timestamp")?;
+    let interface = tokens.next().ok_or("missing interface")?;
+    let frame = tokens.next().ok_or("missing frame")?;
+    // newer candump versions append the direction, it is not needed here
+
+    let timestamp = parse_timestamp(timestamp)?;
+    let (id, rest) = frame.split_once('#').ok_or("missing '#'")?;
+    let raw_id = u32::from_str_radix(id, 16).map_err(|_| format!("invalid id {id:?}"))?;
+    let id = if id.len() > 3 {
+        if raw_id & !EFF_MASK != 0 {
+            // error frame
+            return Ok(None);
+        }
+        Id::Extended(ExtendedId::new(raw_id).ok_or("invalid extended id")?)
+    } else {
+        if raw_id > SFF_MASK {
+            return Err(format!("invalid id {id:?}"));
+        }
+        Id::Standard(StandardId::new(raw_id as u16).ok_or("invalid standard id")?)
+    };
+
+    let (fd, data) = match rest.strip_prefix('#') {
+        // CAN FD, the first character holds the flags
+        Some(rest) => (true, rest.get(1..).ok_or("missing CAN FD flags")?),
+        None if rest.starts_with('R') => return Ok(None),
+        None => (false, rest),
+    };
+    let data = decode_hex(data).map_err(|_| format!("invalid data {data:?}"))?;
+    let max_len = if fd {
+        CANFD_MAX_DLEN
+    } else {
+        CAN_MAX_DLEN as usize
+    };
+    if data.len() > max_len {
+        return Err(format!("frame of {} bytes", data.len()));
+    }
+
+    Ok(Some(CandumpEntry {
+        interface: interface.to_string(),
+        frame: CanFrame {
+            timestamp,
+            id,
+            data,
+            fd,
+        },
+    }))
+}
+
+fn parse_timestamp(text: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("invalid timestamp {text:?}");
+    let (seconds, fraction) = text.split_once('.').unwrap_or((text, "0"));
+    let seconds: u64 = seconds.parse().map_err(|_| invalid())?;
+    if fraction.is_empty() || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    // digits beyond nanoseconds are dropped
+    let digits = fraction.len().min(9);
+    let fraction: u64 = fraction[..digits].parse().map_err(|_| invalid())?;
+    let nanos = fraction * 10u64.pow(9 - digits as u32);
+    Duration::from_secs(seconds)
+        .checked_add(Duration::from_nanos(nanos))
+        .and_then(|since_epoch| UNIX_EPOCH.checked_add(since_epoch))
+        .ok_or_else(invalid)
+}
+
+/// Writes frames in the `candump -l` format
+pub struct CandumpWriter<W: Write> {
+    writer: W,
+}
+
+impl CandumpWriter<BufWriter<File>> {
+    /// Create (or truncate) a log file
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> CandumpWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Append one frame logged on `interface`
+    pub fn write_frame(&mut self, interface: &str, frame: &CanFrame) -> io::Result<()> {
+        let since_epoch = frame
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let separator = if frame.fd { "##0" } else { "#" };
+        writeln!(
+            self.writer,
+            "({}.{:06}) {interface} {}{separator}{}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_micros(),
+            format_id(frame.id),
+            encode_hex(&frame.data)
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Reassembler;
+
+    fn timestamp(micros: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(micros)
+    }
+
+    #[test]
+    fn reads_log() {
+        let log = "\
+(1436509052.249713) vcan0 7E0#0322F190
+(1436509052.250127) vcan0 18DAF110#100A62F190313233
+
+(1436509052.250500) vcan0 7E0#R
+(1436509052.250600) vcan0 20000080#0000000000000000
+(1436509052.251001) can1 7E8##1021062F19031323334353637383940 R
+";
+        let entries = read_candump(log.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].interface, "vcan0");
+        assert_eq!(
+            entries[0].frame,
+            CanFrame::new(
+                timestamp(1436509052249713),
+                StandardId::new(0x7E0).unwrap(),
+                vec![0x03, 0x22, 0xF1, 0x90],
+            )
+        );
+        assert_eq!(
+            entries[1].frame.id,
+            Id::Extended(ExtendedId::new(0x18DAF110).unwrap())
+        );
+        assert_eq!(entries[1].frame.data.len(), 8);
+        assert!(!entries[1].frame.fd);
+        assert_eq!(entries[2].interface, "can1");
+        assert!(entries[2].frame.fd);
+        assert_eq!(entries[2].frame.data[..4], [0x02, 0x10, 0x62, 0xF1]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let frames = vec![
+            CanFrame::new(
+                timestamp(1_000_000),
+                StandardId::new(0x7E0).unwrap(),
+                vec![0x02, 0x10, 0x03],
+            ),
+            CanFrame::new(
+                timestamp(1_000_123),
+                ExtendedId::new(0x18DAF110).unwrap(),
+                vec![],
+            ),
+            CanFrame::new(
+                timestamp(2_500_001),
+                StandardId::new(0x7E8).unwrap(),
+                (0..64).collect(),
+            ),
+        ];
+        let mut writer = CandumpWriter::new(Vec::new());
+        for frame in &frames {
+            writer.write_frame("can0", frame).unwrap();
+        }
+        let log = writer.into_inner();
+        let entries = read_candump(log.as_slice()).unwrap();
+        assert!(entries.iter().all(|entry| entry.interface == "can0"));
+        let read: Vec<CanFrame> = entries.into_iter().map(|entry| entry.frame).collect();
+        assert_eq!(read, frames);
+    }
+
+    #[test]
+    fn invalid_lines() {
+        for line in [
+            "1436509052.249713 vcan0 7E0#00",
+            "(1436509052.249713) vcan0",
+            "(1436509052.249713) vcan0 7E0",
+            "(1436509052.249713) vcan0 800#00",
+            "(1436509052.249713) vcan0 7E0#0",
+            "(1436509052.249713) vcan0 7E0#000000000000000000",
+            "(1436509052.249713) vcan0 7E0##",
+            "(-1.000000) vcan0 7E0#00",
+            "(1.) vcan0 7E0#00",
+            "(1.+5) vcan0 7E0#00",
+            "(1.\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}) vcan0 7E0#00",
+            "(18446744073709551615.999999999) vcan0 7E0#00",
+        ] {
+            let error = read_candump(line.as_bytes()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{line}");
+        }
+    }
+
+    #[test]
+    fn reassembles_pdus() {
+        let log = "\
+(1.000000) vcan0 7E0#0322F190
+(1.001000) vcan0 7E8#100A62F190313233
+(1.002000) vcan0 7E0#3000000000000000
+(1.003000) vcan0 7E8#2134353637
+";
+        let frames: Vec<CanFrame> = read_candump(log.as_bytes())
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.frame)
+            .collect();
+        let pdus = Reassembler::new().reassemble(&frames);
+        assert_eq!(pdus.len(), 2);
+        assert_eq!(pdus[0].data, [0x22, 0xF1, 0x90]);
+        assert_eq!(pdus[1].data, b"\x62\xF1\x90\x31\x32\x33\x34\x35\x36\x37");
+        assert_eq!(pdus[1].timestamp, timestamp(1_003_000));
+    }
+}
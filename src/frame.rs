@@ -0,0 +1,451 @@
+//! Raw CAN frames and ISO 15765-2 segmentation in userspace
+//!
+//! The kernel does the segmentation for [IsoTpSocket](crate::IsoTpSocket), this module
+//! is used where frames have to be produced or consumed without it: synthesizing the
+//! frames of a recorded PDU for capture files and reassembling PDUs and UDS exchanges
+//! from logs.
+
+use crate::Id;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+/// Valid CAN FD data lengths
+pub const CANFD_DATA_LENGTHS: [usize; 8] = [8, 12, 16, 20, 24, 32, 48, 64];
+
+/// `CANFD_MAX_DLEN` According to ISO 11898-7
+pub const CANFD_MAX_DLEN: usize = 64;
+
+/// Largest PDU length encodable in a First Frame without the 32 bit escape
+const FF_DL_12BIT_MAX: usize = 0xFFF;
+
+/// Single CAN or CAN FD frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanFrame {
+    pub timestamp: SystemTime,
+    pub id: Id,
+    pub data: Vec<u8>,
+    /// Frame is a CAN FD frame
+    pub fd: bool,
+}
+
+impl CanFrame {
+    pub fn new(timestamp: SystemTime, id: impl Into<Id>, data: Vec<u8>) -> Self {
+        let fd = data.len() > crate::CAN_MAX_DLEN as usize;
+        Self {
+            timestamp,
+            id: id.into(),
+            data,
+            fd,
+        }
+    }
+}
+
+/// Protocol control information type of an ISO-TP frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    Single,
+    First,
+    Consecutive,
+    FlowControl,
+}
+
+impl FrameType {
+    /// Type of a frame given its first PCI byte
+    pub fn from_pci(pci: u8) -> Option<Self> {
+        match pci >> 4 {
+            0 => Some(FrameType::Single),
+            1 => Some(FrameType::First),
+            2 => Some(FrameType::Consecutive),
+            3 => Some(FrameType::FlowControl),
+            _ => None,
+        }
+    }
+}
+
+/// Parameters used to split a PDU into frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentOptions {
+    /// Address byte prepended to every frame (extended addressing)
+    pub ext_address: Option<u8>,
+    /// Link layer data length, 8 for CAN 2.0 or one of [CANFD_DATA_LENGTHS]
+    pub tx_dl: usize,
+    /// Pad frames to their full length with this byte
+    pub padding: Option<u8>,
+}
+
+impl Default for SegmentOptions {
+    fn default() -> Self {
+        Self {
+            ext_address: None,
+            tx_dl: crate::CAN_MAX_DLEN as usize,
+            padding: None,
+        }
+    }
+}
+
+impl SegmentOptions {
+    fn pci_offset(&self) -> usize {
+        usize::from(self.ext_address.is_some())
+    }
+
+    fn finish(&self, mut frame: Vec<u8>) -> Vec<u8> {
+        let classic = crate::CAN_MAX_DLEN as usize;
+        let target = if self.padding.is_some() {
+            if self.tx_dl > classic {
+                fd_length(frame.len())
+            } else {
+                classic
+            }
+        } else if frame.len() > classic {
+            // CAN FD frames can only have the defined lengths
+            fd_length(frame.len())
+        } else {
+            frame.len()
+        };
+        frame.resize(target, self.padding.unwrap_or(0xCC));
+        frame
+    }
+
+    fn start(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(self.tx_dl);
+        if let Some(ext_address) = self.ext_address {
+            frame.push(ext_address);
+        }
+        frame
+    }
+}
+
+/// Smallest valid CAN FD length holding `len` bytes
+fn fd_length(len: usize) -> usize {
+    CANFD_DATA_LENGTHS
+        .iter()
+        .copied()
+        .find(|&dl| dl >= len)
+        .unwrap_or(CANFD_MAX_DLEN)
+}
+
+/// Split a PDU into the data of the frames sent by the transmitter
+///
+/// Flow control frames of the receiver are not part of the result, see [flow_control].
+pub fn segment(pdu: &[u8], options: &SegmentOptions) -> Vec<Vec<u8>> {
+    let offset = options.pci_offset();
+    let classic = crate::CAN_MAX_DLEN as usize;
+    let mut frames = Vec::new();
+
+    // Single frame, with the escape sequence for CAN FD lengths
+    if pdu.len() <= classic - 1 - offset {
+        let mut frame = options.start();
+        frame.push(pdu.len() as u8);
+        frame.extend_from_slice(pdu);
+        frames.push(options.finish(frame));
+        return frames;
+    }
+    if options.tx_dl > classic && pdu.len() <= options.tx_dl - 2 - offset {
+        let mut frame = options.start();
+        frame.extend_from_slice(&[0x00, pdu.len() as u8]);
+        frame.extend_from_slice(pdu);
+        frames.push(options.finish(frame));
+        return frames;
+    }
+
+    let mut frame = options.start();
+    if pdu.len() <= FF_DL_12BIT_MAX {
+        frame.extend_from_slice(&[0x10 | (pdu.len() >> 8) as u8, pdu.len() as u8]);
+    } else {
+        frame.extend_from_slice(&[0x10, 0x00]);
+        frame.extend_from_slice(&(pdu.len() as u32).to_be_bytes());
+    }
+    let first_len = options.tx_dl - frame.len();
+    frame.extend_from_slice(&pdu[..first_len]);
+    frames.push(frame);
+
+    for (index, chunk) in pdu[first_len..]
+        .chunks(options.tx_dl - 1 - offset)
+        .enumerate()
+    {
+        let mut frame = options.start();
+        frame.push(0x20 | ((index + 1) % 16) as u8);
+        frame.extend_from_slice(chunk);
+        frames.push(options.finish(frame));
+    }
+    frames
+}
+
+/// Data of a Flow Control frame with status "continue to send"
+pub fn flow_control(block_size: u8, st_min: u8, options: &SegmentOptions) -> Vec<u8> {
+    let mut frame = options.start();
+    frame.extend_from_slice(&[0x30, block_size, st_min]);
+    options.finish(frame)
+}
+
+/// PDU reassembled from frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pdu {
+    /// Timestamp of the frame completing the PDU
+    pub timestamp: SystemTime,
+    pub id: Id,
+    /// Address byte of extended addressing
+    pub ext_address: Option<u8>,
+    pub data: Vec<u8>,
+}
+
+struct Transfer {
+    data: Vec<u8>,
+    expected_len: usize,
+    next_sn: u8,
+}
+
+/// Reassembles PDUs from the frames of any number of CAN ids
+///
+/// Frames of a broken transfer (wrong sequence number, missing First Frame) are
+/// dropped, just like the kernel does.
+#[derive(Default)]
+pub struct Reassembler {
+    extended_addressing: bool,
+    transfers: HashMap<(Id, Option<u8>), Transfer>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Treat the first data byte of every frame as extended address
+    pub fn with_extended_addressing(mut self, extended_addressing: bool) -> Self {
+        self.extended_addressing = extended_addressing;
+        self
+    }
+
+    /// Feed a sequence of frames, e.g. read from a log, and collect the completed PDUs
+    pub fn reassemble<'a>(&mut self, frames: impl IntoIterator<Item = &'a CanFrame>) -> Vec<Pdu> {
+        frames
+            .into_iter()
+            .filter_map(|frame| self.push(frame))
+            .collect()
+    }
+
+    /// Feed one frame, returns the PDU it completes if any
+    pub fn push(&mut self, frame: &CanFrame) -> Option<Pdu> {
+        let (ext_address, payload) = if self.extended_addressing {
+            let (&ext_address, payload) = frame.data.split_first()?;
+            (Some(ext_address), payload)
+        } else {
+            (None, frame.data.as_slice())
+        };
+        let key = (frame.id, ext_address);
+        let pci = *payload.first()?;
+
+        let complete = |data: Vec<u8>| Pdu {
+            timestamp: frame.timestamp,
+            id: frame.id,
+            ext_address,
+            data,
+        };
+
+        match FrameType::from_pci(pci)? {
+            FrameType::Single => {
+                self.transfers.remove(&key);
+                let (len, start) = match pci & 0x0F {
+                    0 => (usize::from(*payload.get(1)?), 2),
+                    len => (usize::from(len), 1),
+                };
+                let data = payload.get(start..start + len)?;
+                Some(complete(data.to_vec()))
+            }
+            FrameType::First => {
+                let short_len = (usize::from(pci & 0x0F) << 8) | usize::from(*payload.get(1)?);
+                let (expected_len, start) = if short_len == 0 {
+                    let len = payload.get(2..6)?;
+                    (u32::from_be_bytes(len.try_into().unwrap()) as usize, 6)
+                } else {
+                    (short_len, 2)
+                };
+                let data = payload.get(start..)?.to_vec();
+                if data.len() >= expected_len {
+                    // would have fitted into a Single Frame
+                    self.transfers.remove(&key);
+                    return None;
+                }
+                self.transfers.insert(
+                    key,
+                    Transfer {
+                        data,
+                        expected_len,
+                        next_sn: 1,
+                    },
+                );
+                None
+            }
+            FrameType::Consecutive => {
+                let transfer = self.transfers.get_mut(&key)?;
+                if pci & 0x0F != transfer.next_sn {
+                    self.transfers.remove(&key);
+                    return None;
+                }
+                transfer.next_sn = (transfer.next_sn + 1) % 16;
+                let missing = transfer.expected_len - transfer.data.len();
+                let chunk = &payload[1..];
+                transfer
+                    .data
+                    .extend_from_slice(&chunk[..chunk.len().min(missing)]);
+                if transfer.data.len() < transfer.expected_len {
+                    return None;
+                }
+                let transfer = self.transfers.remove(&key)?;
+                Some(complete(transfer.data))
+            }
+            FrameType::FlowControl => None,
+        }
+    }
+}
+
+/// UDS request and the final response to it, extracted from reassembled PDUs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    pub request: Pdu,
+    /// `None` if the ECU did not answer, e.g. because of suppressPosRspMsgIndicationBit
+    pub response: Option<Pdu>,
+}
+
+/// Pair the requests sent on `tester` with the responses sent on `ecu`
+///
+/// Response pending (NRC 0x78) messages are skipped and responses that do not belong
+/// to the service of the outstanding request are dropped. PDUs of other ids are ignored.
+pub fn exchanges(pdus: impl IntoIterator<Item = Pdu>, tester: Id, ecu: Id) -> Vec<Exchange> {
+    let mut exchanges = Vec::new();
+    let mut pending: Option<Pdu> = None;
+    for pdu in pdus {
+        if pdu.id == tester {
+            if let Some(request) = pending.replace(pdu) {
+                exchanges.push(Exchange {
+                    request,
+                    response: None,
+                });
+            }
+        } else if pdu.id == ecu {
+            let Some(sid) = pending.as_ref().and_then(|request| request.data.first()) else {
+                continue;
+            };
+            let answers = match pdu.data.as_slice() {
+                [0x7F, _, 0x78, ..] => false,
+                [0x7F, rejected, ..] => rejected == sid,
+                [response, ..] => *response == sid.wrapping_add(0x40),
+                [] => false,
+            };
+            if answers {
+                exchanges.push(Exchange {
+                    request: pending.take().unwrap(),
+                    response: Some(pdu),
+                });
+            }
+        }
+    }
+    if let Some(request) = pending {
+        exchanges.push(Exchange {
+            request,
+            response: None,
+        });
+    }
+    exchanges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StandardId;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn id(raw: u16) -> Id {
+        Id::Standard(StandardId::new(raw).unwrap())
+    }
+
+    fn pdu(raw_id: u16, data: &[u8]) -> Pdu {
+        Pdu {
+            timestamp: UNIX_EPOCH,
+            id: id(raw_id),
+            ext_address: None,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn segment_and_reassemble() {
+        let data: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        for options in [
+            SegmentOptions::default(),
+            SegmentOptions {
+                ext_address: Some(0xF1),
+                tx_dl: 64,
+                padding: Some(0xAA),
+            },
+        ] {
+            let frames: Vec<CanFrame> = segment(&data, &options)
+                .into_iter()
+                .enumerate()
+                .map(|(i, frame)| {
+                    CanFrame::new(
+                        UNIX_EPOCH + Duration::from_millis(i as u64),
+                        id(0x7E8),
+                        frame,
+                    )
+                })
+                .collect();
+            let pdus = Reassembler::new()
+                .with_extended_addressing(options.ext_address.is_some())
+                .reassemble(&frames);
+            assert_eq!(pdus.len(), 1);
+            assert_eq!(pdus[0].data, data);
+            assert_eq!(pdus[0].ext_address, options.ext_address);
+            assert_eq!(pdus[0].timestamp, frames.last().unwrap().timestamp);
+        }
+    }
+
+    #[test]
+    fn broken_transfer_is_dropped() {
+        let data = [0x62, 0xF1, 0x90, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let mut frames: Vec<CanFrame> = segment(&data, &SegmentOptions::default())
+            .into_iter()
+            .map(|frame| CanFrame::new(UNIX_EPOCH, id(0x7E8), frame))
+            .collect();
+        frames[1].data[0] = 0x22;
+        assert!(Reassembler::new().reassemble(&frames).is_empty());
+        assert!(Reassembler::new().reassemble(&frames[1..]).is_empty());
+    }
+
+    #[test]
+    fn pairs_exchanges() {
+        let pdus = vec![
+            pdu(0x7E0, &[0x22, 0xF1, 0x90]),
+            pdu(0x7E8, &[0x7F, 0x22, 0x78]),
+            pdu(0x123, &[0x01]),
+            pdu(0x7E8, &[0x62, 0xF1, 0x90, 0x31]),
+            // suppressed positive response
+            pdu(0x7E0, &[0x3E, 0x80]),
+            pdu(0x7E0, &[0x10, 0x02]),
+            // unsolicited response of another service
+            pdu(0x7E8, &[0x51, 0x01]),
+            pdu(0x7E8, &[0x7F, 0x10, 0x22]),
+            pdu(0x7E8, &[0x50, 0x03]),
+            pdu(0x7E0, &[0x11, 0x01]),
+        ];
+        let exchanges = exchanges(pdus, id(0x7E0), id(0x7E8));
+        let summary: Vec<(&[u8], Option<&[u8]>)> = exchanges
+            .iter()
+            .map(|exchange| {
+                (
+                    exchange.request.data.as_slice(),
+                    exchange.response.as_ref().map(|pdu| pdu.data.as_slice()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (&[0x22, 0xF1, 0x90][..], Some(&[0x62, 0xF1, 0x90, 0x31][..])),
+                (&[0x3E, 0x80][..], None),
+                (&[0x10, 0x02][..], Some(&[0x7F, 0x10, 0x22][..])),
+                (&[0x11, 0x01][..], None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,394 @@
+//! Functional addressing, one request answered by many ECUs
+//!
+//! An [IsoTpSocket] binds exactly one rx/tx id pair. A [FunctionalRequest] combines a
+//! transmit-only socket on the functional id (`CAN_ISOTP_SF_BROADCAST`) with one socket
+//! per response id. Each response socket sends the flow control of multi-frame
+//! responses on the physical request id of its ECU.
+//!
+//! Functional requests have to fit into a single frame, the kernel rejects longer
+//! PDUs on a broadcast socket.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use tokio_socketcan_isotp::functional::FunctionalRequest;
+//! use tokio_socketcan_isotp::{Id, StandardId};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! // ECU n responds on 0x7E8 + n and receives flow control on 0x7E0 + n
+//! let responders: Vec<(Id, Id)> = (0..8)
+//!     .map(|n| {
+//!         (
+//!             StandardId::new(0x7E8 + n).unwrap().into(),
+//!             StandardId::new(0x7E0 + n).unwrap().into(),
+//!         )
+//!     })
+//!     .collect();
+//! let functional = FunctionalRequest::open("can0", StandardId::new(0x7DF).unwrap(), &responders)?;
+//! for (responder, payload) in functional
+//!     .request(&[0x22, 0xF1, 0x90], Duration::from_millis(100))
+//!     .await?
+//! {
+//!     match payload {
+//!         Ok(payload) => println!("{responder:?}: {payload:02X?}"),
+//!         Err(err) => eprintln!("{responder:?}: {err}"),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Id, IsoTpBehaviour, IsoTpOptions, IsoTpSocket, IsoTpTransport, LinkLayerOptions};
+use futures::future::join_all;
+use std::io;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Functional request socket with the sockets of the expected responders
+pub struct FunctionalRequest<T = IsoTpSocket> {
+    sender: T,
+    responders: Vec<T>,
+}
+
+impl FunctionalRequest<IsoTpSocket> {
+    /// Open on a named CAN device, `responders` are pairs of response id and flow control id
+    pub fn open(
+        ifname: &str,
+        functional_id: impl Into<Id>,
+        responders: &[(Id, Id)],
+    ) -> Result<Self, crate::Error> {
+        Self::open_with_opts(ifname, functional_id, responders, None, None)
+    }
+
+    /// Open with options applied to all sockets, `CAN_ISOTP_SF_BROADCAST` is added for the sender
+    pub fn open_with_opts(
+        ifname: &str,
+        functional_id: impl Into<Id>,
+        responders: &[(Id, Id)],
+        isotp_options: Option<IsoTpOptions>,
+        link_layer_options: Option<LinkLayerOptions>,
+    ) -> Result<Self, crate::Error> {
+        let functional_id = functional_id.into();
+        let mut broadcast = isotp_options.unwrap_or_default();
+        broadcast.set_flags(
+            broadcast.get_flags().unwrap_or(IsoTpBehaviour::empty())
+                | IsoTpBehaviour::CAN_ISOTP_SF_BROADCAST,
+        );
+        let sender = IsoTpSocket::open_with_opts(
+            ifname,
+            functional_id,
+            functional_id,
+            Some(broadcast),
+            None,
+            link_layer_options,
+        )?;
+        let responders = responders
+            .iter()
+            .map(|(response_id, flow_control_id)| {
+                IsoTpSocket::open_with_opts(
+                    ifname,
+                    *response_id,
+                    *flow_control_id,
+                    isotp_options,
+                    None,
+                    link_layer_options,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(sender, responders))
+    }
+}
+
+impl<T: IsoTpTransport> FunctionalRequest<T> {
+    pub fn new(sender: T, responders: Vec<T>) -> Self {
+        Self { sender, responders }
+    }
+
+    pub fn sender(&self) -> &T {
+        &self.sender
+    }
+
+    pub fn responders(&self) -> &[T] {
+        &self.responders
+    }
+
+    pub fn into_parts(self) -> (T, Vec<T>) {
+        (self.sender, self.responders)
+    }
+
+    /// Functional id the requests are sent on
+    pub fn functional_id(&self) -> Id {
+        self.sender.tx_id()
+    }
+
+    /// Send a request and return every PDU received within the window, in order of arrival
+    ///
+    /// Only a failure to send the request is an error, see [FunctionalRequest::collect]
+    /// for the errors of the responders.
+    pub async fn request(
+        &self,
+        pdu: &[u8],
+        window: Duration,
+    ) -> io::Result<Vec<(Id, io::Result<Vec<u8>>)>> {
+        self.sender.send(pdu).await?;
+        Ok(self.collect(window).await)
+    }
+
+    /// Return every PDU received within the window, in order of arrival
+    ///
+    /// A receive error is returned in place of a PDU and ends the collection of that
+    /// responder, the other responders are still received until the end of the window.
+    pub async fn collect(&self, window: Duration) -> Vec<(Id, io::Result<Vec<u8>>)> {
+        let deadline = Instant::now() + window;
+        let received = join_all(
+            self.responders
+                .iter()
+                .map(|responder| receive_until(responder, deadline)),
+        )
+        .await;
+        let mut responses: Vec<_> = self
+            .responders
+            .iter()
+            .zip(received)
+            .flat_map(|(responder, pdus)| {
+                pdus.into_iter()
+                    .map(|(at, pdu)| (at, responder.rx_id(), pdu))
+            })
+            .collect();
+        responses.sort_by_key(|(at, _, _)| *at);
+        responses
+            .into_iter()
+            .map(|(_, responder, pdu)| (responder, pdu))
+            .collect()
+    }
+}
+
+async fn receive_until(
+    responder: &impl IsoTpTransport,
+    deadline: Instant,
+) -> Vec<(Instant, io::Result<Vec<u8>>)> {
+    let mut pdus = Vec::new();
+    while let Ok(pdu) = tokio::time::timeout_at(deadline, responder.recv()).await {
+        let failed = pdu.is_err();
+        pdus.push((Instant::now(), pdu));
+        if failed {
+            break;
+        }
+    }
+    pdus
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::future::Future;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    type Queue = mpsc::UnboundedSender<io::Result<Vec<u8>>>;
+    type Handler = Box<dyn FnMut(&[u8]) -> Vec<Reply> + Send>;
+
+    /// PDU or error received by a responder after a request
+    pub(crate) struct Reply {
+        responder: usize,
+        delay: Duration,
+        pdu: io::Result<Vec<u8>>,
+    }
+
+    impl Reply {
+        pub(crate) fn new(responder: usize, pdu: Vec<u8>) -> Self {
+            Self {
+                responder,
+                delay: Duration::ZERO,
+                pdu: Ok(pdu),
+            }
+        }
+
+        pub(crate) fn error(responder: usize, kind: io::ErrorKind) -> Self {
+            Self {
+                responder,
+                delay: Duration::ZERO,
+                pdu: Err(kind.into()),
+            }
+        }
+
+        pub(crate) fn after(mut self, delay: Duration) -> Self {
+            self.delay = delay;
+            self
+        }
+    }
+
+    /// Sender or responder of a simulated bus, see [fake_bus]
+    pub(crate) struct FakeTransport {
+        rx_id: Id,
+        tx_id: Id,
+        /// Replies of the ECUs to the requests of the sender, and the queues of the responders
+        handler: Option<(Mutex<Handler>, Vec<Queue>)>,
+        incoming: Queue,
+        queue: tokio::sync::Mutex<mpsc::UnboundedReceiver<io::Result<Vec<u8>>>>,
+        sent: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl FakeTransport {
+        fn new(rx_id: Id, tx_id: Id) -> Self {
+            let (incoming, queue) = mpsc::unbounded_channel();
+            Self {
+                rx_id,
+                tx_id,
+                handler: None,
+                incoming,
+                queue: tokio::sync::Mutex::new(queue),
+                sent: Mutex::new(Vec::new()),
+            }
+        }
+
+        /// All PDUs sent so far
+        pub(crate) fn sent(&self) -> Vec<Vec<u8>> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    impl IsoTpTransport for FakeTransport {
+        fn rx_id(&self) -> Id {
+            self.rx_id
+        }
+
+        fn tx_id(&self) -> Id {
+            self.tx_id
+        }
+
+        fn send<'a>(&'a self, pdu: &'a [u8]) -> impl Future<Output = io::Result<()>> + Send + 'a {
+            self.sent.lock().unwrap().push(pdu.to_vec());
+            if let Some((handler, queues)) = &self.handler {
+                for reply in (handler.lock().unwrap())(pdu) {
+                    let queue = queues[reply.responder].clone();
+                    if reply.delay.is_zero() {
+                        queue.send(reply.pdu).unwrap();
+                    } else {
+                        tokio::spawn(async move {
+                            tokio::time::sleep(reply.delay).await;
+                            queue.send(reply.pdu).ok();
+                        });
+                    }
+                }
+            }
+            async { Ok(()) }
+        }
+
+        async fn recv(&self) -> io::Result<Vec<u8>> {
+            let pdu = self.queue.lock().await.recv().await;
+            // the transport holds a sender itself, so the queue never closes
+            pdu.unwrap()
+        }
+    }
+
+    /// Functional request whose responders, pairs of response and flow control id,
+    /// receive the replies returned by the handler for each request
+    pub(crate) fn fake_bus(
+        functional_id: Id,
+        responders: &[(Id, Id)],
+        handler: impl FnMut(&[u8]) -> Vec<Reply> + Send + 'static,
+    ) -> FunctionalRequest<FakeTransport> {
+        let responders: Vec<FakeTransport> = responders
+            .iter()
+            .map(|(response_id, flow_control_id)| {
+                FakeTransport::new(*response_id, *flow_control_id)
+            })
+            .collect();
+        let queues = responders
+            .iter()
+            .map(|responder| responder.incoming.clone())
+            .collect();
+        let mut sender = FakeTransport::new(functional_id, functional_id);
+        sender.handler = Some((Mutex::new(Box::new(handler)), queues));
+        FunctionalRequest::new(sender, responders)
+    }
+
+    fn id(raw: u16) -> Id {
+        crate::StandardId::new(raw).unwrap().into()
+    }
+
+    fn bus(
+        handler: impl FnMut(&[u8]) -> Vec<Reply> + Send + 'static,
+    ) -> FunctionalRequest<FakeTransport> {
+        let responders: Vec<(Id, Id)> = (0..3).map(|n| (id(0x7E8 + n), id(0x7E0 + n))).collect();
+        fake_bus(id(0x7DF), &responders, handler)
+    }
+
+    fn payloads(responses: Vec<(Id, io::Result<Vec<u8>>)>) -> Vec<(Id, Vec<u8>)> {
+        responses
+            .into_iter()
+            .map(|(responder, pdu)| (responder, pdu.unwrap()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn responses_in_order_of_arrival() {
+        let functional = bus(|_| {
+            vec![
+                Reply::new(2, vec![0x62, 0x02]).after(Duration::from_millis(20)),
+                Reply::new(0, vec![0x62, 0x00]),
+                Reply::new(1, vec![0x62, 0x01]).after(Duration::from_millis(10)),
+                Reply::new(0, vec![0x62, 0x00, 0x00]).after(Duration::from_millis(30)),
+            ]
+        });
+        let responses = functional
+            .request(&[0x22, 0xF1, 0x90], Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert_eq!(
+            payloads(responses),
+            [
+                (id(0x7E8), vec![0x62, 0x00]),
+                (id(0x7E9), vec![0x62, 0x01]),
+                (id(0x7EA), vec![0x62, 0x02]),
+                (id(0x7E8), vec![0x62, 0x00, 0x00]),
+            ]
+        );
+        assert_eq!(functional.sender().sent(), [[0x22, 0xF1, 0x90]]);
+    }
+
+    #[tokio::test]
+    async fn late_responses_are_left_for_the_next_collection() {
+        let functional = bus(|_| {
+            vec![
+                Reply::new(0, vec![0x7E, 0x00]),
+                Reply::new(1, vec![0x7E, 0x00]).after(Duration::from_millis(100)),
+            ]
+        });
+        let started = Instant::now();
+        let responses = functional
+            .request(&[0x3E, 0x00], Duration::from_millis(30))
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert_eq!(payloads(responses), [(id(0x7E8), vec![0x7E, 0x00])]);
+        let responses = functional.collect(Duration::from_millis(150)).await;
+        assert_eq!(payloads(responses), [(id(0x7E9), vec![0x7E, 0x00])]);
+    }
+
+    #[tokio::test]
+    async fn errors_are_per_responder() {
+        let functional = bus(|_| {
+            vec![
+                Reply::new(0, vec![0x50, 0x03]),
+                Reply::error(1, io::ErrorKind::TimedOut),
+                Reply::new(1, vec![0x50, 0x03]),
+                Reply::new(2, vec![0x50, 0x03]).after(Duration::from_millis(10)),
+            ]
+        });
+        let responses = functional
+            .request(&[0x10, 0x03], Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert_eq!(responses.len(), 3);
+        let (responder, error) = &responses[1];
+        assert_eq!(*responder, id(0x7E9));
+        assert_eq!(error.as_ref().unwrap_err().kind(), io::ErrorKind::TimedOut);
+        let (responder, pdu) = &responses[2];
+        assert_eq!(*responder, id(0x7EA));
+        assert_eq!(pdu.as_ref().unwrap(), &[0x50, 0x03]);
+        // the PDU after the error is received by the next collection
+        let responses = functional.collect(Duration::from_millis(20)).await;
+        assert_eq!(payloads(responses), [(id(0x7E9), vec![0x50, 0x03])]);
+    }
+}
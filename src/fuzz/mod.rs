@@ -0,0 +1,812 @@
+//! Fuzzing of ISO-TP peers and UDS servers
+//!
+//! Two harnesses run mutated inputs against an ECU on a test bench:
+//!
+//! * [PduFuzzer] mutates UDS requests and sends them over any
+//!   [IsoTpTransport], usually an [IsoTpSocket](crate::IsoTpSocket). The kernel does
+//!   the segmentation, so the inputs are well-formed ISO-TP.
+//! * [FrameFuzzer] mutates the segmentation itself: sequence number jumps, dropped
+//!   and duplicated Consecutive Frames, wrong First and Single Frame lengths,
+//!   reserved PCI types and unexpected or malformed Flow Control, see
+//!   [SegmentationMutation]. The kernel ISO-TP stack cannot send broken frames and
+//!   this crate has no ISO-TP engine in userspace, so the frames are produced with
+//!   [frame::segment](crate::frame::segment) and written on a [RawCanSocket]. No
+//!   ISO-TP socket may be bound to the ids of the ECU during the run.
+//!
+//! The inputs thus go through the kernel socket or are segmented by the fuzzer
+//! itself. Plugging a userspace ISO-TP engine in as transport is out of scope until
+//! the crate has one.
+//!
+//! Every input is derived from the seeds and a random number generator seeded with
+//! [FuzzConfig::seed] plus the iteration, so a run is repeatable. After each input
+//! the ECU is probed with TesterPresent: an ECU answering again within
+//! [FuzzConfig::recovery_timeout] is reported as [Outcome::Reset], otherwise as
+//! [Outcome::Hang] and the run stops. An input the transport fails to send, or whose
+//! responses fail to arrive, is reported as [Outcome::TransportError] and the run
+//! goes on. The last exchanges before a finding are saved to [FuzzConfig::crash_dir],
+//! PDUs as JSON Lines trace for [replay_pdus] and frames as `candump -l` log for
+//! [replay_frames] or `canplayer`.
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::fuzz::{FuzzConfig, PduFuzzer};
+//! use tokio_socketcan_isotp::{IsoTpSocket, StandardId};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let socket = IsoTpSocket::open(
+//!     "can0",
+//!     StandardId::new(0x7E8).unwrap(),
+//!     StandardId::new(0x7E0).unwrap(),
+//! )?;
+//! let config = FuzzConfig {
+//!     crash_dir: Some("crashes".into()),
+//!     ..FuzzConfig::default()
+//! };
+//! let seeds = vec![vec![0x22, 0xF1, 0x90], vec![0x10, 0x03], vec![0x19, 0x02, 0xFF]];
+//! let report = PduFuzzer::new(socket, config).run(&seeds).await?;
+//! for finding in &report.findings {
+//!     println!("{:?} after iteration {}", finding.outcome, finding.iteration);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod mutate;
+mod raw;
+
+pub use mutate::{mutate_pdu, Rng, SegmentationMutation};
+pub use raw::{FrameTransport, RawCanSocket, CAN_RAW, CAN_RAW_FD_FRAMES, SOL_CAN_RAW};
+
+use crate::candump::CandumpWriter;
+use crate::frame::{segment, CanFrame, FrameType, SegmentOptions};
+use crate::trace::{Direction, TraceFormat, TraceRecord, TraceWriter};
+use crate::uds::service::{ECU_RESET, NEGATIVE_RESPONSE, POSITIVE_RESPONSE_OFFSET, TESTER_PRESENT};
+use crate::{Id, IsoTpTransport};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+
+/// TesterPresent request used as health probe
+const PROBE: [u8; 2] = [TESTER_PRESENT, 0x00];
+
+/// Parameters of a fuzzing run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzConfig {
+    /// Seed of the random number generator
+    pub seed: u64,
+    /// Number of inputs sent
+    pub iterations: u64,
+    /// Upper bound of the mutations stacked on one PDU
+    pub max_mutations: usize,
+    /// Time the responses to an input are collected
+    pub response_timeout: Duration,
+    /// Time to wait for the answer to a TesterPresent probe
+    pub probe_timeout: Duration,
+    /// Time an ECU not answering the probe gets to come back before it counts as hung
+    pub recovery_timeout: Duration,
+    /// Services never sent, e.g. ECUReset which resets the ECU on purpose
+    pub excluded_services: Vec<u8>,
+    /// Number of exchanges kept for the trace of a finding
+    pub history: usize,
+    /// Directory the traces of findings are saved to
+    pub crash_dir: Option<PathBuf>,
+    /// Interface name written to `candump -l` logs
+    pub interface: String,
+}
+
+impl Default for FuzzConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            iterations: 10_000,
+            max_mutations: 4,
+            response_timeout: Duration::from_millis(50),
+            probe_timeout: Duration::from_millis(100),
+            recovery_timeout: Duration::from_secs(5),
+            excluded_services: vec![ECU_RESET],
+            history: 16,
+            crash_dir: None,
+            interface: "can0".to_string(),
+        }
+    }
+}
+
+/// State of the ECU after an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The ECU stopped answering and came back within the recovery timeout
+    Reset,
+    /// The ECU did not answer within the recovery timeout
+    Hang,
+    /// The ECU kept answering, but the transport failed while exchanging the input,
+    /// e.g. the kernel aborted the reception of a broken response
+    TransportError,
+}
+
+/// Input sent to the ECU
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FuzzInput {
+    Pdu(Vec<u8>),
+    Frames {
+        /// PDU segmented into the frames
+        pdu: Vec<u8>,
+        mutation: SegmentationMutation,
+    },
+}
+
+/// Input after which the ECU reset or hung or the transport failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// Iteration of the input, its random numbers are seeded with `seed + iteration`
+    pub iteration: u64,
+    pub input: FuzzInput,
+    pub outcome: Outcome,
+    /// Error of the transport while exchanging the input
+    pub error: Option<String>,
+    /// Saved trace of the last exchanges
+    pub trace: Option<PathBuf>,
+}
+
+/// Result of a fuzzing run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FuzzReport {
+    /// Number of inputs sent
+    pub iterations: u64,
+    pub findings: Vec<Finding>,
+}
+
+/// Fuzzer of UDS requests sent as whole PDUs
+pub struct PduFuzzer<T> {
+    transport: T,
+    config: FuzzConfig,
+    history: VecDeque<TraceRecord>,
+}
+
+impl<T: IsoTpTransport> PduFuzzer<T> {
+    pub fn new(transport: T, config: FuzzConfig) -> Self {
+        Self {
+            transport,
+            config,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &FuzzConfig {
+        &self.config
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Send the configured number of mutated seeds, stopping at the first hang
+    ///
+    /// Errors of the transport are reported as findings, only invalid seeds and
+    /// failures to save a trace end the run with an error.
+    pub async fn run(&mut self, seeds: &[Vec<u8>]) -> io::Result<FuzzReport> {
+        check_seeds(seeds, &self.config)?;
+        let mut report = FuzzReport::default();
+        for iteration in 0..self.config.iterations {
+            let mut rng = Rng::new(self.config.seed.wrapping_add(iteration));
+            let seed = &seeds[rng.below(seeds.len())];
+            let pdu = loop {
+                let pdu = mutate_pdu(
+                    &mut rng,
+                    seed,
+                    self.config.max_mutations,
+                    self.transport.max_pdu_size(),
+                );
+                if !self.config.excluded_services.contains(&pdu[0]) {
+                    break pdu;
+                }
+            };
+            report.iterations += 1;
+            let error = self.exchange(&pdu).await.err();
+            let Some(outcome) = finding_outcome(self.check_health().await, &error) else {
+                continue;
+            };
+            let trace = self.save_trace(iteration)?;
+            report.findings.push(Finding {
+                iteration,
+                input: FuzzInput::Pdu(pdu),
+                outcome,
+                error: error.map(|error| error.to_string()),
+                trace,
+            });
+            if outcome == Outcome::Hang {
+                break;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Send a PDU and record it with all PDUs received within the response timeout
+    async fn exchange(&mut self, pdu: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        self.record(Direction::Tx, pdu.to_vec());
+        self.transport.send(pdu).await?;
+        let deadline = Instant::now() + self.config.response_timeout;
+        let mut responses = Vec::new();
+        while let Ok(response) = tokio::time::timeout_at(deadline, self.transport.recv()).await {
+            let response = response?;
+            self.record(Direction::Rx, response.clone());
+            responses.push(response);
+        }
+        Ok(responses)
+    }
+
+    async fn check_health(&self) -> Option<Outcome> {
+        let transport = &self.transport;
+        let timeout = self.config.probe_timeout;
+        check_health(self.config.recovery_timeout, || async move {
+            let deadline = Instant::now() + timeout;
+            if transport.send(&PROBE).await.is_err() {
+                return failed_probe(deadline).await;
+            }
+            while let Ok(response) = tokio::time::timeout_at(deadline, transport.recv()).await {
+                match response {
+                    Ok(response) if is_probe_response(&response) => return true,
+                    Ok(_) => {}
+                    Err(_) => return failed_probe(deadline).await,
+                }
+            }
+            false
+        })
+        .await
+    }
+
+    fn record(&mut self, direction: Direction, data: Vec<u8>) {
+        let record = TraceRecord::now(
+            direction,
+            self.transport.rx_id(),
+            self.transport.tx_id(),
+            data,
+        );
+        push_bounded(&mut self.history, record, self.config.history);
+    }
+
+    fn save_trace(&mut self, iteration: u64) -> io::Result<Option<PathBuf>> {
+        let Some(dir) = &self.config.crash_dir else {
+            return Ok(None);
+        };
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("crash-{iteration:06}.jsonl"));
+        let writer = TraceWriter::create(&path, TraceFormat::JsonLines)?;
+        for record in self.history.drain(..) {
+            writer.record(&record)?;
+        }
+        writer.flush()?;
+        Ok(Some(path))
+    }
+}
+
+/// Addressing of the ECU attacked by a [FrameFuzzer]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTarget {
+    pub request_id: Id,
+    pub response_id: Id,
+    /// Extended addressing, padding and data length of the request frames
+    pub options: SegmentOptions,
+    /// Address byte in front of the response frames (extended addressing)
+    pub rx_ext_address: Option<u8>,
+}
+
+/// Fuzzer of the ISO-TP segmentation of UDS requests
+pub struct FrameFuzzer<F> {
+    frames: F,
+    target: FrameTarget,
+    config: FuzzConfig,
+    history: VecDeque<CanFrame>,
+}
+
+impl<F: FrameTransport> FrameFuzzer<F> {
+    pub fn new(frames: F, target: FrameTarget, config: FuzzConfig) -> Self {
+        Self {
+            frames,
+            target,
+            config,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &FuzzConfig {
+        &self.config
+    }
+
+    pub fn into_inner(self) -> F {
+        self.frames
+    }
+
+    /// Send the configured number of mutated seeds, stopping at the first hang
+    ///
+    /// Every second input sends the seed PDU unchanged and only mutates its
+    /// segmentation. Errors of the transport are reported as findings, only invalid
+    /// seeds and failures to save a trace end the run with an error.
+    pub async fn run(&mut self, seeds: &[Vec<u8>]) -> io::Result<FuzzReport> {
+        check_seeds(seeds, &self.config)?;
+        let mut report = FuzzReport::default();
+        for iteration in 0..self.config.iterations {
+            let mut rng = Rng::new(self.config.seed.wrapping_add(iteration));
+            let seed = &seeds[rng.below(seeds.len())];
+            let pdu = loop {
+                let pdu = if iteration.is_multiple_of(2) {
+                    seed.clone()
+                } else {
+                    mutate_pdu(&mut rng, seed, self.config.max_mutations, 4095)
+                };
+                if !self.config.excluded_services.contains(&pdu[0]) {
+                    break pdu;
+                }
+            };
+            let mut frames = segment(&pdu, &self.target.options);
+            let mutation = SegmentationMutation::random(&mut rng, &frames);
+            mutation.apply(&mut frames, &self.target.options);
+            report.iterations += 1;
+            let error = self.exchange(&frames, &mutation).await.err();
+            let Some(outcome) = finding_outcome(self.check_health().await, &error) else {
+                continue;
+            };
+            let trace = self.save_trace(iteration)?;
+            report.findings.push(Finding {
+                iteration,
+                input: FuzzInput::Frames { pdu, mutation },
+                outcome,
+                error: error.map(|error| error.to_string()),
+                trace,
+            });
+            if outcome == Outcome::Hang {
+                break;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Send the request frames and answer the response with the flow control of the mutation
+    async fn exchange(
+        &mut self,
+        frames: &[Vec<u8>],
+        mutation: &SegmentationMutation,
+    ) -> io::Result<()> {
+        let options = self.target.options;
+        if let Some(flow_control) = mutation.injected_flow_control(&options) {
+            self.send(&flow_control).await?;
+        }
+        let Some((first, consecutive)) = frames.split_first() else {
+            return Ok(());
+        };
+        self.send(first).await?;
+        if !consecutive.is_empty() {
+            let mut remaining = consecutive.iter();
+            'blocks: loop {
+                // without flow control of the ECU the frames are sent anyway
+                let (block_size, st_min) = self.wait_flow_control().await?.unwrap_or((0, 0));
+                let mut sent = 0;
+                for frame in remaining.by_ref() {
+                    tokio::time::sleep(separation_time(st_min)).await;
+                    self.send(frame).await?;
+                    sent += 1;
+                    if block_size != 0 && sent == block_size {
+                        continue 'blocks;
+                    }
+                }
+                break;
+            }
+        }
+
+        let flow_control = mutation.response_flow_control(&options);
+        let mut deadline = Instant::now() + self.config.response_timeout;
+        while let Ok(frame) = tokio::time::timeout_at(deadline, self.receive()).await {
+            if self.pci(&frame?) == Some(FrameType::First) {
+                self.send(&flow_control).await?;
+            }
+            deadline = Instant::now() + self.config.response_timeout;
+        }
+        Ok(())
+    }
+
+    /// Block size and STmin of the next flow control of the ECU
+    async fn wait_flow_control(&mut self) -> io::Result<Option<(usize, u8)>> {
+        let deadline = Instant::now() + self.config.response_timeout;
+        while let Ok(frame) = tokio::time::timeout_at(deadline, self.receive()).await {
+            let frame = frame?;
+            let offset = usize::from(self.target.rx_ext_address.is_some());
+            if let Some([0x30, block_size, st_min, ..]) = frame.data.get(offset..) {
+                return Ok(Some((usize::from(*block_size), *st_min)));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn check_health(&self) -> Option<Outcome> {
+        let options = SegmentOptions {
+            tx_dl: crate::CAN_MAX_DLEN as usize,
+            ..self.target.options
+        };
+        let probe = &segment(&PROBE, &options)[0];
+        let (frames, target) = (&self.frames, self.target);
+        let timeout = self.config.probe_timeout;
+        check_health(self.config.recovery_timeout, || async move {
+            let deadline = Instant::now() + timeout;
+            if frames.send_frame(target.request_id, probe).await.is_err() {
+                return failed_probe(deadline).await;
+            }
+            while let Ok(frame) = tokio::time::timeout_at(deadline, frames.recv_frame()).await {
+                let Ok(frame) = frame else {
+                    return failed_probe(deadline).await;
+                };
+                if frame.id != target.response_id {
+                    continue;
+                }
+                let offset = usize::from(target.rx_ext_address.is_some());
+                if let Some([length, payload @ ..]) = frame.data.get(offset..) {
+                    let length = usize::from(*length);
+                    if (1..=7).contains(&length)
+                        && payload.len() >= length
+                        && is_probe_response(&payload[..length])
+                    {
+                        return true;
+                    }
+                }
+            }
+            false
+        })
+        .await
+    }
+
+    async fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let frame = CanFrame::new(SystemTime::now(), self.target.request_id, data.to_vec());
+        push_bounded(&mut self.history, frame, self.config.history);
+        self.frames.send_frame(self.target.request_id, data).await
+    }
+
+    /// Next frame of the ECU, frames of other ids are skipped
+    async fn receive(&mut self) -> io::Result<CanFrame> {
+        loop {
+            let frame = self.frames.recv_frame().await?;
+            if frame.id == self.target.response_id
+                && (self.target.rx_ext_address.is_none()
+                    || frame.data.first() == self.target.rx_ext_address.as_ref())
+            {
+                push_bounded(&mut self.history, frame.clone(), self.config.history);
+                return Ok(frame);
+            }
+        }
+    }
+
+    fn pci(&self, frame: &CanFrame) -> Option<FrameType> {
+        let offset = usize::from(self.target.rx_ext_address.is_some());
+        FrameType::from_pci(*frame.data.get(offset)?)
+    }
+
+    fn save_trace(&mut self, iteration: u64) -> io::Result<Option<PathBuf>> {
+        let Some(dir) = &self.config.crash_dir else {
+            return Ok(None);
+        };
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("crash-{iteration:06}.log"));
+        let mut writer = CandumpWriter::create(&path)?;
+        for frame in self.history.drain(..) {
+            writer.write_frame(&self.config.interface, &frame)?;
+        }
+        writer.flush()?;
+        Ok(Some(path))
+    }
+}
+
+/// Send the transmitted PDUs of a trace again, e.g. a saved finding
+pub async fn replay_pdus(
+    transport: &impl IsoTpTransport,
+    records: &[TraceRecord],
+    gap: Duration,
+) -> io::Result<()> {
+    for record in records
+        .iter()
+        .filter(|record| record.direction == Direction::Tx)
+    {
+        transport.send(&record.data).await?;
+        tokio::time::sleep(gap).await;
+    }
+    Ok(())
+}
+
+/// Send the frames of a log on `id` again, keeping their original spacing
+pub async fn replay_frames(
+    transport: &impl FrameTransport,
+    frames: &[CanFrame],
+    id: Id,
+) -> io::Result<()> {
+    let mut previous: Option<SystemTime> = None;
+    for frame in frames.iter().filter(|frame| frame.id == id) {
+        if let Some(gap) =
+            previous.and_then(|previous| frame.timestamp.duration_since(previous).ok())
+        {
+            tokio::time::sleep(gap).await;
+        }
+        previous = Some(frame.timestamp);
+        transport.send_frame(frame.id, &frame.data).await?;
+    }
+    Ok(())
+}
+
+fn check_seeds(seeds: &[Vec<u8>], config: &FuzzConfig) -> io::Result<()> {
+    if seeds.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no seeds"));
+    }
+    if seeds.iter().any(|seed| {
+        seed.first()
+            .is_none_or(|sid| config.excluded_services.contains(sid))
+    }) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "seeds have to start with a service that is not excluded",
+        ));
+    }
+    Ok(())
+}
+
+/// Probe until the ECU answers or the recovery timeout elapsed
+async fn check_health<Fut: Future<Output = bool>>(
+    recovery_timeout: Duration,
+    mut probe: impl FnMut() -> Fut,
+) -> Option<Outcome> {
+    if probe().await {
+        return None;
+    }
+    let deadline = Instant::now() + recovery_timeout;
+    while Instant::now() < deadline {
+        if probe().await {
+            return Some(Outcome::Reset);
+        }
+    }
+    Some(Outcome::Hang)
+}
+
+/// Probe failed by the transport, after the probe timeout so a failing transport is
+/// not probed in a busy loop
+async fn failed_probe(deadline: Instant) -> bool {
+    tokio::time::sleep_until(deadline).await;
+    false
+}
+
+/// Outcome of an input, `None` if the ECU is healthy and the exchange succeeded
+fn finding_outcome(health: Option<Outcome>, error: &Option<io::Error>) -> Option<Outcome> {
+    match (health, error) {
+        (Some(outcome), _) => Some(outcome),
+        (None, Some(_)) => Some(Outcome::TransportError),
+        (None, None) => None,
+    }
+}
+
+/// Positive or negative response to TesterPresent
+fn is_probe_response(pdu: &[u8]) -> bool {
+    match pdu {
+        [sid, ..] if *sid == TESTER_PRESENT + POSITIVE_RESPONSE_OFFSET => true,
+        [NEGATIVE_RESPONSE, TESTER_PRESENT, ..] => true,
+        _ => false,
+    }
+}
+
+/// STmin as time, reserved values as the maximum of 127 ms
+fn separation_time(st_min: u8) -> Duration {
+    match st_min {
+        0x00..=0x7F => Duration::from_millis(u64::from(st_min)),
+        0xF1..=0xF9 => Duration::from_micros(u64::from(st_min - 0xF0) * 100),
+        _ => Duration::from_millis(0x7F),
+    }
+}
+
+fn push_bounded<T>(history: &mut VecDeque<T>, item: T, limit: usize) {
+    if history.len() == limit {
+        history.pop_front();
+    }
+    if limit > 0 {
+        history.push_back(item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::load_trace;
+    use crate::StandardId;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    type Handler = Box<dyn FnMut(&[u8]) -> io::Result<Vec<Vec<u8>>> + Send>;
+
+    /// Transport whose handler answers or fails every sent PDU or frame
+    struct FailingTransport {
+        handler: Mutex<Handler>,
+        responses: mpsc::UnboundedSender<Vec<u8>>,
+        queue: tokio::sync::Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+    }
+
+    impl FailingTransport {
+        fn new(handler: impl FnMut(&[u8]) -> io::Result<Vec<Vec<u8>>> + Send + 'static) -> Self {
+            let (responses, queue) = mpsc::unbounded_channel();
+            Self {
+                handler: Mutex::new(Box::new(handler)),
+                responses,
+                queue: tokio::sync::Mutex::new(queue),
+            }
+        }
+
+        fn handle(&self, data: &[u8]) -> io::Result<()> {
+            for response in (self.handler.lock().unwrap())(data)? {
+                self.responses.send(response).unwrap();
+            }
+            Ok(())
+        }
+
+        async fn next(&self) -> io::Result<Vec<u8>> {
+            let response = self.queue.lock().await.recv().await;
+            response.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+        }
+    }
+
+    impl IsoTpTransport for FailingTransport {
+        fn rx_id(&self) -> Id {
+            response_id()
+        }
+
+        fn tx_id(&self) -> Id {
+            request_id()
+        }
+
+        fn send<'a>(&'a self, pdu: &'a [u8]) -> impl Future<Output = io::Result<()>> + Send + 'a {
+            let result = self.handle(pdu);
+            async { result }
+        }
+
+        async fn recv(&self) -> io::Result<Vec<u8>> {
+            self.next().await
+        }
+    }
+
+    impl FrameTransport for FailingTransport {
+        fn send_frame<'a>(
+            &'a self,
+            _id: Id,
+            data: &'a [u8],
+        ) -> impl Future<Output = io::Result<()>> + Send + 'a {
+            let result = self.handle(data);
+            async { result }
+        }
+
+        async fn recv_frame(&self) -> io::Result<CanFrame> {
+            let data = self.next().await?;
+            Ok(CanFrame::new(SystemTime::now(), response_id(), data))
+        }
+    }
+
+    fn request_id() -> Id {
+        Id::Standard(StandardId::new(0x7E0).unwrap())
+    }
+
+    fn response_id() -> Id {
+        Id::Standard(StandardId::new(0x7E8).unwrap())
+    }
+
+    fn config(iterations: u64) -> FuzzConfig {
+        FuzzConfig {
+            iterations,
+            response_timeout: Duration::from_millis(5),
+            probe_timeout: Duration::from_millis(10),
+            recovery_timeout: Duration::from_millis(50),
+            ..FuzzConfig::default()
+        }
+    }
+
+    fn crash_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("isotp-fuzz-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn transport_errors_are_findings() {
+        let transport = FailingTransport::new(|pdu| match pdu {
+            [TESTER_PRESENT, 0x00] => Ok(vec![vec![0x7E, 0x00]]),
+            _ => Err(io::Error::from_raw_os_error(libc::EMSGSIZE)),
+        });
+        let dir = crash_dir("errors");
+        let config = FuzzConfig {
+            crash_dir: Some(dir.clone()),
+            ..config(3)
+        };
+        let mut fuzzer = PduFuzzer::new(transport, config);
+        let report = fuzzer.run(&[vec![0x22, 0xF1, 0x90]]).await.unwrap();
+        assert_eq!(report.iterations, 3);
+        assert_eq!(report.findings.len(), 3);
+        for (iteration, finding) in report.findings.iter().enumerate() {
+            assert_eq!(finding.iteration, iteration as u64);
+            assert_eq!(finding.outcome, Outcome::TransportError);
+            assert!(finding.error.is_some());
+            let FuzzInput::Pdu(pdu) = &finding.input else {
+                panic!("{:?}", finding.input);
+            };
+            let trace = load_trace(finding.trace.as_ref().unwrap()).unwrap();
+            assert_eq!(trace.len(), 1);
+            assert_eq!(trace[0].direction, Direction::Tx);
+            assert_eq!(&trace[0].data, pdu);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn detects_resets_and_hangs() {
+        // the first input resets the ECU, the second makes it hang for good
+        let mut received = 0;
+        let mut probes = 0;
+        let transport = FailingTransport::new(move |pdu| {
+            if pdu != PROBE {
+                received += 1;
+                return Ok(vec![vec![0x7F, pdu[0], 0x11]]);
+            }
+            probes += 1;
+            match (received, probes) {
+                (1, 1) | (2, _) => Ok(vec![]),
+                _ => Ok(vec![vec![0x7E, 0x00]]),
+            }
+        });
+        let mut fuzzer = PduFuzzer::new(transport, config(10));
+        let report = fuzzer.run(&[vec![0x10, 0x03]]).await.unwrap();
+        assert_eq!(report.iterations, 2);
+        let outcomes: Vec<_> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.iteration, finding.outcome, finding.error.clone()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [(0, Outcome::Reset, None), (1, Outcome::Hang, None)]
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_probes_count_as_hang() {
+        let transport = FailingTransport::new(|_| Err(io::Error::from(io::ErrorKind::BrokenPipe)));
+        let mut fuzzer = PduFuzzer::new(transport, config(10));
+        let report = fuzzer.run(&[vec![0x10, 0x03]]).await.unwrap();
+        assert_eq!(report.iterations, 1);
+        assert_eq!(report.findings[0].outcome, Outcome::Hang);
+        assert!(report.findings[0].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn frame_transport_errors_are_findings() {
+        let transport = FailingTransport::new(|frame| match frame {
+            [0x02, TESTER_PRESENT, 0x00, ..] => Ok(vec![vec![0x02, 0x7E, 0x00]]),
+            _ => Err(io::Error::from(io::ErrorKind::WouldBlock)),
+        });
+        let target = FrameTarget {
+            request_id: request_id(),
+            response_id: response_id(),
+            options: SegmentOptions::default(),
+            rx_ext_address: None,
+        };
+        let dir = crash_dir("frames");
+        let config = FuzzConfig {
+            crash_dir: Some(dir.clone()),
+            ..config(2)
+        };
+        let mut fuzzer = FrameFuzzer::new(transport, target, config);
+        let report = fuzzer.run(&[vec![0x22, 0xF1, 0x90]]).await.unwrap();
+        assert_eq!(report.iterations, 2);
+        assert_eq!(report.findings.len(), 2);
+        for finding in &report.findings {
+            assert_eq!(finding.outcome, Outcome::TransportError);
+            assert!(finding.trace.as_ref().unwrap().exists());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn separation_times() {
+        assert_eq!(separation_time(0x00), Duration::ZERO);
+        assert_eq!(separation_time(0x14), Duration::from_millis(20));
+        assert_eq!(separation_time(0xF5), Duration::from_micros(500));
+        assert_eq!(separation_time(0x80), Duration::from_millis(127));
+    }
+}
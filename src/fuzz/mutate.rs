@@ -0,0 +1,480 @@
+//! Deterministic mutation of PDUs and of their segmentation
+
+use crate::frame::{FrameType, SegmentOptions};
+use std::fmt;
+
+/// PDU lengths at the borders of the frame types of CAN and CAN FD
+const BOUNDARY_LENGTHS: [usize; 8] = [6, 7, 8, 62, 63, 64, 4095, 4096];
+
+/// Bytes with a special meaning in many protocols
+const INTERESTING_BYTES: [u8; 6] = [0x00, 0x01, 0x7E, 0x7F, 0x80, 0xFF];
+
+/// Small deterministic random number generator (SplitMix64)
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Number in `0..bound`, 0 for a bound of 0
+    pub fn below(&mut self, bound: usize) -> usize {
+        if bound == 0 {
+            return 0;
+        }
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    pub fn byte(&mut self) -> u8 {
+        self.next_u64() as u8
+    }
+
+    fn pick<T: Copy>(&mut self, values: &[T]) -> T {
+        values[self.below(values.len())]
+    }
+}
+
+/// Apply 1 to `max_mutations` random mutations to a PDU, the result is never empty
+pub fn mutate_pdu(rng: &mut Rng, pdu: &[u8], max_mutations: usize, max_len: usize) -> Vec<u8> {
+    let mut pdu = pdu.to_vec();
+    if pdu.is_empty() {
+        pdu.push(rng.byte());
+    }
+    for _ in 0..=rng.below(max_mutations.max(1)) {
+        let position = rng.below(pdu.len());
+        match rng.below(7) {
+            0 => pdu[position] ^= 1 << rng.below(8),
+            1 => pdu[position] = rng.byte(),
+            2 => pdu[position] = rng.pick(&INTERESTING_BYTES),
+            3 => pdu.insert(position, rng.byte()),
+            4 if pdu.len() > 1 => {
+                pdu.remove(position);
+            }
+            5 => pdu.truncate(position.max(1)),
+            _ => {
+                let length = rng.pick(&BOUNDARY_LENGTHS).min(max_len);
+                while pdu.len() < length {
+                    pdu.push(rng.byte());
+                }
+            }
+        }
+    }
+    pdu
+}
+
+/// Fault injected into the frames of a request or into the flow control of a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentationMutation {
+    /// Frames as segmented, only the PDU is mutated
+    Unchanged,
+    /// Sequence number of a Consecutive Frame advanced by `jump`
+    SequenceJump { frame: usize, jump: u8 },
+    /// Consecutive Frame left out
+    DropConsecutive { frame: usize },
+    /// Consecutive Frame sent twice
+    DuplicateConsecutive { frame: usize },
+    /// First Frame announcing a different PDU length, above 4095 with the 32 bit escape
+    FirstFrameLength { length: u32 },
+    /// Single Frame announcing a different PDU length
+    SingleFrameLength { length: u8 },
+    /// First frame with a reserved PCI type 4–F
+    InvalidPci { pci: u8 },
+    /// Frame cut to `length` bytes
+    Truncate { frame: usize, length: usize },
+    /// Flow Control frame sent before the request
+    UnexpectedFlowControl {
+        status: u8,
+        block_size: u8,
+        st_min: u8,
+    },
+    /// Flow Control answering the First Frame of the response
+    ResponseFlowControl {
+        status: u8,
+        block_size: u8,
+        st_min: u8,
+    },
+}
+
+impl SegmentationMutation {
+    /// Pick a mutation applicable to `frames`
+    pub fn random(rng: &mut Rng, frames: &[Vec<u8>]) -> Self {
+        let multi_frame = frames.len() > 1;
+        let flow_control = |rng: &mut Rng| {
+            (
+                rng.pick(&[0x00, 0x01, 0x02, 0x03, 0x0F]),
+                rng.pick(&[0x00, 0x01, 0xFF]),
+                rng.pick(&[0x00, 0x7F, 0x80, 0xF1, 0xF9, 0xFA, 0xFF]),
+            )
+        };
+        match rng.below(if multi_frame { 9 } else { 6 }) {
+            0 => SegmentationMutation::Unchanged,
+            1 => {
+                let (status, block_size, st_min) = flow_control(rng);
+                SegmentationMutation::UnexpectedFlowControl {
+                    status,
+                    block_size,
+                    st_min,
+                }
+            }
+            2 => {
+                let (status, block_size, st_min) = flow_control(rng);
+                SegmentationMutation::ResponseFlowControl {
+                    status,
+                    block_size,
+                    st_min,
+                }
+            }
+            3 => SegmentationMutation::InvalidPci {
+                pci: ((4 + rng.below(12) as u8) << 4) | (rng.byte() & 0x0F),
+            },
+            4 => {
+                let frame = rng.below(frames.len());
+                SegmentationMutation::Truncate {
+                    frame,
+                    length: rng.below(frames[frame].len()),
+                }
+            }
+            5 if !multi_frame => SegmentationMutation::SingleFrameLength {
+                length: rng.below(16) as u8,
+            },
+            5 => SegmentationMutation::SequenceJump {
+                frame: 1 + rng.below(frames.len() - 1),
+                jump: 1 + rng.below(15) as u8,
+            },
+            6 => SegmentationMutation::DropConsecutive {
+                frame: 1 + rng.below(frames.len() - 1),
+            },
+            7 => SegmentationMutation::DuplicateConsecutive {
+                frame: 1 + rng.below(frames.len() - 1),
+            },
+            _ => SegmentationMutation::FirstFrameLength {
+                length: rng.pick(&[0, 1, 7, 8, 0xFFF, 0x1000, u32::MAX]),
+            },
+        }
+    }
+
+    /// Apply the mutation to the segmented frames of a request
+    ///
+    /// Single Frame lengths are only changed for 8 byte frames with a 4 bit length.
+    pub fn apply(&self, frames: &mut Vec<Vec<u8>>, options: &SegmentOptions) {
+        let offset = usize::from(options.ext_address.is_some());
+        match *self {
+            SegmentationMutation::SequenceJump { frame, jump } => {
+                if let Some(data) = frames.get_mut(frame) {
+                    let sn = data[offset] & 0x0F;
+                    data[offset] = 0x20 | (sn.wrapping_add(jump) & 0x0F);
+                }
+            }
+            SegmentationMutation::DropConsecutive { frame } if frame < frames.len() => {
+                frames.remove(frame);
+            }
+            SegmentationMutation::DuplicateConsecutive { frame } if frame < frames.len() => {
+                frames.insert(frame, frames[frame].clone());
+            }
+            SegmentationMutation::FirstFrameLength { length } => {
+                let first = &mut frames[0];
+                if FrameType::from_pci(first[offset]) != Some(FrameType::First) {
+                    return;
+                }
+                let mut data = first[..offset].to_vec();
+                if length <= 0xFFF {
+                    data.extend_from_slice(&[0x10 | (length >> 8) as u8, length as u8]);
+                } else {
+                    data.extend_from_slice(&[0x10, 0x00]);
+                    data.extend_from_slice(&length.to_be_bytes());
+                }
+                let payload_start = if first[offset + 1] == 0 && first[offset] == 0x10 {
+                    offset + 6
+                } else {
+                    offset + 2
+                };
+                data.extend_from_slice(&first[payload_start..]);
+                data.truncate(first.len());
+                *first = data;
+            }
+            SegmentationMutation::SingleFrameLength { length } => {
+                let first = &mut frames[0];
+                if FrameType::from_pci(first[offset]) == Some(FrameType::Single)
+                    && first[offset] != 0
+                {
+                    first[offset] = length & 0x0F;
+                }
+            }
+            SegmentationMutation::InvalidPci { pci } => {
+                frames[0][offset] = pci;
+            }
+            SegmentationMutation::Truncate { frame, length } => {
+                if let Some(data) = frames.get_mut(frame) {
+                    data.truncate(length.max(1));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Flow control sent before the request, if any
+    pub fn injected_flow_control(&self, options: &SegmentOptions) -> Option<Vec<u8>> {
+        match *self {
+            SegmentationMutation::UnexpectedFlowControl {
+                status,
+                block_size,
+                st_min,
+            } => Some(flow_control_frame(status, block_size, st_min, options)),
+            _ => None,
+        }
+    }
+
+    /// Flow control answering the First Frame of the response
+    pub fn response_flow_control(&self, options: &SegmentOptions) -> Vec<u8> {
+        match *self {
+            SegmentationMutation::ResponseFlowControl {
+                status,
+                block_size,
+                st_min,
+            } => flow_control_frame(status, block_size, st_min, options),
+            _ => flow_control_frame(0, 0, 0, options),
+        }
+    }
+}
+
+impl fmt::Display for SegmentationMutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentationMutation::Unchanged => write!(f, "unchanged"),
+            SegmentationMutation::SequenceJump { frame, jump } => {
+                write!(f, "sequence number of frame {frame} advanced by {jump}")
+            }
+            SegmentationMutation::DropConsecutive { frame } => write!(f, "frame {frame} dropped"),
+            SegmentationMutation::DuplicateConsecutive { frame } => {
+                write!(f, "frame {frame} duplicated")
+            }
+            SegmentationMutation::FirstFrameLength { length } => {
+                write!(f, "first frame length {length}")
+            }
+            SegmentationMutation::SingleFrameLength { length } => {
+                write!(f, "single frame length {length}")
+            }
+            SegmentationMutation::InvalidPci { pci } => write!(f, "PCI 0x{pci:02X}"),
+            SegmentationMutation::Truncate { frame, length } => {
+                write!(f, "frame {frame} cut to {length} bytes")
+            }
+            SegmentationMutation::UnexpectedFlowControl {
+                status,
+                block_size,
+                st_min,
+            } => write!(
+                f,
+                "unexpected flow control FS={status} BS={block_size} STmin=0x{st_min:02X}"
+            ),
+            SegmentationMutation::ResponseFlowControl {
+                status,
+                block_size,
+                st_min,
+            } => write!(
+                f,
+                "response flow control FS={status} BS={block_size} STmin=0x{st_min:02X}"
+            ),
+        }
+    }
+}
+
+fn flow_control_frame(status: u8, block_size: u8, st_min: u8, options: &SegmentOptions) -> Vec<u8> {
+    let mut frame = crate::frame::flow_control(block_size, st_min, options);
+    frame[usize::from(options.ext_address.is_some())] = 0x30 | (status & 0x0F);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::segment;
+    use std::collections::HashSet;
+    use std::mem::discriminant;
+
+    /// 20 bytes, a First Frame and two Consecutive Frames on CAN 2.0
+    fn multi_frame(options: &SegmentOptions) -> Vec<Vec<u8>> {
+        segment(&(0..20).collect::<Vec<u8>>(), options)
+    }
+
+    #[test]
+    fn rng_is_repeatable() {
+        // SplitMix64 reference output, runs must stay repeatable across versions
+        assert_eq!(Rng::new(0).next_u64(), 0xE220_A839_7B1D_CDAF);
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        let mut rng = Rng::new(7);
+        assert_eq!(rng.below(0), 0);
+        assert!((0..1000).all(|_| rng.below(10) < 10));
+    }
+
+    #[test]
+    fn mutate_pdu_is_repeatable() {
+        let pdu = [0x22, 0xF1, 0x90];
+        let mut changed = 0;
+        for seed in 0..500 {
+            let mutated = mutate_pdu(&mut Rng::new(seed), &pdu, 4, 64);
+            assert_eq!(mutate_pdu(&mut Rng::new(seed), &pdu, 4, 64), mutated);
+            assert!(!mutated.is_empty());
+            // at most 4 mutations, each grows the PDU to at most 64 or by one byte
+            assert!(mutated.len() <= 64 + 4, "{mutated:02X?}");
+            if mutated != pdu {
+                changed += 1;
+            }
+            assert!(!mutate_pdu(&mut Rng::new(seed), &[], 1, 64).is_empty());
+        }
+        assert!(changed > 400, "{changed}");
+    }
+
+    #[test]
+    fn random_fits_the_frames() {
+        let options = SegmentOptions::default();
+        let single = segment(&[0x3E, 0x00], &options);
+        let multi = multi_frame(&options);
+        let mut variants = HashSet::new();
+        for seed in 0..1000 {
+            let mutation = SegmentationMutation::random(&mut Rng::new(seed), &multi);
+            assert_eq!(
+                SegmentationMutation::random(&mut Rng::new(seed), &multi),
+                mutation
+            );
+            match mutation {
+                SegmentationMutation::SequenceJump { frame, jump } => {
+                    assert!((1..3).contains(&frame));
+                    assert!((1..16).contains(&jump));
+                }
+                SegmentationMutation::DropConsecutive { frame }
+                | SegmentationMutation::DuplicateConsecutive { frame } => {
+                    assert!((1..3).contains(&frame))
+                }
+                SegmentationMutation::Truncate { frame, length } => {
+                    assert!(length < multi[frame].len())
+                }
+                SegmentationMutation::InvalidPci { pci } => assert!(pci >= 0x40),
+                SegmentationMutation::SingleFrameLength { .. } => panic!("{mutation}"),
+                _ => (),
+            }
+            variants.insert(discriminant(&mutation));
+
+            let mutation = SegmentationMutation::random(&mut Rng::new(seed), &single);
+            assert!(
+                !matches!(
+                    mutation,
+                    SegmentationMutation::SequenceJump { .. }
+                        | SegmentationMutation::DropConsecutive { .. }
+                        | SegmentationMutation::DuplicateConsecutive { .. }
+                        | SegmentationMutation::FirstFrameLength { .. }
+                ),
+                "{mutation}"
+            );
+        }
+        // every mutation but the Single Frame length is picked for multi-frame PDUs
+        assert_eq!(variants.len(), 9);
+    }
+
+    #[test]
+    fn apply_to_consecutive_frames() {
+        let options = SegmentOptions::default();
+        let apply = |mutation: SegmentationMutation| {
+            let mut frames = multi_frame(&options);
+            mutation.apply(&mut frames, &options);
+            frames
+        };
+        let frames = multi_frame(&options);
+        assert_eq!(frames[0], [0x10, 0x14, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05]);
+        assert_eq!(frames[1][0], 0x21);
+        assert_eq!(frames[2][0], 0x22);
+
+        let mutated = apply(SegmentationMutation::SequenceJump { frame: 2, jump: 15 });
+        assert_eq!(mutated[2][0], 0x21);
+        assert_eq!(mutated[2][1..], frames[2][1..]);
+        let mutated = apply(SegmentationMutation::DropConsecutive { frame: 1 });
+        assert_eq!(mutated, [frames[0].clone(), frames[2].clone()]);
+        let mutated = apply(SegmentationMutation::DuplicateConsecutive { frame: 2 });
+        assert_eq!(mutated.len(), 4);
+        assert_eq!(mutated[3], frames[2]);
+        let mutated = apply(SegmentationMutation::Truncate {
+            frame: 1,
+            length: 0,
+        });
+        assert_eq!(mutated[1], [0x21]);
+        let mutated = apply(SegmentationMutation::InvalidPci { pci: 0x45 });
+        assert_eq!(mutated[0][0], 0x45);
+        // frames beyond the end are left alone
+        assert_eq!(
+            apply(SegmentationMutation::DropConsecutive { frame: 3 }),
+            frames
+        );
+        assert_eq!(
+            apply(SegmentationMutation::SequenceJump { frame: 5, jump: 1 }),
+            frames
+        );
+    }
+
+    #[test]
+    fn apply_lengths() {
+        let options = SegmentOptions {
+            ext_address: Some(0xF1),
+            ..SegmentOptions::default()
+        };
+        let mut frames = multi_frame(&options);
+        assert_eq!(frames[0][..3], [0xF1, 0x10, 0x14]);
+        SegmentationMutation::FirstFrameLength { length: 7 }.apply(&mut frames, &options);
+        assert_eq!(frames[0], [0xF1, 0x10, 0x07, 0x00, 0x01, 0x02, 0x03, 0x04]);
+
+        let mut frames = multi_frame(&options);
+        SegmentationMutation::FirstFrameLength { length: 0x1000 }.apply(&mut frames, &options);
+        assert_eq!(frames[0], [0xF1, 0x10, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00]);
+
+        let mut frames = segment(&[0x3E, 0x00], &options);
+        let unchanged = frames.clone();
+        SegmentationMutation::FirstFrameLength { length: 7 }.apply(&mut frames, &options);
+        assert_eq!(frames, unchanged);
+        SegmentationMutation::SingleFrameLength { length: 0x17 }.apply(&mut frames, &options);
+        assert_eq!(frames, [[0xF1, 0x07, 0x3E, 0x00]]);
+
+        // Single Frames with the CAN FD escape keep their length
+        let fd = SegmentOptions {
+            tx_dl: 64,
+            ..SegmentOptions::default()
+        };
+        let mut frames = segment(&[0xAA; 20], &fd);
+        let unchanged = frames.clone();
+        SegmentationMutation::SingleFrameLength { length: 3 }.apply(&mut frames, &fd);
+        assert_eq!(frames, unchanged);
+    }
+
+    #[test]
+    fn flow_control() {
+        let options = SegmentOptions::default();
+        let mutation = SegmentationMutation::UnexpectedFlowControl {
+            status: 0x01,
+            block_size: 0xFF,
+            st_min: 0xF1,
+        };
+        assert_eq!(
+            mutation.injected_flow_control(&options),
+            Some(vec![0x31, 0xFF, 0xF1])
+        );
+        assert_eq!(mutation.response_flow_control(&options), [0x30, 0x00, 0x00]);
+        let mutation = SegmentationMutation::ResponseFlowControl {
+            status: 0x0F,
+            block_size: 0x01,
+            st_min: 0x7F,
+        };
+        assert_eq!(mutation.injected_flow_control(&options), None);
+        assert_eq!(mutation.response_flow_control(&options), [0x3F, 0x01, 0x7F]);
+    }
+}
@@ -0,0 +1,222 @@
+//! CAN_RAW socket sending and receiving single frames
+
+use crate::frame::CanFrame;
+use crate::{id_from_raw, id_to_raw, Error, Id, AF_CAN, CAN_MAX_DLEN, PF_CAN, SOL_CAN_BASE};
+use libc::{bind, c_int, c_short, c_void, read, setsockopt, sockaddr, socket, write, SOCK_RAW};
+use nix::net::if_::if_nametoindex;
+use std::future::Future;
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::SystemTime;
+use tokio::io::unix::AsyncFd;
+
+/// Raw CAN protocol
+pub const CAN_RAW: c_int = 1;
+
+/// undocumented raw.h constant
+pub const SOL_CAN_RAW: c_int = SOL_CAN_BASE + CAN_RAW;
+
+/// Socket option enabling CAN FD frames
+pub const CAN_RAW_FD_FRAMES: c_int = 5;
+
+/// Size of `struct can_frame`
+const CAN_MTU: usize = 16;
+
+/// Size of `struct canfd_frame`
+const CANFD_MTU: usize = 72;
+
+#[repr(C)]
+struct RawAddr {
+    _af_can: c_short,
+    if_index: c_int,
+    _rx_id: u32,
+    _tx_id: u32,
+    _pgn: u32,
+    _addr: u8,
+}
+
+/// `struct canfd_frame`, the first 16 bytes are a `struct can_frame`
+#[repr(C)]
+struct RawFrame {
+    can_id: u32,
+    len: u8,
+    flags: u8,
+    _res0: u8,
+    _res1: u8,
+    data: [u8; 64],
+}
+
+/// Sends and receives single CAN frames
+pub trait FrameTransport: Send + Sync {
+    /// Send one frame, CAN FD if the data is longer than 8 bytes
+    fn send_frame<'a>(
+        &'a self,
+        id: Id,
+        data: &'a [u8],
+    ) -> impl Future<Output = io::Result<()>> + Send + 'a;
+
+    /// Receive the next frame of any id
+    fn recv_frame(&self) -> impl Future<Output = io::Result<CanFrame>> + Send + '_;
+}
+
+/// Asynchronous CAN_RAW socket receiving all frames of an interface
+pub struct RawCanSocket {
+    inner: AsyncFd<OwnedFd>,
+}
+
+impl RawCanSocket {
+    /// Open a named CAN device such as "vcan0"
+    pub fn open(ifname: &str) -> Result<Self, Error> {
+        let if_index = if_nametoindex(ifname)?;
+        Self::open_if(if_index as c_int)
+    }
+
+    /// Open by kernel interface number, CAN FD frames are enabled
+    pub fn open_if(if_index: c_int) -> Result<Self, Error> {
+        let fd = unsafe { socket(PF_CAN, SOCK_RAW, CAN_RAW) };
+        if fd == -1 {
+            return Err(Error::from(io::Error::last_os_error()));
+        }
+        // closes the socket on every error below
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let enable: c_int = 1;
+        let err = unsafe {
+            setsockopt(
+                fd.as_raw_fd(),
+                SOL_CAN_RAW,
+                CAN_RAW_FD_FRAMES,
+                &enable as *const _ as *const c_void,
+                size_of::<c_int>() as u32,
+            )
+        };
+        if err == -1 {
+            return Err(Error::from(io::Error::last_os_error()));
+        }
+
+        let addr = RawAddr {
+            _af_can: AF_CAN,
+            if_index,
+            _rx_id: 0,
+            _tx_id: 0,
+            _pgn: 0,
+            _addr: 0,
+        };
+        let bind_rv = unsafe {
+            bind(
+                fd.as_raw_fd(),
+                &addr as *const RawAddr as *const sockaddr,
+                size_of::<RawAddr>() as u32,
+            )
+        };
+        if bind_rv == -1 {
+            return Err(Error::from(io::Error::last_os_error()));
+        }
+
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        if flags == -1
+            || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } != 0
+        {
+            return Err(Error::from(io::Error::last_os_error()));
+        }
+        // SAFETY: the OwnedFd is moved into the AsyncFd, nothing else closes or
+        // replaces the descriptor while it is registered.
+        let inner = unsafe { AsyncFd::register(fd) }.map_err(io::Error::from)?;
+        Ok(Self { inner })
+    }
+
+    /// Send one frame, CAN FD if the data is longer than 8 bytes
+    pub async fn write_frame(&self, id: Id, data: &[u8]) -> io::Result<()> {
+        let mut frame = RawFrame {
+            can_id: id_to_raw(id),
+            len: data.len().min(64) as u8,
+            flags: 0,
+            _res0: 0,
+            _res1: 0,
+            data: [0; 64],
+        };
+        frame.data[..usize::from(frame.len)].copy_from_slice(&data[..usize::from(frame.len)]);
+        let size = if data.len() > CAN_MAX_DLEN as usize {
+            CANFD_MTU
+        } else {
+            CAN_MTU
+        };
+        loop {
+            let mut guard = self.inner.writable().await?;
+            let result = guard.try_io(|inner| {
+                let rv = unsafe {
+                    write(
+                        inner.as_raw_fd(),
+                        &frame as *const RawFrame as *const c_void,
+                        size,
+                    )
+                };
+                if rv < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+            if let Ok(result) = result {
+                return result;
+            }
+        }
+    }
+
+    /// Receive the next frame
+    pub async fn read_frame(&self) -> io::Result<CanFrame> {
+        loop {
+            let mut guard = self.inner.readable().await?;
+            let result = guard.try_io(|inner| {
+                let mut frame = RawFrame {
+                    can_id: 0,
+                    len: 0,
+                    flags: 0,
+                    _res0: 0,
+                    _res1: 0,
+                    data: [0; 64],
+                };
+                let rv = unsafe {
+                    read(
+                        inner.as_raw_fd(),
+                        &mut frame as *mut RawFrame as *mut c_void,
+                        CANFD_MTU,
+                    )
+                };
+                if rv < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let max = if rv as usize == CANFD_MTU {
+                    64
+                } else {
+                    CAN_MAX_DLEN as usize
+                };
+                let len = usize::from(frame.len).min(max);
+                let mut can_frame = CanFrame::new(
+                    SystemTime::now(),
+                    id_from_raw(frame.can_id),
+                    frame.data[..len].to_vec(),
+                );
+                can_frame.fd = rv as usize == CANFD_MTU;
+                Ok(can_frame)
+            });
+            if let Ok(result) = result {
+                return result;
+            }
+        }
+    }
+}
+
+impl FrameTransport for RawCanSocket {
+    fn send_frame<'a>(
+        &'a self,
+        id: Id,
+        data: &'a [u8],
+    ) -> impl Future<Output = io::Result<()>> + Send + 'a {
+        self.write_frame(id, data)
+    }
+
+    fn recv_frame(&self) -> impl Future<Output = io::Result<CanFrame>> + Send + '_ {
+        self.read_frame()
+    }
+}
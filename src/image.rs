@@ -0,0 +1,442 @@
+//! Segmented memory images
+//!
+//! A [MemoryImage] holds the data to be written to (or read from) an ECU as a sorted
+//! list of non-overlapping [Segment]s, e.g. the sections of a flash container.
+//!
+//! Images are loaded from Intel HEX, Motorola S-record and raw binary files and can
+//! be passed to a [Download](crate::uds::flash::Download) as they are:
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::image::MemoryImage;
+//!
+//! let mut image = MemoryImage::load("application.hex")?;
+//! // one download sequence for sections up to 256 bytes apart
+//! image.fill_gaps(0xFF, 256);
+//! for segment in image.segments() {
+//!     println!("0x{:08X} {} bytes, CRC 0x{:08X}", segment.address, segment.len(), segment.crc32());
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::text::decode_hex;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Contiguous block of memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u64,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    pub fn new(address: u64, data: Vec<u8>) -> Self {
+        Self { address, data }
+    }
+
+    /// Address following the last byte of the segment
+    pub fn end(&self) -> u64 {
+        self.address + self.data.len() as u64
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// CRC-32 (IEEE 802.3) of the data
+    pub fn crc32(&self) -> u32 {
+        crc32(&self.data)
+    }
+}
+
+/// Memory contents as sorted, non-overlapping segments
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryImage {
+    segments: Vec<Segment>,
+}
+
+impl MemoryImage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Image of a single segment
+    pub fn from_data(address: u64, data: Vec<u8>) -> Self {
+        let mut image = Self::new();
+        if !data.is_empty() {
+            image.segments.push(Segment::new(address, data));
+        }
+        image
+    }
+
+    /// Add data at `address`, merging it with adjacent segments
+    ///
+    /// Data overlapping with the image is rejected with [io::ErrorKind::InvalidData].
+    pub fn insert(&mut self, address: u64, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let end = address
+            .checked_add(data.len() as u64)
+            .ok_or_else(|| invalid_data(format!("data at 0x{address:X} exceeds 64 bit")))?;
+        let index = self
+            .segments
+            .partition_point(|segment| segment.address < address);
+        let overlaps_previous = index > 0 && self.segments[index - 1].end() > address;
+        let overlaps_next = self
+            .segments
+            .get(index)
+            .is_some_and(|segment| segment.address < end);
+        if overlaps_previous || overlaps_next {
+            return Err(invalid_data(format!(
+                "data at 0x{address:X}..0x{end:X} overlaps the image"
+            )));
+        }
+
+        let index = if index > 0 && self.segments[index - 1].end() == address {
+            self.segments[index - 1].data.extend_from_slice(data);
+            index - 1
+        } else {
+            self.segments
+                .insert(index, Segment::new(address, data.to_vec()));
+            index
+        };
+        if self
+            .segments
+            .get(index + 1)
+            .is_some_and(|next| next.address == end)
+        {
+            let next = self.segments.remove(index + 1);
+            self.segments[index].data.extend(next.data);
+        }
+        Ok(())
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn into_segments(self) -> Vec<Segment> {
+        self.segments
+    }
+
+    /// Total number of bytes over all segments
+    pub fn len(&self) -> u64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.len() as u64)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Merge segments at most `max_gap` bytes apart, filling the gaps with `fill`
+    pub fn fill_gaps(&mut self, fill: u8, max_gap: u64) {
+        let mut merged: Vec<Segment> = Vec::with_capacity(self.segments.len());
+        for segment in self.segments.drain(..) {
+            match merged.last_mut() {
+                Some(last) if segment.address - last.end() <= max_gap => {
+                    let gap = (segment.address - last.end()) as usize;
+                    last.data.resize(last.data.len() + gap, fill);
+                    last.data.extend(segment.data);
+                }
+                _ => merged.push(segment),
+            }
+        }
+        self.segments = merged;
+    }
+
+    /// Load an image file, the format is chosen by the extension
+    ///
+    /// `.hex`/`.ihex` are read as Intel HEX, `.s19`/`.s28`/`.s37`/`.srec`/`.mot` as
+    /// Motorola S-record and everything else as raw binary at address 0.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        let file = File::open(path)?;
+        match extension.as_deref() {
+            Some("hex" | "ihex" | "ihx") => read_intel_hex(file),
+            Some("s19" | "s28" | "s37" | "srec" | "mot") => read_srec(file),
+            _ => read_binary(file, 0),
+        }
+    }
+}
+
+/// Read an Intel HEX file
+///
+/// Extended segment (02) and extended linear (04) address records are applied, start
+/// address records are ignored. With segment addressing the offset wraps around within
+/// the 64 KiB segment, as specified for the 8086.
+pub fn read_intel_hex(reader: impl Read) -> io::Result<MemoryImage> {
+    let mut image = MemoryImage::new();
+    let mut base = 0u64;
+    let mut segmented = false;
+    for (number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: String| invalid_data(format!("line {}: {message}", number + 1));
+        let record = line
+            .strip_prefix(':')
+            .and_then(|record| decode_hex(record).ok())
+            .ok_or_else(|| error("invalid record".to_string()))?;
+        let [length, address_high, address_low, record_type, ..] = record[..] else {
+            return Err(error("record too short".to_string()));
+        };
+        if record.len() != usize::from(length) + 5 {
+            return Err(error(format!("length {length} does not match the record")));
+        }
+        if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("checksum mismatch".to_string()));
+        }
+        let data = &record[4..record.len() - 1];
+        let address = u64::from(u16::from_be_bytes([address_high, address_low]));
+        match record_type {
+            0x00 => {
+                let (data, wrapped) = if segmented {
+                    data.split_at(data.len().min((0x10000 - address) as usize))
+                } else {
+                    (data, &[][..])
+                };
+                image
+                    .insert(base + address, data)
+                    .and_then(|()| image.insert(base, wrapped))
+                    .map_err(|err| error(err.to_string()))?
+            }
+            0x01 => break,
+            0x02 | 0x04 => {
+                let [high, low] = data[..] else {
+                    return Err(error("invalid extended address".to_string()));
+                };
+                segmented = record_type == 0x02;
+                let shift = if segmented { 4 } else { 16 };
+                base = u64::from(u16::from_be_bytes([high, low])) << shift;
+            }
+            0x03 | 0x05 => {}
+            other => return Err(error(format!("unknown record type 0x{other:02X}"))),
+        }
+    }
+    Ok(image)
+}
+
+/// Read a Motorola S-record file
+///
+/// Data records S1, S2 and S3 are loaded, header, count and termination records are
+/// only checked for their checksum.
+pub fn read_srec(reader: impl Read) -> io::Result<MemoryImage> {
+    let mut image = MemoryImage::new();
+    for (number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: String| invalid_data(format!("line {}: {message}", number + 1));
+        let (record_type, rest) = line
+            .strip_prefix('S')
+            .and_then(|rest| rest.split_at_checked(1))
+            .ok_or_else(|| error("invalid record".to_string()))?;
+        let record = decode_hex(rest).map_err(|_| error("invalid record".to_string()))?;
+        let Some((&count, _)) = record.split_first() else {
+            return Err(error("record too short".to_string()));
+        };
+        if record.len() != usize::from(count) + 1 {
+            return Err(error(format!("count {count} does not match the record")));
+        }
+        if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0xFF {
+            return Err(error("checksum mismatch".to_string()));
+        }
+        let address_bytes = match record_type {
+            "1" => 2,
+            "2" => 3,
+            "3" => 4,
+            "0" | "5" | "6" | "7" | "8" | "9" => continue,
+            other => return Err(error(format!("unknown record type S{other}"))),
+        };
+        let fields = &record[1..record.len() - 1];
+        if fields.len() < address_bytes {
+            return Err(error("record too short".to_string()));
+        }
+        let (address, data) = fields.split_at(address_bytes);
+        let address = address
+            .iter()
+            .fold(0u64, |address, byte| (address << 8) | u64::from(*byte));
+        image
+            .insert(address, data)
+            .map_err(|err| error(err.to_string()))?;
+    }
+    Ok(image)
+}
+
+/// Read a raw binary as a single segment starting at `address`
+pub fn read_binary(mut reader: impl Read, address: u64) -> io::Result<MemoryImage> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(MemoryImage::from_data(address, data))
+}
+
+/// CRC-32 with the polynomial 0x04C11DB7 as used by zlib and Ethernet
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ u32::from(*byte), |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTEL_HEX: &str = "\
+:10010000214601360121470136007EFE09D2190140
+:100110002146017E17C20001FF5F16002148011928
+:10012000194E79234623965778239EDA3F01B2CAA7
+:100130003F0156702B5E712B722B732146013421C7
+:00000001FF
+";
+
+    const SREC: &str = "\
+S00F000068656C6C6F202020202000003C
+S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026
+S11F001C4BFFFFE5398000007D83637880010014382100107C0803A64E800020E9
+S111003848656C6C6F20776F726C642E0A0042
+S5030003F9
+S9030000FC
+";
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn intel_hex() {
+        let image = read_intel_hex(INTEL_HEX.as_bytes()).unwrap();
+        let [segment] = image.segments() else {
+            panic!("{image:?}");
+        };
+        assert_eq!(segment.address, 0x100);
+        assert_eq!(segment.len(), 64);
+        assert_eq!(segment.data[..4], [0x21, 0x46, 0x01, 0x36]);
+        assert_eq!(segment.crc32(), 0x506E_38F1);
+    }
+
+    #[test]
+    fn intel_hex_extended_addresses() {
+        let hex = "\
+:020000021000EC
+:04FFFE0001020304F5
+:020000040800F2
+:020010000909DC
+:0400000508000000EF
+:00000001FF
+:020020000909CC
+";
+        let image = read_intel_hex(hex.as_bytes()).unwrap();
+        assert_eq!(
+            image.segments(),
+            [
+                // the segment offset wraps around at 64 KiB
+                Segment::new(0x1_0000, vec![3, 4]),
+                Segment::new(0x1_FFFE, vec![1, 2]),
+                Segment::new(0x0800_0010, vec![9, 9]),
+            ]
+        );
+    }
+
+    #[test]
+    fn intel_hex_errors() {
+        for hex in [
+            "10010000214601360121470136007EFE09D2190140",
+            ":10010000214601360121470136007EFE09D2190141",
+            ":10010000214601360121470136007EFE09D21901",
+            ":0100",
+            ":00000006FA",
+            ":0100000202FB",
+            ":020000000102FB\n:020001000102FA",
+        ] {
+            let error = read_intel_hex(hex.as_bytes()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{hex}");
+        }
+    }
+
+    #[test]
+    fn srec() {
+        let image = read_srec(SREC.as_bytes()).unwrap();
+        let [segment] = image.segments() else {
+            panic!("{image:?}");
+        };
+        assert_eq!(segment.address, 0);
+        assert_eq!(segment.len(), 70);
+        assert_eq!(segment.data[0x38..], *b"Hello world.\n\0");
+        assert_eq!(segment.crc32(), 0xE927_C9E2);
+
+        let image =
+            read_srec("S20612345601025A\nS308800000000304056B\nS705800000007A\n".as_bytes())
+                .unwrap();
+        assert_eq!(
+            image.segments(),
+            [
+                Segment::new(0x12_3456, vec![1, 2]),
+                Segment::new(0x8000_0000, vec![3, 4, 5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn srec_errors() {
+        for srec in [
+            "11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026",
+            "S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000027",
+            "S1050000",
+            "S4030003F9",
+            "S",
+            "S\u{e9}",
+            "S1020000FD",
+        ] {
+            let error = read_srec(srec.as_bytes()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{srec}");
+        }
+    }
+
+    #[test]
+    fn insert_and_fill_gaps() {
+        let mut image = MemoryImage::new();
+        image.insert(0x10, &[1, 2]).unwrap();
+        image.insert(0x20, &[5]).unwrap();
+        image.insert(0x12, &[3, 4]).unwrap();
+        assert!(image.insert(0x13, &[0]).is_err());
+        assert!(image.insert(u64::MAX, &[0, 0]).is_err());
+        assert_eq!(image.segments().len(), 2);
+        assert_eq!(image.len(), 5);
+
+        image.fill_gaps(0xFF, 0x0B);
+        assert_eq!(image.segments().len(), 2);
+        image.fill_gaps(0xFF, 0x0C);
+        assert_eq!(
+            image.segments(),
+            [Segment::new(
+                0x10,
+                [&[1, 2, 3, 4][..], &[0xFF; 12], &[5]].concat()
+            )]
+        );
+    }
+}
@@ -12,7 +12,7 @@
 //!
 //! Example of basic echoing server on vcan0:
 //!
-//! ```rust
+//! ```rust,no_run
 //! use tokio_socketcan_isotp::{IsoTpSocket, StandardId, Error};
 //!
 //! #[tokio::main]
@@ -23,10 +23,11 @@
 //!        StandardId::new(0x321).expect("Invalid src id")
 //!            )?;
 //!            
-//!     while let Ok(packet) = socket.read_packet()?.await {
+//!     while let Ok(packet) = socket.read_packet().await {
 //!         println!("{:?}", packet);
-//!         let rx = socket.write_packet(packet)?.await;
+//!         let rx = socket.write_packet(&packet).await;
 //!     }
+//!     Ok(())
 //! }
 //! ```
 //!
@@ -37,16 +38,63 @@
 //! sudo ip link add dev vcan0 type vcan
 //! sudo ip link set up vcan0
 //! ```
+//!
+//! Higher layers are written against the [IsoTpTransport] trait, which is implemented by
+//! [IsoTpSocket] as well as by the offline transports. Conversations of other nodes
+//! are observed with the listen-mode sockets of [sniffer].
+//!
+//! Optional cargo features:
+//!
+//! * `trace` - recording of PDUs to JSON Lines or binary traces and their replay, see [trace]
+//! * `pcap` - pcap/pcapng export and import of CAN frames for Wireshark, see [pcap]
+//! * `logs` - `candump -l` and Vector ASC import and export, see [candump] and [asc]
+//! * `uds` - UDS (ISO 14229-1) client, see [uds]
+//! * `seed-key-library` - SecurityAccess key algorithms from shared libraries
+//! * `did` - DID registry with typed codecs, see `uds::did`
+//! * `scan` - discovery and enumeration of ECUs, see `uds::scan` and `uds::enumerate`
+//! * `odx` - import of ODX/PDX diagnostic descriptions, see `odx`
+//! * `functional` - functional requests collecting the responses of many ECUs, see `functional`
+//! * `obd` - OBD-II client for emission related ECUs, see `obd`
+//! * `fuzz` - fuzzing of UDS requests and ISO-TP segmentation, see `fuzz`
+//! * `server` - UDS server for ECU simulators, see `uds::server`
+//! * `ecu-sim` - `isotp-ecu-sim` binary simulating ECUs described in TOML or YAML, see `uds::sim`
+//! * `cli` - `tokio-isotp` binary sending, receiving and dumping PDUs from the shell
+//! * `repl` - `isotp-repl` binary, interactive UDS shell for bench work
+//! * `monitor` - `isotp-monitor` binary, terminal UI of the ISO-TP conversations built on [sniffer]
 
+#[cfg(feature = "logs")]
+pub mod asc;
+#[cfg(feature = "logs")]
+pub mod candump;
+pub mod frame;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+#[cfg(feature = "functional")]
+pub mod functional;
+pub mod image;
+#[cfg(feature = "obd")]
+pub mod obd;
+#[cfg(feature = "odx")]
+pub mod odx;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+pub mod sniffer;
 mod socketcan_isotp;
+pub mod text;
+#[cfg(feature = "trace")]
+pub mod trace;
+mod transport;
+#[cfg(feature = "uds")]
+pub mod uds;
 
 pub use crate::socketcan_isotp::{
-    Error, ExtendedId, FlowControlOptions, Id, IsoTpBehaviour, IsoTpOptions, LinkLayerOptions,
-    StandardId, TxFlags, AF_CAN, CAN_ISOTP, CAN_ISOTP_LL_OPTS, CAN_ISOTP_OPTS, CAN_ISOTP_RECV_FC,
-    CAN_ISOTP_RX_STMIN, CAN_ISOTP_TX_STMIN, CAN_MAX_DLEN, EFF_FLAG, EFF_MASK, ERR_FLAG, ERR_MASK,
-    ERR_MASK_ALL, ERR_MASK_NONE, PF_CAN, RECV_BUFFER_SIZE, RTR_FLAG, SFF_MASK, SOL_CAN_BASE,
-    SOL_CAN_ISOTP,
+    id_from_raw, id_to_raw, Error, ExtendedId, FlowControlOptions, Id, IsoTpBehaviour,
+    IsoTpOptions, LinkLayerOptions, StandardId, TxFlags, AF_CAN, CAN_ISOTP, CAN_ISOTP_LL_OPTS,
+    CAN_ISOTP_OPTS, CAN_ISOTP_RECV_FC, CAN_ISOTP_RX_STMIN, CAN_ISOTP_TX_STMIN, CAN_MAX_DLEN, CANFD_MTU,
+    EFF_FLAG, EFF_MASK, ERR_FLAG, ERR_MASK, ERR_MASK_ALL, ERR_MASK_NONE, PF_CAN, RECV_BUFFER_SIZE,
+    RTR_FLAG, SFF_MASK, SOL_CAN_BASE, SOL_CAN_ISOTP, StMinOptions,
 };
+pub use crate::transport::IsoTpTransport;
 use futures::prelude::*;
 use futures::ready;
 use std::io;
@@ -66,8 +114,8 @@ impl Future for IsoTpWriteFuture<'_> {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         loop {
-            let mut guard = ready!(self.socket.0.poll_write_ready(cx))?;
-            match self.socket.0.get_ref().write(self.packet) {
+            let mut guard = ready!(self.socket.inner.poll_write_ready(cx))?;
+            match self.socket.inner.get_ref().write(self.packet) {
                 Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
                     guard.clear_ready(); // Comment this line if you are on older Kernel and the communication soft-locks
                     continue;
@@ -89,7 +137,7 @@ impl Future for IsoTpReadFuture<'_> {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         loop {
-            let mut ready_guard = ready!(self.socket.0.poll_read_ready(cx))?;
+            let mut ready_guard = ready!(self.socket.inner.poll_read_ready(cx))?;
             match ready_guard.try_io(|inner| inner.get_ref().read_to_vec()) {
                 Ok(result) => return Poll::Ready(result),
                 Err(_would_block) => continue,
@@ -100,7 +148,12 @@ impl Future for IsoTpReadFuture<'_> {
 
 /// An asynchronous I/O wrapped socketcan_isotp::IsoTpSocket
 /// For reading and writting to the socket use [IsoTpSocket::read_packet] and [IsoTpSocket::write_packet] respectively.
-pub struct IsoTpSocket(AsyncFd<socketcan_isotp::IsoTpSocket>);
+pub struct IsoTpSocket {
+    inner: AsyncFd<socketcan_isotp::IsoTpSocket>,
+    rx_id: Id,
+    tx_id: Id,
+    max_pdu_size: usize,
+}
 #[allow(dead_code)]
 impl IsoTpSocket {
     /// Open a named CAN device such as "vcan0"
@@ -109,9 +162,9 @@ impl IsoTpSocket {
         src: impl Into<Id>,
         dst: impl Into<Id>,
     ) -> Result<IsoTpSocket, socketcan_isotp::Error> {
+        let (src, dst) = (src.into(), dst.into());
         let sock = socketcan_isotp::IsoTpSocket::open(ifname, src, dst)?;
-        sock.set_nonblocking(true)?;
-        Ok(IsoTpSocket(AsyncFd::new(sock)?))
+        IsoTpSocket::from_sync(sock, src, dst)
     }
 
     pub fn open_with_opts(
@@ -122,6 +175,7 @@ impl IsoTpSocket {
         rx_flow_control_options: Option<FlowControlOptions>,
         link_layer_options: Option<LinkLayerOptions>,
     ) -> Result<IsoTpSocket, socketcan_isotp::Error> {
+        let (src, dst) = (src.into(), dst.into());
         let sock = socketcan_isotp::IsoTpSocket::open_with_opts(
             ifname,
             src,
@@ -130,8 +184,32 @@ impl IsoTpSocket {
             rx_flow_control_options,
             link_layer_options,
         )?;
-        sock.set_nonblocking(true)?;
-        Ok(IsoTpSocket(AsyncFd::new(sock)?))
+        IsoTpSocket::from_sync(sock, src, dst)
+    }
+
+    /// Open a named CAN device with separation times forced by the
+    /// [IsoTpBehaviour::CAN_ISOTP_FORCE_TXSTMIN] and
+    /// [IsoTpBehaviour::CAN_ISOTP_FORCE_RXSTMIN] flags
+    pub fn open_with_stmin(
+        ifname: &str,
+        src: impl Into<Id>,
+        dst: impl Into<Id>,
+        isotp_options: Option<IsoTpOptions>,
+        rx_flow_control_options: Option<FlowControlOptions>,
+        link_layer_options: Option<LinkLayerOptions>,
+        stmin_options: StMinOptions,
+    ) -> Result<IsoTpSocket, socketcan_isotp::Error> {
+        let (src, dst) = (src.into(), dst.into());
+        let sock = socketcan_isotp::IsoTpSocket::open_with_stmin(
+            ifname,
+            src,
+            dst,
+            isotp_options,
+            rx_flow_control_options,
+            link_layer_options,
+            stmin_options,
+        )?;
+        IsoTpSocket::from_sync(sock, src, dst)
     }
 
     /// Open by kernel interface number
@@ -140,9 +218,9 @@ impl IsoTpSocket {
         src: impl Into<Id>,
         dst: impl Into<Id>,
     ) -> Result<IsoTpSocket, socketcan_isotp::Error> {
+        let (src, dst) = (src.into(), dst.into());
         let sock = socketcan_isotp::IsoTpSocket::open_if(if_index, src, dst)?;
-        sock.set_nonblocking(true)?;
-        Ok(IsoTpSocket(AsyncFd::new(sock)?))
+        IsoTpSocket::from_sync(sock, src, dst)
     }
 
     pub fn open_if_with_opts(
@@ -153,6 +231,7 @@ impl IsoTpSocket {
         rx_flow_control_options: Option<FlowControlOptions>,
         link_layer_options: Option<LinkLayerOptions>,
     ) -> Result<IsoTpSocket, socketcan_isotp::Error> {
+        let (src, dst) = (src.into(), dst.into());
         let sock = socketcan_isotp::IsoTpSocket::open_if_with_opts(
             if_index,
             src,
@@ -161,18 +240,58 @@ impl IsoTpSocket {
             rx_flow_control_options,
             link_layer_options,
         )?;
+        IsoTpSocket::from_sync(sock, src, dst)
+    }
+
+    /// Register an opened blocking socket with the tokio reactor
+    fn from_sync(
+        sock: socketcan_isotp::IsoTpSocket,
+        rx_id: Id,
+        tx_id: Id,
+    ) -> Result<IsoTpSocket, socketcan_isotp::Error> {
         sock.set_nonblocking(true)?;
-        Ok(IsoTpSocket(AsyncFd::new(sock)?))
+        // SAFETY: the socket owns its file descriptor and is moved into the AsyncFd,
+        // the descriptor stays open and unchanged while it is registered.
+        let inner = unsafe { AsyncFd::register(sock) }.map_err(io::Error::from)?;
+        Ok(IsoTpSocket {
+            inner,
+            rx_id,
+            tx_id,
+            max_pdu_size: RECV_BUFFER_SIZE,
+        })
+    }
+
+    /// Limit the PDU size the layers above may use, e.g. to the buffer size of the peer
+    ///
+    /// The limit is capped at [RECV_BUFFER_SIZE], larger PDUs cannot be received.
+    pub fn with_max_pdu_size(mut self, max_pdu_size: usize) -> Self {
+        self.max_pdu_size = max_pdu_size.min(RECV_BUFFER_SIZE);
+        self
+    }
+
+    /// Largest PDU the layers above may send or expect, see [IsoTpSocket::with_max_pdu_size]
+    pub fn max_pdu_size(&self) -> usize {
+        self.max_pdu_size
+    }
+
+    /// CAN id this socket receives on
+    pub fn rx_id(&self) -> Id {
+        self.rx_id
+    }
+
+    /// CAN id this socket transmits on
+    pub fn tx_id(&self) -> Id {
+        self.tx_id
     }
 
-    pub fn write_packet<'a>(&'a self, packet: &'a [u8]) -> IsoTpWriteFuture {
+    pub fn write_packet<'a>(&'a self, packet: &'a [u8]) -> IsoTpWriteFuture<'a> {
         IsoTpWriteFuture {
             socket: self,
             packet,
         }
     }
 
-    pub fn read_packet(&self) -> IsoTpReadFuture {
+    pub fn read_packet(&self) -> IsoTpReadFuture<'_> {
         IsoTpReadFuture { socket: self }
     }
 }
@@ -0,0 +1,603 @@
+use super::dtc::{parse_dtcs, Dtc};
+use super::pid::{decode_pid, PidValue};
+use super::{
+    Error, CLEAR_DTCS, CURRENT_DATA, FREEZE_FRAME_DATA, NEGATIVE_RESPONSE, PENDING_DTCS,
+    PERMANENT_DTCS, POSITIVE_RESPONSE_OFFSET, RESPONSE_PENDING, STORED_DTCS, VEHICLE_INFORMATION,
+};
+use crate::functional::FunctionalRequest;
+use crate::{
+    ExtendedId, Id, IsoTpBehaviour, IsoTpOptions, IsoTpSocket, IsoTpTransport, StandardId,
+};
+use futures::future::join_all;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// InfoType of the vehicle identification number
+const INFO_VIN: u8 = 0x02;
+/// InfoType of the calibration identifications
+const INFO_CALID: u8 = 0x04;
+/// InfoType of the calibration verification numbers
+const INFO_CVN: u8 = 0x06;
+/// InfoType of the ECU name
+const INFO_ECU_NAME: u8 = 0x0A;
+
+/// Largest request fitting into a single frame
+const MAX_FUNCTIONAL_REQUEST: usize = 7;
+
+/// Result of a request for every responding ECU, identified by its response id
+pub type Responses<V> = Vec<(Id, Result<V, Error>)>;
+
+/// CAN identifiers of ISO 15765-4
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObdAddressing {
+    /// 11-bit ids, functional 0x7DF, ECU n requested on 0x7E0 + n and responding on 0x7E8 + n
+    Standard,
+    /// 29-bit ids, functional 0x18DB33F1, ECU xx requested on 0x18DAxxF1 and responding on 0x18DAF1xx
+    Extended,
+}
+
+impl ObdAddressing {
+    /// Address of the external test equipment in 29-bit ids
+    pub const TESTER_ADDRESS: u8 = 0xF1;
+
+    pub fn functional_id(self) -> Id {
+        match self {
+            ObdAddressing::Standard => StandardId::new(0x7DF).unwrap().into(),
+            ObdAddressing::Extended => ExtendedId::new(0x18DB_33F1).unwrap().into(),
+        }
+    }
+
+    /// Physical request id of an ECU, `ecu` is 0–7 with 11-bit and the ECU address with 29-bit ids
+    pub fn request_id(self, ecu: u8) -> Id {
+        match self {
+            ObdAddressing::Standard => StandardId::new(0x7E0 + u16::from(ecu & 0x07))
+                .unwrap()
+                .into(),
+            ObdAddressing::Extended => {
+                ExtendedId::new(0x18DA_0000 | u32::from(ecu) << 8 | u32::from(Self::TESTER_ADDRESS))
+                    .unwrap()
+                    .into()
+            }
+        }
+    }
+
+    /// Response id of an ECU, `ecu` is 0–7 with 11-bit and the ECU address with 29-bit ids
+    pub fn response_id(self, ecu: u8) -> Id {
+        match self {
+            ObdAddressing::Standard => StandardId::new(0x7E8 + u16::from(ecu & 0x07))
+                .unwrap()
+                .into(),
+            ObdAddressing::Extended => {
+                ExtendedId::new(0x18DA_0000 | u32::from(Self::TESTER_ADDRESS) << 8 | u32::from(ecu))
+                    .unwrap()
+                    .into()
+            }
+        }
+    }
+
+    /// All ECUs allowed to respond, every address but the tester's with 29-bit ids
+    pub fn ecus(self) -> Vec<u8> {
+        match self {
+            ObdAddressing::Standard => (0..8).collect(),
+            ObdAddressing::Extended => (0..=0xFF)
+                .filter(|ecu| *ecu != Self::TESTER_ADDRESS)
+                .collect(),
+        }
+    }
+}
+
+/// Response timing of an [ObdClient]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObdConfig {
+    /// Time to wait for the complete responses, P2 of ISO 15765-4 (50 ms) plus the time
+    /// to receive multi-frame responses
+    pub timeout: Duration,
+    /// Time to wait after a response pending (NRC 0x78), P2* of ISO 15765-4
+    pub pending_timeout: Duration,
+    /// Maximum number of response pending (NRC 0x78) responses of one ECU
+    pub max_response_pending: usize,
+}
+
+impl Default for ObdConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(200),
+            pending_timeout: Duration::from_millis(5000),
+            max_response_pending: 50,
+        }
+    }
+}
+
+/// OBD client sending functional requests and collecting the responses of all ECUs
+pub struct ObdClient<T = IsoTpSocket> {
+    functional: FunctionalRequest<T>,
+    config: ObdConfig,
+    /// Held for the duration of a request and the collection of its responses
+    exchange: tokio::sync::Mutex<()>,
+}
+
+impl ObdClient<IsoTpSocket> {
+    /// Open the functional socket and the sockets of all ECUs of the addressing
+    ///
+    /// With [ObdAddressing::Extended] every address but the tester's may respond, so 255
+    /// response sockets are opened. When the ECUs are known, [ObdClient::open_ecus]
+    /// opens only theirs, [ObdClient::detect] finds them.
+    pub fn open(ifname: &str, addressing: ObdAddressing) -> Result<Self, crate::Error> {
+        Self::open_ecus(ifname, addressing, addressing.ecus())
+    }
+
+    /// Open the functional socket and the sockets of the given ECUs
+    pub fn open_ecus(
+        ifname: &str,
+        addressing: ObdAddressing,
+        ecus: impl IntoIterator<Item = u8>,
+    ) -> Result<Self, crate::Error> {
+        let responders: Vec<(Id, Id)> = ecus
+            .into_iter()
+            .map(|ecu| (addressing.response_id(ecu), addressing.request_id(ecu)))
+            .collect();
+        // ISO 15765-4 requires all frames to be 8 bytes long
+        let mut options = IsoTpOptions::default();
+        options.set_flags(IsoTpBehaviour::CAN_ISOTP_TX_PADDING);
+        let functional = FunctionalRequest::open_with_opts(
+            ifname,
+            addressing.functional_id(),
+            &responders,
+            Some(options),
+            None,
+        )?;
+        Ok(Self::from_functional(functional))
+    }
+}
+
+impl<T: IsoTpTransport> ObdClient<T> {
+    /// Client sending on `functional` and receiving on the `responders`
+    pub fn new(functional: T, responders: Vec<T>) -> Self {
+        Self::with_config(functional, responders, ObdConfig::default())
+    }
+
+    pub fn with_config(functional: T, responders: Vec<T>, config: ObdConfig) -> Self {
+        Self {
+            functional: FunctionalRequest::new(functional, responders),
+            config,
+            exchange: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn from_functional(functional: FunctionalRequest<T>) -> Self {
+        Self {
+            functional,
+            config: ObdConfig::default(),
+            exchange: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn config(&self) -> ObdConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: ObdConfig) {
+        self.config = config;
+    }
+
+    pub fn functional(&self) -> &FunctionalRequest<T> {
+        &self.functional
+    }
+
+    /// Send a raw functional request and collect the responses of all ECUs
+    ///
+    /// A positive response is returned whole, including the response mode byte.
+    pub async fn request(&self, request: &[u8]) -> Result<Responses<Vec<u8>>, Error> {
+        if request.is_empty() || request.len() > MAX_FUNCTIONAL_REQUEST {
+            return Err(Error::invalid_request(format!(
+                "functional requests take 1 to {MAX_FUNCTIONAL_REQUEST} bytes, not {}",
+                request.len()
+            )));
+        }
+        let _exchange = self.exchange.lock().await;
+        self.functional.sender().send(request).await?;
+        let responders = self.functional.responders();
+        let responses = join_all(
+            responders
+                .iter()
+                .map(|responder| self.receive(responder, request)),
+        )
+        .await;
+        Ok(responders
+            .iter()
+            .zip(responses)
+            .filter_map(|(responder, response)| Some((responder.rx_id(), response?)))
+            .collect())
+    }
+
+    /// Response of one ECU, `None` if it stays silent
+    async fn receive(&self, responder: &T, request: &[u8]) -> Option<Result<Vec<u8>, Error>> {
+        let mode = request[0];
+        let mut deadline = Instant::now() + self.config.timeout;
+        let mut pending = 0;
+        loop {
+            let response = match tokio::time::timeout_at(deadline, responder.recv()).await {
+                Err(_elapsed) => return None,
+                Ok(Err(error)) => return Some(Err(error.into())),
+                Ok(Ok(response)) => response,
+            };
+            match response[..] {
+                [NEGATIVE_RESPONSE, service, RESPONSE_PENDING, ..] if service == mode => {
+                    pending += 1;
+                    if pending > self.config.max_response_pending {
+                        return Some(Err(Error::ResponsePendingLimit { mode }));
+                    }
+                    deadline = Instant::now() + self.config.pending_timeout;
+                }
+                [NEGATIVE_RESPONSE, service, code, ..] if service == mode => {
+                    return Some(Err(Error::NegativeResponse { mode, code }));
+                }
+                // the PID or InfoType is echoed behind the mode
+                [first, ..]
+                    if first == mode.wrapping_add(POSITIVE_RESPONSE_OFFSET)
+                        && (request.len() < 2 || response.get(1) == request.get(1)) =>
+                {
+                    return Some(Ok(response));
+                }
+                // late response to an earlier request
+                _ => {}
+            }
+        }
+    }
+
+    /// Mode 01, current value of a PID
+    pub async fn current_data(&self, pid: u8) -> Result<Responses<PidValue>, Error> {
+        let responses = self.request(&[CURRENT_DATA, pid]).await?;
+        Ok(map(responses, |response| decode_pid(pid, &response[2..])))
+    }
+
+    /// Mode 01, PIDs supported by each ECU, queried in ranges of 0x20 PIDs
+    pub async fn supported_pids(&self) -> Result<Responses<Vec<u8>>, Error> {
+        let mut supported: Responses<Vec<u8>> = Vec::new();
+        let mut range = 0x00u8;
+        loop {
+            let mut next_range = false;
+            for (ecu, value) in self.current_data(range).await? {
+                let pids = match value {
+                    Ok(PidValue::Supported(pids)) => Ok(pids),
+                    Ok(other) => Err(Error::invalid_response(format!(
+                        "PID 0x{range:02X} decoded as {other:?}"
+                    ))),
+                    Err(error) => Err(error),
+                };
+                let known = supported.iter_mut().find(|(known, _)| *known == ecu);
+                match (known, pids) {
+                    (Some((_, Ok(known))), Ok(pids)) => {
+                        next_range |= pids.contains(&range.wrapping_add(0x20));
+                        known.extend(pids);
+                    }
+                    // an ECU failing a later range keeps the PIDs known so far
+                    (Some(_), _) => {}
+                    (None, pids) => {
+                        if let Ok(pids) = &pids {
+                            next_range |= pids.contains(&range.wrapping_add(0x20));
+                        }
+                        supported.push((ecu, pids));
+                    }
+                }
+            }
+            match range.checked_add(0x20) {
+                Some(next) if next_range => range = next,
+                _ => return Ok(supported),
+            }
+        }
+    }
+
+    /// Mode 02, value of a PID in a freeze frame
+    pub async fn freeze_frame_data(
+        &self,
+        pid: u8,
+        frame: u8,
+    ) -> Result<Responses<PidValue>, Error> {
+        let responses = self.request(&[FREEZE_FRAME_DATA, pid, frame]).await?;
+        Ok(map(responses, |response| match response.get(2) {
+            Some(echo) if *echo == frame => decode_pid(pid, &response[3..]),
+            _ => Err(Error::invalid_response(format!(
+                "freeze frame {frame} not echoed"
+            ))),
+        }))
+    }
+
+    /// Mode 03, emission related DTCs
+    pub async fn stored_dtcs(&self) -> Result<Responses<Vec<Dtc>>, Error> {
+        self.dtcs(STORED_DTCS).await
+    }
+
+    /// Mode 07, DTCs detected during the current or last driving cycle
+    pub async fn pending_dtcs(&self) -> Result<Responses<Vec<Dtc>>, Error> {
+        self.dtcs(PENDING_DTCS).await
+    }
+
+    /// Mode 0A, DTCs only cleared by the ECU itself
+    pub async fn permanent_dtcs(&self) -> Result<Responses<Vec<Dtc>>, Error> {
+        self.dtcs(PERMANENT_DTCS).await
+    }
+
+    async fn dtcs(&self, mode: u8) -> Result<Responses<Vec<Dtc>>, Error> {
+        let responses = self.request(&[mode]).await?;
+        Ok(map(responses, |response| parse_dtcs(&response[1..])))
+    }
+
+    /// Mode 04, clear the emission related diagnostic information
+    pub async fn clear_dtcs(&self) -> Result<Responses<()>, Error> {
+        let responses = self.request(&[CLEAR_DTCS]).await?;
+        Ok(map(responses, |_| Ok(())))
+    }
+
+    /// Mode 09, data of an InfoType following the InfoType byte
+    pub async fn vehicle_information(&self, info_type: u8) -> Result<Responses<Vec<u8>>, Error> {
+        let responses = self.request(&[VEHICLE_INFORMATION, info_type]).await?;
+        Ok(map(responses, |response| Ok(response[2..].to_vec())))
+    }
+
+    /// Mode 09, vehicle identification number
+    pub async fn vin(&self) -> Result<Responses<String>, Error> {
+        let responses = self.data_items(INFO_VIN, 17).await?;
+        Ok(map(responses, |items| {
+            items
+                .into_iter()
+                .next()
+                .ok_or_else(|| Error::invalid_response("no VIN"))
+                .map(|vin| ascii(&vin))
+        }))
+    }
+
+    /// Mode 09, calibration identifications
+    pub async fn calibration_ids(&self) -> Result<Responses<Vec<String>>, Error> {
+        let responses = self.data_items(INFO_CALID, 16).await?;
+        Ok(map(responses, |items| {
+            Ok(items.iter().map(|item| ascii(item)).collect())
+        }))
+    }
+
+    /// Mode 09, calibration verification numbers
+    pub async fn calibration_verification_numbers(&self) -> Result<Responses<Vec<u32>>, Error> {
+        let responses = self.data_items(INFO_CVN, 4).await?;
+        Ok(map(responses, |items| {
+            Ok(items
+                .iter()
+                .map(|item| u32::from_be_bytes(item[..].try_into().unwrap()))
+                .collect())
+        }))
+    }
+
+    /// Mode 09, name of the ECU
+    pub async fn ecu_name(&self) -> Result<Responses<String>, Error> {
+        let responses = self.data_items(INFO_ECU_NAME, 20).await?;
+        Ok(map(responses, |items| {
+            items
+                .into_iter()
+                .next()
+                .ok_or_else(|| Error::invalid_response("no ECU name"))
+                .map(|name| ascii(&name))
+        }))
+    }
+
+    /// Mode 09 data items of a fixed size, preceded by their number
+    async fn data_items(
+        &self,
+        info_type: u8,
+        size: usize,
+    ) -> Result<Responses<Vec<Vec<u8>>>, Error> {
+        let responses = self.vehicle_information(info_type).await?;
+        Ok(map(responses, |data| {
+            let Some((&count, items)) = data.split_first() else {
+                return Err(Error::invalid_response(format!(
+                    "InfoType 0x{info_type:02X} without number of data items"
+                )));
+            };
+            if items.len() != usize::from(count) * size {
+                return Err(Error::invalid_response(format!(
+                    "InfoType 0x{info_type:02X}: {} bytes for {count} items of {size} bytes",
+                    items.len()
+                )));
+            }
+            Ok(items.chunks_exact(size).map(<[u8]>::to_vec).collect())
+        }))
+    }
+}
+
+fn map<V, W>(responses: Responses<V>, decode: impl Fn(V) -> Result<W, Error>) -> Responses<W> {
+    responses
+        .into_iter()
+        .map(|(ecu, result)| (ecu, result.and_then(&decode)))
+        .collect()
+}
+
+/// Text of a data item, padding NUL bytes removed
+fn ascii(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functional::tests::{fake_bus, FakeTransport, Reply};
+    use std::io;
+
+    const VIN: &[u8; 17] = b"WVWZZZ1JZXW000001";
+
+    /// Client of `ecus` ECUs with 11-bit ids answering with the replies of the handler
+    fn client(
+        ecus: u8,
+        handler: impl FnMut(&[u8]) -> Vec<Reply> + Send + 'static,
+    ) -> ObdClient<FakeTransport> {
+        let addressing = ObdAddressing::Standard;
+        let responders: Vec<(Id, Id)> = (0..ecus)
+            .map(|ecu| (addressing.response_id(ecu), addressing.request_id(ecu)))
+            .collect();
+        let functional = fake_bus(addressing.functional_id(), &responders, handler);
+        let mut client = ObdClient::from_functional(functional);
+        client.set_config(ObdConfig {
+            timeout: Duration::from_millis(50),
+            pending_timeout: Duration::from_millis(100),
+            max_response_pending: 2,
+        });
+        client
+    }
+
+    fn ecu(ecu: u8) -> Id {
+        ObdAddressing::Standard.response_id(ecu)
+    }
+
+    #[tokio::test]
+    async fn collects_responses_of_all_ecus() {
+        let client = client(3, |request| match request {
+            [0x01, 0x0D] => vec![
+                Reply::new(1, vec![0x41, 0x0D, 0x28]),
+                Reply::new(0, vec![0x41, 0x0D, 0x32]),
+            ],
+            _ => vec![],
+        });
+        let speeds: Vec<(Id, String)> = client
+            .current_data(0x0D)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(ecu, value)| (ecu, value.unwrap().to_string()))
+            .collect();
+        // the silent ECU 2 is missing
+        assert_eq!(
+            speeds,
+            [
+                (ecu(0), "50 km/h".to_string()),
+                (ecu(1), "40 km/h".to_string())
+            ]
+        );
+        assert_eq!(client.functional().sender().sent(), [[0x01, 0x0D]]);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_request_lengths() {
+        let client = client(1, |_| vec![]);
+        for request in [&[][..], &[0x01; 8]] {
+            let error = client.request(request).await.unwrap_err();
+            assert!(matches!(error, Error::InvalidRequest { .. }), "{error:?}");
+        }
+        assert!(client.functional().sender().sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn negative_and_pending_responses() {
+        let client = client(3, |_| {
+            vec![
+                Reply::new(0, vec![0x7F, 0x01, 0x78]),
+                Reply::new(0, vec![0x41, 0x0D, 0x32]).after(Duration::from_millis(80)),
+                Reply::new(1, vec![0x7F, 0x01, 0x12]),
+                Reply::new(2, vec![0x7F, 0x01, 0x78]),
+                Reply::new(2, vec![0x7F, 0x01, 0x78]),
+                Reply::new(2, vec![0x7F, 0x01, 0x78]),
+            ]
+        });
+        let responses = client.request(&[0x01, 0x0D]).await.unwrap();
+        assert_eq!(responses.len(), 3);
+        // the pending response extends the wait beyond the timeout of 50 ms
+        assert_eq!(responses[0].1.as_ref().unwrap(), &[0x41, 0x0D, 0x32]);
+        assert!(matches!(
+            responses[1].1,
+            Err(Error::NegativeResponse {
+                mode: 0x01,
+                code: 0x12
+            })
+        ));
+        assert!(matches!(
+            responses[2].1,
+            Err(Error::ResponsePendingLimit { mode: 0x01 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn skips_late_and_foreign_responses() {
+        let mut requests = 0;
+        let client = client(2, move |_| {
+            requests += 1;
+            match requests {
+                // ECU 1 answers the first request too late
+                1 => vec![
+                    Reply::new(0, vec![0x41, 0x0C, 0x1A, 0xF8]),
+                    Reply::new(1, vec![0x41, 0x0C, 0x0F, 0xA0]).after(Duration::from_millis(70)),
+                ],
+                _ => vec![
+                    Reply::new(0, vec![0x7F, 0x22, 0x11]),
+                    Reply::new(0, vec![0x62, 0xF1, 0x90]),
+                    Reply::new(0, vec![0x41, 0x0D, 0x00]),
+                    Reply::new(1, vec![0x41, 0x0D, 0x28]),
+                ],
+            }
+        });
+        let responses = client.current_data(0x0C).await.unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].0, ecu(0));
+        tokio::time::sleep(Duration::from_millis(40)).await;
+
+        let responses = client.current_data(0x0D).await.unwrap();
+        let speeds: Vec<(Id, String)> = responses
+            .into_iter()
+            .map(|(ecu, value)| (ecu, value.unwrap().to_string()))
+            .collect();
+        assert_eq!(
+            speeds,
+            [
+                (ecu(0), "0 km/h".to_string()),
+                (ecu(1), "40 km/h".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn transport_errors_are_per_ecu() {
+        let client = client(2, |_| {
+            vec![
+                Reply::error(0, io::ErrorKind::ConnectionReset),
+                Reply::new(1, vec![0x43, 0x01, 0x01, 0x43]),
+            ]
+        });
+        let responses = client.stored_dtcs().await.unwrap();
+        assert!(matches!(responses[0].1, Err(Error::Io { .. })));
+        let dtcs = responses[1].1.as_ref().unwrap();
+        assert_eq!(dtcs[0].to_string(), "P0143");
+    }
+
+    #[tokio::test]
+    async fn reassembles_vehicle_information() {
+        let client = client(2, |request| match request {
+            [0x09, 0x02] => {
+                let mut vin = vec![0x49, 0x02, 0x01];
+                vin.extend_from_slice(VIN);
+                vec![Reply::new(0, vin)]
+            }
+            [0x09, 0x04] => {
+                let mut calids = vec![0x49, 0x04, 0x02];
+                calids.extend_from_slice(b"CAL-0001\0\0\0\0\0\0\0\0CAL-0002        ");
+                // one byte short of the announced item
+                let mut short = vec![0x49, 0x04, 0x01];
+                short.extend_from_slice(&[b'X'; 15]);
+                vec![Reply::new(0, calids), Reply::new(1, short)]
+            }
+            [0x09, 0x06] => vec![Reply::new(
+                1,
+                vec![
+                    0x49, 0x06, 0x02, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0,
+                ],
+            )],
+            _ => vec![],
+        });
+        let vins = client.vin().await.unwrap();
+        assert_eq!(vins[0].1.as_ref().unwrap(), "WVWZZZ1JZXW000001");
+
+        let calids = client.calibration_ids().await.unwrap();
+        assert_eq!(calids[0].1.as_ref().unwrap(), &["CAL-0001", "CAL-0002"]);
+        assert!(matches!(calids[1].1, Err(Error::InvalidResponse { .. })));
+
+        let cvns = client.calibration_verification_numbers().await.unwrap();
+        assert_eq!(cvns[0].0, ecu(1));
+        assert_eq!(cvns[0].1.as_ref().unwrap(), &[0x1234_5678, 0x9ABC_DEF0]);
+    }
+}
@@ -0,0 +1,246 @@
+//! Detection of the CAN identifiers used by a vehicle (ISO 15765-4)
+//!
+//! The initialisation sequence sends the functional request 0x01 0x00 with 11-bit
+//! and then with 29-bit ids. The first addressing getting a response is used, and
+//! the client is reopened with sockets for the responding ECUs only.
+//!
+//! The bitrate is a property of the CAN interface and cannot be changed through
+//! ISO-TP sockets. To probe 500 kbit/s and 250 kbit/s, configure the interface, e.g.
+//! `ip link set can0 type can bitrate 500000`, and run the detection once per bitrate.
+//! At a wrong bitrate no ECU responds.
+
+use super::pid::{decode_pid, PidValue};
+use super::{Error, ObdAddressing, ObdClient, CURRENT_DATA};
+use crate::{Id, IsoTpSocket, IsoTpTransport};
+use std::io;
+
+/// ECU responding to the detection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedEcu {
+    /// Index 0–7 with 11-bit and ECU address with 29-bit ids
+    pub ecu: u8,
+    pub request_id: Id,
+    pub response_id: Id,
+    /// PIDs 0x01–0x20 reported as supported, empty after a negative response
+    pub supported_pids: Vec<u8>,
+}
+
+/// Result of the detection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detection {
+    pub addressing: ObdAddressing,
+    pub ecus: Vec<DetectedEcu>,
+}
+
+impl ObdClient<IsoTpSocket> {
+    /// Detect the addressing on a named CAN device and open the responding ECUs
+    pub async fn detect(ifname: &str) -> Result<(Self, Detection), Error> {
+        Self::detect_with(|addressing, ecus| {
+            Self::open_ecus(ifname, addressing, ecus.iter().copied()).map_err(io_error)
+        })
+        .await
+    }
+}
+
+impl<T: IsoTpTransport> ObdClient<T> {
+    /// Detect the addressing with clients opened by `open` for an addressing and ECUs
+    ///
+    /// The probing client is dropped before the final one is opened.
+    pub async fn detect_with(
+        mut open: impl FnMut(ObdAddressing, &[u8]) -> io::Result<Self>,
+    ) -> Result<(Self, Detection), Error> {
+        for addressing in [ObdAddressing::Standard, ObdAddressing::Extended] {
+            let candidates = addressing.ecus();
+            let probe = open(addressing, &candidates)?;
+            let responses = probe.request(&[CURRENT_DATA, 0x00]).await?;
+            drop(probe);
+            let ecus: Vec<DetectedEcu> = responses
+                .into_iter()
+                .filter_map(|(response_id, result)| {
+                    let ecu = *candidates
+                        .iter()
+                        .find(|ecu| addressing.response_id(**ecu) == response_id)?;
+                    let supported_pids =
+                        match result.and_then(|response| decode_pid(0x00, &response[2..])) {
+                            Ok(PidValue::Supported(pids)) => pids,
+                            _ => Vec::new(),
+                        };
+                    Some(DetectedEcu {
+                        ecu,
+                        request_id: addressing.request_id(ecu),
+                        response_id,
+                        supported_pids,
+                    })
+                })
+                .collect();
+            if ecus.is_empty() {
+                continue;
+            }
+            let addresses: Vec<u8> = ecus.iter().map(|ecu| ecu.ecu).collect();
+            let client = open(addressing, &addresses)?;
+            return Ok((client, Detection { addressing, ecus }));
+        }
+        Err(Error::NoResponse)
+    }
+}
+
+fn io_error(error: crate::Error) -> io::Error {
+    match error {
+        crate::Error::Lookup { source } => source.into(),
+        crate::Error::Io { source } => source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functional::tests::{fake_bus, FakeTransport, Reply};
+    use crate::obd::ObdConfig;
+    use std::time::Duration;
+
+    /// Opens clients whose ECUs with the given addressing answer the detection
+    struct Vehicle {
+        addressing: Option<ObdAddressing>,
+        /// ECUs responding, with their supported PIDs or a negative response if empty
+        ecus: Vec<(u8, Vec<u8>)>,
+        /// Addressing and ECUs of every client opened
+        opened: Vec<(ObdAddressing, Vec<u8>)>,
+    }
+
+    impl Vehicle {
+        fn new(addressing: Option<ObdAddressing>, ecus: Vec<(u8, Vec<u8>)>) -> Self {
+            Self {
+                addressing,
+                ecus,
+                opened: Vec::new(),
+            }
+        }
+
+        fn open(
+            &mut self,
+            addressing: ObdAddressing,
+            ecus: &[u8],
+        ) -> io::Result<ObdClient<FakeTransport>> {
+            self.opened.push((addressing, ecus.to_vec()));
+            let responders: Vec<(Id, Id)> = ecus
+                .iter()
+                .map(|ecu| (addressing.response_id(*ecu), addressing.request_id(*ecu)))
+                .collect();
+            let replies: Vec<(usize, Vec<u8>)> = match self.addressing {
+                Some(responding) if responding == addressing => self
+                    .ecus
+                    .iter()
+                    .filter_map(|(ecu, supported)| {
+                        let index = ecus.iter().position(|candidate| candidate == ecu)?;
+                        let response = if supported.is_empty() {
+                            vec![0x7F, 0x01, 0x12]
+                        } else {
+                            [&[0x41, 0x00][..], supported].concat()
+                        };
+                        Some((index, response))
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let functional = fake_bus(addressing.functional_id(), &responders, move |_| {
+                replies
+                    .iter()
+                    .map(|(index, response)| Reply::new(*index, response.clone()))
+                    .collect()
+            });
+            let mut client = ObdClient::from_functional(functional);
+            client.set_config(ObdConfig {
+                timeout: Duration::from_millis(20),
+                ..ObdConfig::default()
+            });
+            Ok(client)
+        }
+    }
+
+    #[tokio::test]
+    async fn detects_standard_ids_first() {
+        let mut vehicle = Vehicle::new(
+            Some(ObdAddressing::Standard),
+            vec![(0, vec![0x98, 0x18, 0x00, 0x01]), (2, vec![])],
+        );
+        let (client, detection) =
+            ObdClient::detect_with(|addressing, ecus| vehicle.open(addressing, ecus))
+                .await
+                .unwrap();
+        assert_eq!(detection.addressing, ObdAddressing::Standard);
+        assert_eq!(
+            detection.ecus,
+            [
+                DetectedEcu {
+                    ecu: 0,
+                    request_id: ObdAddressing::Standard.request_id(0),
+                    response_id: ObdAddressing::Standard.response_id(0),
+                    supported_pids: vec![0x01, 0x04, 0x05, 0x0C, 0x0D, 0x20],
+                },
+                DetectedEcu {
+                    ecu: 2,
+                    request_id: ObdAddressing::Standard.request_id(2),
+                    response_id: ObdAddressing::Standard.response_id(2),
+                    supported_pids: Vec::new(),
+                },
+            ]
+        );
+        // the probe with all ECUs, then the client of the responding ones
+        assert_eq!(
+            vehicle.opened,
+            [
+                (ObdAddressing::Standard, (0..8).collect()),
+                (ObdAddressing::Standard, vec![0, 2]),
+            ]
+        );
+        assert_eq!(client.functional().responders().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_extended_ids() {
+        let mut vehicle = Vehicle::new(
+            Some(ObdAddressing::Extended),
+            vec![(0x10, vec![0x80, 0x00, 0x00, 0x00])],
+        );
+        let (client, detection) =
+            ObdClient::detect_with(|addressing, ecus| vehicle.open(addressing, ecus))
+                .await
+                .unwrap();
+        assert_eq!(detection.addressing, ObdAddressing::Extended);
+        assert_eq!(detection.ecus.len(), 1);
+        assert_eq!(detection.ecus[0].ecu, 0x10);
+        assert_eq!(detection.ecus[0].supported_pids, [0x01]);
+        assert_eq!(
+            client.functional().responders()[0].rx_id(),
+            ObdAddressing::Extended.response_id(0x10)
+        );
+        let opened: Vec<(ObdAddressing, usize)> = vehicle
+            .opened
+            .iter()
+            .map(|(addressing, ecus)| (*addressing, ecus.len()))
+            .collect();
+        assert_eq!(
+            opened,
+            [
+                (ObdAddressing::Standard, 8),
+                (ObdAddressing::Extended, 255),
+                (ObdAddressing::Extended, 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_response() {
+        let mut vehicle = Vehicle::new(None, Vec::new());
+        let result =
+            ObdClient::detect_with(|addressing, ecus| vehicle.open(addressing, ecus)).await;
+        assert!(matches!(result, Err(Error::NoResponse)));
+        assert_eq!(vehicle.opened.len(), 2);
+
+        let result = ObdClient::<FakeTransport>::detect_with(|_, _| {
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        })
+        .await;
+        assert!(matches!(result, Err(Error::Io { .. })));
+    }
+}
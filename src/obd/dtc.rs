@@ -0,0 +1,119 @@
+//! Two byte DTCs of modes 03, 07 and 0A
+
+use super::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// ISO 15031-6 diagnostic trouble code, displayed as e.g. `P0123`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Dtc(pub u16);
+
+impl Dtc {
+    pub fn from_bytes(bytes: [u8; 2]) -> Self {
+        Self(u16::from_be_bytes(bytes))
+    }
+
+    pub fn to_bytes(self) -> [u8; 2] {
+        self.0.to_be_bytes()
+    }
+
+    /// Code such as `P0123`
+    pub fn code(self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Dtc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let system = ['P', 'C', 'B', 'U'][usize::from(self.0 >> 14)];
+        write!(f, "{system}{}{:03X}", (self.0 >> 12) & 0x3, self.0 & 0x0FFF)
+    }
+}
+
+impl FromStr for Dtc {
+    type Err = Error;
+
+    /// Parses `P0123`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::invalid_request(format!("invalid DTC {text:?}"));
+        let mut chars = text.chars();
+        let system = match chars.next().map(|system| system.to_ascii_uppercase()) {
+            Some('P') => 0,
+            Some('C') => 1,
+            Some('B') => 2,
+            Some('U') => 3,
+            _ => return Err(invalid()),
+        };
+        let rest = chars.as_str();
+        if rest.len() != 4 || !rest.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let number = u16::from_str_radix(rest, 16).map_err(|_| invalid())?;
+        if number > 0x3FFF {
+            return Err(invalid());
+        }
+        Ok(Dtc((system << 14) | number))
+    }
+}
+
+/// DTCs of a mode 03, 07 or 0A response after the mode byte
+///
+/// On CAN the record starts with the number of DTCs, unused `0x0000` entries of
+/// other buses are skipped as well.
+pub(crate) fn parse_dtcs(record: &[u8]) -> Result<Vec<Dtc>, Error> {
+    let Some((&count, codes)) = record.split_first() else {
+        return Err(Error::invalid_response("missing number of DTCs"));
+    };
+    if codes.len() != 2 * usize::from(count) {
+        return Err(Error::invalid_response(format!(
+            "{} bytes for {count} DTCs",
+            codes.len()
+        )));
+    }
+    Ok(codes
+        .chunks_exact(2)
+        .map(|code| Dtc::from_bytes([code[0], code[1]]))
+        .filter(|dtc| dtc.0 != 0)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_parses() {
+        let cases = [
+            (0x0123, "P0123"),
+            (0x4ABC, "C0ABC"),
+            (0x9234, "B1234"),
+            (0xF00D, "U300D"),
+        ];
+        for (raw, text) in cases {
+            assert_eq!(Dtc(raw).to_string(), text);
+            assert_eq!(text.parse::<Dtc>().unwrap(), Dtc(raw));
+        }
+        assert_eq!("p0abc".parse::<Dtc>().unwrap(), Dtc(0x0ABC));
+        assert_eq!(Dtc::from_bytes([0x01, 0x23]).code(), "P0123");
+        assert_eq!(Dtc(0xC123).to_bytes(), [0xC1, 0x23]);
+    }
+
+    #[test]
+    fn rejects_invalid_codes() {
+        for text in [
+            "", "P", "P012", "P01234", "X0123", "P4000", "P+123", "P-123", "P 123",
+        ] {
+            assert!(text.parse::<Dtc>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn parses_dtc_records() {
+        let dtcs = parse_dtcs(&[0x03, 0x01, 0x43, 0x00, 0x00, 0xC1, 0x00]).unwrap();
+        assert_eq!(dtcs, [Dtc(0x0143), Dtc(0xC100)]);
+        assert_eq!(parse_dtcs(&[0x00]).unwrap(), []);
+        assert!(parse_dtcs(&[]).is_err());
+        assert!(parse_dtcs(&[0x02, 0x01, 0x43]).is_err());
+        assert!(parse_dtcs(&[0x01, 0x01, 0x43, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! OBD-II (ISO 15031-5 / SAE J1979) client on top of ISO-TP
+//!
+//! [ObdClient] sends each request once on the functional id (0x7DF, or 0x18DB33F1
+//! with 29-bit ids) and collects the physical responses of all emission related
+//! ECUs (0x7E8–0x7EF, or 0x18DAF1xx) through a
+//! [FunctionalRequest](crate::functional::FunctionalRequest). Every ECU has its own
+//! ISO-TP socket, which sends the flow control for multi-frame responses such as the VIN.
+//!
+//! Results are returned per responding ECU, ECUs not supporting a request stay
+//! silent and are simply missing from the result.
+//!
+//! When the addressing of a vehicle is unknown, [ObdClient::detect] probes 11-bit
+//! and 29-bit ids as specified by ISO 15765-4.
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::obd::{ObdAddressing, ObdClient};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let client = ObdClient::open("can0", ObdAddressing::Standard)?;
+//! for (ecu, speed) in client.current_data(0x0D).await? {
+//!     println!("{ecu:?}: {}", speed?);
+//! }
+//! for (ecu, vin) in client.vin().await? {
+//!     println!("{ecu:?}: VIN {}", vin?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod client;
+mod detect;
+mod dtc;
+mod pid;
+
+pub use client::{ObdAddressing, ObdClient, ObdConfig, Responses};
+pub use detect::{DetectedEcu, Detection};
+pub use dtc::Dtc;
+pub use pid::{decode_pid, pid_name, PidValue};
+
+use std::io;
+use thiserror::Error;
+
+/// Show current data
+pub const CURRENT_DATA: u8 = 0x01;
+/// Show freeze frame data
+pub const FREEZE_FRAME_DATA: u8 = 0x02;
+/// Show stored DTCs
+pub const STORED_DTCS: u8 = 0x03;
+/// Clear DTCs and stored values
+pub const CLEAR_DTCS: u8 = 0x04;
+/// Show pending DTCs
+pub const PENDING_DTCS: u8 = 0x07;
+/// Request vehicle information
+pub const VEHICLE_INFORMATION: u8 = 0x09;
+/// Show permanent DTCs
+pub const PERMANENT_DTCS: u8 = 0x0A;
+
+/// Added to the mode in positive responses
+pub const POSITIVE_RESPONSE_OFFSET: u8 = 0x40;
+/// First byte of negative responses
+pub const NEGATIVE_RESPONSE: u8 = 0x7F;
+/// Negative response code asking to wait for the response
+pub const RESPONSE_PENDING: u8 = 0x78;
+
+#[derive(Error, Debug)]
+/// Possible errors of the OBD layer
+pub enum Error {
+    /// IO Error of the underlying transport
+    #[error("IO error: {source:?}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+
+    /// The ECU rejected the request
+    #[error("Negative response to mode 0x{mode:02X}: NRC 0x{code:02X}")]
+    NegativeResponse { mode: u8, code: u8 },
+
+    /// The response does not match the request
+    #[error("Invalid response: {reason}")]
+    InvalidResponse { reason: String },
+
+    /// The ECU kept answering with response pending (NRC 0x78)
+    #[error("Too many response pending to mode 0x{mode:02X}")]
+    ResponsePendingLimit { mode: u8 },
+
+    /// The request cannot be encoded
+    #[error("Invalid request: {reason}")]
+    InvalidRequest { reason: String },
+
+    /// No ECU responded to the detection
+    #[error("No ECU responded with 11-bit or 29-bit ids")]
+    NoResponse,
+}
+
+impl Error {
+    pub(crate) fn invalid_response(reason: impl Into<String>) -> Self {
+        Error::InvalidResponse {
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn invalid_request(reason: impl Into<String>) -> Self {
+        Error::InvalidRequest {
+            reason: reason.into(),
+        }
+    }
+}
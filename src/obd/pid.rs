@@ -0,0 +1,354 @@
+//! Decoding of the PIDs of modes 01 and 02 (SAE J1979 / ISO 15031-5 annex B)
+
+use super::dtc::Dtc;
+use super::Error;
+use std::fmt;
+
+/// Decoded data of a PID
+#[derive(Debug, Clone, PartialEq)]
+pub enum PidValue {
+    /// Scaled value with its unit
+    Number { value: f64, unit: &'static str },
+    /// PIDs reported as supported by PID 0x00, 0x20, 0x40, ...
+    Supported(Vec<u8>),
+    /// Monitor status since DTCs cleared (PID 0x01)
+    MonitorStatus {
+        /// Malfunction indicator lamp
+        mil: bool,
+        dtc_count: u8,
+        /// Test availability and completeness bytes B to D
+        tests: [u8; 3],
+    },
+    /// DTC that caused the freeze frame (PID 0x02)
+    Dtc(Dtc),
+    /// PID without decoding in this crate
+    Raw(Vec<u8>),
+}
+
+impl fmt::Display for PidValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PidValue::Number { value, unit: "" } => write!(f, "{value}"),
+            PidValue::Number { value, unit } => write!(f, "{value} {unit}"),
+            PidValue::Supported(pids) => {
+                let pids: Vec<String> = pids.iter().map(|pid| format!("{pid:02X}")).collect();
+                f.write_str(&pids.join(" "))
+            }
+            PidValue::MonitorStatus { mil, dtc_count, .. } => write!(
+                f,
+                "MIL {}, {dtc_count} DTCs",
+                if *mil { "on" } else { "off" }
+            ),
+            PidValue::Dtc(dtc) => write!(f, "{dtc}"),
+            PidValue::Raw(data) => {
+                let bytes: Vec<String> = data.iter().map(|byte| format!("{byte:02X}")).collect();
+                f.write_str(&bytes.join(" "))
+            }
+        }
+    }
+}
+
+/// PID whose physical value is `raw * factor + offset` of its big endian data bytes
+struct Linear {
+    pid: u8,
+    name: &'static str,
+    bytes: usize,
+    factor: f64,
+    offset: f64,
+    unit: &'static str,
+}
+
+const fn linear(
+    pid: u8,
+    name: &'static str,
+    bytes: usize,
+    factor: f64,
+    offset: f64,
+    unit: &'static str,
+) -> Linear {
+    Linear {
+        pid,
+        name,
+        bytes,
+        factor,
+        offset,
+        unit,
+    }
+}
+
+const PERCENT: f64 = 100.0 / 255.0;
+
+const LINEAR: &[Linear] = &[
+    linear(0x04, "Calculated engine load", 1, PERCENT, 0.0, "%"),
+    linear(0x05, "Engine coolant temperature", 1, 1.0, -40.0, "°C"),
+    linear(
+        0x06,
+        "Short term fuel trim bank 1",
+        1,
+        100.0 / 128.0,
+        -100.0,
+        "%",
+    ),
+    linear(
+        0x07,
+        "Long term fuel trim bank 1",
+        1,
+        100.0 / 128.0,
+        -100.0,
+        "%",
+    ),
+    linear(
+        0x08,
+        "Short term fuel trim bank 2",
+        1,
+        100.0 / 128.0,
+        -100.0,
+        "%",
+    ),
+    linear(
+        0x09,
+        "Long term fuel trim bank 2",
+        1,
+        100.0 / 128.0,
+        -100.0,
+        "%",
+    ),
+    linear(0x0A, "Fuel pressure", 1, 3.0, 0.0, "kPa"),
+    linear(
+        0x0B,
+        "Intake manifold absolute pressure",
+        1,
+        1.0,
+        0.0,
+        "kPa",
+    ),
+    linear(0x0C, "Engine speed", 2, 0.25, 0.0, "rpm"),
+    linear(0x0D, "Vehicle speed", 1, 1.0, 0.0, "km/h"),
+    linear(0x0E, "Timing advance", 1, 0.5, -64.0, "°"),
+    linear(0x0F, "Intake air temperature", 1, 1.0, -40.0, "°C"),
+    linear(0x10, "Mass air flow rate", 2, 0.01, 0.0, "g/s"),
+    linear(0x11, "Throttle position", 1, PERCENT, 0.0, "%"),
+    linear(0x1F, "Run time since engine start", 2, 1.0, 0.0, "s"),
+    linear(0x21, "Distance traveled with MIL on", 2, 1.0, 0.0, "km"),
+    linear(0x22, "Fuel rail pressure", 2, 0.079, 0.0, "kPa"),
+    linear(0x23, "Fuel rail gauge pressure", 2, 10.0, 0.0, "kPa"),
+    linear(0x2C, "Commanded EGR", 1, PERCENT, 0.0, "%"),
+    linear(0x2D, "EGR error", 1, 100.0 / 128.0, -100.0, "%"),
+    linear(0x2E, "Commanded evaporative purge", 1, PERCENT, 0.0, "%"),
+    linear(0x2F, "Fuel tank level input", 1, PERCENT, 0.0, "%"),
+    linear(0x30, "Warm-ups since codes cleared", 1, 1.0, 0.0, ""),
+    linear(
+        0x31,
+        "Distance traveled since codes cleared",
+        2,
+        1.0,
+        0.0,
+        "km",
+    ),
+    linear(0x33, "Absolute barometric pressure", 1, 1.0, 0.0, "kPa"),
+    linear(0x42, "Control module voltage", 2, 0.001, 0.0, "V"),
+    linear(0x43, "Absolute load value", 2, PERCENT, 0.0, "%"),
+    linear(
+        0x44,
+        "Commanded air-fuel equivalence ratio",
+        2,
+        2.0 / 65536.0,
+        0.0,
+        "",
+    ),
+    linear(0x45, "Relative throttle position", 1, PERCENT, 0.0, "%"),
+    linear(0x46, "Ambient air temperature", 1, 1.0, -40.0, "°C"),
+    linear(0x47, "Absolute throttle position B", 1, PERCENT, 0.0, "%"),
+    linear(0x49, "Accelerator pedal position D", 1, PERCENT, 0.0, "%"),
+    linear(0x4A, "Accelerator pedal position E", 1, PERCENT, 0.0, "%"),
+    linear(0x4C, "Commanded throttle actuator", 1, PERCENT, 0.0, "%"),
+    linear(0x4D, "Time run with MIL on", 2, 1.0, 0.0, "min"),
+    linear(0x4E, "Time since trouble codes cleared", 2, 1.0, 0.0, "min"),
+    linear(0x52, "Ethanol fuel", 1, PERCENT, 0.0, "%"),
+    linear(
+        0x5A,
+        "Relative accelerator pedal position",
+        1,
+        PERCENT,
+        0.0,
+        "%",
+    ),
+    linear(
+        0x5B,
+        "Hybrid battery pack remaining life",
+        1,
+        PERCENT,
+        0.0,
+        "%",
+    ),
+    linear(0x5C, "Engine oil temperature", 1, 1.0, -40.0, "°C"),
+    linear(0x5E, "Engine fuel rate", 2, 0.05, 0.0, "L/h"),
+    linear(0xA6, "Odometer", 4, 0.1, 0.0, "km"),
+];
+
+/// Name of a PID as used by SAE J1979
+pub fn pid_name(pid: u8) -> Option<&'static str> {
+    match pid {
+        pid if pid.is_multiple_of(0x20) => Some("PIDs supported"),
+        0x01 => Some("Monitor status since DTCs cleared"),
+        0x02 => Some("DTC that caused freeze frame"),
+        0x03 => Some("Fuel system status"),
+        0x1C => Some("OBD standards this vehicle conforms to"),
+        0x51 => Some("Fuel type"),
+        pid => LINEAR
+            .iter()
+            .find(|linear| linear.pid == pid)
+            .map(|linear| linear.name),
+    }
+}
+
+/// Decode the data bytes of a mode 01 or 02 PID
+pub fn decode_pid(pid: u8, data: &[u8]) -> Result<PidValue, Error> {
+    let expect = |length: usize| {
+        if data.len() < length {
+            return Err(Error::invalid_response(format!(
+                "PID 0x{pid:02X}: {} bytes instead of {length}",
+                data.len()
+            )));
+        }
+        Ok(&data[..length])
+    };
+    if pid.is_multiple_of(0x20) {
+        let mask = u32::from_be_bytes(expect(4)?.try_into().unwrap());
+        let supported = (1..=32u8)
+            .filter(|bit| mask & (1 << (32 - bit)) != 0)
+            .filter_map(|bit| pid.checked_add(bit))
+            .collect();
+        return Ok(PidValue::Supported(supported));
+    }
+    match pid {
+        0x01 => {
+            let data = expect(4)?;
+            Ok(PidValue::MonitorStatus {
+                mil: data[0] & 0x80 != 0,
+                dtc_count: data[0] & 0x7F,
+                tests: [data[1], data[2], data[3]],
+            })
+        }
+        0x02 => {
+            let data = expect(2)?;
+            Ok(PidValue::Dtc(Dtc::from_bytes([data[0], data[1]])))
+        }
+        pid => match LINEAR.iter().find(|linear| linear.pid == pid) {
+            Some(linear) => {
+                let raw = expect(linear.bytes)?
+                    .iter()
+                    .fold(0u64, |raw, byte| (raw << 8) | u64::from(*byte));
+                Ok(PidValue::Number {
+                    value: raw as f64 * linear.factor + linear.offset,
+                    unit: linear.unit,
+                })
+            }
+            None => Ok(PidValue::Raw(data.to_vec())),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(pid: u8, data: &[u8]) -> (f64, &'static str) {
+        match decode_pid(pid, data).unwrap() {
+            PidValue::Number { value, unit } => (value, unit),
+            other => panic!("PID 0x{pid:02X} decoded as {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decodes_linear_pids() {
+        assert_eq!(number(0x05, &[0x7B]), (83.0, "°C"));
+        assert_eq!(number(0x0C, &[0x1A, 0xF8]), (1726.0, "rpm"));
+        assert_eq!(number(0x0D, &[0x3C]), (60.0, "km/h"));
+        assert_eq!(number(0x0E, &[0x00]), (-64.0, "°"));
+        assert_eq!(number(0x04, &[0xFF]), (100.0, "%"));
+        assert_eq!(number(0x06, &[0x80]), (0.0, "%"));
+        assert_eq!(number(0xA6, &[0x00, 0x01, 0x86, 0xA0]), (10000.0, "km"));
+        // trailing bytes are ignored
+        assert_eq!(number(0x0D, &[0x3C, 0xAA]), (60.0, "km/h"));
+        assert_eq!(decode_pid(0x0D, &[0x3C]).unwrap().to_string(), "60 km/h");
+        assert_eq!(decode_pid(0x30, &[0x05]).unwrap().to_string(), "5");
+    }
+
+    #[test]
+    fn decodes_supported_pids() {
+        let value = decode_pid(0x00, &[0xBE, 0x1F, 0xA8, 0x13]).unwrap();
+        let PidValue::Supported(pids) = &value else {
+            panic!("decoded as {value:?}");
+        };
+        assert_eq!(
+            pids[..],
+            [
+                0x01, 0x03, 0x04, 0x05, 0x06, 0x07, 0x0C, 0x0D, 0x0E, 0x0F, 0x10, 0x11, 0x13, 0x15,
+                0x1C, 0x1F, 0x20
+            ]
+        );
+        assert_eq!(
+            decode_pid(0xE0, &[0x00, 0x00, 0x00, 0x01]).unwrap(),
+            PidValue::Supported(vec![])
+        );
+        assert_eq!(
+            decode_pid(0x40, &[0x80, 0x00, 0x00, 0x01])
+                .unwrap()
+                .to_string(),
+            "41 60"
+        );
+    }
+
+    #[test]
+    fn decodes_status_and_freeze_frame_dtc() {
+        assert_eq!(
+            decode_pid(0x01, &[0x83, 0x07, 0x65, 0x04]).unwrap(),
+            PidValue::MonitorStatus {
+                mil: true,
+                dtc_count: 3,
+                tests: [0x07, 0x65, 0x04],
+            }
+        );
+        assert_eq!(
+            decode_pid(0x01, &[0x00, 0x00, 0x00, 0x00])
+                .unwrap()
+                .to_string(),
+            "MIL off, 0 DTCs"
+        );
+        assert_eq!(
+            decode_pid(0x02, &[0x01, 0x43]).unwrap(),
+            PidValue::Dtc(Dtc(0x0143))
+        );
+        assert_eq!(
+            decode_pid(0x1C, &[0x06]).unwrap(),
+            PidValue::Raw(vec![0x06])
+        );
+        assert_eq!(
+            decode_pid(0x03, &[0x02, 0x00]).unwrap().to_string(),
+            "02 00"
+        );
+    }
+
+    #[test]
+    fn rejects_short_data() {
+        for (pid, data) in [
+            (0x00, &[0xBE, 0x1F, 0xA8][..]),
+            (0x01, &[0x83]),
+            (0x02, &[0x01]),
+            (0x0C, &[0x1A]),
+            (0x0D, &[]),
+        ] {
+            assert!(decode_pid(pid, data).is_err(), "PID 0x{pid:02X}");
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(pid_name(0x00), Some("PIDs supported"));
+        assert_eq!(pid_name(0xC0), Some("PIDs supported"));
+        assert_eq!(pid_name(0x01), Some("Monitor status since DTCs cleared"));
+        assert_eq!(pid_name(0x0C), Some("Engine speed"));
+        assert_eq!(pid_name(0xFF), None);
+    }
+}
@@ -0,0 +1,1225 @@
+//! Import of ODX (ASAM MCD-2D) diagnostic descriptions
+//!
+//! An [OdxDatabase] is read from a single ODX document or from all documents of a PDX
+//! container and holds one [OdxEcu] per BASE-VARIANT and ECU-VARIANT with
+//!
+//! * its DIAG-SERVICEs and the constant prefix of their requests,
+//! * a [DidRegistry] built from the DOPs and COMPU-METHODs of its ReadDataByIdentifier
+//!   and WriteDataByIdentifier services,
+//! * an [IsoTpConfig] from its ISO 15765-2 COMPARAMs, ready to open an [IsoTpSocket].
+//!
+//! Services and COMPARAMs are inherited along the PARENT-REFS, a variant overriding what
+//! its base variant or protocol defines under the same name.
+//!
+//! Only DOPs with a [Codec] counterpart are imported: integers, strings and byte fields
+//! with IDENTICAL, LINEAR, single-scale SCALE-LINEAR and TEXTTABLE compu-methods, bit
+//! parameters (as raw bitfields) and STRUCTUREs of those. DIDs using anything else are
+//! listed in [OdxEcu::unsupported] instead of failing the import.
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::odx::OdxDatabase;
+//! use tokio_socketcan_isotp::uds::UdsClient;
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let database = OdxDatabase::load("engine.pdx")?;
+//! let ecu = database.ecu("EngineControl").expect("no such ECU");
+//! let client = UdsClient::new(ecu.isotp.open("can0")?);
+//! let vin = client.read_did(&ecu.dids, 0xF190).await?;
+//! println!("VIN: {vin}");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::uds::did::{
+    BitField, ByteOrder, Codec, DidDefinition, DidRegistry, EnumValue, StructField,
+};
+use crate::uds::service::{READ_DATA_BY_IDENTIFIER, WRITE_DATA_BY_IDENTIFIER};
+use crate::{
+    id_from_raw, Error, FlowControlOptions, Id, IsoTpBehaviour, IsoTpOptions, IsoTpSocket,
+    EFF_FLAG, SFF_MASK,
+};
+use roxmltree::{Document, Node};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek};
+use std::path::Path;
+
+/// Nesting of STRUCTUREs, deeper ones are most likely cyclic references
+const MAX_STRUCTURE_DEPTH: usize = 16;
+
+/// Diagnostic descriptions of all ECUs of an ODX or PDX file
+#[derive(Debug, Clone, Default)]
+pub struct OdxDatabase {
+    pub ecus: Vec<OdxEcu>,
+}
+
+/// BASE-VARIANT or ECU-VARIANT
+#[derive(Debug, Clone)]
+pub struct OdxEcu {
+    pub name: String,
+    pub services: Vec<OdxService>,
+    pub dids: DidRegistry,
+    /// DIDs left out of [dids](Self::dids) and why
+    pub unsupported: Vec<(u16, String)>,
+    pub isotp: IsoTpConfig,
+}
+
+/// DIAG-SERVICE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OdxService {
+    pub name: String,
+    /// SEMANTIC attribute such as "DATA-READ" or "SESSION"
+    pub semantic: Option<String>,
+    /// Leading constant bytes of the request, the SID and e.g. sub-function or DID
+    pub request: Vec<u8>,
+}
+
+/// ISO-TP settings of an ECU from its COMPARAMs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IsoTpConfig {
+    /// CP_CanPhysReqId
+    pub request_id: Option<Id>,
+    /// CP_CanRespUSDTId
+    pub response_id: Option<Id>,
+    /// CP_CanFuncReqId
+    pub functional_id: Option<Id>,
+    /// CP_CanPhysReqExtAddr
+    pub request_ext_address: Option<u8>,
+    /// CP_CanRespUSDTExtAddr
+    pub response_ext_address: Option<u8>,
+    /// CP_BlockSize sent in flow control frames
+    pub block_size: Option<u8>,
+    /// CP_STmin sent in flow control frames, converted from the microseconds of the
+    /// COMPARAM to the encoding of the frame
+    pub stmin: Option<u8>,
+    /// CP_CanFillerByte if CP_CanFillerByteHandling is enabled
+    pub padding: Option<u8>,
+}
+
+impl OdxDatabase {
+    /// Parse a single ODX document
+    pub fn parse(xml: &str) -> io::Result<Self> {
+        Self::parse_documents(&[xml])
+    }
+
+    /// Read all ODX documents of a PDX container
+    pub fn read_pdx(reader: impl Read + Seek) -> io::Result<Self> {
+        let mut archive = zip::ZipArchive::new(reader).map_err(invalid_data)?;
+        let mut documents = Vec::new();
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index).map_err(invalid_data)?;
+            let is_odx = Path::new(file.name())
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| extension.to_ascii_lowercase().starts_with("odx"));
+            if is_odx {
+                let mut text = String::new();
+                file.read_to_string(&mut text)?;
+                documents.push(text);
+            }
+        }
+        let documents: Vec<&str> = documents.iter().map(String::as_str).collect();
+        Self::parse_documents(&documents)
+    }
+
+    /// Load a `.pdx` container or an ODX document
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let is_pdx = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("pdx"));
+        if is_pdx {
+            Self::read_pdx(File::open(path)?)
+        } else {
+            Self::parse(&fs::read_to_string(path)?)
+        }
+    }
+
+    /// ECU by SHORT-NAME
+    pub fn ecu(&self, name: &str) -> Option<&OdxEcu> {
+        self.ecus.iter().find(|ecu| ecu.name == name)
+    }
+
+    fn parse_documents(texts: &[&str]) -> io::Result<Self> {
+        let documents = texts
+            .iter()
+            .map(|text| Document::parse(text).map_err(invalid_data))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut odx = Odx::default();
+        for node in documents.iter().flat_map(|document| document.descendants()) {
+            if let Some(id) = node.attribute("ID") {
+                odx.ids.insert(id, node);
+            }
+            if matches!(tag(node), "DATA-OBJECT-PROP" | "STRUCTURE") {
+                odx.dops.entry(short_name(node)).or_insert(node);
+            }
+        }
+        let ecus: Vec<OdxEcu> = documents
+            .iter()
+            .flat_map(|document| document.descendants())
+            .filter(|node| matches!(tag(*node), "BASE-VARIANT" | "ECU-VARIANT"))
+            .map(|layer| odx.ecu(layer))
+            .collect();
+        if ecus.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no BASE-VARIANT or ECU-VARIANT",
+            ));
+        }
+        Ok(Self { ecus })
+    }
+}
+
+impl OdxService {
+    /// DID of a ReadDataByIdentifier or WriteDataByIdentifier service
+    pub fn did(&self) -> Option<u16> {
+        match self.request[..] {
+            [READ_DATA_BY_IDENTIFIER | WRITE_DATA_BY_IDENTIFIER, high, low, ..] => {
+                Some(u16::from_be_bytes([high, low]))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl IsoTpConfig {
+    /// Open a socket to the ECU on a named CAN device
+    pub fn open(&self, ifname: &str) -> Result<IsoTpSocket, Error> {
+        let (Some(request_id), Some(response_id)) = (self.request_id, self.response_id) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "CP_CanPhysReqId or CP_CanRespUSDTId missing",
+            )
+            .into());
+        };
+        IsoTpSocket::open_with_opts(
+            ifname,
+            response_id,
+            request_id,
+            Some(self.isotp_options()),
+            Some(self.flow_control_options()),
+            None,
+        )
+    }
+
+    /// Addressing and padding options
+    pub fn isotp_options(&self) -> IsoTpOptions {
+        let mut options = IsoTpOptions::default();
+        let mut flags = IsoTpBehaviour::empty();
+        if let Some(address) = self.request_ext_address {
+            flags |= IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR;
+            options.set_ext_address(address);
+        }
+        if let Some(address) = self.response_ext_address {
+            flags |= IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR | IsoTpBehaviour::CAN_ISOTP_RX_EXT_ADDR;
+            options.set_rx_ext_address(address);
+        }
+        if let Some(padding) = self.padding {
+            flags |= IsoTpBehaviour::CAN_ISOTP_TX_PADDING;
+            options.set_txpad_content(padding);
+        }
+        options.set_flags(flags);
+        options
+    }
+
+    /// Flow control sent while receiving, kernel defaults where not configured
+    pub fn flow_control_options(&self) -> FlowControlOptions {
+        FlowControlOptions::new(self.block_size.unwrap_or(0), self.stmin.unwrap_or(0), 0)
+    }
+}
+
+/// Index of all documents
+#[derive(Default)]
+struct Odx<'a, 'input> {
+    ids: HashMap<&'a str, Node<'a, 'input>>,
+    /// DOPs by SHORT-NAME for DOP-SNREF
+    dops: HashMap<&'a str, Node<'a, 'input>>,
+}
+
+/// Bit sized parameter of a bitfield
+struct BitParam {
+    name: String,
+    bit: u32,
+    width: u32,
+}
+
+impl<'a, 'input> Odx<'a, 'input> {
+    fn resolve(&self, node: Node<'a, 'input>, reference: &str) -> Option<Node<'a, 'input>> {
+        self.ids
+            .get(child(node, reference)?.attribute("ID-REF")?)
+            .copied()
+    }
+
+    fn ecu(&self, layer: Node<'a, 'input>) -> OdxEcu {
+        let mut layers = Vec::new();
+        self.collect_layers(layer, &mut HashSet::new(), &mut layers);
+
+        let mut services: Vec<(Node, OdxService)> = Vec::new();
+        for service in layers
+            .iter()
+            .filter_map(|layer| child(*layer, "DIAG-COMMS"))
+            .flat_map(|comms| comms.children())
+            .filter_map(|comm| match tag(comm) {
+                "DIAG-SERVICE" => Some(comm),
+                "DIAG-COMM-REF" => self.ids.get(comm.attribute("ID-REF")?).copied(),
+                _ => None,
+            })
+            .filter(|comm| tag(*comm) == "DIAG-SERVICE")
+        {
+            let parsed = OdxService {
+                name: short_name(service).to_string(),
+                semantic: service.attribute("SEMANTIC").map(str::to_string),
+                request: self
+                    .resolve(service, "REQUEST-REF")
+                    .map(request_prefix)
+                    .unwrap_or_default(),
+            };
+            // inherited services are overridden by name
+            services.retain(|(_, known)| known.name != parsed.name);
+            services.push((service, parsed));
+        }
+
+        let mut dids = DidRegistry::new();
+        let mut unsupported = BTreeMap::new();
+        // reads first, writes only describe DIDs without a read service
+        for sid in [READ_DATA_BY_IDENTIFIER, WRITE_DATA_BY_IDENTIFIER] {
+            for (node, service) in &services {
+                let Some(did) = service.did().filter(|_| service.request[0] == sid) else {
+                    continue;
+                };
+                if dids.get(did).is_some() {
+                    continue;
+                }
+                let params = if sid == READ_DATA_BY_IDENTIFIER {
+                    child(*node, "POS-RESPONSE-REFS")
+                        .and_then(|refs| child(refs, "POS-RESPONSE-REF"))
+                        .and_then(|reference| self.ids.get(reference.attribute("ID-REF")?))
+                        .map(|response| params(*response))
+                } else {
+                    self.resolve(*node, "REQUEST-REF").map(params)
+                };
+                let codec = params
+                    .ok_or_else(|| format!("{} has no parameters", service.name))
+                    .and_then(|params| self.params_codec(params, 3, 0))
+                    .and_then(|codec| codec.validate().map(|()| codec));
+                match codec {
+                    Ok(codec) => {
+                        unsupported.remove(&did);
+                        dids.insert(DidDefinition {
+                            did,
+                            name: service.name.clone(),
+                            codec,
+                        });
+                    }
+                    Err(reason) => {
+                        unsupported.insert(did, format!("{}: {reason}", service.name));
+                    }
+                }
+            }
+        }
+
+        OdxEcu {
+            name: short_name(layer).to_string(),
+            services: services.into_iter().map(|(_, service)| service).collect(),
+            dids,
+            unsupported: unsupported.into_iter().collect(),
+            isotp: self.isotp_config(&layers),
+        }
+    }
+
+    /// Layer and its parents, outermost parent first
+    fn collect_layers(
+        &self,
+        layer: Node<'a, 'input>,
+        visited: &mut HashSet<roxmltree::NodeId>,
+        layers: &mut Vec<Node<'a, 'input>>,
+    ) {
+        if !visited.insert(layer.id()) {
+            return;
+        }
+        for parent in child(layer, "PARENT-REFS")
+            .into_iter()
+            .flat_map(|refs| refs.children())
+            .filter_map(|reference| self.ids.get(reference.attribute("ID-REF")?))
+        {
+            self.collect_layers(*parent, visited, layers);
+        }
+        layers.push(layer);
+    }
+
+    fn isotp_config(&self, layers: &[Node<'a, 'input>]) -> IsoTpConfig {
+        let mut values: HashMap<String, &str> = HashMap::new();
+        for reference in layers
+            .iter()
+            .filter_map(|layer| child(*layer, "COMPARAM-REFS"))
+            .flat_map(|refs| refs.children())
+            .filter(|node| tag(*node) == "COMPARAM-REF")
+        {
+            let id = reference.attribute("ID-REF").unwrap_or_default();
+            let definition = self.ids.get(id);
+            if let Some(value) = child(reference, "SIMPLE-VALUE")
+                .or_else(|| child(reference, "VALUE"))
+                .and_then(|value| value.text())
+            {
+                let name = definition.map_or_else(|| comparam_name(id), |node| short_name(*node));
+                values.insert(name.to_ascii_lowercase(), value.trim());
+            } else if let (Some(complex), Some(definition)) =
+                (child(reference, "COMPLEX-VALUE"), definition)
+            {
+                // values follow the order of the COMPARAMs of the COMPLEX-COMPARAM
+                let names = definition
+                    .children()
+                    .filter(|node| matches!(tag(*node), "COMPARAM" | "COMPLEX-COMPARAM"));
+                let simple_values = complex
+                    .children()
+                    .filter(|node| node.is_element())
+                    .map(|node| node.text().unwrap_or_default());
+                for (name, value) in names.zip(simple_values) {
+                    values.insert(short_name(name).to_ascii_lowercase(), value.trim());
+                }
+            }
+        }
+        let number = |name: &str| {
+            values
+                .get(&name.to_ascii_lowercase())
+                .and_then(|v| parse_u64(v))
+        };
+        let byte = |name: &str| number(name).and_then(|value| u8::try_from(value).ok());
+        let id = |name: &str| {
+            number(name).and_then(|raw| {
+                let raw = u32::try_from(raw).ok()?;
+                Some(if raw > SFF_MASK {
+                    id_from_raw(raw | EFF_FLAG)
+                } else {
+                    id_from_raw(raw)
+                })
+            })
+        };
+        IsoTpConfig {
+            request_id: id("CP_CanPhysReqId"),
+            response_id: id("CP_CanRespUSDTId"),
+            functional_id: id("CP_CanFuncReqId"),
+            request_ext_address: byte("CP_CanPhysReqExtAddr"),
+            response_ext_address: byte("CP_CanRespUSDTExtAddr"),
+            block_size: byte("CP_BlockSize"),
+            stmin: number("CP_STmin").map(stmin_from_micros),
+            padding: byte("CP_CanFillerByte")
+                .filter(|_| number("CP_CanFillerByteHandling").is_some_and(|enabled| enabled != 0)),
+        }
+    }
+
+    /// Codec of the VALUE parameters from byte `start` on, `depth` STRUCTUREs down
+    fn params_codec(
+        &self,
+        params: Vec<Node<'a, 'input>>,
+        start: usize,
+        depth: usize,
+    ) -> Result<Codec, String> {
+        let mut fields: Vec<StructField> = Vec::new();
+        let mut offset = start;
+        let mut open_ended = false;
+        let mut params = params.into_iter().peekable();
+        while let Some(param) = params.next() {
+            let kind = xsi_type(param);
+            if !matches!(kind, "VALUE" | "PHYS-CONST" | "RESERVED") {
+                continue;
+            }
+            let position = match child_text(param, "BYTE-POSITION") {
+                Some(position) => parse_u64(position)
+                    .ok_or_else(|| format!("invalid BYTE-POSITION {position:?}"))?
+                    as usize,
+                None => offset,
+            };
+            if position < start {
+                continue;
+            }
+            if open_ended || position < offset {
+                return Err(format!("{} overlaps other parameters", short_name(param)));
+            }
+            if position > offset {
+                fields.push(StructField {
+                    name: format!("reserved_{}", offset - start),
+                    codec: Codec::Bytes {
+                        length: Some(position - offset),
+                    },
+                });
+            }
+
+            let (name, codec) = if kind == "RESERVED" {
+                let bits = child_text(param, "BIT-LENGTH")
+                    .and_then(parse_u64)
+                    .ok_or("RESERVED without BIT-LENGTH")? as usize;
+                let length = bits.div_ceil(8);
+                (
+                    format!("reserved_{}", position - start),
+                    Codec::Bytes {
+                        length: Some(length),
+                    },
+                )
+            } else if let Some(first) = self.bit_param(param)? {
+                // parameters sharing the byte form one bitfield
+                let mut bits = vec![first];
+                while let Some(next) = params.peek().copied() {
+                    let same_byte = child_text(next, "BYTE-POSITION")
+                        .and_then(parse_u64)
+                        .is_some_and(|next| next as usize == position);
+                    match self.bit_param(next)? {
+                        Some(bit) if same_byte => bits.push(bit),
+                        _ => break,
+                    }
+                    params.next();
+                }
+                let bytes = bits
+                    .iter()
+                    .map(|param| (param.bit + param.width).div_ceil(8) as usize)
+                    .max()
+                    .unwrap_or(1);
+                let name = bits
+                    .iter()
+                    .map(|param| param.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join("_");
+                let fields = bits
+                    .into_iter()
+                    .map(|param| {
+                        // bit positions count from the end of the bytes each parameter spans
+                        let span = (param.bit + param.width).div_ceil(8) as usize;
+                        BitField {
+                            name: param.name,
+                            bit: param.bit + 8 * (bytes - span) as u32,
+                            width: param.width,
+                        }
+                    })
+                    .collect();
+                (name, Codec::Bitfield { bytes, fields })
+            } else {
+                let dop = self.param_dop(param)?;
+                (short_name(param).to_string(), self.dop_codec(dop, depth)?)
+            };
+            match codec.length() {
+                Some(length) => offset = position + length,
+                None => {
+                    offset = position;
+                    open_ended = true;
+                }
+            }
+            fields.push(StructField { name, codec });
+        }
+        match fields.len() {
+            0 => Err("no VALUE parameters".to_string()),
+            // a single parameter right at the start is not wrapped
+            1 => Ok(fields.remove(0).codec),
+            _ => Ok(Codec::Struct { fields }),
+        }
+    }
+
+    fn param_dop(&self, param: Node<'a, 'input>) -> Result<Node<'a, 'input>, String> {
+        let dop = match (child(param, "DOP-REF"), child(param, "DOP-SNREF")) {
+            (Some(reference), _) => self
+                .ids
+                .get(reference.attribute("ID-REF").unwrap_or_default()),
+            (None, Some(reference)) => self
+                .dops
+                .get(reference.attribute("SHORT-NAME").unwrap_or_default()),
+            (None, None) => None,
+        };
+        dop.copied()
+            .ok_or_else(|| format!("DOP of {} not found", short_name(param)))
+    }
+
+    /// Parameter not aligned to whole bytes
+    fn bit_param(&self, param: Node<'a, 'input>) -> Result<Option<BitParam>, String> {
+        if xsi_type(param) != "VALUE" {
+            return Ok(None);
+        }
+        let dop = self.param_dop(param)?;
+        if tag(dop) != "DATA-OBJECT-PROP" {
+            return Ok(None);
+        }
+        let Some(width) = coded_type(dop)?.bits else {
+            return Ok(None);
+        };
+        let bit = child_text(param, "BIT-POSITION")
+            .and_then(parse_u64)
+            .unwrap_or(0);
+        if bit == 0 && width % 8 == 0 {
+            return Ok(None);
+        }
+        if bit > 7 || !(1..=64).contains(&width) {
+            return Err(format!(
+                "{} has BIT-POSITION {bit} and {width} bits",
+                short_name(param)
+            ));
+        }
+        Ok(Some(BitParam {
+            name: short_name(param).to_string(),
+            bit: bit as u32,
+            width: width as u32,
+        }))
+    }
+
+    fn dop_codec(&self, dop: Node<'a, 'input>, depth: usize) -> Result<Codec, String> {
+        match tag(dop) {
+            "DATA-OBJECT-PROP" => self.data_object_prop(dop),
+            "STRUCTURE" if depth >= MAX_STRUCTURE_DEPTH => Err(format!(
+                "STRUCTURE {} nested more than {MAX_STRUCTURE_DEPTH} levels deep",
+                short_name(dop)
+            )),
+            "STRUCTURE" => self.params_codec(params(dop), 0, depth + 1),
+            other => Err(format!("{other} {} not supported", short_name(dop))),
+        }
+    }
+
+    fn data_object_prop(&self, dop: Node<'a, 'input>) -> Result<Codec, String> {
+        let coded = coded_type(dop)?;
+        let bytes = match coded.bits {
+            Some(bits) if bits % 8 != 0 => {
+                return Err(format!("{} has {bits} bits", short_name(dop)));
+            }
+            bits => bits.map(|bits| bits / 8),
+        };
+        let byte_order = if coded.little_endian {
+            ByteOrder::Little
+        } else {
+            ByteOrder::Big
+        };
+        let compu = child(dop, "COMPU-METHOD");
+        let category = compu
+            .and_then(|compu| child_text(compu, "CATEGORY"))
+            .unwrap_or("IDENTICAL");
+        match coded.base_type {
+            "A_UINT32" | "A_INT32" => {
+                let bytes =
+                    bytes.ok_or_else(|| format!("{} has no fixed length", short_name(dop)))?;
+                let signed = coded.base_type == "A_INT32";
+                match (category, compu) {
+                    ("IDENTICAL" | "LINEAR" | "SCALE-LINEAR", _) => {
+                        let (scale, offset) = match compu {
+                            Some(compu) if category != "IDENTICAL" => linear(compu)?,
+                            _ => (1.0, 0.0),
+                        };
+                        let unit = self.unit(dop);
+                        Ok(if signed {
+                            Codec::Signed {
+                                bytes,
+                                scale,
+                                offset,
+                                unit,
+                                byte_order,
+                            }
+                        } else {
+                            Codec::Unsigned {
+                                bytes,
+                                scale,
+                                offset,
+                                unit,
+                                byte_order,
+                            }
+                        })
+                    }
+                    ("TEXTTABLE", Some(compu))
+                        if !signed && (bytes == 1 || byte_order == ByteOrder::Big) =>
+                    {
+                        Ok(Codec::Enum {
+                            bytes,
+                            values: texttable(compu)?,
+                        })
+                    }
+                    (other, _) => Err(format!(
+                        "{} COMPU-METHOD {other} not supported",
+                        short_name(dop)
+                    )),
+                }
+            }
+            "A_ASCIISTRING" | "A_UTF8STRING" => Ok(Codec::Ascii { length: bytes }),
+            "A_BYTEFIELD" => Ok(Codec::Bytes { length: bytes }),
+            other => Err(format!("{} of type {other} not supported", short_name(dop))),
+        }
+    }
+
+    fn unit(&self, dop: Node<'a, 'input>) -> Option<String> {
+        let unit = self.resolve(dop, "UNIT-REF")?;
+        child_text(unit, "DISPLAY-NAME")
+            .or_else(|| child_text(unit, "SHORT-NAME"))
+            .map(str::to_string)
+    }
+}
+
+/// DIAG-CODED-TYPE
+struct CodedType<'a> {
+    base_type: &'a str,
+    /// `None` for variable length types
+    bits: Option<usize>,
+    little_endian: bool,
+}
+
+fn coded_type<'a>(node: Node<'a, '_>) -> Result<CodedType<'a>, String> {
+    let coded = child(node, "DIAG-CODED-TYPE")
+        .ok_or_else(|| format!("{} has no DIAG-CODED-TYPE", short_name(node)))?;
+    let bits = match xsi_type(coded) {
+        "STANDARD-LENGTH-TYPE" => Some(
+            child_text(coded, "BIT-LENGTH")
+                .and_then(parse_u64)
+                .ok_or_else(|| format!("{} has no BIT-LENGTH", short_name(node)))?
+                as usize,
+        ),
+        "MIN-MAX-LENGTH-TYPE" => None,
+        other => return Err(format!("{} of {other} not supported", short_name(node))),
+    };
+    Ok(CodedType {
+        base_type: coded.attribute("BASE-DATA-TYPE").unwrap_or_default(),
+        bits,
+        little_endian: coded.attribute("IS-HIGHLOW-BYTE-ORDER") == Some("false"),
+    })
+}
+
+/// Scale and offset of a LINEAR or single scale SCALE-LINEAR COMPU-METHOD
+fn linear(compu: Node) -> Result<(f64, f64), String> {
+    let scales: Vec<Node> = compu_scales(compu).collect();
+    let [scale] = scales[..] else {
+        return Err(format!("{} COMPU-SCALEs not supported", scales.len()));
+    };
+    let coefficients = |name: &str| -> Vec<f64> {
+        child(scale, "COMPU-RATIONAL-COEFFS")
+            .and_then(|coeffs| child(coeffs, name))
+            .into_iter()
+            .flat_map(|node| node.children())
+            .filter(|node| tag(*node) == "V")
+            .filter_map(|node| node.text()?.trim().parse().ok())
+            .collect()
+    };
+    let numerator = coefficients("COMPU-NUMERATOR");
+    let denominator = coefficients("COMPU-DENOMINATOR")
+        .first()
+        .copied()
+        .unwrap_or(1.0);
+    match numerator[..] {
+        [offset, factor] if denominator != 0.0 => Ok((factor / denominator, offset / denominator)),
+        _ => Err("LINEAR COMPU-METHOD needs two numerator coefficients".to_string()),
+    }
+}
+
+fn texttable(compu: Node) -> Result<Vec<EnumValue>, String> {
+    compu_scales(compu)
+        .map(|scale| {
+            let lower = child_text(scale, "LOWER-LIMIT").and_then(parse_u64);
+            let upper = child_text(scale, "UPPER-LIMIT").and_then(parse_u64);
+            let name = child(scale, "COMPU-CONST").and_then(|constant| child_text(constant, "VT"));
+            match (lower, name) {
+                (Some(value), Some(name)) if upper.unwrap_or(value) == value => Ok(EnumValue {
+                    value,
+                    name: name.to_string(),
+                }),
+                _ => Err("TEXTTABLE ranges not supported".to_string()),
+            }
+        })
+        .collect()
+}
+
+fn compu_scales<'a, 'input>(compu: Node<'a, 'input>) -> impl Iterator<Item = Node<'a, 'input>> {
+    child(compu, "COMPU-INTERNAL-TO-PHYS")
+        .and_then(|internal| child(internal, "COMPU-SCALES"))
+        .into_iter()
+        .flat_map(|scales| scales.children())
+        .filter(|node| tag(*node) == "COMPU-SCALE")
+}
+
+/// Leading CODED-CONST parameters of a request
+fn request_prefix(request: Node) -> Vec<u8> {
+    let mut prefix = Vec::new();
+    for param in params(request) {
+        let position = child_text(param, "BYTE-POSITION").and_then(parse_u64);
+        if xsi_type(param) != "CODED-CONST" || position.is_some_and(|p| p as usize != prefix.len())
+        {
+            break;
+        }
+        let bits = coded_type(param).ok().and_then(|coded| coded.bits);
+        let value = child_text(param, "CODED-VALUE").and_then(parse_u64);
+        let (Some(bits), Some(value)) = (bits, value) else {
+            break;
+        };
+        if bits % 8 != 0 || bits > 64 {
+            break;
+        }
+        prefix.extend_from_slice(&value.to_be_bytes()[8 - bits / 8..]);
+    }
+    prefix
+}
+
+/// PARAMs of a REQUEST, POS-RESPONSE or STRUCTURE in byte order
+fn params<'a, 'input>(node: Node<'a, 'input>) -> Vec<Node<'a, 'input>> {
+    let mut params: Vec<Node> = child(node, "PARAMS")
+        .into_iter()
+        .flat_map(|params| params.children())
+        .filter(|node| tag(*node) == "PARAM")
+        .collect();
+    // parameters without a position follow the previous one
+    params.sort_by_key(|param| {
+        child_text(*param, "BYTE-POSITION")
+            .and_then(parse_u64)
+            .unwrap_or(u64::MAX)
+    });
+    params
+}
+
+/// STmin byte of a separation time in microseconds, rounded up to the next encodable value
+fn stmin_from_micros(micros: u64) -> u8 {
+    match micros {
+        0 => 0x00,
+        // 0xF1..=0xF9 encode 100 to 900 microseconds
+        1..=900 => 0xF0 + micros.div_ceil(100) as u8,
+        _ => micros.div_ceil(1000).min(0x7F) as u8,
+    }
+}
+
+/// COMPARAM name of an ID-REF without definition, e.g. "ISO_15765_2.CP_BlockSize"
+fn comparam_name(id: &str) -> &str {
+    id.find("CP_").map_or(id, |start| &id[start..])
+}
+
+fn tag<'a>(node: Node<'a, '_>) -> &'a str {
+    node.tag_name().name()
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| tag(*child) == name)
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name)?.text().map(str::trim)
+}
+
+fn short_name<'a>(node: Node<'a, '_>) -> &'a str {
+    child_text(node, "SHORT-NAME").unwrap_or_default()
+}
+
+/// `xsi:type` attribute
+fn xsi_type<'a>(node: Node<'a, '_>) -> &'a str {
+    node.attributes()
+        .find(|attribute| attribute.name() == "type")
+        .map_or("", |attribute| attribute.value())
+}
+
+/// Decimal or `0x` prefixed hexadecimal number
+fn parse_u64(text: &str) -> Option<u64> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn invalid_data(error: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::did::Value;
+
+    const ENGINE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ODX xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" MODEL-VERSION="2.2.0">
+  <DIAG-LAYER-CONTAINER ID="DLC.Engine">
+    <SHORT-NAME>Engine</SHORT-NAME>
+    <BASE-VARIANTS>
+      <BASE-VARIANT ID="BV.EngineControl">
+        <SHORT-NAME>EngineControl</SHORT-NAME>
+        <DIAG-DATA-DICTIONARY-SPEC>
+          <DATA-OBJECT-PROPS>
+            <DATA-OBJECT-PROP ID="DOP.VIN">
+              <SHORT-NAME>VIN</SHORT-NAME>
+              <DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_ASCIISTRING"><BIT-LENGTH>136</BIT-LENGTH></DIAG-CODED-TYPE>
+            </DATA-OBJECT-PROP>
+            <DATA-OBJECT-PROP ID="DOP.Temperature">
+              <SHORT-NAME>Temperature</SHORT-NAME>
+              <COMPU-METHOD>
+                <CATEGORY>LINEAR</CATEGORY>
+                <COMPU-INTERNAL-TO-PHYS><COMPU-SCALES><COMPU-SCALE>
+                  <COMPU-RATIONAL-COEFFS>
+                    <COMPU-NUMERATOR><V>-4000</V><V>1</V></COMPU-NUMERATOR>
+                    <COMPU-DENOMINATOR><V>100</V></COMPU-DENOMINATOR>
+                  </COMPU-RATIONAL-COEFFS>
+                </COMPU-SCALE></COMPU-SCALES></COMPU-INTERNAL-TO-PHYS>
+              </COMPU-METHOD>
+              <DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>16</BIT-LENGTH></DIAG-CODED-TYPE>
+              <UNIT-REF ID-REF="UNIT.degC"/>
+            </DATA-OBJECT-PROP>
+            <DATA-OBJECT-PROP ID="DOP.Gear">
+              <SHORT-NAME>Gear</SHORT-NAME>
+              <COMPU-METHOD>
+                <CATEGORY>TEXTTABLE</CATEGORY>
+                <COMPU-INTERNAL-TO-PHYS><COMPU-SCALES>
+                  <COMPU-SCALE><LOWER-LIMIT>0</LOWER-LIMIT><UPPER-LIMIT>0</UPPER-LIMIT><COMPU-CONST><VT>Neutral</VT></COMPU-CONST></COMPU-SCALE>
+                  <COMPU-SCALE><LOWER-LIMIT>1</LOWER-LIMIT><UPPER-LIMIT>1</UPPER-LIMIT><COMPU-CONST><VT>First</VT></COMPU-CONST></COMPU-SCALE>
+                </COMPU-SCALES></COMPU-INTERNAL-TO-PHYS>
+              </COMPU-METHOD>
+              <DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE>
+            </DATA-OBJECT-PROP>
+            <DATA-OBJECT-PROP ID="DOP.Flag">
+              <SHORT-NAME>Flag</SHORT-NAME>
+              <DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>1</BIT-LENGTH></DIAG-CODED-TYPE>
+            </DATA-OBJECT-PROP>
+            <DATA-OBJECT-PROP ID="DOP.Mode">
+              <SHORT-NAME>Mode</SHORT-NAME>
+              <DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>3</BIT-LENGTH></DIAG-CODED-TYPE>
+            </DATA-OBJECT-PROP>
+            <DATA-OBJECT-PROP ID="DOP.Ratio">
+              <SHORT-NAME>Ratio</SHORT-NAME>
+              <DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_FLOAT32"><BIT-LENGTH>32</BIT-LENGTH></DIAG-CODED-TYPE>
+            </DATA-OBJECT-PROP>
+          </DATA-OBJECT-PROPS>
+          <UNIT-SPEC><UNITS>
+            <UNIT ID="UNIT.degC"><SHORT-NAME>degC</SHORT-NAME><DISPLAY-NAME>°C</DISPLAY-NAME></UNIT>
+          </UNITS></UNIT-SPEC>
+        </DIAG-DATA-DICTIONARY-SPEC>
+        <DIAG-COMMS>
+          <DIAG-SERVICE ID="DS.ExtendedSession" SEMANTIC="SESSION">
+            <SHORT-NAME>ExtendedSession</SHORT-NAME>
+            <REQUEST-REF ID-REF="RQ.ExtendedSession"/>
+          </DIAG-SERVICE>
+          <DIAG-SERVICE ID="DS.ReadVIN" SEMANTIC="IDENTIFICATION">
+            <SHORT-NAME>ReadVIN</SHORT-NAME>
+            <REQUEST-REF ID-REF="RQ.ReadVIN"/>
+            <POS-RESPONSE-REFS><POS-RESPONSE-REF ID-REF="PR.ReadVIN"/></POS-RESPONSE-REFS>
+          </DIAG-SERVICE>
+          <DIAG-SERVICE ID="DS.ReadTemperature" SEMANTIC="CURRENTDATA">
+            <SHORT-NAME>ReadTemperature</SHORT-NAME>
+            <REQUEST-REF ID-REF="RQ.ReadTemperature"/>
+            <POS-RESPONSE-REFS><POS-RESPONSE-REF ID-REF="PR.ReadTemperature"/></POS-RESPONSE-REFS>
+          </DIAG-SERVICE>
+          <DIAG-SERVICE ID="DS.ReadStatus" SEMANTIC="CURRENTDATA">
+            <SHORT-NAME>ReadStatus</SHORT-NAME>
+            <REQUEST-REF ID-REF="RQ.ReadStatus"/>
+            <POS-RESPONSE-REFS><POS-RESPONSE-REF ID-REF="PR.ReadStatus"/></POS-RESPONSE-REFS>
+          </DIAG-SERVICE>
+          <DIAG-SERVICE ID="DS.ReadRatio" SEMANTIC="CURRENTDATA">
+            <SHORT-NAME>ReadRatio</SHORT-NAME>
+            <REQUEST-REF ID-REF="RQ.ReadRatio"/>
+            <POS-RESPONSE-REFS><POS-RESPONSE-REF ID-REF="PR.ReadRatio"/></POS-RESPONSE-REFS>
+          </DIAG-SERVICE>
+          <DIAG-SERVICE ID="DS.WriteGear" SEMANTIC="DATA-WRITE">
+            <SHORT-NAME>WriteGear</SHORT-NAME>
+            <REQUEST-REF ID-REF="RQ.WriteGear"/>
+          </DIAG-SERVICE>
+        </DIAG-COMMS>
+        <REQUESTS>
+          <REQUEST ID="RQ.ExtendedSession">
+            <SHORT-NAME>ExtendedSession</SHORT-NAME>
+            <PARAMS>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="SERVICE-ID"><SHORT-NAME>SID</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><CODED-VALUE>0x10</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="CODED-CONST"><SHORT-NAME>Session</SHORT-NAME><BYTE-POSITION>1</BYTE-POSITION><CODED-VALUE>3</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+            </PARAMS>
+          </REQUEST>
+          <REQUEST ID="RQ.ReadVIN">
+            <SHORT-NAME>ReadVIN</SHORT-NAME>
+            <PARAMS>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="SERVICE-ID"><SHORT-NAME>SID</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><CODED-VALUE>0x22</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="ID"><SHORT-NAME>DID</SHORT-NAME><BYTE-POSITION>1</BYTE-POSITION><CODED-VALUE>0xF190</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>16</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+            </PARAMS>
+          </REQUEST>
+          <REQUEST ID="RQ.ReadTemperature">
+            <SHORT-NAME>ReadTemperature</SHORT-NAME>
+            <PARAMS>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="SERVICE-ID"><SHORT-NAME>SID</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><CODED-VALUE>0x22</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="ID"><SHORT-NAME>DID</SHORT-NAME><BYTE-POSITION>1</BYTE-POSITION><CODED-VALUE>0x0D01</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>16</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+            </PARAMS>
+          </REQUEST>
+          <REQUEST ID="RQ.ReadStatus">
+            <SHORT-NAME>ReadStatus</SHORT-NAME>
+            <PARAMS>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="SERVICE-ID"><SHORT-NAME>SID</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><CODED-VALUE>0x22</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="ID"><SHORT-NAME>DID</SHORT-NAME><BYTE-POSITION>1</BYTE-POSITION><CODED-VALUE>0x0D03</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>16</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+            </PARAMS>
+          </REQUEST>
+          <REQUEST ID="RQ.ReadRatio">
+            <SHORT-NAME>ReadRatio</SHORT-NAME>
+            <PARAMS>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="SERVICE-ID"><SHORT-NAME>SID</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><CODED-VALUE>0x22</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="ID"><SHORT-NAME>DID</SHORT-NAME><BYTE-POSITION>1</BYTE-POSITION><CODED-VALUE>0x0D04</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>16</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+            </PARAMS>
+          </REQUEST>
+          <REQUEST ID="RQ.WriteGear">
+            <SHORT-NAME>WriteGear</SHORT-NAME>
+            <PARAMS>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="SERVICE-ID"><SHORT-NAME>SID</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><CODED-VALUE>0x2E</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="ID"><SHORT-NAME>DID</SHORT-NAME><BYTE-POSITION>1</BYTE-POSITION><CODED-VALUE>0x0D02</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>16</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="VALUE"><SHORT-NAME>Gear</SHORT-NAME><BYTE-POSITION>3</BYTE-POSITION><DOP-SNREF SHORT-NAME="Gear"/></PARAM>
+            </PARAMS>
+          </REQUEST>
+        </REQUESTS>
+        <POS-RESPONSES>
+          <POS-RESPONSE ID="PR.ReadVIN">
+            <SHORT-NAME>ReadVIN</SHORT-NAME>
+            <PARAMS>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="SERVICE-ID"><SHORT-NAME>SID</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><CODED-VALUE>0x62</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="VALUE"><SHORT-NAME>VIN</SHORT-NAME><BYTE-POSITION>3</BYTE-POSITION><DOP-REF ID-REF="DOP.VIN"/></PARAM>
+            </PARAMS>
+          </POS-RESPONSE>
+          <POS-RESPONSE ID="PR.ReadTemperature">
+            <SHORT-NAME>ReadTemperature</SHORT-NAME>
+            <PARAMS>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="SERVICE-ID"><SHORT-NAME>SID</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><CODED-VALUE>0x62</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="VALUE"><SHORT-NAME>Temperature</SHORT-NAME><BYTE-POSITION>3</BYTE-POSITION><DOP-REF ID-REF="DOP.Temperature"/></PARAM>
+            </PARAMS>
+          </POS-RESPONSE>
+          <POS-RESPONSE ID="PR.ReadStatus">
+            <SHORT-NAME>ReadStatus</SHORT-NAME>
+            <PARAMS>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="SERVICE-ID"><SHORT-NAME>SID</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><CODED-VALUE>0x62</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="VALUE"><SHORT-NAME>Gear</SHORT-NAME><BYTE-POSITION>3</BYTE-POSITION><DOP-REF ID-REF="DOP.Gear"/></PARAM>
+              <PARAM xsi:type="VALUE"><SHORT-NAME>Flag</SHORT-NAME><BYTE-POSITION>4</BYTE-POSITION><BIT-POSITION>0</BIT-POSITION><DOP-REF ID-REF="DOP.Flag"/></PARAM>
+              <PARAM xsi:type="VALUE"><SHORT-NAME>Mode</SHORT-NAME><BYTE-POSITION>4</BYTE-POSITION><BIT-POSITION>1</BIT-POSITION><DOP-REF ID-REF="DOP.Mode"/></PARAM>
+            </PARAMS>
+          </POS-RESPONSE>
+          <POS-RESPONSE ID="PR.ReadRatio">
+            <SHORT-NAME>ReadRatio</SHORT-NAME>
+            <PARAMS>
+              <PARAM xsi:type="CODED-CONST" SEMANTIC="SERVICE-ID"><SHORT-NAME>SID</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><CODED-VALUE>0x62</CODED-VALUE><DIAG-CODED-TYPE xsi:type="STANDARD-LENGTH-TYPE" BASE-DATA-TYPE="A_UINT32"><BIT-LENGTH>8</BIT-LENGTH></DIAG-CODED-TYPE></PARAM>
+              <PARAM xsi:type="VALUE"><SHORT-NAME>Ratio</SHORT-NAME><BYTE-POSITION>3</BYTE-POSITION><DOP-REF ID-REF="DOP.Ratio"/></PARAM>
+            </PARAMS>
+          </POS-RESPONSE>
+        </POS-RESPONSES>
+        <COMPARAM-REFS>
+          <COMPARAM-REF ID-REF="ISO_15765_2.CP_CanPhysReqId"><SIMPLE-VALUE>0x7E0</SIMPLE-VALUE></COMPARAM-REF>
+          <COMPARAM-REF ID-REF="ISO_15765_2.CP_CanRespUSDTId"><SIMPLE-VALUE>0x7E8</SIMPLE-VALUE></COMPARAM-REF>
+          <COMPARAM-REF ID-REF="ISO_15765_2.CP_CanFuncReqId"><SIMPLE-VALUE>0x7DF</SIMPLE-VALUE></COMPARAM-REF>
+          <COMPARAM-REF ID-REF="ISO_15765_2.CP_BlockSize"><SIMPLE-VALUE>8</SIMPLE-VALUE></COMPARAM-REF>
+          <COMPARAM-REF ID-REF="ISO_15765_2.CP_STmin"><SIMPLE-VALUE>500</SIMPLE-VALUE></COMPARAM-REF>
+          <COMPARAM-REF ID-REF="ISO_15765_2.CP_CanFillerByte"><SIMPLE-VALUE>0xAA</SIMPLE-VALUE></COMPARAM-REF>
+          <COMPARAM-REF ID-REF="ISO_15765_2.CP_CanFillerByteHandling"><SIMPLE-VALUE>1</SIMPLE-VALUE></COMPARAM-REF>
+        </COMPARAM-REFS>
+      </BASE-VARIANT>
+    </BASE-VARIANTS>
+    <ECU-VARIANTS>
+      <ECU-VARIANT ID="EV.EngineControl_V2">
+        <SHORT-NAME>EngineControl_V2</SHORT-NAME>
+        <COMPARAM-REFS>
+          <COMPARAM-REF ID-REF="ISO_15765_2.CP_CanRespUSDTId"><SIMPLE-VALUE>0x18DAF110</SIMPLE-VALUE></COMPARAM-REF>
+          <COMPARAM-REF ID-REF="ISO_15765_2.CP_STmin"><SIMPLE-VALUE>20000</SIMPLE-VALUE></COMPARAM-REF>
+        </COMPARAM-REFS>
+        <PARENT-REFS>
+          <PARENT-REF ID-REF="BV.EngineControl" xsi:type="BASE-VARIANT-REF"/>
+        </PARENT-REFS>
+      </ECU-VARIANT>
+    </ECU-VARIANTS>
+  </DIAG-LAYER-CONTAINER>
+</ODX>
+"#;
+
+    #[test]
+    fn reads_services() {
+        let database = OdxDatabase::parse(ENGINE).unwrap();
+        let names: Vec<&str> = database.ecus.iter().map(|ecu| ecu.name.as_str()).collect();
+        assert_eq!(names, ["EngineControl", "EngineControl_V2"]);
+        let ecu = database.ecu("EngineControl").unwrap();
+        let services: Vec<(&str, Option<&str>, &[u8])> = ecu
+            .services
+            .iter()
+            .map(|service| {
+                (
+                    service.name.as_str(),
+                    service.semantic.as_deref(),
+                    &service.request[..],
+                )
+            })
+            .collect();
+        assert_eq!(
+            services,
+            [
+                ("ExtendedSession", Some("SESSION"), &[0x10, 0x03][..]),
+                ("ReadVIN", Some("IDENTIFICATION"), &[0x22, 0xF1, 0x90]),
+                ("ReadTemperature", Some("CURRENTDATA"), &[0x22, 0x0D, 0x01]),
+                ("ReadStatus", Some("CURRENTDATA"), &[0x22, 0x0D, 0x03]),
+                ("ReadRatio", Some("CURRENTDATA"), &[0x22, 0x0D, 0x04]),
+                ("WriteGear", Some("DATA-WRITE"), &[0x2E, 0x0D, 0x02]),
+            ]
+        );
+        assert_eq!(ecu.services[0].did(), None);
+        assert_eq!(ecu.services[1].did(), Some(0xF190));
+        // services of the base variant are inherited
+        let variant = database.ecu("EngineControl_V2").unwrap();
+        assert_eq!(variant.services, ecu.services);
+        assert!(database.ecu("Transmission").is_none());
+    }
+
+    #[test]
+    fn reads_dids() {
+        let database = OdxDatabase::parse(ENGINE).unwrap();
+        let ecu = database.ecu("EngineControl").unwrap();
+        let cases = [
+            (0xF190, b"WVWZZZ1JZXW000001".to_vec(), "WVWZZZ1JZXW000001"),
+            (0x0D01, vec![0x1F, 0x40], "40 °C"),
+            (0x0D02, vec![0x01], "First"),
+            (
+                0x0D03,
+                vec![0x00, 0x0B],
+                "{Gear: Neutral, Flag_Mode: Flag=1, Mode=5}",
+            ),
+        ];
+        for (did, data, text) in cases {
+            let value = ecu.dids.decode(did, &data).unwrap();
+            assert_eq!(value.to_string(), text);
+            assert_eq!(ecu.dids.encode(did, &value).unwrap(), data);
+        }
+        assert_eq!(ecu.dids.get(0x0D02).unwrap().name, "WriteGear");
+        assert!(matches!(
+            ecu.dids.decode(0x0D01, &[0x1F, 0x40]).unwrap(),
+            Value::Number { raw: 8000, .. }
+        ));
+        assert_eq!(
+            ecu.dids.get(0x0D03).unwrap().codec,
+            Codec::Struct {
+                fields: vec![
+                    StructField {
+                        name: "Gear".to_string(),
+                        codec: Codec::Enum {
+                            bytes: 1,
+                            values: vec![
+                                EnumValue {
+                                    value: 0,
+                                    name: "Neutral".to_string(),
+                                },
+                                EnumValue {
+                                    value: 1,
+                                    name: "First".to_string(),
+                                },
+                            ],
+                        },
+                    },
+                    StructField {
+                        name: "Flag_Mode".to_string(),
+                        codec: Codec::Bitfield {
+                            bytes: 1,
+                            fields: vec![
+                                BitField {
+                                    name: "Flag".to_string(),
+                                    bit: 0,
+                                    width: 1,
+                                },
+                                BitField {
+                                    name: "Mode".to_string(),
+                                    bit: 1,
+                                    width: 3,
+                                },
+                            ],
+                        },
+                    },
+                ],
+            }
+        );
+        assert_eq!(ecu.dids.len(), 4);
+        assert_eq!(
+            ecu.unsupported,
+            [(
+                0x0D04,
+                "ReadRatio: Ratio of type A_FLOAT32 not supported".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn invalid_bit_position_is_unsupported() {
+        let xml = ENGINE.replace(
+            "<BIT-POSITION>1</BIT-POSITION>",
+            "<BIT-POSITION>9</BIT-POSITION>",
+        );
+        let database = OdxDatabase::parse(&xml).unwrap();
+        let ecu = database.ecu("EngineControl").unwrap();
+        assert!(ecu.dids.get(0x0D03).is_none());
+        assert_eq!(
+            ecu.unsupported[0],
+            (
+                0x0D03,
+                "ReadStatus: Mode has BIT-POSITION 9 and 3 bits".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn cyclic_structure_is_unsupported() {
+        let structure = r#"</DATA-OBJECT-PROPS>
+          <STRUCTURES>
+            <STRUCTURE ID="STR.Loop">
+              <SHORT-NAME>Loop</SHORT-NAME>
+              <PARAMS>
+                <PARAM xsi:type="VALUE"><SHORT-NAME>Inner</SHORT-NAME><BYTE-POSITION>0</BYTE-POSITION><DOP-REF ID-REF="STR.Loop"/></PARAM>
+              </PARAMS>
+            </STRUCTURE>
+          </STRUCTURES>"#;
+        let xml = ENGINE.replace("</DATA-OBJECT-PROPS>", structure).replace(
+            r#"<DOP-REF ID-REF="DOP.Ratio"/>"#,
+            r#"<DOP-REF ID-REF="STR.Loop"/>"#,
+        );
+        let database = OdxDatabase::parse(&xml).unwrap();
+        let ecu = database.ecu("EngineControl").unwrap();
+        assert!(ecu.dids.get(0x0D04).is_none());
+        assert_eq!(ecu.dids.len(), 4);
+        assert_eq!(
+            ecu.unsupported,
+            [(
+                0x0D04,
+                "ReadRatio: STRUCTURE Loop nested more than 16 levels deep".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn reads_comparams() {
+        let database = OdxDatabase::parse(ENGINE).unwrap();
+        let base = database.ecu("EngineControl").unwrap().isotp;
+        assert_eq!(
+            base,
+            IsoTpConfig {
+                request_id: Some(id_from_raw(0x7E0)),
+                response_id: Some(id_from_raw(0x7E8)),
+                functional_id: Some(id_from_raw(0x7DF)),
+                request_ext_address: None,
+                response_ext_address: None,
+                block_size: Some(8),
+                stmin: Some(0xF5),
+                padding: Some(0xAA),
+            }
+        );
+        // the variant overrides COMPARAMs of its base variant
+        let variant = database.ecu("EngineControl_V2").unwrap().isotp;
+        assert_eq!(
+            variant.response_id,
+            Some(id_from_raw(0x18DAF110 | EFF_FLAG))
+        );
+        assert_eq!(variant.stmin, Some(20));
+        assert_eq!(variant.block_size, Some(8));
+
+        let xml = ENGINE.replace(
+            "CP_CanFillerByteHandling\"><SIMPLE-VALUE>1",
+            "CP_CanFillerByteHandling\"><SIMPLE-VALUE>0",
+        );
+        let database = OdxDatabase::parse(&xml).unwrap();
+        assert_eq!(database.ecus[0].isotp.padding, None);
+    }
+
+    #[test]
+    fn stmin_conversion() {
+        let cases = [
+            (0, 0x00),
+            (50, 0xF1),
+            (100, 0xF1),
+            (500, 0xF5),
+            (900, 0xF9),
+            (901, 0x01),
+            (1000, 0x01),
+            (20000, 0x14),
+            (127_000, 0x7F),
+            (200_000, 0x7F),
+        ];
+        for (micros, stmin) in cases {
+            assert_eq!(stmin_from_micros(micros), stmin, "{micros}");
+        }
+    }
+
+    #[test]
+    fn invalid_documents() {
+        assert!(OdxDatabase::parse("<ODX").is_err());
+        assert!(OdxDatabase::parse("<ODX></ODX>").is_err());
+    }
+}
@@ -0,0 +1,622 @@
+//! pcap / pcapng export and import of CAN frames
+//!
+//! Frames are stored with `LINKTYPE_CAN_SOCKETCAN`, so Wireshark dissects the ISO-TP
+//! and UDS content without any further configuration. The [PcapngWriter] writes raw
+//! frames handed to it, e.g. by a sniffer, or synthesizes the frames of the PDUs
+//! sent and received by a [RecordingTransport](crate::trace::RecordingTransport).
+//! Timestamps are written with nanosecond resolution.
+//!
+//! [read_pcap] loads classic pcap as well as pcapng files, [load_replay] turns a
+//! capture into a [ReplayTransport] for one pair of CAN ids.
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//! use tokio_socketcan_isotp::pcap::PcapngWriter;
+//! use tokio_socketcan_isotp::trace::RecordingTransport;
+//! use tokio_socketcan_isotp::{IsoTpSocket, StandardId};
+//!
+//! # fn run() -> std::io::Result<()> {
+//! let socket = IsoTpSocket::open(
+//!     "vcan0",
+//!     StandardId::new(0x7E8).unwrap(),
+//!     StandardId::new(0x7E0).unwrap(),
+//! )
+//! .unwrap();
+//! let socket = RecordingTransport::new(socket, Arc::new(PcapngWriter::create("session.pcapng")?));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::frame::{flow_control, segment, CanFrame, Reassembler, SegmentOptions, CANFD_MAX_DLEN};
+use crate::trace::{
+    read_exact_or_eof, records_from_frames, Direction, ReplayTransport, TraceRecord, TraceSink,
+};
+use crate::{id_from_raw, id_to_raw, Id, CAN_MAX_DLEN, ERR_FLAG, RTR_FLAG};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Link type of frames with the socketcan pseudo header
+pub const LINKTYPE_CAN_SOCKETCAN: u16 = 227;
+
+/// Marks a CAN FD frame in the socketcan pseudo header
+const CANFD_FDF: u8 = 0x04;
+
+/// Size of the socketcan pseudo header preceding the frame data
+const SOCKETCAN_HEADER_LEN: usize = 8;
+
+const PCAPNG_SHB: u32 = 0x0A0D_0D0A;
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_SPB: u32 = 0x0000_0003;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_OPT_END: u16 = 0;
+const PCAPNG_OPT_IF_TSRESOL: u16 = 9;
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+
+/// Writes CAN frames into a pcapng file
+///
+/// The writer is internally synchronized and implements [TraceSink], so it can be
+/// shared between several recording transports.
+pub struct PcapngWriter {
+    writer: Mutex<Box<dyn Write + Send>>,
+    segment_options: SegmentOptions,
+}
+
+impl PcapngWriter {
+    /// Start a capture on the given writer, writing the section and interface headers
+    pub fn new(writer: impl Write + Send + 'static) -> io::Result<Self> {
+        let mut writer: Box<dyn Write + Send> = Box::new(writer);
+
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // section length not specified
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, PCAPNG_SHB, &shb)?;
+
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_CAN_SOCKETCAN.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&((SOCKETCAN_HEADER_LEN + CANFD_MAX_DLEN) as u32).to_le_bytes());
+        // nanosecond timestamps
+        idb.extend_from_slice(&PCAPNG_OPT_IF_TSRESOL.to_le_bytes());
+        idb.extend_from_slice(&1u16.to_le_bytes());
+        idb.extend_from_slice(&[9, 0, 0, 0]);
+        idb.extend_from_slice(&PCAPNG_OPT_END.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        write_block(&mut writer, PCAPNG_IDB, &idb)?;
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+            segment_options: SegmentOptions::default(),
+        })
+    }
+
+    /// Create (or truncate) a capture file
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Segmentation used when synthesizing the frames of a PDU
+    pub fn with_segment_options(mut self, segment_options: SegmentOptions) -> Self {
+        self.segment_options = segment_options;
+        self
+    }
+
+    /// Append one frame
+    pub fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        let data_len = if frame.fd {
+            CANFD_MAX_DLEN
+        } else {
+            CAN_MAX_DLEN as usize
+        };
+        if frame.data.len() > data_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("frame of {} bytes", frame.data.len()),
+            ));
+        }
+
+        let mut packet = Vec::with_capacity(SOCKETCAN_HEADER_LEN + data_len);
+        packet.extend_from_slice(&id_to_raw(frame.id).to_be_bytes());
+        packet.push(frame.data.len() as u8);
+        packet.push(if frame.fd { CANFD_FDF } else { 0 });
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(&frame.data);
+        packet.resize(SOCKETCAN_HEADER_LEN + data_len, 0);
+
+        let nanos = frame
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut epb = Vec::with_capacity(20 + packet.len());
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((nanos >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(nanos as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&packet);
+        write_block(&mut *self.writer.lock().unwrap(), PCAPNG_EPB, &epb)
+    }
+
+    /// Append the frames a PDU is transferred in, including the receiver's flow control
+    pub fn write_record(&self, record: &TraceRecord) -> io::Result<()> {
+        let (id, peer_id) = match record.direction {
+            Direction::Tx => (record.tx_id, record.rx_id),
+            Direction::Rx => (record.rx_id, record.tx_id),
+        };
+        let fd = self.segment_options.tx_dl > CAN_MAX_DLEN as usize;
+        let frame = |id: Id, data: Vec<u8>| CanFrame {
+            timestamp: record.timestamp,
+            id,
+            data,
+            fd,
+        };
+
+        let frames = segment(&record.data, &self.segment_options);
+        let multi_frame = frames.len() > 1;
+        for (index, data) in frames.into_iter().enumerate() {
+            self.write_frame(&frame(id, data))?;
+            if index == 0 && multi_frame {
+                let fc = flow_control(0, 0, &self.segment_options);
+                self.write_frame(&frame(peer_id, fc))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush buffered frames to the underlying writer
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+impl TraceSink for PcapngWriter {
+    fn record(&self, record: &TraceRecord) -> io::Result<()> {
+        self.write_record(record)
+    }
+}
+
+impl Drop for PcapngWriter {
+    fn drop(&mut self) {
+        self.flush().ok(); // ignore result
+    }
+}
+
+fn write_block(writer: &mut dyn Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let padding = (4 - body.len() % 4) % 4;
+    let total_len = (12 + body.len() + padding) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&[0u8; 3][..padding])?;
+    writer.write_all(&total_len.to_le_bytes())
+}
+
+/// Read all CAN frames of a pcap or pcapng capture
+///
+/// Packets of interfaces with a link type other than `LINKTYPE_CAN_SOCKETCAN` are skipped,
+/// as are remote and error frames.
+pub fn read_pcap(reader: impl Read) -> io::Result<Vec<CanFrame>> {
+    let mut reader = BufReader::new(reader);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if u32::from_le_bytes(magic) == PCAPNG_SHB {
+        read_pcapng(reader, magic)
+    } else {
+        read_classic(reader, magic)
+    }
+}
+
+/// Read all CAN frames of a capture file
+pub fn load_pcap(path: impl AsRef<Path>) -> io::Result<Vec<CanFrame>> {
+    read_pcap(File::open(path)?)
+}
+
+/// Replay the PDUs of the transport bound to `rx_id`/`tx_id` from a capture file
+pub fn load_replay(path: impl AsRef<Path>, rx_id: Id, tx_id: Id) -> io::Result<ReplayTransport> {
+    let frames = load_pcap(path)?;
+    let records = records_from_frames(&frames, rx_id, tx_id, Reassembler::new());
+    Ok(ReplayTransport::for_ids(records, rx_id, tx_id))
+}
+
+#[derive(Clone, Copy)]
+enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn u16(self, bytes: &[u8]) -> u16 {
+        let bytes = bytes[..2].try_into().unwrap();
+        match self {
+            Endian::Little => u16::from_le_bytes(bytes),
+            Endian::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes = bytes[..4].try_into().unwrap();
+        match self {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    /// Field of a block body, which may be shorter than its type requires
+    fn u32_at(self, body: &[u8], at: usize) -> io::Result<u32> {
+        body.get(at..at + 4)
+            .map(|bytes| self.u32(bytes))
+            .ok_or_else(|| invalid_data("truncated block".into()))
+    }
+}
+
+fn read_classic(mut reader: impl Read, magic: [u8; 4]) -> io::Result<Vec<CanFrame>> {
+    let (endian, nanos) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+        (PCAP_MAGIC_MICROS, _) => (Endian::Little, false),
+        (PCAP_MAGIC_NANOS, _) => (Endian::Little, true),
+        (_, PCAP_MAGIC_MICROS) => (Endian::Big, false),
+        (_, PCAP_MAGIC_NANOS) => (Endian::Big, true),
+        _ => return Err(invalid_data("not a pcap or pcapng file".into())),
+    };
+    let mut header = [0u8; 20];
+    reader.read_exact(&mut header)?;
+    let link_type = endian.u32(&header[16..]);
+    if link_type != u32::from(LINKTYPE_CAN_SOCKETCAN) {
+        return Err(invalid_data(format!("unsupported link type {link_type}")));
+    }
+
+    let mut frames = Vec::new();
+    let mut record = [0u8; 16];
+    loop {
+        if !read_exact_or_eof(&mut reader, &mut record)? {
+            return Ok(frames);
+        }
+        let seconds = u64::from(endian.u32(&record[0..]));
+        let fraction = u64::from(endian.u32(&record[4..]));
+        let captured_len = endian.u32(&record[8..]) as usize;
+        let packet = read_len(&mut reader, captured_len)?;
+
+        let fraction = if nanos { fraction } else { fraction * 1_000 };
+        let timestamp = UNIX_EPOCH + Duration::from_secs(seconds) + Duration::from_nanos(fraction);
+        frames.extend(parse_socketcan(&packet, timestamp)?);
+    }
+}
+
+struct Interface {
+    link_type: u16,
+    /// if_tsresol option
+    resolution: u8,
+}
+
+fn read_pcapng(mut reader: impl Read, first_type: [u8; 4]) -> io::Result<Vec<CanFrame>> {
+    let mut frames = Vec::new();
+    let mut interfaces = Vec::new();
+    let mut endian = Endian::Little;
+    let mut block_type = first_type;
+
+    loop {
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len)?;
+        if u32::from_le_bytes(block_type) == PCAPNG_SHB {
+            // the byte order magic follows the length
+            let mut order = [0u8; 4];
+            reader.read_exact(&mut order)?;
+            endian = if u32::from_le_bytes(order) == PCAPNG_BYTE_ORDER_MAGIC {
+                Endian::Little
+            } else {
+                Endian::Big
+            };
+            interfaces.clear();
+            let total_len = endian.u32(&len) as usize;
+            skip(&mut reader, total_len.saturating_sub(12))?;
+        } else {
+            let total_len = endian.u32(&len) as usize;
+            if total_len < 12 {
+                return Err(invalid_data(format!("invalid block length {total_len}")));
+            }
+            let body = read_len(&mut reader, total_len - 8)?;
+            let body = &body[..body.len() - 4];
+            match endian.u32(&block_type) {
+                PCAPNG_IDB => interfaces.push(parse_interface(body, endian)?),
+                PCAPNG_EPB => {
+                    let interface = interfaces
+                        .get(endian.u32_at(body, 0)? as usize)
+                        .ok_or_else(|| invalid_data("packet of unknown interface".into()))?;
+                    if interface.link_type == LINKTYPE_CAN_SOCKETCAN {
+                        let ticks = (u64::from(endian.u32_at(body, 4)?) << 32)
+                            | u64::from(endian.u32_at(body, 8)?);
+                        let captured_len = endian.u32_at(body, 12)? as usize;
+                        let packet = body
+                            .get(20..)
+                            .and_then(|packet| packet.get(..captured_len))
+                            .ok_or_else(|| invalid_data("truncated packet".into()))?;
+                        let timestamp =
+                            UNIX_EPOCH + ticks_to_duration(ticks, interface.resolution)?;
+                        frames.extend(parse_socketcan(packet, timestamp)?);
+                    }
+                }
+                PCAPNG_SPB
+                    if interfaces
+                        .first()
+                        .is_some_and(|interface| interface.link_type == LINKTYPE_CAN_SOCKETCAN) =>
+                {
+                    // simple packets carry no timestamp
+                    let original_len = endian.u32_at(body, 0)? as usize;
+                    let packet = &body[4..];
+                    let packet = &packet[..packet.len().min(original_len)];
+                    frames.extend(parse_socketcan(packet, UNIX_EPOCH)?);
+                }
+                _ => {}
+            }
+        }
+
+        if !read_exact_or_eof(&mut reader, &mut block_type)? {
+            return Ok(frames);
+        }
+    }
+}
+
+fn parse_interface(body: &[u8], endian: Endian) -> io::Result<Interface> {
+    if body.len() < 8 {
+        return Err(invalid_data("truncated interface block".into()));
+    }
+    let mut interface = Interface {
+        link_type: endian.u16(body),
+        resolution: 6,
+    };
+    let mut options = &body[8..];
+    while options.len() >= 4 {
+        let code = endian.u16(options);
+        let len = endian.u16(&options[2..]) as usize;
+        if code == PCAPNG_OPT_END {
+            break;
+        }
+        if code == PCAPNG_OPT_IF_TSRESOL && len == 1 && options.len() > 4 {
+            interface.resolution = options[4];
+        }
+        let padded = 4 + len + (4 - len % 4) % 4;
+        options = options.get(padded..).unwrap_or_default();
+    }
+    Ok(interface)
+}
+
+/// Time since the epoch of a timestamp in units of the if_tsresol option
+fn ticks_to_duration(ticks: u64, resolution: u8) -> io::Result<Duration> {
+    let nanos = if resolution & 0x80 != 0 {
+        let shift = u32::from(resolution & 0x7F);
+        u64::try_from((u128::from(ticks) * 1_000_000_000) >> shift).ok()
+    } else if resolution <= 9 {
+        10u64
+            .checked_pow(9 - u32::from(resolution))
+            .and_then(|factor| ticks.checked_mul(factor))
+    } else {
+        // units finer than 10^-28 s leave nothing of a u64 but zero
+        Some(
+            10u64
+                .checked_pow(u32::from(resolution) - 9)
+                .map_or(0, |divisor| ticks / divisor),
+        )
+    };
+    nanos
+        .map(Duration::from_nanos)
+        .ok_or_else(|| invalid_data(format!("timestamp {ticks} out of range")))
+}
+
+/// Data frame of a packet, `None` for remote and error frames
+fn parse_socketcan(packet: &[u8], timestamp: SystemTime) -> io::Result<Option<CanFrame>> {
+    if packet.len() < SOCKETCAN_HEADER_LEN {
+        return Err(invalid_data("truncated socketcan header".into()));
+    }
+    let raw_id = u32::from_be_bytes(packet[0..4].try_into().unwrap());
+    if raw_id & (ERR_FLAG | RTR_FLAG) != 0 {
+        return Ok(None);
+    }
+    let len = usize::from(packet[4]);
+    let data = packet
+        .get(SOCKETCAN_HEADER_LEN..SOCKETCAN_HEADER_LEN + len)
+        .ok_or_else(|| invalid_data("truncated frame data".into()))?;
+    Ok(Some(CanFrame {
+        timestamp,
+        id: id_from_raw(raw_id),
+        data: data.to_vec(),
+        fd: packet[5] & CANFD_FDF != 0
+            || len > CAN_MAX_DLEN as usize
+            || packet.len() == SOCKETCAN_HEADER_LEN + CANFD_MAX_DLEN,
+    }))
+}
+
+/// Read `len` bytes, allocating only as much as the input actually holds
+fn read_len(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(len as u64).read_to_end(&mut data)?;
+    if data.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "truncated capture",
+        ));
+    }
+    Ok(data)
+}
+
+fn skip(reader: &mut impl Read, len: usize) -> io::Result<()> {
+    io::copy(&mut reader.take(len as u64), &mut io::sink())?;
+    Ok(())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::tests::SharedBuffer;
+    use crate::StandardId;
+
+    fn id(raw: u16) -> Id {
+        Id::Standard(StandardId::new(raw).unwrap())
+    }
+
+    fn frames() -> Vec<CanFrame> {
+        let start = UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789);
+        vec![
+            CanFrame::new(start, id(0x7E0), vec![0x03, 0x22, 0xF1, 0x90]),
+            CanFrame::new(
+                start + Duration::from_millis(10),
+                id(0x7E8),
+                (0..64).collect(),
+            ),
+        ]
+    }
+
+    fn capture() -> Vec<u8> {
+        let buffer = SharedBuffer::default();
+        let writer = PcapngWriter::new(buffer.clone()).unwrap();
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+        drop(writer);
+        buffer.bytes()
+    }
+
+    #[test]
+    fn pcapng_round_trip() {
+        let frames = read_pcap(&capture()[..]).unwrap();
+        assert_eq!(frames, self::frames());
+        assert!(!frames[0].fd);
+        assert!(frames[1].fd);
+    }
+
+    #[test]
+    fn synthesized_record_frames() {
+        let buffer = SharedBuffer::default();
+        let writer = PcapngWriter::new(buffer.clone()).unwrap();
+        let record = TraceRecord {
+            timestamp: UNIX_EPOCH,
+            direction: Direction::Tx,
+            rx_id: id(0x7E8),
+            tx_id: id(0x7E0),
+            data: (0..20).collect(),
+        };
+        writer.write_record(&record).unwrap();
+        drop(writer);
+        let frames = read_pcap(&buffer.bytes()[..]).unwrap();
+        // first frame, flow control, 2 consecutive frames
+        let ids: Vec<_> = frames.iter().map(|frame| frame.id).collect();
+        assert_eq!(ids, [id(0x7E0), id(0x7E8), id(0x7E0), id(0x7E0)]);
+        assert_eq!(frames[0].data[..2], [0x10, 20]);
+        assert_eq!(frames[1].data[0], 0x30);
+    }
+
+    #[test]
+    fn classic_pcap() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&PCAP_MAGIC_MICROS.to_be_bytes());
+        bytes.extend_from_slice(&[0, 2, 0, 4]);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&72u32.to_be_bytes());
+        bytes.extend_from_slice(&u32::from(LINKTYPE_CAN_SOCKETCAN).to_be_bytes());
+        let packet = [0, 0, 0x07, 0xE8, 3, 0, 0, 0, 0x02, 0x50, 0x03];
+        bytes.extend_from_slice(&10u32.to_be_bytes());
+        bytes.extend_from_slice(&500u32.to_be_bytes());
+        bytes.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&packet);
+
+        let frames = read_pcap(&bytes[..]).unwrap();
+        assert_eq!(
+            frames,
+            [CanFrame::new(
+                UNIX_EPOCH + Duration::from_micros(10_000_500),
+                id(0x7E8),
+                vec![0x02, 0x50, 0x03]
+            )]
+        );
+        let err = read_pcap(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn skips_remote_and_error_frames() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&PCAP_MAGIC_MICROS.to_le_bytes());
+        bytes.extend_from_slice(&[2, 0, 4, 0]);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&72u32.to_le_bytes());
+        bytes.extend_from_slice(&u32::from(LINKTYPE_CAN_SOCKETCAN).to_le_bytes());
+        for raw_id in [0x7E0 | RTR_FLAG, 0x004 | ERR_FLAG, 0x7E8] {
+            let mut packet = u32::to_be_bytes(raw_id).to_vec();
+            packet.extend_from_slice(&[3, 0, 0, 0, 0x02, 0x50, 0x03]);
+            bytes.extend_from_slice(&[0; 8]);
+            bytes.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&packet);
+        }
+
+        assert_eq!(
+            read_pcap(&bytes[..]).unwrap(),
+            [CanFrame::new(UNIX_EPOCH, id(0x7E8), vec![0x02, 0x50, 0x03])]
+        );
+    }
+
+    #[test]
+    fn truncated_captures_fail_without_panic() {
+        let bytes = capture();
+        for len in 0..bytes.len() {
+            // a capture cut at a block boundary holds the frames before it
+            if let Ok(frames) = read_pcap(&bytes[..len]) {
+                assert!(self::frames().starts_with(&frames), "{len} bytes");
+            }
+        }
+    }
+
+    #[test]
+    fn short_blocks() {
+        let mut bytes = capture();
+        // block framing, packet fields and the padded classic and FD frame
+        let header_len = bytes.len() - (12 + 20 + 16) - (12 + 20 + 72);
+        bytes.truncate(header_len);
+        // enhanced packet block without any fields
+        bytes.extend_from_slice(&PCAPNG_EPB.to_le_bytes());
+        bytes.extend_from_slice(&12u32.to_le_bytes());
+        bytes.extend_from_slice(&12u32.to_le_bytes());
+        assert_eq!(
+            read_pcap(&bytes[..]).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // simple packet block claiming 4 GiB
+        bytes.truncate(header_len);
+        bytes.extend_from_slice(&PCAPNG_SPB.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            read_pcap(&bytes[..]).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn timestamp_resolutions() {
+        assert_eq!(ticks_to_duration(5, 6).unwrap(), Duration::from_micros(5));
+        assert_eq!(ticks_to_duration(5, 9).unwrap(), Duration::from_nanos(5));
+        assert_eq!(
+            ticks_to_duration(5000, 12).unwrap(),
+            Duration::from_nanos(5)
+        );
+        assert_eq!(
+            ticks_to_duration(1 << 10, 0x8A).unwrap(),
+            Duration::from_secs(1)
+        );
+        assert_eq!(ticks_to_duration(u64::MAX, 40).unwrap(), Duration::ZERO);
+        assert!(ticks_to_duration(u64::MAX, 0).is_err());
+        assert!(ticks_to_duration(u64::MAX, 0x80).is_err());
+    }
+}
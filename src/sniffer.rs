@@ -0,0 +1,187 @@
+//! Passive observation of ISO-TP conversations
+//!
+//! An [IsoTpSniffer] opens two sockets in listen mode, one for each direction of the
+//! conversation between the ids `a` and `b`. The kernel reassembles the PDUs without
+//! sending flow control, so the peers are not disturbed.
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::sniffer::IsoTpSniffer;
+//! use tokio_socketcan_isotp::StandardId;
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let sniffer = IsoTpSniffer::open(
+//!     "vcan0",
+//!     StandardId::new(0x7E0).unwrap(),
+//!     StandardId::new(0x7E8).unwrap(),
+//! )?;
+//! loop {
+//!     let pdu = sniffer.recv().await?;
+//!     println!("{:?} -> {:?}: {:02X?}", pdu.tx_id, pdu.rx_id, pdu.data);
+//! }
+//! # }
+//! ```
+
+use crate::{Error, Id, IsoTpBehaviour, IsoTpOptions, IsoTpSocket, LinkLayerOptions};
+use futures::future::{select, Either};
+use std::io;
+use std::time::SystemTime;
+
+/// PDU observed by an [IsoTpSniffer]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SniffedPdu {
+    /// Time of reception of the last frame
+    pub timestamp: SystemTime,
+    /// CAN id the PDU was sent on
+    pub tx_id: Id,
+    /// CAN id of the peer, the flow control of the PDU is sent on it
+    pub rx_id: Id,
+    pub data: Vec<u8>,
+}
+
+/// Listen-mode sockets for both directions of a conversation
+pub struct IsoTpSniffer {
+    /// Receives the PDUs sent on `a`
+    from_a: IsoTpSocket,
+    /// Receives the PDUs sent on `b`
+    from_b: IsoTpSocket,
+}
+
+impl IsoTpSniffer {
+    /// Observe the conversation of `a` and `b` on a named CAN device
+    pub fn open(ifname: &str, a: impl Into<Id>, b: impl Into<Id>) -> Result<Self, Error> {
+        Self::open_with_opts(ifname, a, b, None, None)
+    }
+
+    /// Observe with the options of the node sending on `a`
+    ///
+    /// `ext_address` of the options is the address in front of the frames sent on `a`,
+    /// `rx_ext_address` the one in front of the frames sent on `b`. The listen mode flag
+    /// is added.
+    pub fn open_with_opts(
+        ifname: &str,
+        a: impl Into<Id>,
+        b: impl Into<Id>,
+        isotp_options: Option<IsoTpOptions>,
+        link_layer_options: Option<LinkLayerOptions>,
+    ) -> Result<Self, Error> {
+        let (a, b) = (a.into(), b.into());
+        let (from_a, from_b) = listen_options(isotp_options.unwrap_or_default());
+        Ok(Self {
+            from_a: IsoTpSocket::open_with_opts(
+                ifname,
+                a,
+                b,
+                Some(from_a),
+                None,
+                link_layer_options,
+            )?,
+            from_b: IsoTpSocket::open_with_opts(
+                ifname,
+                b,
+                a,
+                Some(from_b),
+                None,
+                link_layer_options,
+            )?,
+        })
+    }
+
+    /// Receive the next PDU of either direction
+    pub async fn recv(&self) -> io::Result<SniffedPdu> {
+        let (socket, data) =
+            match select(self.from_a.read_packet(), self.from_b.read_packet()).await {
+                Either::Left((data, _)) => (&self.from_a, data?),
+                Either::Right((data, _)) => (&self.from_b, data?),
+            };
+        Ok(SniffedPdu {
+            timestamp: SystemTime::now(),
+            tx_id: socket.rx_id(),
+            rx_id: socket.tx_id(),
+            data,
+        })
+    }
+}
+
+/// Options of the sockets receiving the PDUs sent on `a` and on `b`
+fn listen_options(options: IsoTpOptions) -> (IsoTpOptions, IsoTpOptions) {
+    let flags = options.get_flags().map_or(0, |flags| flags.bits())
+        | IsoTpBehaviour::CAN_ISOTP_LISTEN_MODE.bits();
+    let with = |extra: IsoTpBehaviour| IsoTpBehaviour::from_bits_retain(flags | extra.bits());
+
+    // frames sent on b carry rx_ext_address, or ext_address without a separate one,
+    // which is how a socket with the options receives anyway
+    let mut from_b = options;
+    from_b.set_flags(with(IsoTpBehaviour::empty()));
+
+    // frames sent on a carry ext_address
+    let mut from_a = options;
+    if flags & IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR.bits() != 0 {
+        from_a.set_rx_ext_address(options.get_ext_address());
+        from_a.set_flags(with(IsoTpBehaviour::CAN_ISOTP_RX_EXT_ADDR));
+    } else {
+        from_a.set_flags(with(IsoTpBehaviour::empty()));
+    }
+    (from_a, from_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(options: &IsoTpOptions) -> u32 {
+        options.get_flags().unwrap().bits()
+    }
+
+    #[test]
+    fn listen_mode_is_added() {
+        let (from_a, from_b) = listen_options(IsoTpOptions::default());
+        assert_eq!(flags(&from_a), IsoTpBehaviour::CAN_ISOTP_LISTEN_MODE.bits());
+        assert_eq!(flags(&from_b), IsoTpBehaviour::CAN_ISOTP_LISTEN_MODE.bits());
+    }
+
+    #[test]
+    fn extended_addresses_of_both_directions() {
+        let mut options = IsoTpOptions::default();
+        options.set_flags(
+            IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR | IsoTpBehaviour::CAN_ISOTP_TX_PADDING,
+        );
+        options.set_ext_address(0x10);
+        options.set_rx_ext_address(0xF1);
+        let (from_a, from_b) = listen_options(options);
+
+        // the frames sent on a start with the target address of the node
+        assert_eq!(
+            flags(&from_a),
+            (IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR
+                | IsoTpBehaviour::CAN_ISOTP_TX_PADDING
+                | IsoTpBehaviour::CAN_ISOTP_LISTEN_MODE
+                | IsoTpBehaviour::CAN_ISOTP_RX_EXT_ADDR)
+                .bits()
+        );
+        assert_eq!(from_a.get_ext_address(), 0x10);
+        assert_eq!(from_a.get_rx_ext_address(), 0x10);
+
+        // the frames sent on b are received as by the node itself
+        assert_eq!(
+            flags(&from_b),
+            (IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR
+                | IsoTpBehaviour::CAN_ISOTP_TX_PADDING
+                | IsoTpBehaviour::CAN_ISOTP_LISTEN_MODE)
+                .bits()
+        );
+        assert_eq!(from_b.get_ext_address(), 0x10);
+        assert_eq!(from_b.get_rx_ext_address(), 0xF1);
+
+        // a separate rx address of the node stays in place for b
+        options.set_flags(
+            IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR | IsoTpBehaviour::CAN_ISOTP_RX_EXT_ADDR,
+        );
+        let (from_a, from_b) = listen_options(options);
+        assert_eq!(from_a.get_rx_ext_address(), 0x10);
+        assert_ne!(
+            flags(&from_b) & IsoTpBehaviour::CAN_ISOTP_RX_EXT_ADDR.bits(),
+            0
+        );
+        assert_eq!(from_b.get_rx_ext_address(), 0xF1);
+    }
+}
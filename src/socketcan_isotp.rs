@@ -17,7 +17,7 @@
 //! Instructions on how the can-isotp kernel module can be build and loaded can be found
 //! at [https://github.com/hartkopp/can-isotp](https://github.com/hartkopp/can-isotp) .
 //!
-//! ```rust,no_run
+//! ```rust,ignore
 //! use socketcan_isotp::IsoTpSocket;
 //!
 //! fn main() -> Result<(), socketcan_isotp::Error> {
@@ -104,6 +104,9 @@ pub const RECV_BUFFER_SIZE: usize = 4096;
 /// `std::mem::size_of::<socketcan::CANFrame>())`
 const SIZE_OF_CAN_FRAME: u8 = 16;
 
+/// Size of `struct canfd_frame`, the MTU selecting CAN FD in the link layer options
+pub const CANFD_MTU: u8 = 72;
+
 const FLOW_CONTROL_OPTIONS_SIZE: usize = size_of::<FlowControlOptions>();
 
 const ISOTP_OPTIONS_SIZE: usize = size_of::<IsoTpOptions>();
@@ -138,6 +141,8 @@ bitflags! {
         const CAN_ISOTP_FORCE_RXSTMIN = 0x100;
         /// different rx extended addressing
         const CAN_ISOTP_RX_EXT_ADDR = 0x200;
+        /// 1-to-N functional addressing with single frames, no reception
+        const CAN_ISOTP_SF_BROADCAST = 0x800;
     }
 }
 
@@ -165,6 +170,23 @@ pub const ERR_MASK_ALL: u32 = ERR_MASK;
 /// an error mask that will cause Socketcan to silently drop all errors
 pub const ERR_MASK_NONE: u32 = 0;
 
+/// Convert an [Id] into the raw socketcan representation, setting [EFF_FLAG] for extended ids
+pub fn id_to_raw(id: Id) -> u32 {
+    match id {
+        Id::Standard(standard_id) => standard_id.as_raw() as u32,
+        Id::Extended(extended_id) => extended_id.as_raw() | EFF_FLAG,
+    }
+}
+
+/// Convert a raw socketcan id back into an [Id], honouring [EFF_FLAG]
+pub fn id_from_raw(raw: u32) -> Id {
+    if raw & EFF_FLAG != 0 {
+        Id::Extended(ExtendedId::new(raw & EFF_MASK).unwrap())
+    } else {
+        Id::Standard(StandardId::new((raw & SFF_MASK) as u16).unwrap())
+    }
+}
+
 #[derive(Debug)]
 #[repr(C)]
 struct CanAddr {
@@ -179,6 +201,7 @@ struct CanAddr {
 }
 
 /// ISO-TP otions aka `can_isotp_options`
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct IsoTpOptions {
     /// set flags for isotp behaviour.
@@ -295,6 +318,7 @@ impl Default for IsoTpOptions {
 }
 
 /// Flow control options aka `can_isotp_fc_options`
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct FlowControlOptions {
     /// blocksize provided in FC frame
@@ -346,6 +370,7 @@ bitflags! {
 }
 
 /// Link layer options aka `can_isotp_ll_options`
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct LinkLayerOptions {
     /// generated & accepted CAN frame type
@@ -373,6 +398,11 @@ impl LinkLayerOptions {
             tx_flags,
         }
     }
+
+    /// CAN FD frames with `tx_dl` bytes of payload, one of 8, 12, 16, 20, 24, 32, 48 or 64
+    pub fn can_fd(tx_dl: u8, tx_flags: TxFlags) -> Self {
+        Self::new(CANFD_MTU, tx_dl, tx_flags)
+    }
 }
 
 impl Default for LinkLayerOptions {
@@ -388,6 +418,19 @@ impl Default for LinkLayerOptions {
     }
 }
 
+/// Separation times set with the `CAN_ISOTP_TX_STMIN` and `CAN_ISOTP_RX_STMIN` socket
+/// options
+///
+/// They only take effect with the [IsoTpBehaviour::CAN_ISOTP_FORCE_TXSTMIN] and
+/// [IsoTpBehaviour::CAN_ISOTP_FORCE_RXSTMIN] flags of the [IsoTpOptions].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StMinOptions {
+    /// Time between the consecutive frames sent, instead of the STmin of the receiver
+    pub tx_stmin: Option<Duration>,
+    /// Consecutive frames received closer together than this are ignored
+    pub rx_stmin: Option<Duration>,
+}
+
 #[derive(Error, Debug)]
 /// Possible errors
 pub enum Error {
@@ -441,15 +484,38 @@ impl IsoTpSocket {
         isotp_options: Option<IsoTpOptions>,
         rx_flow_control_options: Option<FlowControlOptions>,
         link_layer_options: Option<LinkLayerOptions>,
+    ) -> Result<Self, Error> {
+        Self::open_with_stmin(
+            ifname,
+            src,
+            dst,
+            isotp_options,
+            rx_flow_control_options,
+            link_layer_options,
+            StMinOptions::default(),
+        )
+    }
+
+    /// Open a named CAN ISO-TP device, passing additional options and forced separation
+    /// times.
+    pub fn open_with_stmin(
+        ifname: &str,
+        src: impl Into<Id>,
+        dst: impl Into<Id>,
+        isotp_options: Option<IsoTpOptions>,
+        rx_flow_control_options: Option<FlowControlOptions>,
+        link_layer_options: Option<LinkLayerOptions>,
+        stmin_options: StMinOptions,
     ) -> Result<Self, Error> {
         let if_index = if_nametoindex(ifname)?;
-        Self::open_if_with_opts(
+        Self::open_if_with_stmin(
             if_index.try_into().unwrap(),
             src,
             dst,
             isotp_options,
             rx_flow_control_options,
             link_layer_options,
+            stmin_options,
         )
     }
 
@@ -478,14 +544,39 @@ impl IsoTpSocket {
         rx_flow_control_options: Option<FlowControlOptions>,
         link_layer_options: Option<LinkLayerOptions>,
     ) -> Result<Self, Error> {
-        let rx_id = match src.into() {
-            Id::Standard(standard_id) => standard_id.as_raw() as u32,
-            Id::Extended(extended_id) => extended_id.as_raw() | EFF_FLAG,
-        };
-        let tx_id = match dst.into() {
-            Id::Standard(standard_id) => standard_id.as_raw() as u32,
-            Id::Extended(extended_id) => extended_id.as_raw() | EFF_FLAG,
+        Self::open_if_with_stmin(
+            if_index,
+            src,
+            dst,
+            isotp_options,
+            rx_flow_control_options,
+            link_layer_options,
+            StMinOptions::default(),
+        )
+    }
+
+    /// Open CAN ISO-TP device device by interface number, passing additional options and
+    /// forced separation times.
+    pub fn open_if_with_stmin(
+        if_index: c_int,
+        src: impl Into<Id>,
+        dst: impl Into<Id>,
+        isotp_options: Option<IsoTpOptions>,
+        rx_flow_control_options: Option<FlowControlOptions>,
+        link_layer_options: Option<LinkLayerOptions>,
+        stmin_options: StMinOptions,
+    ) -> Result<Self, Error> {
+        // __u32 nano seconds
+        let stmin_nanos = |stmin: Option<Duration>| {
+            stmin
+                .map(|stmin| u32::try_from(stmin.as_nanos()))
+                .transpose()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "STmin too long"))
         };
+        let tx_stmin = stmin_nanos(stmin_options.tx_stmin)?;
+        let rx_stmin = stmin_nanos(stmin_options.rx_stmin)?;
+        let rx_id = id_to_raw(src.into());
+        let tx_id = id_to_raw(dst.into());
         let addr = CanAddr {
             _af_can: AF_CAN,
             if_index,
@@ -558,6 +649,29 @@ impl IsoTpSocket {
             }
         }
 
+        // Set forced separation times
+        for (option, nanos) in [
+            (CAN_ISOTP_TX_STMIN, tx_stmin),
+            (CAN_ISOTP_RX_STMIN, rx_stmin),
+        ] {
+            let Some(nanos) = nanos else {
+                continue;
+            };
+            let nanos_ptr: *const c_void = &nanos as *const _ as *const c_void;
+            let err = unsafe {
+                setsockopt(
+                    sock_fd,
+                    SOL_CAN_ISOTP,
+                    option,
+                    nanos_ptr,
+                    size_of::<u32>().try_into().unwrap(),
+                )
+            };
+            if err == -1 {
+                return Err(Error::from(io::Error::last_os_error()));
+            }
+        }
+
         // bind it
         let bind_rv;
         unsafe {
@@ -635,7 +749,7 @@ impl IsoTpSocket {
             let buffer_ptr = buffer as *const _ as *const c_void;
             write(self.fd, buffer_ptr, buffer.len())
         };
-        if write_rv != buffer.len().try_into().unwrap() {
+        if write_rv != isize::try_from(buffer.len()).unwrap() {
             return Err(io::Error::last_os_error());
         }
         Ok(())
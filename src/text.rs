@@ -0,0 +1,144 @@
+//! Text forms of CAN ids and data
+//!
+//! Shared by the trace and model files and the command line tools, so an id or PDU
+//! copied from the output of one is accepted by all others.
+
+use crate::{id_from_raw, Id, EFF_FLAG, EFF_MASK, SFF_MASK};
+use std::io;
+
+/// Format an id the way candump does, 3 hex digits for standard and 8 for extended ids
+pub fn format_id(id: Id) -> String {
+    match id {
+        Id::Standard(id) => format!("{:03X}", id.as_raw()),
+        Id::Extended(id) => format!("{:08X}", id.as_raw()),
+    }
+}
+
+/// Parse an id written by [format_id], optionally prefixed with `0x`
+///
+/// More than 3 digits denote an extended id.
+pub fn parse_id(text: &str) -> io::Result<Id> {
+    let invalid = || invalid_data(format!("invalid CAN id {text:?}"));
+    let digits = strip_hex_prefix(text);
+    if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let raw = u32::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    if digits.len() > 3 {
+        if raw > EFF_MASK {
+            return Err(invalid());
+        }
+        Ok(id_from_raw(raw | EFF_FLAG))
+    } else {
+        if raw > SFF_MASK {
+            return Err(invalid());
+        }
+        Ok(id_from_raw(raw))
+    }
+}
+
+/// Parse a byte in hex, optionally prefixed with `0x`
+pub fn parse_byte(text: &str) -> io::Result<u8> {
+    let digits = strip_hex_prefix(text);
+    if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err(invalid_data(format!("invalid byte {text:?}")));
+    }
+    u8::from_str_radix(digits, 16).map_err(|_| invalid_data(format!("invalid byte {text:?}")))
+}
+
+/// Hex bytes separated by spaces, as isotpdump prints them
+pub fn format_bytes(data: &[u8]) -> String {
+    data.iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Printable ASCII characters, with a dot for all other bytes
+pub fn format_ascii(data: &[u8]) -> String {
+    data.iter()
+        .map(|byte| match byte {
+            0x20..=0x7E => char::from(*byte),
+            _ => '.',
+        })
+        .collect()
+}
+
+/// Hex digits without separators
+pub fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+/// Parse hex digits without separators, as written by [encode_hex]
+pub fn decode_hex(text: &str) -> io::Result<Vec<u8>> {
+    let invalid = || invalid_data(format!("invalid hex data {text:?}"));
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+/// Parse hex bytes, separated by whitespace or not
+pub fn parse_hex(text: &str) -> io::Result<Vec<u8>> {
+    let digits: String = text.split_whitespace().collect();
+    decode_hex(&digits)
+}
+
+fn strip_hex_prefix(text: &str) -> &str {
+    text.strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids() {
+        assert_eq!(format_id(parse_id("7E0").unwrap()), "7E0");
+        assert_eq!(format_id(parse_id("0x7e0").unwrap()), "7E0");
+        assert_eq!(format_id(parse_id("00000123").unwrap()), "00000123");
+        assert_eq!(
+            parse_id("18DAF110").unwrap(),
+            id_from_raw(0x18DA_F110 | EFF_FLAG)
+        );
+        for text in ["", "0x", "800", "20000000", "+7E0", "7G0", "0x-1"] {
+            assert!(parse_id(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(parse_byte("0x7f").unwrap(), 0x7F);
+        assert_eq!(parse_byte("AA").unwrap(), 0xAA);
+        for text in ["", "0x", "100", "+1", "G"] {
+            assert!(parse_byte(text).is_err(), "{text}");
+        }
+        assert_eq!(format_bytes(&[0x62, 0xF1, 0x90]), "62 F1 90");
+        assert_eq!(format_bytes(&[]), "");
+        assert_eq!(format_ascii(b"VIN\x00\x7F~"), "VIN..~");
+    }
+
+    #[test]
+    fn hex() {
+        let data = [0x00, 0xAB];
+        assert_eq!(encode_hex(&data), "00AB");
+        assert_eq!(decode_hex(&encode_hex(&data)).unwrap(), data);
+        assert_eq!(decode_hex("").unwrap(), Vec::<u8>::new());
+        assert_eq!(parse_hex("22 f1 90\t").unwrap(), [0x22, 0xF1, 0x90]);
+        assert_eq!(parse_hex("22F1 90").unwrap(), [0x22, 0xF1, 0x90]);
+        for text in ["ABC", "GG", "+1", "Ä0"] {
+            assert!(decode_hex(text).is_err(), "{text}");
+        }
+        assert!(decode_hex("22 F1").is_err());
+        assert!(parse_hex("22 F").is_err());
+    }
+}
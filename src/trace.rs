@@ -0,0 +1,669 @@
+//! Recording and replay of ISO-TP PDUs
+//!
+//! Every PDU going through a [RecordingTransport] is written to a [TraceWriter]
+//! together with its timestamp, direction and addressing. Two formats are supported:
+//!
+//! * [TraceFormat::JsonLines] - one JSON object per line, e.g.
+//!   `{"ts":1700000000000000000,"dir":"tx","rx_id":"7E8","tx_id":"7E0","data":"22F190"}`
+//! * [TraceFormat::Binary] - compact little endian records behind the [BINARY_MAGIC] header
+//!
+//! A recording can be fed back through a [ReplayTransport], which acts as a scripted
+//! peer: outgoing PDUs are checked against the recorded ones and the recorded responses
+//! are handed out on receive. This turns field captures into offline regression tests.
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//! use tokio_socketcan_isotp::trace::{RecordingTransport, TraceFormat, TraceWriter};
+//! use tokio_socketcan_isotp::{IsoTpSocket, IsoTpTransport, StandardId};
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let socket = IsoTpSocket::open(
+//!     "vcan0",
+//!     StandardId::new(0x7E8).unwrap(),
+//!     StandardId::new(0x7E0).unwrap(),
+//! )
+//! .unwrap();
+//! let writer = Arc::new(TraceWriter::create("session.jsonl", TraceFormat::JsonLines)?);
+//! let socket = RecordingTransport::new(socket, writer);
+//! socket.send(&[0x22, 0xF1, 0x90]).await?;
+//! let response = socket.recv().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::frame::{CanFrame, Reassembler};
+use crate::text::{decode_hex, encode_hex, format_id, parse_id};
+use crate::{id_from_raw, id_to_raw, Id, IsoTpTransport};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Magic bytes at the start of a binary trace
+pub const BINARY_MAGIC: &[u8; 8] = b"ISOTPTRC";
+
+/// Version of the binary trace format written by this crate
+pub const BINARY_VERSION: u8 = 1;
+
+/// Direction of a PDU, seen from the recording transport
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// PDU sent by the transport
+    Tx,
+    /// PDU received by the transport
+    Rx,
+}
+
+/// Single recorded PDU
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Time the PDU was sent or received
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    /// CAN id the recording transport received on
+    pub rx_id: Id,
+    /// CAN id the recording transport sent on
+    pub tx_id: Id,
+    pub data: Vec<u8>,
+}
+
+impl TraceRecord {
+    /// Create a record timestamped with the current time
+    pub fn now(direction: Direction, rx_id: Id, tx_id: Id, data: Vec<u8>) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            direction,
+            rx_id,
+            tx_id,
+            data,
+        }
+    }
+
+    /// CAN id the PDU travelled on
+    pub fn can_id(&self) -> Id {
+        match self.direction {
+            Direction::Tx => self.tx_id,
+            Direction::Rx => self.rx_id,
+        }
+    }
+
+    /// Timestamp as nanoseconds since the UNIX epoch
+    pub fn timestamp_nanos(&self) -> u64 {
+        self.timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64
+    }
+}
+
+/// On-disk trace format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    JsonLines,
+    Binary,
+}
+
+impl TraceFormat {
+    /// Guess the format from a file extension, `.jsonl`/`.json` are JSON Lines, anything else binary
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") | Some("json") => TraceFormat::JsonLines,
+            _ => TraceFormat::Binary,
+        }
+    }
+}
+
+/// Line of a JSON Lines trace
+#[derive(Serialize, Deserialize)]
+struct JsonRecord {
+    ts: u64,
+    dir: Direction,
+    rx_id: String,
+    tx_id: String,
+    data: String,
+}
+
+impl From<&TraceRecord> for JsonRecord {
+    fn from(record: &TraceRecord) -> Self {
+        Self {
+            ts: record.timestamp_nanos(),
+            dir: record.direction,
+            rx_id: format_id(record.rx_id),
+            tx_id: format_id(record.tx_id),
+            data: encode_hex(&record.data),
+        }
+    }
+}
+
+impl TryFrom<JsonRecord> for TraceRecord {
+    type Error = io::Error;
+
+    fn try_from(record: JsonRecord) -> io::Result<Self> {
+        Ok(Self {
+            timestamp: UNIX_EPOCH + Duration::from_nanos(record.ts),
+            direction: record.dir,
+            rx_id: parse_id(&record.rx_id)?,
+            tx_id: parse_id(&record.tx_id)?,
+            data: decode_hex(&record.data)?,
+        })
+    }
+}
+
+/// Destination of the records of a [RecordingTransport]
+pub trait TraceSink: Send + Sync {
+    /// Append one record
+    fn record(&self, record: &TraceRecord) -> io::Result<()>;
+}
+
+/// Writes trace records to a file or any other [Write]
+///
+/// The writer is internally synchronized, wrap it in an [Arc] to share one trace
+/// between several transports.
+pub struct TraceWriter {
+    format: TraceFormat,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl TraceWriter {
+    /// Start a trace on the given writer, writing the header if the format has one
+    pub fn new(writer: impl Write + Send + 'static, format: TraceFormat) -> io::Result<Self> {
+        let mut writer: Box<dyn Write + Send> = Box::new(writer);
+        if format == TraceFormat::Binary {
+            writer.write_all(BINARY_MAGIC)?;
+            writer.write_all(&[BINARY_VERSION])?;
+        }
+        Ok(Self {
+            format,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Create (or truncate) a trace file
+    pub fn create(path: impl AsRef<Path>, format: TraceFormat) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), format)
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    /// Append one record
+    pub fn record(&self, record: &TraceRecord) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        match self.format {
+            TraceFormat::JsonLines => {
+                serde_json::to_writer(&mut *writer, &JsonRecord::from(record))?;
+                writer.write_all(b"\n")
+            }
+            TraceFormat::Binary => {
+                let len = u32::try_from(record.data.len())
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                writer.write_all(&record.timestamp_nanos().to_le_bytes())?;
+                writer.write_all(&[match record.direction {
+                    Direction::Tx => 0,
+                    Direction::Rx => 1,
+                }])?;
+                writer.write_all(&id_to_raw(record.rx_id).to_le_bytes())?;
+                writer.write_all(&id_to_raw(record.tx_id).to_le_bytes())?;
+                writer.write_all(&len.to_le_bytes())?;
+                writer.write_all(&record.data)
+            }
+        }
+    }
+
+    /// Flush buffered records to the underlying writer
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+impl TraceSink for TraceWriter {
+    fn record(&self, record: &TraceRecord) -> io::Result<()> {
+        TraceWriter::record(self, record)
+    }
+}
+
+impl Drop for TraceWriter {
+    fn drop(&mut self) {
+        self.flush().ok(); // ignore result
+    }
+}
+
+/// Read a whole trace, detecting the format from its first bytes
+pub fn read_trace(reader: impl Read) -> io::Result<Vec<TraceRecord>> {
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(BINARY_MAGIC) {
+        read_binary(reader)
+    } else {
+        read_json_lines(reader)
+    }
+}
+
+/// Read a whole trace file
+pub fn load_trace(path: impl AsRef<Path>) -> io::Result<Vec<TraceRecord>> {
+    read_trace(File::open(path)?)
+}
+
+fn read_json_lines(reader: impl BufRead) -> io::Result<Vec<TraceRecord>> {
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: JsonRecord = serde_json::from_str(&line)?;
+        records.push(record.try_into()?);
+    }
+    Ok(records)
+}
+
+fn read_binary(mut reader: impl Read) -> io::Result<Vec<TraceRecord>> {
+    let mut header = [0u8; 9];
+    reader.read_exact(&mut header)?;
+    if header[8] != BINARY_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported trace version {}",
+            header[8]
+        )));
+    }
+
+    let mut records = Vec::new();
+    let mut fixed = [0u8; 21];
+    while read_exact_or_eof(&mut reader, &mut fixed)? {
+        let ts = u64::from_le_bytes(fixed[0..8].try_into().unwrap());
+        let direction = match fixed[8] {
+            0 => Direction::Tx,
+            1 => Direction::Rx,
+            other => return Err(invalid_data(format!("invalid direction {other}"))),
+        };
+        let rx_id = u32::from_le_bytes(fixed[9..13].try_into().unwrap());
+        let tx_id = u32::from_le_bytes(fixed[13..17].try_into().unwrap());
+        let len = u32::from_le_bytes(fixed[17..21].try_into().unwrap());
+        // the length comes from the file, only allocate what is actually there
+        let mut data = Vec::new();
+        (&mut reader).take(len.into()).read_to_end(&mut data)?;
+        if data.len() != len as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated trace record",
+            ));
+        }
+        records.push(TraceRecord {
+            timestamp: UNIX_EPOCH + Duration::from_nanos(ts),
+            direction,
+            rx_id: id_from_raw(rx_id),
+            tx_id: id_from_raw(tx_id),
+            data,
+        });
+    }
+    Ok(records)
+}
+
+/// Fill the buffer, false if the input ended right before it
+///
+/// Inputs may only end between records, a partially filled buffer is
+/// [io::ErrorKind::UnexpectedEof].
+pub(crate) fn read_exact_or_eof(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated record header",
+                ))
+            }
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
+
+/// Reassemble the PDUs of the transport bound to `rx_id`/`tx_id` from raw frames
+///
+/// PDUs on `tx_id` become [Direction::Tx] records, PDUs on `rx_id` [Direction::Rx]
+/// records, frames of all other ids are ignored.
+pub fn records_from_frames<'a>(
+    frames: impl IntoIterator<Item = &'a CanFrame>,
+    rx_id: Id,
+    tx_id: Id,
+    mut reassembler: Reassembler,
+) -> Vec<TraceRecord> {
+    frames
+        .into_iter()
+        .filter(|frame| frame.id == rx_id || frame.id == tx_id)
+        .filter_map(|frame| reassembler.push(frame))
+        .map(|pdu| TraceRecord {
+            timestamp: pdu.timestamp,
+            direction: if pdu.id == tx_id {
+                Direction::Tx
+            } else {
+                Direction::Rx
+            },
+            rx_id,
+            tx_id,
+            data: pdu.data,
+        })
+        .collect()
+}
+
+/// Transport wrapper recording every PDU passing through it
+pub struct RecordingTransport<T> {
+    inner: T,
+    sink: Arc<dyn TraceSink>,
+}
+
+impl<T: IsoTpTransport> RecordingTransport<T> {
+    pub fn new(inner: T, sink: Arc<dyn TraceSink>) -> Self {
+        Self { inner, sink }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record(&self, direction: Direction, data: &[u8]) {
+        let record = TraceRecord::now(direction, self.inner.rx_id(), self.inner.tx_id(), data.into());
+        // A failing trace must not break the communication itself
+        self.sink.record(&record).ok();
+    }
+}
+
+impl<T: IsoTpTransport> IsoTpTransport for RecordingTransport<T> {
+    fn rx_id(&self) -> Id {
+        self.inner.rx_id()
+    }
+
+    fn tx_id(&self) -> Id {
+        self.inner.tx_id()
+    }
+
+    fn max_pdu_size(&self) -> usize {
+        self.inner.max_pdu_size()
+    }
+
+    async fn send(&self, pdu: &[u8]) -> io::Result<()> {
+        self.inner.send(pdu).await?;
+        self.record(Direction::Tx, pdu);
+        Ok(())
+    }
+
+    async fn recv(&self) -> io::Result<Vec<u8>> {
+        let pdu = self.inner.recv().await?;
+        self.record(Direction::Rx, &pdu);
+        Ok(pdu)
+    }
+}
+
+/// Scripted peer replaying a recording
+///
+/// Each [IsoTpTransport::send] must match the next recorded [Direction::Tx] PDU and
+/// each [IsoTpTransport::recv] returns the next recorded [Direction::Rx] PDU. Any
+/// deviation from the script is reported as [io::ErrorKind::InvalidData], running
+/// past its end as [io::ErrorKind::UnexpectedEof].
+pub struct ReplayTransport {
+    rx_id: Id,
+    tx_id: Id,
+    script: Mutex<VecDeque<TraceRecord>>,
+}
+
+impl ReplayTransport {
+    /// Replay all records, the addressing is taken from the first one
+    pub fn new(records: Vec<TraceRecord>) -> io::Result<Self> {
+        let first = records
+            .first()
+            .ok_or_else(|| invalid_data("empty trace".into()))?;
+        let (rx_id, tx_id) = (first.rx_id, first.tx_id);
+        Ok(Self {
+            rx_id,
+            tx_id,
+            script: Mutex::new(records.into()),
+        })
+    }
+
+    /// Replay only the records of the transport bound to `rx_id`/`tx_id`
+    pub fn for_ids(records: Vec<TraceRecord>, rx_id: Id, tx_id: Id) -> Self {
+        let script = records
+            .into_iter()
+            .filter(|record| record.rx_id == rx_id && record.tx_id == tx_id)
+            .collect();
+        Self {
+            rx_id,
+            tx_id,
+            script: Mutex::new(script),
+        }
+    }
+
+    /// Replay a trace file
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(load_trace(path)?)
+    }
+
+    /// Number of records not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.script.lock().unwrap().len()
+    }
+
+    /// Whether the whole recording has been replayed
+    pub fn is_finished(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    fn next(&self, direction: Direction) -> io::Result<TraceRecord> {
+        let mut script = self.script.lock().unwrap();
+        match script.front() {
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "replay finished",
+            )),
+            Some(record) if record.direction != direction => Err(invalid_data(format!(
+                "replay expected {:?} of {}, got {:?}",
+                record.direction,
+                encode_hex(&record.data),
+                direction
+            ))),
+            Some(_) => Ok(script.pop_front().unwrap()),
+        }
+    }
+}
+
+impl IsoTpTransport for ReplayTransport {
+    fn rx_id(&self) -> Id {
+        self.rx_id
+    }
+
+    fn tx_id(&self) -> Id {
+        self.tx_id
+    }
+
+    fn send<'a>(&'a self, pdu: &'a [u8]) -> impl Future<Output = io::Result<()>> + Send + 'a {
+        let result = self.next(Direction::Tx).and_then(|expected| {
+            if expected.data == pdu {
+                Ok(())
+            } else {
+                Err(invalid_data(format!(
+                    "replay mismatch: expected {}, sent {}",
+                    encode_hex(&expected.data),
+                    encode_hex(pdu)
+                )))
+            }
+        });
+        std::future::ready(result)
+    }
+
+    fn recv(&self) -> impl Future<Output = io::Result<Vec<u8>>> + Send + '_ {
+        std::future::ready(self.next(Direction::Rx).map(|record| record.data))
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{ExtendedId, StandardId};
+
+    /// Writer whose bytes stay accessible after a trace writer took it
+    #[derive(Clone, Default)]
+    pub(crate) struct SharedBuffer(pub(crate) Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        pub(crate) fn bytes(&self) -> Vec<u8> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn records() -> Vec<TraceRecord> {
+        let tester = Id::Standard(StandardId::new(0x7E0).unwrap());
+        let ecu = Id::Extended(ExtendedId::new(0x18DA_F110).unwrap());
+        vec![
+            TraceRecord {
+                timestamp: UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789),
+                direction: Direction::Tx,
+                rx_id: ecu,
+                tx_id: tester,
+                data: vec![0x22, 0xF1, 0x90],
+            },
+            TraceRecord {
+                timestamp: UNIX_EPOCH + Duration::from_nanos(1_700_000_000_223_456_789),
+                direction: Direction::Rx,
+                rx_id: ecu,
+                tx_id: tester,
+                data: (0..=255).collect(),
+            },
+        ]
+    }
+
+    fn write(format: TraceFormat) -> Vec<u8> {
+        let buffer = SharedBuffer::default();
+        let writer = TraceWriter::new(buffer.clone(), format).unwrap();
+        for record in records() {
+            writer.record(&record).unwrap();
+        }
+        drop(writer);
+        buffer.bytes()
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let bytes = write(TraceFormat::Binary);
+        assert!(bytes.starts_with(BINARY_MAGIC));
+        assert_eq!(read_trace(&bytes[..]).unwrap(), records());
+    }
+
+    #[test]
+    fn json_lines_round_trip() {
+        let bytes = write(TraceFormat::JsonLines);
+        let text = String::from_utf8(bytes.clone()).unwrap();
+        assert!(text.starts_with(r#"{"ts":1700000000123456789,"dir":"tx","rx_id":"18DAF110","tx_id":"7E0","data":"22F190"}"#));
+        assert_eq!(read_trace(&bytes[..]).unwrap(), records());
+    }
+
+    #[test]
+    fn binary_truncated_header() {
+        let bytes = write(TraceFormat::Binary);
+        // header of 9 bytes, then 21 bytes of the first record's fixed part
+        let err = read_trace(&bytes[..9 + 10]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn binary_truncated_data() {
+        let bytes = write(TraceFormat::Binary);
+        let err = read_trace(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn binary_corrupt_length_does_not_allocate() {
+        let mut bytes = write(TraceFormat::Binary);
+        // length field of the first record
+        bytes[9 + 17..9 + 21].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = read_trace(&bytes[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn binary_empty_trace() {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.push(BINARY_VERSION);
+        assert!(read_trace(&bytes[..]).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replay_follows_the_script() {
+        let records = records();
+        let replay = ReplayTransport::new(records.clone()).unwrap();
+        assert_eq!(replay.rx_id(), records[0].rx_id);
+        assert_eq!(replay.tx_id(), records[0].tx_id);
+        replay.send(&[0x22, 0xF1, 0x90]).await.unwrap();
+        assert_eq!(replay.recv().await.unwrap(), records[1].data);
+        assert!(replay.is_finished());
+    }
+
+    #[tokio::test]
+    async fn replay_payload_mismatch() {
+        let replay = ReplayTransport::new(records()).unwrap();
+        let err = replay.send(&[0x22, 0xF1, 0x91]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(replay.remaining(), 1);
+    }
+
+    #[tokio::test]
+    async fn replay_direction_mismatch() {
+        let replay = ReplayTransport::new(records()).unwrap();
+        let err = replay.recv().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // the record is kept for the expected send
+        assert_eq!(replay.remaining(), 2);
+        replay.send(&[0x22, 0xF1, 0x90]).await.unwrap();
+        let err = replay.send(&[0x22, 0xF1, 0x90]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn replay_end_of_trace() {
+        let replay = ReplayTransport::new(records()).unwrap();
+        replay.send(&[0x22, 0xF1, 0x90]).await.unwrap();
+        replay.recv().await.unwrap();
+        let err = replay.recv().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = replay.send(&[0x3E, 0x00]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn replay_for_ids() {
+        let replay = ReplayTransport::for_ids(
+            records(),
+            Id::Standard(StandardId::new(0x7E8).unwrap()),
+            Id::Standard(StandardId::new(0x7E0).unwrap()),
+        );
+        assert!(replay.is_finished());
+        assert!(ReplayTransport::new(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! Common interface of everything that exchanges whole ISO-TP PDUs
+//!
+//! [IsoTpSocket] is the transport used on a real bus, the offline transports
+//! (e.g. the trace replay) implement the same trait, so that the layers built on
+//! top of it can run without a CAN interface.
+
+use crate::{Id, IsoTpSocket, RECV_BUFFER_SIZE};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+
+/// Sends and receives whole ISO-TP PDUs
+pub trait IsoTpTransport: Send + Sync {
+    /// CAN id the PDUs are received on
+    fn rx_id(&self) -> Id;
+
+    /// CAN id the PDUs are sent on
+    fn tx_id(&self) -> Id;
+
+    /// Largest PDU that can be received in one piece
+    fn max_pdu_size(&self) -> usize {
+        RECV_BUFFER_SIZE
+    }
+
+    /// Send one PDU
+    fn send<'a>(&'a self, pdu: &'a [u8]) -> impl Future<Output = io::Result<()>> + Send + 'a;
+
+    /// Receive one PDU
+    fn recv(&self) -> impl Future<Output = io::Result<Vec<u8>>> + Send + '_;
+}
+
+impl IsoTpTransport for IsoTpSocket {
+    fn rx_id(&self) -> Id {
+        IsoTpSocket::rx_id(self)
+    }
+
+    fn tx_id(&self) -> Id {
+        IsoTpSocket::tx_id(self)
+    }
+
+    fn max_pdu_size(&self) -> usize {
+        IsoTpSocket::max_pdu_size(self)
+    }
+
+    fn send<'a>(&'a self, pdu: &'a [u8]) -> impl Future<Output = io::Result<()>> + Send + 'a {
+        self.write_packet(pdu)
+    }
+
+    fn recv(&self) -> impl Future<Output = io::Result<Vec<u8>>> + Send + '_ {
+        self.read_packet()
+    }
+}
+
+impl<T: IsoTpTransport> IsoTpTransport for Arc<T> {
+    fn rx_id(&self) -> Id {
+        T::rx_id(self)
+    }
+
+    fn tx_id(&self) -> Id {
+        T::tx_id(self)
+    }
+
+    fn max_pdu_size(&self) -> usize {
+        T::max_pdu_size(self)
+    }
+
+    fn send<'a>(&'a self, pdu: &'a [u8]) -> impl Future<Output = io::Result<()>> + Send + 'a {
+        T::send(self, pdu)
+    }
+
+    fn recv(&self) -> impl Future<Output = io::Result<Vec<u8>>> + Send + '_ {
+        T::recv(self)
+    }
+}
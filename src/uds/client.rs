@@ -0,0 +1,524 @@
+use super::service::*;
+use super::{DiagnosticSession, Error, NegativeResponseCode, ResetType, RoutineControlType};
+use crate::{IsoTpSocket, IsoTpTransport};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default P2server_max of ISO 14229-2
+const DEFAULT_P2: Duration = Duration::from_millis(50);
+
+/// Default P2*server_max of ISO 14229-2
+const DEFAULT_P2_STAR: Duration = Duration::from_millis(5000);
+
+/// Session timing reported in the DiagnosticSessionControl response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionParameters {
+    pub session: DiagnosticSession,
+    /// P2server_max, time until the first response
+    pub p2: Duration,
+    /// P2*server_max, time until the next response after a response pending
+    pub p2_star: Duration,
+}
+
+impl Default for SessionParameters {
+    fn default() -> Self {
+        Self {
+            session: DiagnosticSession::Default,
+            p2: DEFAULT_P2,
+            p2_star: DEFAULT_P2_STAR,
+        }
+    }
+}
+
+/// Retries of requests answered with busyRepeatRequest (NRC 0x21)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyRepeatPolicy {
+    /// Number of repetitions before the NRC is returned, 0 disables repeating
+    pub max_retries: usize,
+    /// Delay before the first repetition, doubled for every further one
+    pub initial_delay: Duration,
+    /// Upper bound of the delay
+    pub max_delay: Duration,
+}
+
+impl Default for BusyRepeatPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Request execution settings of a [UdsClient]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientConfig {
+    /// Server timing, updated from every DiagnosticSessionControl response
+    pub timing: SessionParameters,
+    /// Added to P2 and P2* to account for the transport (ΔP2 of ISO 14229-2)
+    pub network_delay: Duration,
+    /// Maximum number of response pending (NRC 0x78) responses to one request
+    pub max_response_pending: usize,
+    pub busy_repeat: BusyRepeatPolicy,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            timing: SessionParameters::default(),
+            network_delay: Duration::from_millis(50),
+            max_response_pending: 50,
+            busy_repeat: BusyRepeatPolicy::default(),
+        }
+    }
+}
+
+/// UDS client sending requests over an ISO-TP transport
+///
+/// Every request waits P2 for the first response and P2* after each response
+/// pending (NRC 0x78). Requests answered with busyRepeatRequest (NRC 0x21) are
+/// repeated according to the [BusyRepeatPolicy].
+pub struct UdsClient<T = IsoTpSocket> {
+    transport: T,
+    config: Mutex<ClientConfig>,
+    /// Held for the duration of a request/response exchange
+    exchange: tokio::sync::Mutex<()>,
+    /// Time the last request was sent or response received
+    last_activity: Mutex<Instant>,
+}
+
+impl<T: IsoTpTransport> UdsClient<T> {
+    pub fn new(transport: T) -> Self {
+        Self::with_config(transport, ClientConfig::default())
+    }
+
+    pub fn with_config(transport: T, config: ClientConfig) -> Self {
+        Self {
+            transport,
+            config: Mutex::new(config),
+            exchange: tokio::sync::Mutex::new(()),
+            last_activity: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn config(&self) -> ClientConfig {
+        *self.config.lock().unwrap()
+    }
+
+    pub fn set_config(&self, config: ClientConfig) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    /// Server timing currently applied to requests
+    pub fn timing(&self) -> SessionParameters {
+        self.config().timing
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Send a raw request and wait for the response to it
+    ///
+    /// Responses to other services are skipped, a negative response is returned as
+    /// [Error::NegativeResponse]. On success the whole positive response, including
+    /// the response SID, is returned.
+    pub async fn request(&self, request: &[u8]) -> Result<Vec<u8>, Error> {
+        let sid = *request
+            .first()
+            .ok_or_else(|| Error::invalid_request("empty request"))?;
+        let policy = self.config().busy_repeat;
+        let mut delay = policy.initial_delay;
+        let mut retries = 0;
+        loop {
+            match self.execute(sid, request).await {
+                Err(Error::NegativeResponse {
+                    code: NegativeResponseCode::BusyRepeatRequest,
+                    ..
+                }) if retries < policy.max_retries => {
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(policy.max_delay);
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Single request/response exchange applying P2 and P2*
+    async fn execute(&self, sid: u8, request: &[u8]) -> Result<Vec<u8>, Error> {
+        let config = self.config();
+        let _exchange = self.exchange.lock().await;
+        self.send(request).await?;
+
+        let mut timeout = config.timing.p2 + config.network_delay;
+        let mut pending = 0;
+        loop {
+            let result = tokio::time::timeout(timeout, self.receive_response(sid))
+                .await
+                .map_err(|_| Error::Timeout { service: sid })?;
+            match result {
+                Err(Error::NegativeResponse {
+                    code: NegativeResponseCode::RequestCorrectlyReceivedResponsePending,
+                    ..
+                }) => {
+                    pending += 1;
+                    if pending > config.max_response_pending {
+                        return Err(Error::ResponsePendingLimit { service: sid });
+                    }
+                    timeout = config.timing.p2_star + config.network_delay;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send(&self, request: &[u8]) -> Result<(), Error> {
+        self.transport.send(request).await?;
+        *self.last_activity.lock().unwrap() = Instant::now();
+        Ok(())
+    }
+
+    /// Send a request without waiting for a response
+    pub(crate) async fn send_only(&self, request: &[u8]) -> Result<(), Error> {
+        let _exchange = self.exchange.lock().await;
+        self.send(request).await
+    }
+
+    /// Send a request unless another exchange is in progress, returns whether it was sent
+    pub(crate) async fn try_send_only(&self, request: &[u8]) -> Result<bool, Error> {
+        let Ok(_exchange) = self.exchange.try_lock() else {
+            return Ok(false);
+        };
+        self.send(request).await?;
+        Ok(true)
+    }
+
+    /// Time elapsed since the last request was sent or response received
+    pub fn idle_time(&self) -> Duration {
+        self.last_activity.lock().unwrap().elapsed()
+    }
+
+    async fn receive_response(&self, sid: u8) -> Result<Vec<u8>, Error> {
+        loop {
+            let response = self.transport.recv().await?;
+            *self.last_activity.lock().unwrap() = Instant::now();
+            if let Some(result) = check_response(sid, response) {
+                return result;
+            }
+        }
+    }
+
+    /// Send a request with a sub-function, not waiting for the response if it is suppressed
+    pub(crate) async fn request_sub_function(
+        &self,
+        sid: u8,
+        sub_function: u8,
+        suppress_positive_response: bool,
+        parameters: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let mut request = vec![sid, sub_function];
+        if suppress_positive_response {
+            request[1] |= SUPPRESS_POSITIVE_RESPONSE;
+        }
+        request.extend_from_slice(parameters);
+        if suppress_positive_response {
+            self.send_only(&request).await?;
+            return Ok(None);
+        }
+        let response = self.request(&request).await?;
+        if response.get(1).map(|echo| echo & !SUPPRESS_POSITIVE_RESPONSE) != Some(sub_function) {
+            return Err(Error::invalid_response(format!(
+                "sub-function 0x{sub_function:02X} not echoed"
+            )));
+        }
+        Ok(Some(response))
+    }
+
+    /// DiagnosticSessionControl (0x10), the reported timing is applied to all further requests
+    pub async fn diagnostic_session_control(
+        &self,
+        session: DiagnosticSession,
+    ) -> Result<SessionParameters, Error> {
+        let response = self
+            .request_sub_function(DIAGNOSTIC_SESSION_CONTROL, session.into(), false, &[])
+            .await?
+            .unwrap_or_default();
+        let parameters = parse_session_parameters(session, &response[2..]);
+        self.config.lock().unwrap().timing = parameters;
+        Ok(parameters)
+    }
+
+    /// ECUReset (0x11), returns the powerDownTime if the ECU reported one
+    pub async fn ecu_reset(&self, reset_type: ResetType) -> Result<Option<u8>, Error> {
+        let response = self
+            .request_sub_function(ECU_RESET, reset_type.into(), false, &[])
+            .await?
+            .unwrap_or_default();
+        Ok(response.get(2).copied())
+    }
+
+    /// ReadDataByIdentifier (0x22) of a single data identifier
+    pub async fn read_data_by_identifier(&self, did: u16) -> Result<Vec<u8>, Error> {
+        let [high, low] = did.to_be_bytes();
+        let response = self.request(&[READ_DATA_BY_IDENTIFIER, high, low]).await?;
+        check_identifier(&response, did)?;
+        Ok(response[3..].to_vec())
+    }
+
+    /// WriteDataByIdentifier (0x2E)
+    pub async fn write_data_by_identifier(&self, did: u16, data: &[u8]) -> Result<(), Error> {
+        let mut request = vec![WRITE_DATA_BY_IDENTIFIER];
+        request.extend_from_slice(&did.to_be_bytes());
+        request.extend_from_slice(data);
+        let response = self.request(&request).await?;
+        check_identifier(&response, did)
+    }
+
+    /// RoutineControl (0x31), returns the routineStatusRecord
+    pub async fn routine_control(
+        &self,
+        control_type: RoutineControlType,
+        routine: u16,
+        option_record: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let mut parameters = routine.to_be_bytes().to_vec();
+        parameters.extend_from_slice(option_record);
+        let response = self
+            .request_sub_function(ROUTINE_CONTROL, control_type.into(), false, &parameters)
+            .await?
+            .unwrap_or_default();
+        if response.get(2..4) != Some(&routine.to_be_bytes()[..]) {
+            return Err(Error::invalid_response(format!(
+                "routine 0x{routine:04X} not echoed"
+            )));
+        }
+        Ok(response[4..].to_vec())
+    }
+
+    /// TesterPresent (0x3E)
+    pub async fn tester_present(&self, suppress_positive_response: bool) -> Result<(), Error> {
+        self.request_sub_function(TESTER_PRESENT, 0x00, suppress_positive_response, &[])
+            .await?;
+        Ok(())
+    }
+}
+
+/// Classify a PDU received after a request with the given SID
+///
+/// Returns `None` for PDUs which are not a response to the request.
+pub(crate) fn check_response(sid: u8, response: Vec<u8>) -> Option<Result<Vec<u8>, Error>> {
+    match response.as_slice() {
+        [first, ..] if *first == sid.wrapping_add(POSITIVE_RESPONSE_OFFSET) => Some(Ok(response)),
+        [NEGATIVE_RESPONSE, service, code, ..] if *service == sid => {
+            Some(Err(Error::NegativeResponse {
+                service: sid,
+                code: NegativeResponseCode::from(*code),
+            }))
+        }
+        _ => None,
+    }
+}
+
+fn check_identifier(response: &[u8], did: u16) -> Result<(), Error> {
+    if response.get(1..3) != Some(&did.to_be_bytes()[..]) {
+        return Err(Error::invalid_response(format!(
+            "data identifier 0x{did:04X} not echoed"
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn parse_session_parameters(
+    session: DiagnosticSession,
+    record: &[u8],
+) -> SessionParameters {
+    match record {
+        [p2_high, p2_low, p2_star_high, p2_star_low, ..] => SessionParameters {
+            session,
+            p2: Duration::from_millis(u64::from(u16::from_be_bytes([*p2_high, *p2_low]))),
+            p2_star: Duration::from_millis(
+                10 * u64::from(u16::from_be_bytes([*p2_star_high, *p2_star_low])),
+            ),
+        },
+        // the timing record is optional for some ECUs
+        _ => SessionParameters {
+            session,
+            p2: DEFAULT_P2,
+            p2_star: DEFAULT_P2_STAR,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::tests::MockTransport;
+
+    #[tokio::test]
+    async fn empty_request_is_invalid() {
+        let client = UdsClient::new(MockTransport::new(|_| vec![]));
+        let error = client.request(&[]).await.unwrap_err();
+        assert!(matches!(error, Error::InvalidRequest { .. }), "{error:?}");
+        assert!(client.transport().requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn response_pending_and_negative_response() {
+        let client = UdsClient::new(MockTransport::new(|request| match request {
+            [0x22, ..] => vec![vec![0x7F, 0x22, 0x78], vec![0x62, 0xF1, 0x90, 0x01]],
+            _ => vec![vec![0x7F, request[0], 0x11]],
+        }));
+        let response = client.request(&[0x22, 0xF1, 0x90]).await.unwrap();
+        assert_eq!(response, [0x62, 0xF1, 0x90, 0x01]);
+        let error = client.request(&[0x85, 0x01]).await.unwrap_err();
+        assert_eq!(
+            error.negative_response_code(),
+            Some(NegativeResponseCode::ServiceNotSupported)
+        );
+    }
+
+    #[tokio::test]
+    async fn no_response_times_out() {
+        let client = UdsClient::new(MockTransport::new(|_| vec![]));
+        let error = client.request(&[0x3E, 0x00]).await.unwrap_err();
+        assert!(
+            matches!(error, Error::Timeout { service: 0x3E }),
+            "{error:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn response_pending_limit() {
+        let config = ClientConfig {
+            max_response_pending: 2,
+            ..ClientConfig::default()
+        };
+        let client = UdsClient::with_config(
+            MockTransport::new(|_| vec![vec![0x7F, 0x31, 0x78]; 3]),
+            config,
+        );
+        let error = client.request(&[0x31, 0x01, 0xFF, 0x00]).await.unwrap_err();
+        assert!(
+            matches!(error, Error::ResponsePendingLimit { service: 0x31 }),
+            "{error:?}"
+        );
+        assert_eq!(client.transport().requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn busy_repeat_retries_with_backoff() {
+        let config = ClientConfig {
+            busy_repeat: BusyRepeatPolicy {
+                max_retries: 3,
+                initial_delay: Duration::from_millis(20),
+                max_delay: Duration::from_millis(30),
+            },
+            ..ClientConfig::default()
+        };
+        let mut busy = 3;
+        let client = UdsClient::with_config(
+            MockTransport::new(move |_| {
+                if busy > 0 {
+                    busy -= 1;
+                    vec![vec![0x7F, 0x22, 0x21]]
+                } else {
+                    vec![vec![0x62, 0xF1, 0x90, 0x01]]
+                }
+            }),
+            config,
+        );
+        let start = Instant::now();
+        let response = client.request(&[0x22, 0xF1, 0x90]).await.unwrap();
+        assert_eq!(response, [0x62, 0xF1, 0x90, 0x01]);
+        assert_eq!(client.transport().requests().len(), 4);
+        // 20 ms, then doubled but capped at 30 ms
+        assert!(start.elapsed() >= Duration::from_millis(80));
+    }
+
+    #[tokio::test]
+    async fn busy_repeat_gives_up() {
+        let config = ClientConfig {
+            busy_repeat: BusyRepeatPolicy {
+                max_retries: 1,
+                initial_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+            },
+            ..ClientConfig::default()
+        };
+        let client =
+            UdsClient::with_config(MockTransport::new(|_| vec![vec![0x7F, 0x22, 0x21]]), config);
+        let error = client.request(&[0x22, 0xF1, 0x90]).await.unwrap_err();
+        assert_eq!(
+            error.negative_response_code(),
+            Some(NegativeResponseCode::BusyRepeatRequest)
+        );
+        assert_eq!(client.transport().requests().len(), 2);
+
+        let config = ClientConfig {
+            busy_repeat: BusyRepeatPolicy {
+                max_retries: 0,
+                ..config.busy_repeat
+            },
+            ..config
+        };
+        client.set_config(config);
+        client.request(&[0x22, 0xF1, 0x90]).await.unwrap_err();
+        assert_eq!(client.transport().requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn session_control_updates_timing() {
+        let client = UdsClient::new(MockTransport::new(|request| match request {
+            [0x10, 0x03] => vec![vec![0x50, 0x03, 0x00, 0x19, 0x00, 0x64]],
+            [0x10, session] => vec![vec![0x50, *session]],
+            _ => vec![],
+        }));
+        let parameters = client
+            .diagnostic_session_control(DiagnosticSession::Extended)
+            .await
+            .unwrap();
+        let expected = SessionParameters {
+            session: DiagnosticSession::Extended,
+            p2: Duration::from_millis(25),
+            p2_star: Duration::from_millis(1000),
+        };
+        assert_eq!(parameters, expected);
+        assert_eq!(client.timing(), expected);
+
+        // the timing record is optional
+        client
+            .diagnostic_session_control(DiagnosticSession::Default)
+            .await
+            .unwrap();
+        assert_eq!(client.timing(), SessionParameters::default());
+    }
+
+    #[tokio::test]
+    async fn session_timing_applies_to_requests() {
+        let config = ClientConfig {
+            network_delay: Duration::ZERO,
+            ..ClientConfig::default()
+        };
+        let client = UdsClient::with_config(
+            MockTransport::new(|request| match request {
+                [0x10, 0x03] => vec![vec![0x50, 0x03, 0x00, 0x0A, 0x00, 0x01]],
+                _ => vec![],
+            }),
+            config,
+        );
+        client
+            .diagnostic_session_control(DiagnosticSession::Extended)
+            .await
+            .unwrap();
+        let start = Instant::now();
+        client.request(&[0x3E, 0x00]).await.unwrap_err();
+        // P2 of 10 ms instead of the default 50 ms
+        assert!(start.elapsed() < DEFAULT_P2);
+    }
+}
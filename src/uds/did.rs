@@ -0,0 +1,941 @@
+//! Registry of data identifiers (DIDs) with typed codecs
+//!
+//! A [DidRegistry] maps DIDs to a [Codec] describing the layout of their data, so that
+//! ReadDataByIdentifier responses are decoded into [Value]s and values are encoded for
+//! WriteDataByIdentifier. Definitions are loaded from TOML or YAML files:
+//!
+//! ```toml
+//! [[did]]
+//! did = 0xF190
+//! name = "VIN"
+//! type = "ascii"
+//! length = 17
+//!
+//! [[did]]
+//! did = 0x0D01
+//! name = "Vehicle speed"
+//! type = "unsigned"
+//! bytes = 2
+//! scale = 0.01
+//! unit = "km/h"
+//!
+//! [[did]]
+//! did = 0x0D02
+//! name = "Gear"
+//! type = "enum"
+//! bytes = 1
+//! values = [{ value = 0, name = "Neutral" }, { value = 1, name = "First" }]
+//!
+//! [[did]]
+//! did = 0x0D03
+//! name = "Lamp state"
+//! type = "struct"
+//! fields = [
+//!     { name = "flags", type = "bitfield", bytes = 1, fields = [{ name = "on", bit = 0 }] },
+//!     { name = "hours", type = "bcd", bytes = 2 },
+//! ]
+//! ```
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::uds::did::DidRegistry;
+//! use tokio_socketcan_isotp::uds::UdsClient;
+//! # async fn run(client: UdsClient) -> Result<(), Box<dyn std::error::Error>> {
+//! let registry = DidRegistry::load("dids.toml")?;
+//! let vin = client.read_did(&registry, 0xF190).await?;
+//! println!("VIN: {vin}");
+//! # Ok(())
+//! # }
+//! ```
+
+use super::{Error, UdsClient};
+use crate::IsoTpTransport;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Byte order of integers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+/// Named value of an enum codec
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnumValue {
+    pub value: u64,
+    pub name: String,
+}
+
+/// Bits of a bitfield codec
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BitField {
+    pub name: String,
+    /// Position of the least significant bit
+    pub bit: u32,
+    #[serde(default = "default_width")]
+    pub width: u32,
+}
+
+/// Field of a struct codec
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StructField {
+    pub name: String,
+    #[serde(flatten)]
+    pub codec: Codec,
+}
+
+/// Layout of the data of a DID
+///
+/// Codecs without a length take the rest of the data, in a struct only the last
+/// field may do so.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Codec {
+    /// Unsigned integer, physical value is `raw * scale + offset`
+    Unsigned {
+        bytes: usize,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        offset: f64,
+        #[serde(default)]
+        unit: Option<String>,
+        #[serde(default)]
+        byte_order: ByteOrder,
+    },
+    /// Two's complement integer, physical value is `raw * scale + offset`
+    Signed {
+        bytes: usize,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        offset: f64,
+        #[serde(default)]
+        unit: Option<String>,
+        #[serde(default)]
+        byte_order: ByteOrder,
+    },
+    /// ASCII text, trailing spaces and NUL bytes are removed
+    Ascii {
+        #[serde(default)]
+        length: Option<usize>,
+    },
+    /// Packed BCD digits, 0xF nibbles are padding
+    Bcd {
+        #[serde(default)]
+        bytes: Option<usize>,
+    },
+    /// Unsigned integer with named values
+    Enum {
+        bytes: usize,
+        values: Vec<EnumValue>,
+    },
+    /// Unsigned integer split into named bit ranges
+    Bitfield { bytes: usize, fields: Vec<BitField> },
+    /// Sequence of fields
+    Struct { fields: Vec<StructField> },
+    /// Raw bytes
+    Bytes {
+        #[serde(default)]
+        length: Option<usize>,
+    },
+}
+
+/// Decoded data of a DID
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Scaled integer
+    Number {
+        raw: i128,
+        value: f64,
+        unit: Option<String>,
+    },
+    Text(String),
+    Enum {
+        raw: u64,
+        name: Option<String>,
+    },
+    Bits(Vec<(String, u64)>),
+    Struct(Vec<(String, Value)>),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number { value, unit, .. } => {
+                write!(f, "{value}")?;
+                if let Some(unit) = unit {
+                    write!(f, " {unit}")?;
+                }
+                Ok(())
+            }
+            Value::Text(text) => f.write_str(text),
+            Value::Enum {
+                name: Some(name), ..
+            } => f.write_str(name),
+            Value::Enum { raw, name: None } => write!(f, "{raw}"),
+            Value::Bits(bits) => {
+                let bits: Vec<_> = bits
+                    .iter()
+                    .map(|(name, bits)| format!("{name}={bits}"))
+                    .collect();
+                f.write_str(&bits.join(", "))
+            }
+            Value::Struct(fields) => {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|(name, value)| format!("{name}: {value}"))
+                    .collect();
+                write!(f, "{{{}}}", fields.join(", "))
+            }
+            Value::Bytes(bytes) => {
+                let bytes: Vec<_> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+                f.write_str(&bytes.join(" "))
+            }
+        }
+    }
+}
+
+impl Codec {
+    /// Number of bytes taken, `None` if the codec takes the rest of the data
+    pub fn length(&self) -> Option<usize> {
+        match self {
+            Codec::Unsigned { bytes, .. }
+            | Codec::Signed { bytes, .. }
+            | Codec::Enum { bytes, .. }
+            | Codec::Bitfield { bytes, .. } => Some(*bytes),
+            Codec::Ascii { length } | Codec::Bytes { length } => *length,
+            Codec::Bcd { bytes } => *bytes,
+            Codec::Struct { fields } => fields
+                .iter()
+                .map(|field| field.codec.length())
+                .sum::<Option<usize>>(),
+        }
+    }
+
+    /// Check the layout, e.g. that the bit fields fit into their integer and that
+    /// only the last field of a struct takes the rest of the data
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Codec::Unsigned { bytes, .. }
+            | Codec::Signed { bytes, .. }
+            | Codec::Enum { bytes, .. }
+            | Codec::Bitfield { bytes, .. }
+                if !(1..=8).contains(bytes) =>
+            {
+                Err(format!("integer of {bytes} bytes"))
+            }
+            Codec::Bitfield { bytes, fields } => {
+                let bits = 8 * *bytes as u32;
+                for field in fields {
+                    if field.width == 0 || field.bit.saturating_add(field.width) > bits {
+                        return Err(format!(
+                            "bit field {} of bits {}..{} exceeds {bits} bits",
+                            field.name,
+                            field.bit,
+                            field.bit.saturating_add(field.width)
+                        ));
+                    }
+                }
+                Ok(())
+            }
+            Codec::Struct { fields } => {
+                let leading = fields.split_last().map_or(&[][..], |(_, leading)| leading);
+                if let Some(field) = leading.iter().find(|field| field.codec.length().is_none()) {
+                    return Err(format!(
+                        "{}: only the last field may take the rest of the data",
+                        field.name
+                    ));
+                }
+                fields.iter().try_for_each(|field| {
+                    field
+                        .codec
+                        .validate()
+                        .map_err(|err| format!("{}: {err}", field.name))
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Decode the whole data
+    pub fn decode(&self, data: &[u8]) -> Result<Value, String> {
+        let (value, used) = self.decode_prefix(data)?;
+        if used != data.len() {
+            return Err(format!("{} bytes left over", data.len() - used));
+        }
+        Ok(value)
+    }
+
+    /// Encode a value
+    pub fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        self.encode_into(value, &mut data)?;
+        Ok(data)
+    }
+
+    fn decode_prefix(&self, data: &[u8]) -> Result<(Value, usize), String> {
+        let length = self.length().unwrap_or(data.len());
+        let field = data
+            .get(..length)
+            .ok_or_else(|| format!("{} bytes instead of {length}", data.len()))?;
+        let value = match self {
+            Codec::Unsigned {
+                scale,
+                offset,
+                unit,
+                byte_order,
+                ..
+            } => {
+                let raw = i128::from(read_unsigned(field, *byte_order)?);
+                number(raw, *scale, *offset, unit)
+            }
+            Codec::Signed {
+                scale,
+                offset,
+                unit,
+                byte_order,
+                ..
+            } => {
+                let unsigned = read_unsigned(field, *byte_order)?;
+                let shift = 64 - 8 * field.len() as u32;
+                let raw = i128::from(((unsigned << shift) as i64) >> shift);
+                number(raw, *scale, *offset, unit)
+            }
+            Codec::Ascii { .. } => {
+                if !field.is_ascii() {
+                    return Err("non-ASCII text".to_string());
+                }
+                let text = String::from_utf8_lossy(field);
+                Value::Text(text.trim_end_matches([' ', '\0']).to_string())
+            }
+            Codec::Bcd { .. } => {
+                let mut digits = String::new();
+                for nibble in field.iter().flat_map(|byte| [byte >> 4, byte & 0x0F]) {
+                    match nibble {
+                        0..=9 => digits.push(char::from(b'0' + nibble)),
+                        0x0F => {}
+                        _ => return Err(format!("invalid BCD digit {nibble:X}")),
+                    }
+                }
+                Value::Text(digits)
+            }
+            Codec::Enum { values, .. } => {
+                let raw = read_unsigned(field, ByteOrder::Big)?;
+                let name = values
+                    .iter()
+                    .find(|value| value.value == raw)
+                    .map(|value| value.name.clone());
+                Value::Enum { raw, name }
+            }
+            Codec::Bitfield { fields, .. } => {
+                let raw = read_unsigned(field, ByteOrder::Big)?;
+                Value::Bits(
+                    fields
+                        .iter()
+                        .map(|field| {
+                            let bits = raw.checked_shr(field.bit).unwrap_or(0);
+                            (field.name.clone(), bits & mask(field.width))
+                        })
+                        .collect(),
+                )
+            }
+            Codec::Struct { fields } => {
+                let mut values = Vec::with_capacity(fields.len());
+                let mut offset = 0;
+                for field in fields {
+                    let (value, used) = field
+                        .codec
+                        .decode_prefix(&data[offset..])
+                        .map_err(|err| format!("{}: {err}", field.name))?;
+                    values.push((field.name.clone(), value));
+                    offset += used;
+                }
+                return Ok((Value::Struct(values), offset));
+            }
+            Codec::Bytes { .. } => Value::Bytes(field.to_vec()),
+        };
+        Ok((value, length))
+    }
+
+    fn encode_into(&self, value: &Value, data: &mut Vec<u8>) -> Result<(), String> {
+        match (self, value) {
+            (
+                Codec::Unsigned {
+                    bytes,
+                    scale,
+                    offset,
+                    byte_order,
+                    ..
+                }
+                | Codec::Signed {
+                    bytes,
+                    scale,
+                    offset,
+                    byte_order,
+                    ..
+                },
+                Value::Number { value, .. },
+            ) => {
+                let raw = ((value - offset) / scale).round() as i128;
+                let signed = matches!(self, Codec::Signed { .. });
+                let bits = 8 * (*bytes).clamp(1, 8) as u32;
+                let (min, max) = match signed {
+                    true => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+                    false => (0, (1i128 << bits) - 1),
+                };
+                if !(min..=max).contains(&raw) {
+                    return Err(format!("{value} out of range"));
+                }
+                write_unsigned(raw as u64, *bytes, *byte_order, data)?;
+            }
+            (Codec::Ascii { length }, Value::Text(text)) => {
+                if !text.is_ascii() || length.is_some_and(|length| text.len() > length) {
+                    return Err(format!("text {text:?} does not fit"));
+                }
+                data.extend_from_slice(text.as_bytes());
+                let padding = length.unwrap_or(text.len()) - text.len();
+                data.extend(std::iter::repeat_n(b' ', padding));
+            }
+            (Codec::Bcd { bytes }, Value::Text(digits)) => {
+                let mut nibbles: Vec<u8> = digits
+                    .chars()
+                    .map(|digit| digit.to_digit(10).map(|digit| digit as u8))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| format!("invalid BCD digits {digits:?}"))?;
+                let length = bytes.unwrap_or(nibbles.len().div_ceil(2));
+                if nibbles.len() > 2 * length {
+                    return Err(format!("digits {digits:?} do not fit"));
+                }
+                // pad at the front, the value keeps its magnitude
+                let mut padded = vec![0; 2 * length - nibbles.len()];
+                padded.append(&mut nibbles);
+                data.extend(padded.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+            }
+            (Codec::Enum { bytes, values }, Value::Enum { raw, name }) => {
+                let raw = match name {
+                    Some(name) => values
+                        .iter()
+                        .find(|value| &value.name == name)
+                        .map(|value| value.value)
+                        .ok_or_else(|| format!("unknown value {name:?}"))?,
+                    None => *raw,
+                };
+                check_fits(raw, *bytes)?;
+                write_unsigned(raw, *bytes, ByteOrder::Big, data)?;
+            }
+            (Codec::Bitfield { bytes, fields }, Value::Bits(bits)) => {
+                let mut raw = 0;
+                for (name, value) in bits {
+                    let field = fields
+                        .iter()
+                        .find(|field| &field.name == name)
+                        .ok_or_else(|| format!("unknown bit field {name:?}"))?;
+                    if value & !mask(field.width) != 0 {
+                        return Err(format!("{name} = {value} out of range"));
+                    }
+                    raw |= value
+                        .checked_shl(field.bit)
+                        .filter(|shifted| shifted >> field.bit == *value)
+                        .ok_or_else(|| format!("bit field {name} exceeds 64 bits"))?;
+                }
+                check_fits(raw, *bytes)?;
+                write_unsigned(raw, *bytes, ByteOrder::Big, data)?;
+            }
+            (Codec::Struct { fields }, Value::Struct(values)) => {
+                for field in fields {
+                    let value = values
+                        .iter()
+                        .find(|(name, _)| name == &field.name)
+                        .map(|(_, value)| value)
+                        .ok_or_else(|| format!("missing field {:?}", field.name))?;
+                    field
+                        .codec
+                        .encode_into(value, data)
+                        .map_err(|err| format!("{}: {err}", field.name))?;
+                }
+            }
+            (Codec::Bytes { length }, Value::Bytes(bytes)) => {
+                if length.is_some_and(|length| bytes.len() != length) {
+                    return Err(format!("{} bytes instead of {length:?}", bytes.len()));
+                }
+                data.extend_from_slice(bytes);
+            }
+            _ => return Err(format!("value {value} does not match the codec")),
+        }
+        Ok(())
+    }
+}
+
+/// Definition of one DID
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DidDefinition {
+    pub did: u16,
+    pub name: String,
+    #[serde(flatten)]
+    pub codec: Codec,
+}
+
+/// Layout of the definition files
+#[derive(Deserialize, Serialize)]
+struct DidFile {
+    #[serde(default, rename = "did")]
+    dids: Vec<DidDefinition>,
+}
+
+/// DIDs with their codecs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DidRegistry {
+    definitions: BTreeMap<u16, DidDefinition>,
+}
+
+impl DidRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a definition, replacing an earlier one of the same DID
+    pub fn insert(&mut self, definition: DidDefinition) {
+        self.definitions.insert(definition.did, definition);
+    }
+
+    pub fn get(&self, did: u16) -> Option<&DidDefinition> {
+        self.definitions.get(&did)
+    }
+
+    /// Definitions ordered by DID
+    pub fn iter(&self) -> impl Iterator<Item = &DidDefinition> {
+        self.definitions.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.definitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.definitions.is_empty()
+    }
+
+    /// Parse definitions in TOML
+    pub fn from_toml(text: &str) -> io::Result<Self> {
+        let file: DidFile = toml::from_str(text).map_err(|err| invalid_data(err.to_string()))?;
+        Self::validated(file.dids)
+    }
+
+    /// Parse definitions in YAML
+    pub fn from_yaml(text: &str) -> io::Result<Self> {
+        let file: DidFile =
+            serde_yaml::from_str(text).map_err(|err| invalid_data(err.to_string()))?;
+        Self::validated(file.dids)
+    }
+
+    /// Registry of definitions whose codecs passed [Codec::validate]
+    fn validated(definitions: Vec<DidDefinition>) -> io::Result<Self> {
+        for definition in &definitions {
+            definition.codec.validate().map_err(|reason| {
+                invalid_data(format!("DID 0x{:04X}: {reason}", definition.did))
+            })?;
+        }
+        Ok(Self::from_iter(definitions))
+    }
+
+    /// Definitions in TOML
+    pub fn to_toml(&self) -> String {
+        let file = DidFile {
+            dids: self.definitions.values().cloned().collect(),
+        };
+        // the definitions contain nothing TOML cannot represent
+        toml::to_string(&file).unwrap()
+    }
+
+    /// Load a definition file, `.yaml`/`.yml` are read as YAML and all others as TOML
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&text),
+            _ => Self::from_toml(&text),
+        }
+    }
+
+    /// Decode the data of a DID
+    pub fn decode(&self, did: u16, data: &[u8]) -> Result<Value, Error> {
+        self.definition(did)?
+            .codec
+            .decode(data)
+            .map_err(|reason| Error::invalid_response(format!("DID 0x{did:04X}: {reason}")))
+    }
+
+    /// Encode a value of a DID
+    pub fn encode(&self, did: u16, value: &Value) -> Result<Vec<u8>, Error> {
+        self.definition(did)?
+            .codec
+            .encode(value)
+            .map_err(|reason| Error::invalid_request(format!("DID 0x{did:04X}: {reason}")))
+    }
+
+    fn definition(&self, did: u16) -> Result<&DidDefinition, Error> {
+        self.get(did)
+            .ok_or_else(|| Error::invalid_request(format!("unknown DID 0x{did:04X}")))
+    }
+}
+
+impl FromIterator<DidDefinition> for DidRegistry {
+    fn from_iter<I: IntoIterator<Item = DidDefinition>>(definitions: I) -> Self {
+        let mut registry = Self::new();
+        for definition in definitions {
+            registry.insert(definition);
+        }
+        registry
+    }
+}
+
+impl<T: IsoTpTransport> UdsClient<T> {
+    /// ReadDataByIdentifier (0x22) decoded with the codec of the registry
+    pub async fn read_did(&self, registry: &DidRegistry, did: u16) -> Result<Value, Error> {
+        // fail before the request if the DID is unknown
+        registry.definition(did)?;
+        let data = self.read_data_by_identifier(did).await?;
+        registry.decode(did, &data)
+    }
+
+    /// WriteDataByIdentifier (0x2E) encoded with the codec of the registry
+    pub async fn write_did(
+        &self,
+        registry: &DidRegistry,
+        did: u16,
+        value: &Value,
+    ) -> Result<(), Error> {
+        let data = registry.encode(did, value)?;
+        self.write_data_by_identifier(did, &data).await
+    }
+}
+
+fn number(raw: i128, scale: f64, offset: f64, unit: &Option<String>) -> Value {
+    Value::Number {
+        raw,
+        value: raw as f64 * scale + offset,
+        unit: unit.clone(),
+    }
+}
+
+fn read_unsigned(bytes: &[u8], byte_order: ByteOrder) -> Result<u64, String> {
+    if !(1..=8).contains(&bytes.len()) {
+        return Err(format!("integer of {} bytes", bytes.len()));
+    }
+    let fold = |value: u64, byte: &u8| (value << 8) | u64::from(*byte);
+    Ok(match byte_order {
+        ByteOrder::Big => bytes.iter().fold(0, fold),
+        ByteOrder::Little => bytes.iter().rev().fold(0, fold),
+    })
+}
+
+fn write_unsigned(
+    value: u64,
+    bytes: usize,
+    byte_order: ByteOrder,
+    data: &mut Vec<u8>,
+) -> Result<(), String> {
+    if !(1..=8).contains(&bytes) {
+        return Err(format!("integer of {bytes} bytes"));
+    }
+    let be = &value.to_be_bytes()[8 - bytes..];
+    match byte_order {
+        ByteOrder::Big => data.extend_from_slice(be),
+        ByteOrder::Little => data.extend(be.iter().rev()),
+    }
+    Ok(())
+}
+
+fn check_fits(value: u64, bytes: usize) -> Result<(), String> {
+    if bytes < 8 && value >> (8 * bytes) != 0 {
+        return Err(format!("{value} does not fit into {bytes} bytes"));
+    }
+    Ok(())
+}
+
+fn mask(width: u32) -> u64 {
+    u64::MAX.checked_shr(64 - width.min(64)).unwrap_or(0)
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_width() -> u32 {
+    1
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::tests::MockTransport;
+
+    /// The example of the module documentation
+    const EXAMPLE: &str = r#"
+[[did]]
+did = 0xF190
+name = "VIN"
+type = "ascii"
+length = 17
+
+[[did]]
+did = 0x0D01
+name = "Vehicle speed"
+type = "unsigned"
+bytes = 2
+scale = 0.01
+unit = "km/h"
+
+[[did]]
+did = 0x0D02
+name = "Gear"
+type = "enum"
+bytes = 1
+values = [{ value = 0, name = "Neutral" }, { value = 1, name = "First" }]
+
+[[did]]
+did = 0x0D03
+name = "Lamp state"
+type = "struct"
+fields = [
+    { name = "flags", type = "bitfield", bytes = 1, fields = [{ name = "on", bit = 0 }] },
+    { name = "hours", type = "bcd", bytes = 2 },
+]
+"#;
+
+    #[test]
+    fn documented_example_loads() {
+        let registry = DidRegistry::from_toml(EXAMPLE).unwrap();
+        assert_eq!(registry.len(), 4);
+        assert_eq!(
+            registry.get(0xF190).unwrap().codec,
+            Codec::Ascii { length: Some(17) }
+        );
+        assert_eq!(
+            registry.get(0x0D03).unwrap().codec,
+            Codec::Struct {
+                fields: vec![
+                    StructField {
+                        name: "flags".to_string(),
+                        codec: Codec::Bitfield {
+                            bytes: 1,
+                            fields: vec![BitField {
+                                name: "on".to_string(),
+                                bit: 0,
+                                width: 1,
+                            }],
+                        },
+                    },
+                    StructField {
+                        name: "hours".to_string(),
+                        codec: Codec::Bcd { bytes: Some(2) },
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            DidRegistry::from_toml(&registry.to_toml()).unwrap(),
+            registry
+        );
+    }
+
+    #[test]
+    fn decodes_and_encodes() {
+        let registry = DidRegistry::from_toml(EXAMPLE).unwrap();
+        let cases = [
+            (0xF190, b"WVWZZZ1JZXW000001".to_vec(), "WVWZZZ1JZXW000001"),
+            (0x0D01, vec![0x27, 0x10], "100 km/h"),
+            (0x0D02, vec![0x01], "First"),
+            (0x0D03, vec![0x01, 0x12, 0x34], "{flags: on=1, hours: 1234}"),
+        ];
+        for (did, data, text) in cases {
+            let value = registry.decode(did, &data).unwrap();
+            assert_eq!(value.to_string(), text);
+            assert_eq!(registry.encode(did, &value).unwrap(), data);
+        }
+        assert!(registry.decode(0x0D01, &[0x01]).is_err());
+        assert!(registry.decode(0x0D01, &[0x01, 0x02, 0x03]).is_err());
+        assert!(registry.decode(0x1234, &[]).is_err());
+        let too_fast = Value::Number {
+            raw: 0,
+            value: 1000.0,
+            unit: None,
+        };
+        assert!(registry.encode(0x0D01, &too_fast).is_err());
+    }
+
+    #[test]
+    fn signed_little_endian_and_bytes() {
+        let yaml = r#"
+did:
+  - did: 0x0100
+    name: Temperature
+    type: signed
+    bytes: 2
+    byte_order: little
+    offset: -40
+  - did: 0x0101
+    name: Blob
+    type: bytes
+  - did: 0x0102
+    name: Modes
+    type: bitfield
+    bytes: 2
+    fields:
+      - { name: low, bit: 0, width: 4 }
+      - { name: high, bit: 12, width: 4 }
+"#;
+        let registry = DidRegistry::from_yaml(yaml).unwrap();
+        let value = registry.decode(0x0100, &[0xFE, 0xFF]).unwrap();
+        assert_eq!(
+            value,
+            Value::Number {
+                raw: -2,
+                value: -42.0,
+                unit: None,
+            }
+        );
+        assert_eq!(registry.encode(0x0100, &value).unwrap(), [0xFE, 0xFF]);
+        assert_eq!(
+            registry.decode(0x0101, &[1, 2, 3]).unwrap(),
+            Value::Bytes(vec![1, 2, 3])
+        );
+        let bits = registry.decode(0x0102, &[0xA0, 0x05]).unwrap();
+        assert_eq!(bits.to_string(), "low=5, high=10");
+        let invalid = Value::Bits(vec![("low".to_string(), 16)]);
+        assert!(registry.encode(0x0102, &invalid).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_bitfields_at_load() {
+        for (bytes, bit, width) in [
+            (1, 8, 1),
+            (1, 7, 2),
+            (8, 64, 1),
+            (8, 63, 2),
+            (9, 0, 1),
+            (1, 0, 0),
+        ] {
+            let toml = format!(
+                "[[did]]\ndid = 1\nname = \"x\"\ntype = \"bitfield\"\nbytes = {bytes}\n\
+                 fields = [{{ name = \"f\", bit = {bit}, width = {width} }}]\n"
+            );
+            let error = DidRegistry::from_toml(&toml).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{toml}");
+        }
+        let nested = r#"
+[[did]]
+did = 1
+name = "x"
+type = "struct"
+fields = [{ name = "b", type = "bitfield", bytes = 8, fields = [{ name = "f", bit = 4294967295 }] }]
+"#;
+        assert!(DidRegistry::from_toml(nested).is_err());
+        assert!(DidRegistry::from_toml(
+            "[[did]]\ndid = 1\nname = \"x\"\ntype = \"unsigned\"\nbytes = 0\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn unvalidated_bitfields_do_not_panic() {
+        let codec = Codec::Bitfield {
+            bytes: 8,
+            fields: vec![BitField {
+                name: "f".to_string(),
+                bit: 70,
+                width: 3,
+            }],
+        };
+        assert_eq!(
+            codec.decode(&[0xFF; 8]).unwrap(),
+            Value::Bits(vec![("f".to_string(), 0)])
+        );
+        assert!(codec
+            .encode(&Value::Bits(vec![("f".to_string(), 1)]))
+            .is_err());
+    }
+
+    #[test]
+    fn only_the_last_struct_field_is_open_ended() {
+        let open_ended_first = r#"
+[[did]]
+did = 1
+name = "x"
+type = "struct"
+fields = [{ name = "text", type = "ascii" }, { name = "count", type = "unsigned", bytes = 1 }]
+"#;
+        let error = DidRegistry::from_toml(open_ended_first).unwrap_err();
+        assert!(error.to_string().contains("text"), "{error}");
+        let nested = r#"
+[[did]]
+did = 1
+name = "x"
+type = "struct"
+fields = [
+    { name = "inner", type = "struct", fields = [{ name = "rest", type = "bytes" }] },
+    { name = "count", type = "unsigned", bytes = 1 },
+]
+"#;
+        assert!(DidRegistry::from_toml(nested).is_err());
+        let open_ended_last = r#"
+[[did]]
+did = 1
+name = "x"
+type = "struct"
+fields = [{ name = "count", type = "unsigned", bytes = 1 }, { name = "text", type = "ascii" }]
+"#;
+        let registry = DidRegistry::from_toml(open_ended_last).unwrap();
+        assert_eq!(
+            registry.decode(1, b"\x02ab").unwrap().to_string(),
+            "{count: 2, text: ab}"
+        );
+        assert!(Codec::Struct { fields: vec![] }.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn reads_and_writes_through_the_client() {
+        let client = UdsClient::new(MockTransport::new(|request| match request {
+            [0x22, 0x0D, 0x01] => vec![vec![0x62, 0x0D, 0x01, 0x27, 0x10]],
+            [0x22, 0x0D, 0x02] => vec![vec![0x62, 0x0D, 0x02, 0x01, 0x00]],
+            [0x2E, 0x0D, 0x02, _] => vec![vec![0x6E, 0x0D, 0x02]],
+            _ => vec![vec![0x7F, request[0], 0x31]],
+        }));
+        let registry = DidRegistry::from_toml(EXAMPLE).unwrap();
+
+        let speed = client.read_did(&registry, 0x0D01).await.unwrap();
+        assert_eq!(speed.to_string(), "100 km/h");
+        let gear = Value::Enum {
+            raw: 1,
+            name: Some("First".to_string()),
+        };
+        client.write_did(&registry, 0x0D02, &gear).await.unwrap();
+        assert_eq!(client.transport().requests()[1], [0x2E, 0x0D, 0x02, 0x01]);
+
+        // unknown DIDs and invalid values fail before the request
+        let error = client.read_did(&registry, 0x1234).await.unwrap_err();
+        assert!(matches!(error, Error::InvalidRequest { .. }), "{error:?}");
+        let error = client
+            .write_did(&registry, 0x0D01, &Value::Text("fast".to_string()))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidRequest { .. }), "{error:?}");
+        assert_eq!(client.transport().requests().len(), 2);
+
+        // data not matching the codec
+        let error = client.read_did(&registry, 0x0D02).await.unwrap_err();
+        assert!(matches!(error, Error::InvalidResponse { .. }), "{error:?}");
+    }
+}
@@ -0,0 +1,605 @@
+//! Diagnostic trouble codes, ReadDTCInformation (0x19) and ClearDiagnosticInformation (0x14)
+
+use super::service::{DtcReportType, CLEAR_DIAGNOSTIC_INFORMATION, READ_DTC_INFORMATION};
+use super::{Error, UdsClient};
+use crate::IsoTpTransport;
+use std::fmt;
+use std::str::FromStr;
+
+/// Diagnostic trouble code of three bytes
+///
+/// The two high bytes are the ISO 15031-6 code, e.g. `P0123`, the low byte is the
+/// failure type. It is displayed as `P0123-45`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Dtc(u32);
+
+impl Dtc {
+    /// groupOfDTC selecting all DTCs in ClearDiagnosticInformation
+    pub const ALL: Dtc = Dtc(0xFF_FFFF);
+
+    /// DTC of the low three bytes of the value
+    pub fn new(value: u32) -> Self {
+        Self(value & 0xFF_FFFF)
+    }
+
+    pub fn from_bytes(bytes: [u8; 3]) -> Self {
+        Self(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
+    }
+
+    pub fn to_bytes(self) -> [u8; 3] {
+        let [_, high, middle, low] = self.0.to_be_bytes();
+        [high, middle, low]
+    }
+
+    pub fn value(self) -> u32 {
+        self.0
+    }
+
+    /// ISO 15031-6 code of the two high bytes, e.g. `P0123`
+    pub fn code(self) -> String {
+        let code = (self.0 >> 8) as u16;
+        let system = ['P', 'C', 'B', 'U'][usize::from(code >> 14)];
+        format!("{system}{}{:03X}", (code >> 12) & 0x3, code & 0x0FFF)
+    }
+
+    /// Failure type byte
+    pub fn failure_type(self) -> u8 {
+        self.0 as u8
+    }
+}
+
+impl fmt::Display for Dtc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{:02X}", self.code(), self.failure_type())
+    }
+}
+
+impl FromStr for Dtc {
+    type Err = Error;
+
+    /// Parses `P0123` and `P0123-45`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::invalid_request(format!("invalid DTC {text:?}"));
+        let (code, failure_type) = text.split_once('-').unwrap_or((text, "00"));
+        let mut chars = code.chars();
+        let system = match chars.next().map(|system| system.to_ascii_uppercase()) {
+            Some('P') => 0,
+            Some('C') => 1,
+            Some('B') => 2,
+            Some('U') => 3,
+            _ => return Err(invalid()),
+        };
+        let rest = chars.as_str();
+        let hex = |digits: &str, len| {
+            digits.len() == len && digits.bytes().all(|digit| digit.is_ascii_hexdigit())
+        };
+        if !hex(rest, 4) || !hex(failure_type, 2) {
+            return Err(invalid());
+        }
+        let number = u16::from_str_radix(rest, 16).map_err(|_| invalid())?;
+        let failure_type = u8::from_str_radix(failure_type, 16).map_err(|_| invalid())?;
+        if number > 0x3FFF {
+            return Err(invalid());
+        }
+        let code = (system << 14) | number;
+        Ok(Dtc((u32::from(code) << 8) | u32::from(failure_type)))
+    }
+}
+
+/// Status byte of a DTC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DtcStatus(pub u8);
+
+impl DtcStatus {
+    pub const TEST_FAILED: DtcStatus = DtcStatus(0x01);
+    pub const TEST_FAILED_THIS_OPERATION_CYCLE: DtcStatus = DtcStatus(0x02);
+    pub const PENDING_DTC: DtcStatus = DtcStatus(0x04);
+    pub const CONFIRMED_DTC: DtcStatus = DtcStatus(0x08);
+    pub const TEST_NOT_COMPLETED_SINCE_LAST_CLEAR: DtcStatus = DtcStatus(0x10);
+    pub const TEST_FAILED_SINCE_LAST_CLEAR: DtcStatus = DtcStatus(0x20);
+    pub const TEST_NOT_COMPLETED_THIS_OPERATION_CYCLE: DtcStatus = DtcStatus(0x40);
+    pub const WARNING_INDICATOR_REQUESTED: DtcStatus = DtcStatus(0x80);
+    /// Mask selecting all DTCs
+    pub const ALL: DtcStatus = DtcStatus(0xFF);
+
+    const NAMES: [&'static str; 8] = [
+        "testFailed",
+        "testFailedThisOperationCycle",
+        "pendingDTC",
+        "confirmedDTC",
+        "testNotCompletedSinceLastClear",
+        "testFailedSinceLastClear",
+        "testNotCompletedThisOperationCycle",
+        "warningIndicatorRequested",
+    ];
+
+    /// Whether all bits of `other` are set
+    pub fn contains(self, other: DtcStatus) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Names of the set bits as used by the standard
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        Self::NAMES
+            .into_iter()
+            .enumerate()
+            .filter(move |(bit, _)| self.0 & (1 << bit) != 0)
+            .map(|(_, name)| name)
+    }
+}
+
+impl std::ops::BitOr for DtcStatus {
+    type Output = DtcStatus;
+
+    fn bitor(self, other: DtcStatus) -> DtcStatus {
+        DtcStatus(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for DtcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:02X}", self.0)?;
+        let names: Vec<_> = self.names().collect();
+        if !names.is_empty() {
+            write!(f, " ({})", names.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+byte_enum! {
+    /// DTCFormatIdentifier of reportNumberOfDTCByStatusMask
+    DtcFormat {
+        Iso15031_6 = 0x00, "SAE_J2012-DA_DTCFormat_00";
+        Iso14229_1 = 0x01, "ISO_14229-1_DTCFormat";
+        SaeJ1939_73 = 0x02, "SAE_J1939-73_DTCFormat";
+        Iso11992_4 = 0x03, "ISO_11992-4_DTCFormat";
+        SaeJ2012Da04 = 0x04, "SAE_J2012-DA_DTCFormat_04";
+    }
+}
+
+/// DTC together with its status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DtcRecord {
+    pub dtc: Dtc,
+    pub status: DtcStatus,
+}
+
+/// Response of reportNumberOfDTCByStatusMask
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DtcCount {
+    /// Status bits supported by the ECU
+    pub availability_mask: DtcStatus,
+    pub format: DtcFormat,
+    pub count: u16,
+}
+
+/// Response of reportDTCByStatusMask and reportSupportedDTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtcList {
+    /// Status bits supported by the ECU
+    pub availability_mask: DtcStatus,
+    pub records: Vec<DtcRecord>,
+}
+
+/// Data identifier with its data of a snapshot record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotData {
+    pub did: u16,
+    pub data: Vec<u8>,
+}
+
+/// DTCSnapshotRecord of a DTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotRecord {
+    pub number: u8,
+    pub data: Vec<SnapshotData>,
+}
+
+/// Response of reportDTCSnapshotRecordByDTCNumber
+///
+/// The length of the data of each identifier is defined by the ECU, the records are
+/// kept raw until [DtcSnapshot::records] splits them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtcSnapshot {
+    pub record: DtcRecord,
+    /// Snapshot records, each starting with its number
+    pub data: Vec<u8>,
+}
+
+impl DtcSnapshot {
+    /// Split the records given the data length of each data identifier
+    ///
+    /// Returns `None` if a length is unknown or the data does not match.
+    pub fn records(
+        &self,
+        did_length: impl Fn(u16) -> Option<usize>,
+    ) -> Option<Vec<SnapshotRecord>> {
+        let mut records = Vec::new();
+        let mut rest = &self.data[..];
+        while let [number, count, tail @ ..] = rest {
+            rest = tail;
+            let mut data = Vec::new();
+            for _ in 0..*count {
+                let [high, low, tail @ ..] = rest else {
+                    return None;
+                };
+                let did = u16::from_be_bytes([*high, *low]);
+                let length = did_length(did)?;
+                data.push(SnapshotData {
+                    did,
+                    data: tail.get(..length)?.to_vec(),
+                });
+                rest = &tail[length..];
+            }
+            records.push(SnapshotRecord {
+                number: *number,
+                data,
+            });
+        }
+        rest.is_empty().then_some(records)
+    }
+}
+
+/// DTCExtendedDataRecord of a DTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedDataRecord {
+    pub number: u8,
+    pub data: Vec<u8>,
+}
+
+/// Response of reportDTCExtDataRecordByDTCNumber
+///
+/// The length of each record is defined by the ECU, the records are kept raw until
+/// [DtcExtendedData::records] splits them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtcExtendedData {
+    pub record: DtcRecord,
+    /// Extended data records, each starting with its number
+    pub data: Vec<u8>,
+}
+
+impl DtcExtendedData {
+    /// Split the records given the length of each record number
+    ///
+    /// Returns `None` if a length is unknown or the data does not match.
+    pub fn records(
+        &self,
+        record_length: impl Fn(u8) -> Option<usize>,
+    ) -> Option<Vec<ExtendedDataRecord>> {
+        let mut records = Vec::new();
+        let mut rest = &self.data[..];
+        while let [number, tail @ ..] = rest {
+            let length = record_length(*number)?;
+            records.push(ExtendedDataRecord {
+                number: *number,
+                data: tail.get(..length)?.to_vec(),
+            });
+            rest = &tail[length..];
+        }
+        Some(records)
+    }
+}
+
+impl<T: IsoTpTransport> UdsClient<T> {
+    /// ReadDTCInformation (0x19) of any report type, returns the response after the
+    /// echoed report type
+    pub async fn read_dtc_information(
+        &self,
+        report_type: DtcReportType,
+        parameters: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let response = self
+            .request_sub_function(READ_DTC_INFORMATION, report_type.into(), false, parameters)
+            .await?
+            .unwrap_or_default();
+        Ok(response[2..].to_vec())
+    }
+
+    /// reportNumberOfDTCByStatusMask (0x19 0x01)
+    pub async fn report_number_of_dtc_by_status_mask(
+        &self,
+        mask: DtcStatus,
+    ) -> Result<DtcCount, Error> {
+        let response = self
+            .read_dtc_information(DtcReportType::NumberOfDtcByStatusMask, &[mask.0])
+            .await?;
+        match response[..] {
+            [availability_mask, format, count_high, count_low] => Ok(DtcCount {
+                availability_mask: DtcStatus(availability_mask),
+                format: DtcFormat::from(format),
+                count: u16::from_be_bytes([count_high, count_low]),
+            }),
+            _ => Err(Error::invalid_response("invalid DTC count")),
+        }
+    }
+
+    /// reportDTCByStatusMask (0x19 0x02)
+    pub async fn report_dtc_by_status_mask(&self, mask: DtcStatus) -> Result<DtcList, Error> {
+        let response = self
+            .read_dtc_information(DtcReportType::DtcByStatusMask, &[mask.0])
+            .await?;
+        parse_dtc_list(&response)
+    }
+
+    /// reportDTCSnapshotRecordByDTCNumber (0x19 0x04), record number 0xFF reads all
+    pub async fn report_dtc_snapshot_record_by_dtc_number(
+        &self,
+        dtc: Dtc,
+        record_number: u8,
+    ) -> Result<DtcSnapshot, Error> {
+        let response = self
+            .read_dtc_information(
+                DtcReportType::DtcSnapshotRecordByDtcNumber,
+                &dtc_parameters(dtc, record_number),
+            )
+            .await?;
+        let (record, data) = parse_dtc_record(&response, dtc)?;
+        Ok(DtcSnapshot { record, data })
+    }
+
+    /// reportDTCExtDataRecordByDTCNumber (0x19 0x06), record number 0xFF reads all
+    pub async fn report_dtc_ext_data_record_by_dtc_number(
+        &self,
+        dtc: Dtc,
+        record_number: u8,
+    ) -> Result<DtcExtendedData, Error> {
+        let response = self
+            .read_dtc_information(
+                DtcReportType::DtcExtDataRecordByDtcNumber,
+                &dtc_parameters(dtc, record_number),
+            )
+            .await?;
+        let (record, data) = parse_dtc_record(&response, dtc)?;
+        Ok(DtcExtendedData { record, data })
+    }
+
+    /// reportSupportedDTC (0x19 0x0A)
+    pub async fn report_supported_dtc(&self) -> Result<DtcList, Error> {
+        let response = self
+            .read_dtc_information(DtcReportType::SupportedDtc, &[])
+            .await?;
+        parse_dtc_list(&response)
+    }
+
+    /// ClearDiagnosticInformation (0x14) of a group of DTCs, [Dtc::ALL] clears all
+    pub async fn clear_diagnostic_information(&self, group: Dtc) -> Result<(), Error> {
+        let mut request = vec![CLEAR_DIAGNOSTIC_INFORMATION];
+        request.extend_from_slice(&group.to_bytes());
+        self.request(&request).await?;
+        Ok(())
+    }
+}
+
+fn dtc_parameters(dtc: Dtc, record_number: u8) -> Vec<u8> {
+    let mut parameters = dtc.to_bytes().to_vec();
+    parameters.push(record_number);
+    parameters
+}
+
+fn parse_dtc_list(response: &[u8]) -> Result<DtcList, Error> {
+    let Some((&availability_mask, records)) = response.split_first() else {
+        return Err(Error::invalid_response("missing DTCStatusAvailabilityMask"));
+    };
+    if !records.len().is_multiple_of(4) {
+        return Err(Error::invalid_response("truncated DTC record"));
+    }
+    Ok(DtcList {
+        availability_mask: DtcStatus(availability_mask),
+        records: records
+            .chunks_exact(4)
+            .map(|record| DtcRecord {
+                dtc: Dtc::from_bytes([record[0], record[1], record[2]]),
+                status: DtcStatus(record[3]),
+            })
+            .collect(),
+    })
+}
+
+fn parse_dtc_record(response: &[u8], dtc: Dtc) -> Result<(DtcRecord, Vec<u8>), Error> {
+    let [high, middle, low, status, ref data @ ..] = response[..] else {
+        return Err(Error::invalid_response("truncated DTC record"));
+    };
+    let record = DtcRecord {
+        dtc: Dtc::from_bytes([high, middle, low]),
+        status: DtcStatus(status),
+    };
+    if record.dtc != dtc {
+        return Err(Error::invalid_response(format!("DTC {dtc} not echoed")));
+    }
+    Ok((record, data.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::tests::MockTransport;
+
+    #[test]
+    fn formats_and_parses_dtcs() {
+        for (text, value) in [
+            ("P0301-1A", 0x03_011A),
+            ("C1234-00", 0x52_3400),
+            ("B2A00-FF", 0xAA_00FF),
+            ("U0100-87", 0xC1_0087),
+            ("P3FFF-01", 0x3F_FF01),
+        ] {
+            let dtc = Dtc::new(value);
+            assert_eq!(dtc.to_string(), text);
+            assert_eq!(text.parse::<Dtc>().unwrap(), dtc);
+            assert_eq!(text.to_lowercase().parse::<Dtc>().unwrap(), dtc);
+        }
+        let dtc: Dtc = "P0301".parse().unwrap();
+        assert_eq!(dtc, Dtc::from_bytes([0x03, 0x01, 0x00]));
+        assert_eq!(dtc.code(), "P0301");
+        assert_eq!(dtc.failure_type(), 0);
+        assert_eq!(Dtc::new(0x1234_5678).to_bytes(), [0x34, 0x56, 0x78]);
+        assert_eq!(Dtc::ALL.to_string(), "U3FFF-FF");
+    }
+
+    #[test]
+    fn rejects_invalid_dtcs() {
+        for text in [
+            "",
+            "X0301",
+            "P030",
+            "P03011",
+            "P0301-1",
+            "P0301-1A2",
+            "P4000",
+            "P+301",
+            "P0301-+1",
+            "P03\u{e9}",
+            "P0301-\u{e9}",
+        ] {
+            let error = text.parse::<Dtc>().unwrap_err();
+            assert!(matches!(error, Error::InvalidRequest { .. }), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn status_names() {
+        let status = DtcStatus::TEST_FAILED | DtcStatus::CONFIRMED_DTC;
+        assert!(status.contains(DtcStatus::CONFIRMED_DTC));
+        assert!(!status.contains(DtcStatus::PENDING_DTC | DtcStatus::TEST_FAILED));
+        assert_eq!(status.to_string(), "0x09 (testFailed, confirmedDTC)");
+        assert_eq!(DtcStatus(0).to_string(), "0x00");
+    }
+
+    fn ecu() -> UdsClient<MockTransport> {
+        UdsClient::new(MockTransport::new(|request| match request {
+            [0x19, 0x01, _] => vec![vec![0x59, 0x01, 0x7F, 0x01, 0x00, 0x02]],
+            [0x19, 0x02, _] => vec![vec![
+                0x59, 0x02, 0x7F, 0x03, 0x01, 0x1A, 0x09, 0xC1, 0x00, 0x87, 0x08,
+            ]],
+            [0x19, 0x0A] => vec![vec![0x59, 0x0A, 0x7F, 0x03, 0x01, 0x1A, 0x09]],
+            [0x19, 0x04, 0x03, 0x01, 0x1A, 0xFF] => vec![vec![
+                0x59, 0x04, 0x03, 0x01, 0x1A, 0x09, 0x01, 0x02, 0xF1, 0x90, 0xAA, 0xBB, 0x01, 0x23,
+                0xCC,
+            ]],
+            [0x19, 0x04, ..] => vec![vec![0x59, 0x04, 0xC1, 0x00, 0x87, 0x08]],
+            [0x19, 0x06, 0x03, 0x01, 0x1A, 0xFF] => vec![vec![
+                0x59, 0x06, 0x03, 0x01, 0x1A, 0x09, 0x01, 0x05, 0x02, 0x00, 0x10,
+            ]],
+            [0x14, 0xFF, 0xFF, 0xFF] => vec![vec![0x54]],
+            _ => vec![vec![0x7F, request[0], 0x31]],
+        }))
+    }
+
+    #[tokio::test]
+    async fn reads_dtc_information() {
+        let client = ecu();
+        let count = client
+            .report_number_of_dtc_by_status_mask(DtcStatus::CONFIRMED_DTC)
+            .await
+            .unwrap();
+        assert_eq!(
+            count,
+            DtcCount {
+                availability_mask: DtcStatus(0x7F),
+                format: DtcFormat::Iso14229_1,
+                count: 2,
+            }
+        );
+
+        let list = client
+            .report_dtc_by_status_mask(DtcStatus::ALL)
+            .await
+            .unwrap();
+        assert_eq!(list.availability_mask, DtcStatus(0x7F));
+        assert_eq!(
+            list.records,
+            [
+                DtcRecord {
+                    dtc: "P0301-1A".parse().unwrap(),
+                    status: DtcStatus(0x09),
+                },
+                DtcRecord {
+                    dtc: "U0100-87".parse().unwrap(),
+                    status: DtcStatus(0x08),
+                },
+            ]
+        );
+        assert_eq!(
+            client.report_supported_dtc().await.unwrap().records.len(),
+            1
+        );
+        client.clear_diagnostic_information(Dtc::ALL).await.unwrap();
+        assert_eq!(client.transport().requests()[0], [0x19, 0x01, 0x08]);
+    }
+
+    #[tokio::test]
+    async fn reads_snapshot_and_extended_data() {
+        let client = ecu();
+        let dtc: Dtc = "P0301-1A".parse().unwrap();
+        let snapshot = client
+            .report_dtc_snapshot_record_by_dtc_number(dtc, 0xFF)
+            .await
+            .unwrap();
+        assert_eq!(snapshot.record.status, DtcStatus(0x09));
+        let records = snapshot
+            .records(|did| match did {
+                0xF190 => Some(2),
+                0x0123 => Some(1),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            records,
+            [SnapshotRecord {
+                number: 1,
+                data: vec![
+                    SnapshotData {
+                        did: 0xF190,
+                        data: vec![0xAA, 0xBB],
+                    },
+                    SnapshotData {
+                        did: 0x0123,
+                        data: vec![0xCC],
+                    },
+                ],
+            }]
+        );
+        assert_eq!(snapshot.records(|_| Some(3)), None);
+        assert_eq!(snapshot.records(|_| None), None);
+
+        let extended = client
+            .report_dtc_ext_data_record_by_dtc_number(dtc, 0xFF)
+            .await
+            .unwrap();
+        let records = extended
+            .records(|number| match number {
+                1 => Some(1),
+                _ => Some(2),
+            })
+            .unwrap();
+        assert_eq!(
+            records,
+            [
+                ExtendedDataRecord {
+                    number: 1,
+                    data: vec![0x05],
+                },
+                ExtendedDataRecord {
+                    number: 2,
+                    data: vec![0x00, 0x10],
+                },
+            ]
+        );
+        assert_eq!(extended.records(|_| Some(5)), None);
+
+        let error = client
+            .report_dtc_snapshot_record_by_dtc_number("P0420-00".parse().unwrap(), 0x01)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidResponse { .. }), "{error:?}");
+    }
+
+    #[test]
+    fn parses_dtc_lists() {
+        assert!(parse_dtc_list(&[]).is_err());
+        assert!(parse_dtc_list(&[0xFF, 0x03, 0x01, 0x1A]).is_err());
+        assert_eq!(parse_dtc_list(&[0xFF]).unwrap().records, []);
+        assert!(parse_dtc_record(&[0x03, 0x01, 0x1A], Dtc::new(0x03011A)).is_err());
+    }
+}
@@ -0,0 +1,460 @@
+//! Enumeration of the services, sub-functions, DIDs and routines of an ECU
+//!
+//! The [Enumerator] enters every configured session, keeps it alive with a
+//! [SessionGuard](super::SessionGuard) and probes:
+//!
+//! * every service with a request consisting of the SID only, an ECU supporting the
+//!   service answers with a positive response or an NRC like 0x13
+//! * the sub-functions 0x00–0x7F of the configured services available in the session
+//! * ReadDataByIdentifier of the configured DIDs
+//! * RoutineControl requestRoutineResults of the configured routines
+//!
+//! The negative responses are classified as [Support]: 0x11, 0x12 and 0x31 mean not
+//! supported, 0x7F and 0x7E not supported in the active session. Every other NRC
+//! means the ECU knows the request but refuses it, e.g. without security access.
+//!
+//! Sub-functions are only probed for services listed in
+//! [EnumerationConfig::sub_function_services], by default the side-effect free
+//! ReadDTCInformation and TesterPresent. Listing services like ECUReset or
+//! CommunicationControl executes them on the ECU.
+//!
+//! The [CapabilityMap] is written as JSON with hex keys, entries answered with
+//! "not supported" are left out:
+//!
+//! ```json
+//! {"sessions":{"03":{"entered":"supported","services":{"22":"supported","27":{"restricted":34}},
+//!  "sub_functions":{"19":{"01":"supported"}},"dids":{"F190":"supported"},"routines":{}}}}
+//! ```
+
+use super::service::{
+    READ_DATA_BY_IDENTIFIER, READ_DTC_INFORMATION, ROUTINE_CONTROL, TESTER_PRESENT,
+};
+use super::{
+    DiagnosticSession, Error, NegativeResponseCode, RoutineControlType, UdsClient,
+    DEFAULT_TESTER_PRESENT_INTERVAL,
+};
+use crate::IsoTpTransport;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Classified answer of the ECU to a probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Support {
+    /// Positive response
+    Supported,
+    /// Negative response other than "not supported", the NRC is kept
+    Restricted(u8),
+    /// NRC 0x7F or 0x7E
+    NotSupportedInSession,
+    /// NRC 0x11, 0x12 or 0x31
+    NotSupported,
+    /// No response within P2/P2*
+    NoResponse,
+}
+
+impl Support {
+    /// Classify the result of a request, errors of the transport are passed on
+    pub fn classify(result: Result<Vec<u8>, Error>) -> Result<Self, Error> {
+        match result {
+            Ok(_) => Ok(Support::Supported),
+            Err(Error::NegativeResponse { code, .. }) => Ok(match code {
+                NegativeResponseCode::ServiceNotSupported
+                | NegativeResponseCode::SubFunctionNotSupported
+                | NegativeResponseCode::RequestOutOfRange => Support::NotSupported,
+                NegativeResponseCode::ServiceNotSupportedInActiveSession
+                | NegativeResponseCode::SubFunctionNotSupportedInActiveSession => {
+                    Support::NotSupportedInSession
+                }
+                code => Support::Restricted(code.into()),
+            }),
+            Err(Error::Timeout { .. }) | Err(Error::ResponsePendingLimit { .. }) => {
+                Ok(Support::NoResponse)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Whether the ECU knows the request in the session, possibly refusing it
+    pub fn is_available(self) -> bool {
+        matches!(self, Support::Supported | Support::Restricted(_))
+    }
+}
+
+/// Requests probed by an [Enumerator]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnumerationConfig {
+    /// Sessions entered one after another
+    pub sessions: Vec<DiagnosticSession>,
+    /// Service identifiers probed in every session
+    pub services: Vec<u8>,
+    /// Services whose sub-functions are probed
+    pub sub_function_services: Vec<u8>,
+    /// DIDs read in every session
+    pub dids: Vec<RangeInclusive<u16>>,
+    /// Routines whose results are requested in every session
+    pub routines: Vec<RangeInclusive<u16>>,
+    /// TesterPresent interval of non-default sessions
+    pub keepalive: Duration,
+}
+
+impl Default for EnumerationConfig {
+    fn default() -> Self {
+        Self {
+            sessions: vec![DiagnosticSession::Default, DiagnosticSession::Extended],
+            services: (0x10..=0x3E).chain(0x83..=0x88).collect(),
+            sub_function_services: vec![READ_DTC_INFORMATION, TESTER_PRESENT],
+            dids: vec![0xF180..=0xF19F],
+            routines: vec![0xFF00..=0xFF01],
+            keepalive: DEFAULT_TESTER_PRESENT_INTERVAL,
+        }
+    }
+}
+
+/// Capabilities of the ECU in one session, keys are hex strings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionCapabilities {
+    /// Answer to DiagnosticSessionControl, nothing else is probed unless supported
+    pub entered: Support,
+    pub services: BTreeMap<String, Support>,
+    pub sub_functions: BTreeMap<String, BTreeMap<String, Support>>,
+    pub dids: BTreeMap<String, Support>,
+    pub routines: BTreeMap<String, Support>,
+}
+
+impl SessionCapabilities {
+    fn new(entered: Support) -> Self {
+        Self {
+            entered,
+            services: BTreeMap::new(),
+            sub_functions: BTreeMap::new(),
+            dids: BTreeMap::new(),
+            routines: BTreeMap::new(),
+        }
+    }
+
+    pub fn service(&self, sid: u8) -> Support {
+        lookup(&self.services, &format!("{sid:02X}"))
+    }
+
+    pub fn sub_function(&self, sid: u8, sub_function: u8) -> Support {
+        self.sub_functions
+            .get(&format!("{sid:02X}"))
+            .map_or(Support::NotSupported, |sub_functions| {
+                lookup(sub_functions, &format!("{sub_function:02X}"))
+            })
+    }
+
+    pub fn did(&self, did: u16) -> Support {
+        lookup(&self.dids, &format!("{did:04X}"))
+    }
+
+    pub fn routine(&self, routine: u16) -> Support {
+        lookup(&self.routines, &format!("{routine:04X}"))
+    }
+}
+
+fn lookup(map: &BTreeMap<String, Support>, key: &str) -> Support {
+    map.get(key).copied().unwrap_or(Support::NotSupported)
+}
+
+/// Result of an enumeration, sessions keyed by the session type in hex
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilityMap {
+    pub sessions: BTreeMap<String, SessionCapabilities>,
+}
+
+impl CapabilityMap {
+    pub fn session(&self, session: DiagnosticSession) -> Option<&SessionCapabilities> {
+        self.sessions.get(&format!("{:02X}", u8::from(session)))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("capability map serializes")
+    }
+
+    pub fn from_json(text: &str) -> io::Result<Self> {
+        serde_json::from_str(text)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// Systematic prober of the capabilities of one ECU
+pub struct Enumerator<T: IsoTpTransport + 'static> {
+    client: Arc<UdsClient<T>>,
+    config: EnumerationConfig,
+}
+
+impl<T: IsoTpTransport + 'static> Enumerator<T> {
+    pub fn new(client: Arc<UdsClient<T>>, config: EnumerationConfig) -> Self {
+        Self { client, config }
+    }
+
+    pub fn client(&self) -> &Arc<UdsClient<T>> {
+        &self.client
+    }
+
+    /// Probe all sessions, the ECU is returned to the default session at the end
+    pub async fn run(&self) -> Result<CapabilityMap, Error> {
+        let mut map = CapabilityMap::default();
+        for session in &self.config.sessions {
+            let capabilities = self.enumerate_session(*session).await?;
+            map.sessions
+                .insert(format!("{:02X}", u8::from(*session)), capabilities);
+        }
+        Ok(map)
+    }
+
+    /// Enter a session and probe it
+    pub async fn enumerate_session(
+        &self,
+        session: DiagnosticSession,
+    ) -> Result<SessionCapabilities, Error> {
+        if session == DiagnosticSession::Default {
+            let entered = Support::classify(
+                self.client
+                    .diagnostic_session_control(session)
+                    .await
+                    .map(|_| Vec::new()),
+            )?;
+            if entered != Support::Supported {
+                return Ok(SessionCapabilities::new(entered));
+            }
+            return self.probe_session().await;
+        }
+        let guard = match self
+            .client
+            .enter_session(session, self.config.keepalive)
+            .await
+        {
+            Ok(guard) => guard,
+            Err(error) => return Ok(SessionCapabilities::new(Support::classify(Err(error))?)),
+        };
+        let capabilities = self.probe_session().await;
+        guard.close().await?;
+        capabilities
+    }
+
+    /// Probe the active session
+    async fn probe_session(&self) -> Result<SessionCapabilities, Error> {
+        let mut capabilities = SessionCapabilities::new(Support::Supported);
+        for sid in &self.config.services {
+            let support = self.probe(&[*sid]).await?;
+            insert(&mut capabilities.services, format!("{sid:02X}"), support);
+        }
+        for sid in &self.config.sub_function_services {
+            if !capabilities.service(*sid).is_available() {
+                continue;
+            }
+            let mut sub_functions = BTreeMap::new();
+            for sub_function in 0x00..=0x7F {
+                let support = self.probe(&[*sid, sub_function]).await?;
+                insert(&mut sub_functions, format!("{sub_function:02X}"), support);
+            }
+            capabilities
+                .sub_functions
+                .insert(format!("{sid:02X}"), sub_functions);
+        }
+        if capabilities.service(READ_DATA_BY_IDENTIFIER).is_available() {
+            for did in self.config.dids.iter().cloned().flatten() {
+                let [high, low] = did.to_be_bytes();
+                let support = self.probe(&[READ_DATA_BY_IDENTIFIER, high, low]).await?;
+                insert(&mut capabilities.dids, format!("{did:04X}"), support);
+            }
+        }
+        if capabilities.service(ROUTINE_CONTROL).is_available() {
+            for routine in self.config.routines.iter().cloned().flatten() {
+                let [high, low] = routine.to_be_bytes();
+                let request = [
+                    ROUTINE_CONTROL,
+                    RoutineControlType::RequestResults.into(),
+                    high,
+                    low,
+                ];
+                let support = self.probe(&request).await?;
+                insert(
+                    &mut capabilities.routines,
+                    format!("{routine:04X}"),
+                    support,
+                );
+            }
+        }
+        Ok(capabilities)
+    }
+
+    async fn probe(&self, request: &[u8]) -> Result<Support, Error> {
+        Support::classify(self.client.request(request).await)
+    }
+}
+
+fn insert(map: &mut BTreeMap<String, Support>, key: String, support: Support) {
+    if support != Support::NotSupported {
+        map.insert(key, support);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::client::ClientConfig;
+    use crate::uds::tests::MockTransport;
+
+    fn negative(code: u8) -> Result<Vec<u8>, Error> {
+        Err(Error::NegativeResponse {
+            service: 0x22,
+            code: code.into(),
+        })
+    }
+
+    #[test]
+    fn classify() {
+        let classify = |result| Support::classify(result).unwrap();
+        assert_eq!(classify(Ok(vec![0x62, 0xF1, 0x90])), Support::Supported);
+        for code in [0x11, 0x12, 0x31] {
+            assert_eq!(classify(negative(code)), Support::NotSupported);
+        }
+        for code in [0x7E, 0x7F] {
+            assert_eq!(classify(negative(code)), Support::NotSupportedInSession);
+        }
+        for code in [0x10, 0x13, 0x22, 0x33, 0x35, 0xF0] {
+            assert_eq!(classify(negative(code)), Support::Restricted(code));
+        }
+        assert_eq!(
+            classify(Err(Error::Timeout { service: 0x22 })),
+            Support::NoResponse
+        );
+        assert_eq!(
+            classify(Err(Error::ResponsePendingLimit { service: 0x22 })),
+            Support::NoResponse
+        );
+
+        let error = Support::classify(Err(io::Error::from(io::ErrorKind::BrokenPipe).into()));
+        assert!(matches!(error, Err(Error::Io { .. })), "{error:?}");
+        let error = Support::classify(Err(Error::invalid_response("too short")));
+        assert!(
+            matches!(error, Err(Error::InvalidResponse { .. })),
+            "{error:?}"
+        );
+
+        assert!(Support::Supported.is_available());
+        assert!(Support::Restricted(0x33).is_available());
+        assert!(!Support::NotSupportedInSession.is_available());
+        assert!(!Support::NotSupported.is_available());
+        assert!(!Support::NoResponse.is_available());
+    }
+
+    /// ECU with ReadDTCInformation in all sessions, ReadDataByIdentifier and
+    /// RoutineControl in the extended session only
+    fn ecu() -> MockTransport {
+        let mut session = 0x01;
+        MockTransport::new(move |request| {
+            let extended = session == 0x03;
+            let response = match request {
+                // P2 and P2* of 10 ms
+                [0x10, 0x01 | 0x03] => {
+                    session = request[1];
+                    vec![0x50, session, 0x00, 0x0A, 0x00, 0x01]
+                }
+                [0x10, _] => vec![0x7F, 0x10, 0x12],
+                [0x3E, 0x00 | 0x80] => vec![0x7E, 0x00],
+                [0x19, 0x01] => vec![0x59, 0x01, 0xFF, 0x01, 0x00, 0x02],
+                [0x19, 0x02] => vec![0x7F, 0x19, 0x33],
+                [0x10 | 0x19 | 0x3E] => vec![0x7F, request[0], 0x13],
+                [0x19 | 0x3E, _] => vec![0x7F, request[0], 0x12],
+                [0x22 | 0x31, ..] if !extended => vec![0x7F, request[0], 0x7F],
+                [0x22] => vec![0x7F, 0x22, 0x13],
+                [0x22, 0xF1, 0x90] => b"\x62\xF1\x90WVW".to_vec(),
+                [0x22, 0xF1, 0x91] => vec![0x7F, 0x22, 0x31],
+                [0x22, 0xF1, 0x92] => vec![0x7F, 0x22, 0x33],
+                [0x31] => vec![0x7F, 0x31, 0x13],
+                // the routine is never answered
+                [0x31, 0x03, 0xFF, 0x00] => return Vec::new(),
+                _ => vec![0x7F, request[0], 0x11],
+            };
+            vec![response]
+        })
+    }
+
+    #[tokio::test]
+    async fn enumerates_sessions() {
+        let client = UdsClient::with_config(
+            ecu(),
+            ClientConfig {
+                network_delay: Duration::from_millis(10),
+                ..ClientConfig::default()
+            },
+        );
+        let config = EnumerationConfig {
+            services: vec![0x10, 0x11, 0x19, 0x22, 0x31, 0x3E],
+            sub_function_services: vec![0x19, 0x3E, 0x22],
+            dids: vec![0xF190..=0xF192],
+            routines: vec![0xFF00..=0xFF01],
+            ..EnumerationConfig::default()
+        };
+        let enumerator = Enumerator::new(Arc::new(client), config);
+        let map = enumerator.run().await.unwrap();
+        assert_eq!(map.sessions.len(), 2);
+
+        let default = map.session(DiagnosticSession::Default).unwrap();
+        assert_eq!(default.entered, Support::Supported);
+        assert_eq!(default.service(0x10), Support::Restricted(0x13));
+        assert_eq!(default.service(0x11), Support::NotSupported);
+        assert_eq!(default.service(0x22), Support::NotSupportedInSession);
+        assert_eq!(default.service(0x31), Support::NotSupportedInSession);
+        assert_eq!(default.sub_function(0x19, 0x01), Support::Supported);
+        assert_eq!(default.sub_function(0x19, 0x02), Support::Restricted(0x33));
+        assert_eq!(default.sub_function(0x19, 0x03), Support::NotSupported);
+        assert_eq!(default.sub_function(0x3E, 0x00), Support::Supported);
+        assert_eq!(default.sub_functions["19"].len(), 2);
+        // sub-functions of unavailable services are not probed
+        assert!(!default.sub_functions.contains_key("22"));
+        assert!(default.dids.is_empty());
+        assert!(default.routines.is_empty());
+
+        let extended = map.session(DiagnosticSession::Extended).unwrap();
+        assert_eq!(extended.entered, Support::Supported);
+        assert_eq!(extended.service(0x22), Support::Restricted(0x13));
+        assert_eq!(extended.did(0xF190), Support::Supported);
+        assert_eq!(extended.did(0xF191), Support::NotSupported);
+        assert_eq!(extended.did(0xF192), Support::Restricted(0x33));
+        assert_eq!(extended.dids.len(), 2);
+        assert_eq!(extended.routine(0xFF00), Support::NoResponse);
+        assert_eq!(extended.routine(0xFF01), Support::NotSupported);
+        assert!(extended.sub_functions.contains_key("22"));
+
+        // the ECU is returned to the default session
+        let requests = enumerator.client().transport().requests();
+        assert_eq!(requests.last().unwrap(), &[0x10, 0x01]);
+
+        let json = map.to_json();
+        assert!(json.contains(r#""F192": {"#));
+        assert!(json.contains(r#""restricted": 51"#));
+        assert!(json.contains(r#""FF00": "no_response""#));
+        assert_eq!(CapabilityMap::from_json(&json).unwrap(), map);
+    }
+
+    #[tokio::test]
+    async fn sessions_not_entered_are_not_probed() {
+        let client = UdsClient::new(ecu());
+        let config = EnumerationConfig {
+            sessions: vec![DiagnosticSession::Programming],
+            ..EnumerationConfig::default()
+        };
+        let enumerator = Enumerator::new(Arc::new(client), config);
+        let map = enumerator.run().await.unwrap();
+        let programming = map.session(DiagnosticSession::Programming).unwrap();
+        assert_eq!(programming.entered, Support::NotSupported);
+        assert!(programming.services.is_empty());
+        assert_eq!(enumerator.client().transport().requests(), [[0x10, 0x02]]);
+
+        let json = r#"{"sessions":{"02":{"entered":"not_supported","services":{},
+            "sub_functions":{},"dids":{},"routines":{}}}}"#;
+        assert_eq!(CapabilityMap::from_json(json).unwrap(), map);
+        let error = CapabilityMap::from_json(r#"{"sessions":{"02":{}}}"#).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
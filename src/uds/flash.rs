@@ -0,0 +1,455 @@
+//! Download of memory images (RequestDownload, TransferData, RequestTransferExit)
+//!
+//! A [Download] keeps the position of the transfer, so that a download interrupted by
+//! an error can be resumed by running it again: the first block not acknowledged by
+//! the ECU is requested anew with a RequestDownload starting at its address. If only
+//! the RequestTransferExit of a segment failed, just that is repeated.
+//!
+//! Preconditions like the programming session, SecurityAccess and erasing the memory
+//! are ECU specific and left to the caller.
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::image::MemoryImage;
+//! use tokio_socketcan_isotp::uds::flash::{Download, DownloadOptions};
+//! use tokio_socketcan_isotp::uds::UdsClient;
+//! # async fn run(client: UdsClient, image: MemoryImage) -> Result<(), tokio_socketcan_isotp::uds::Error> {
+//! let mut download = Download::new(image, DownloadOptions::default());
+//! client
+//!     .download(&mut download, |progress| {
+//!         println!("{}/{} bytes", progress.transferred, progress.total)
+//!     })
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::service::{
+    AddressAndLengthFormat, DataFormat, REQUEST_DOWNLOAD, REQUEST_TRANSFER_EXIT, TRANSFER_DATA,
+};
+use super::{Error, UdsClient};
+use crate::image::MemoryImage;
+use crate::IsoTpTransport;
+
+/// Settings of a [Download]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadOptions {
+    /// dataFormatIdentifier, the image is sent as it is, already compressed or
+    /// encrypted accordingly
+    pub data_format: DataFormat,
+    /// Format of address and size, `None` uses the smallest fitting one per request
+    pub address_format: Option<AddressAndLengthFormat>,
+    /// Upper bound of the TransferData request length, further limiting the
+    /// maxNumberOfBlockLength of the ECU
+    pub max_block_length: Option<usize>,
+    /// Repetitions of a block after a timeout or transport error
+    pub block_retries: usize,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            data_format: DataFormat::PLAIN,
+            address_format: None,
+            max_block_length: None,
+            block_retries: 2,
+        }
+    }
+}
+
+/// Position within the image of a [Download]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DownloadPosition {
+    /// Index of the segment
+    pub segment: usize,
+    /// Bytes of the segment acknowledged by the ECU
+    pub offset: usize,
+    /// All blocks of the segment are acknowledged, the RequestTransferExit is missing
+    pub exit_pending: bool,
+}
+
+/// Progress reported after every acknowledged block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub position: DownloadPosition,
+    /// Address following the last acknowledged byte
+    pub address: u64,
+    /// Bytes acknowledged over the whole image
+    pub transferred: u64,
+    /// Bytes of the whole image
+    pub total: u64,
+}
+
+/// Resumable transfer of a memory image to an ECU
+#[derive(Debug, Clone)]
+pub struct Download {
+    image: MemoryImage,
+    options: DownloadOptions,
+    position: DownloadPosition,
+}
+
+impl Download {
+    pub fn new(image: MemoryImage, options: DownloadOptions) -> Self {
+        Self {
+            image,
+            options,
+            position: DownloadPosition::default(),
+        }
+    }
+
+    /// Continue at the given position, e.g. one stored by a previous process
+    pub fn with_position(mut self, position: DownloadPosition) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn image(&self) -> &MemoryImage {
+        &self.image
+    }
+
+    pub fn options(&self) -> &DownloadOptions {
+        &self.options
+    }
+
+    /// Position of the first byte not acknowledged yet
+    pub fn position(&self) -> DownloadPosition {
+        self.position
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position.segment >= self.image.segments().len()
+    }
+
+    fn progress(&self) -> Progress {
+        let segments = self.image.segments();
+        let done = segments.iter().take(self.position.segment);
+        let address = match segments.get(self.position.segment) {
+            Some(segment) => segment.address + self.position.offset as u64,
+            None => segments.last().map_or(0, |segment| segment.end()),
+        };
+        Progress {
+            position: self.position,
+            address,
+            transferred: done.map(|segment| segment.len() as u64).sum::<u64>()
+                + self.position.offset as u64,
+            total: self.image.len(),
+        }
+    }
+}
+
+impl<T: IsoTpTransport> UdsClient<T> {
+    /// RequestDownload (0x34), returns the maxNumberOfBlockLength
+    pub async fn request_download(
+        &self,
+        data_format: DataFormat,
+        format: AddressAndLengthFormat,
+        address: u64,
+        size: u64,
+    ) -> Result<usize, Error> {
+        let mut request = vec![REQUEST_DOWNLOAD, data_format.identifier()];
+        request.extend(format.encode(address, size)?);
+        let response = self.request(&request).await?;
+        let (max_block_length, _) = parse_max_block_length(&response[1..])?;
+        Ok(max_block_length)
+    }
+
+    /// TransferData (0x36), returns the transferResponseParameterRecord
+    pub async fn transfer_data(
+        &self,
+        block_sequence_counter: u8,
+        data: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let mut request = vec![TRANSFER_DATA, block_sequence_counter];
+        request.extend_from_slice(data);
+        let response = self.request(&request).await?;
+        if response.get(1) != Some(&block_sequence_counter) {
+            return Err(Error::invalid_response(format!(
+                "block sequence counter 0x{block_sequence_counter:02X} not echoed"
+            )));
+        }
+        Ok(response[2..].to_vec())
+    }
+
+    /// RequestTransferExit (0x37), returns the transferResponseParameterRecord
+    pub async fn request_transfer_exit(&self, parameters: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut request = vec![REQUEST_TRANSFER_EXIT];
+        request.extend_from_slice(parameters);
+        let response = self.request(&request).await?;
+        Ok(response[1..].to_vec())
+    }
+
+    /// Transfer the remaining part of the image, one download sequence per segment
+    ///
+    /// On error the position of the download stays at the first unacknowledged block
+    /// and calling this again resumes from there. A position beyond the data of its
+    /// segment is rejected as [Error::InvalidRequest].
+    pub async fn download(
+        &self,
+        download: &mut Download,
+        mut progress: impl FnMut(Progress),
+    ) -> Result<(), Error> {
+        while let Some(segment) = download.image.segments().get(download.position.segment) {
+            if !download.position.exit_pending {
+                let offset = download.position.offset;
+                let remaining = segment.data.get(offset..).ok_or_else(|| {
+                    Error::invalid_request(format!(
+                        "offset {offset} beyond segment of {} bytes",
+                        segment.len()
+                    ))
+                })?;
+                let address = segment.address + offset as u64;
+                let size = remaining.len() as u64;
+                let options = download.options;
+                let format = options
+                    .address_format
+                    .unwrap_or_else(|| AddressAndLengthFormat::fitting(address, size));
+
+                let mut block_length = self
+                    .request_download(options.data_format, format, address, size)
+                    .await?;
+                if let Some(max) = options.max_block_length {
+                    block_length = block_length.min(max);
+                }
+                let chunk_size = block_data_size(block_length)?;
+
+                let mut counter: u8 = 1;
+                for chunk in remaining.chunks(chunk_size) {
+                    self.transfer_block(counter, chunk, options.block_retries)
+                        .await?;
+                    download.position.offset += chunk.len();
+                    download.position.exit_pending = download.position.offset == segment.len();
+                    progress(download.progress());
+                    counter = counter.wrapping_add(1);
+                }
+            }
+
+            self.request_transfer_exit(&[]).await?;
+            download.position = DownloadPosition {
+                segment: download.position.segment + 1,
+                offset: 0,
+                exit_pending: false,
+            };
+        }
+        Ok(())
+    }
+
+    /// TransferData repeated on transient errors, the ECU acknowledges a repeated
+    /// block sequence counter without writing the block again
+    pub(crate) async fn transfer_block(
+        &self,
+        counter: u8,
+        data: &[u8],
+        retries: usize,
+    ) -> Result<(), Error> {
+        let mut attempt = 0;
+        loop {
+            match self.transfer_data(counter, data).await {
+                Err(Error::Timeout { .. } | Error::Io { .. }) if attempt < retries => attempt += 1,
+                result => return result.map(|_| ()),
+            }
+        }
+    }
+}
+
+/// Data bytes of a TransferData request or response of the given block length
+pub(crate) fn block_data_size(max_block_length: usize) -> Result<usize, Error> {
+    // the block length includes the SID and the block sequence counter
+    max_block_length
+        .checked_sub(2)
+        .filter(|size| *size > 0)
+        .ok_or_else(|| {
+            Error::invalid_response(format!("maxNumberOfBlockLength of {max_block_length}"))
+        })
+}
+
+/// maxNumberOfBlockLength following the lengthFormatIdentifier at the start of the
+/// record, returns it together with the rest of the record
+pub(crate) fn parse_max_block_length(record: &[u8]) -> Result<(usize, &[u8]), Error> {
+    let (length_format, rest) = record
+        .split_first()
+        .ok_or_else(|| Error::invalid_response("missing lengthFormatIdentifier"))?;
+    let bytes = usize::from(length_format >> 4);
+    if !(1..=8).contains(&bytes) || rest.len() < bytes {
+        return Err(Error::invalid_response("invalid maxNumberOfBlockLength"));
+    }
+    let (value, rest) = rest.split_at(bytes);
+    let length = usize::try_from(parse_unsigned(value))
+        .map_err(|_| Error::invalid_response("invalid maxNumberOfBlockLength"))?;
+    Ok((length, rest))
+}
+
+/// Big endian unsigned integer of at most 8 bytes
+pub(crate) fn parse_unsigned(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0u64, |value, byte| (value << 8) | u64::from(*byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::tests::MockTransport;
+    use crate::uds::NegativeResponseCode;
+
+    /// ECU accepting blocks of 8 data bytes, failing the given TransferData block
+    /// counters and RequestTransferExit requests once
+    fn ecu(mut failing_blocks: Vec<u8>, mut failing_exits: usize) -> UdsClient<MockTransport> {
+        UdsClient::new(MockTransport::new(move |request| match request {
+            [0x34, ..] => vec![vec![0x74, 0x20, 0x00, 0x0A]],
+            [0x36, counter, ..] => match failing_blocks.iter().position(|c| c == counter) {
+                Some(index) => {
+                    failing_blocks.remove(index);
+                    vec![vec![0x7F, 0x36, 0x72]]
+                }
+                None => vec![vec![0x76, *counter]],
+            },
+            [0x37] if failing_exits > 0 => {
+                failing_exits -= 1;
+                vec![vec![0x7F, 0x37, 0x22]]
+            }
+            [0x37] => vec![vec![0x77]],
+            _ => vec![vec![0x7F, request[0], 0x11]],
+        }))
+    }
+
+    fn image() -> MemoryImage {
+        let mut image = MemoryImage::from_data(0x1000, (0..20).collect());
+        image.insert(0x2000, &[0xAA; 4]).unwrap();
+        image
+    }
+
+    fn services(client: &UdsClient<MockTransport>) -> Vec<u8> {
+        client
+            .transport()
+            .requests()
+            .iter()
+            .map(|request| request[0])
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn downloads_all_segments() {
+        let client = ecu(vec![], 0);
+        let mut download = Download::new(image(), DownloadOptions::default());
+        let mut reports = Vec::new();
+        client
+            .download(&mut download, |progress| reports.push(progress.transferred))
+            .await
+            .unwrap();
+        assert!(download.is_finished());
+        assert_eq!(reports, [8, 16, 20, 24]);
+        let requests = client.transport().requests();
+        assert_eq!(requests[0], [0x34, 0x00, 0x12, 0x10, 0x00, 0x14]);
+        assert_eq!(requests[1], [0x36, 0x01, 0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(requests[3], [0x36, 0x03, 16, 17, 18, 19]);
+        assert_eq!(requests[5], [0x34, 0x00, 0x12, 0x20, 0x00, 0x04]);
+        assert_eq!(
+            services(&client),
+            [0x34, 0x36, 0x36, 0x36, 0x37, 0x34, 0x36, 0x37]
+        );
+    }
+
+    #[tokio::test]
+    async fn resumes_at_first_unacknowledged_block() {
+        let client = ecu(vec![2], 0);
+        let mut download = Download::new(image(), DownloadOptions::default());
+        let error = client.download(&mut download, |_| {}).await.unwrap_err();
+        assert_eq!(
+            error.negative_response_code(),
+            Some(NegativeResponseCode::GeneralProgrammingFailure)
+        );
+        assert_eq!(
+            download.position(),
+            DownloadPosition {
+                segment: 0,
+                offset: 8,
+                exit_pending: false,
+            }
+        );
+
+        client.download(&mut download, |_| {}).await.unwrap();
+        assert!(download.is_finished());
+        let requests = client.transport().requests();
+        assert_eq!(requests[3], [0x34, 0x00, 0x12, 0x10, 0x08, 0x0C]);
+        assert_eq!(requests[4], [0x36, 0x01, 8, 9, 10, 11, 12, 13, 14, 15]);
+    }
+
+    #[tokio::test]
+    async fn resume_after_failed_exit_repeats_only_the_exit() {
+        let client = ecu(vec![], 1);
+        let mut download = Download::new(image(), DownloadOptions::default());
+        let error = client.download(&mut download, |_| {}).await.unwrap_err();
+        assert_eq!(
+            error.negative_response_code(),
+            Some(NegativeResponseCode::ConditionsNotCorrect)
+        );
+        assert_eq!(
+            download.position(),
+            DownloadPosition {
+                segment: 0,
+                offset: 20,
+                exit_pending: true,
+            }
+        );
+
+        client.download(&mut download, |_| {}).await.unwrap();
+        assert!(download.is_finished());
+        assert_eq!(
+            services(&client),
+            [0x34, 0x36, 0x36, 0x36, 0x37, 0x37, 0x34, 0x36, 0x37]
+        );
+    }
+
+    #[tokio::test]
+    async fn resumes_from_stored_position() {
+        let client = ecu(vec![], 0);
+        let position = DownloadPosition {
+            segment: 1,
+            offset: 0,
+            exit_pending: true,
+        };
+        let mut download =
+            Download::new(image(), DownloadOptions::default()).with_position(position);
+        client.download(&mut download, |_| {}).await.unwrap();
+        assert_eq!(client.transport().requests(), [vec![0x37]]);
+    }
+
+    #[tokio::test]
+    async fn encodes_data_format() {
+        let client = ecu(vec![], 0);
+        let options = DownloadOptions {
+            data_format: DataFormat::new(1, 2),
+            ..DownloadOptions::default()
+        };
+        let mut download = Download::new(image(), options);
+        client.download(&mut download, |_| {}).await.unwrap();
+        assert_eq!(client.transport().requests()[0][..2], [0x34, 0x12]);
+    }
+
+    #[tokio::test]
+    async fn rejects_position_beyond_segment() {
+        let client = ecu(vec![], 0);
+        let position = DownloadPosition {
+            segment: 1,
+            offset: 5,
+            exit_pending: false,
+        };
+        let mut download =
+            Download::new(image(), DownloadOptions::default()).with_position(position);
+        let error = client.download(&mut download, |_| {}).await.unwrap_err();
+        assert!(matches!(error, Error::InvalidRequest { .. }), "{error:?}");
+        assert_eq!(download.position(), position);
+        assert!(client.transport().requests().is_empty());
+    }
+
+    #[test]
+    fn block_lengths() {
+        assert_eq!(
+            parse_max_block_length(&[0x20, 0x0F, 0xFF]).unwrap().0,
+            0xFFF
+        );
+        assert!(parse_max_block_length(&[0x00]).is_err());
+        assert!(parse_max_block_length(&[0x30, 0x01]).is_err());
+        assert_eq!(block_data_size(0x102).unwrap(), 0x100);
+        assert!(block_data_size(2).is_err());
+    }
+}
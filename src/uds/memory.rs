@@ -0,0 +1,216 @@
+//! ReadMemoryByAddress (0x23) and WriteMemoryByAddress (0x3D)
+//!
+//! Transfers larger than one PDU are split into consecutive requests, each fitting into
+//! the [max_pdu_size](crate::IsoTpTransport::max_pdu_size) of the transport.
+
+use super::service::{AddressAndLengthFormat, READ_MEMORY_BY_ADDRESS, WRITE_MEMORY_BY_ADDRESS};
+use super::{Error, UdsClient};
+use crate::IsoTpTransport;
+
+impl<T: IsoTpTransport> UdsClient<T> {
+    /// ReadMemoryByAddress (0x23) with the smallest fitting addressAndLengthFormatIdentifier
+    pub async fn read_memory_by_address(&self, address: u64, size: u64) -> Result<Vec<u8>, Error> {
+        // the response carries the SID in front of the data
+        let chunk_size = self.transport().max_pdu_size().saturating_sub(1) as u64;
+        let format = fitting_format(address, size, chunk_size)?;
+        self.read_memory_by_address_with_format(format, address, size)
+            .await
+    }
+
+    /// ReadMemoryByAddress (0x23) with a fixed addressAndLengthFormatIdentifier
+    pub async fn read_memory_by_address_with_format(
+        &self,
+        format: AddressAndLengthFormat,
+        address: u64,
+        size: u64,
+    ) -> Result<Vec<u8>, Error> {
+        let chunk_size = self.transport().max_pdu_size().saturating_sub(1) as u64;
+        check_chunk_size(chunk_size)?;
+        // every address + offset below lies within the checked range
+        last_address(address, size)?;
+        let mut data = Vec::new();
+        let mut offset = 0;
+        while offset < size {
+            let length = (size - offset).min(chunk_size);
+            let mut request = vec![READ_MEMORY_BY_ADDRESS];
+            request.extend(format.encode(address + offset, length)?);
+            let response = self.request(&request).await?;
+            let chunk = &response[1..];
+            if chunk.len() as u64 != length {
+                return Err(Error::invalid_response(format!(
+                    "{} bytes instead of {length} at 0x{:X}",
+                    chunk.len(),
+                    address + offset
+                )));
+            }
+            data.extend_from_slice(chunk);
+            offset += length;
+        }
+        Ok(data)
+    }
+
+    /// WriteMemoryByAddress (0x3D) with the smallest fitting addressAndLengthFormatIdentifier
+    pub async fn write_memory_by_address(&self, address: u64, data: &[u8]) -> Result<(), Error> {
+        let size = data.len() as u64;
+        // SID and identifier in front, address and size of at most 8 bytes each
+        let max_pdu_size = self.transport().max_pdu_size() as u64;
+        let format = fitting_format(address, size, max_pdu_size.saturating_sub(2))?;
+        self.write_memory_by_address_with_format(format, address, data)
+            .await
+    }
+
+    /// WriteMemoryByAddress (0x3D) with a fixed addressAndLengthFormatIdentifier
+    pub async fn write_memory_by_address_with_format(
+        &self,
+        format: AddressAndLengthFormat,
+        address: u64,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let overhead = 2 + usize::from(format.address_bytes) + usize::from(format.size_bytes);
+        let chunk_size = self.transport().max_pdu_size().saturating_sub(overhead);
+        check_chunk_size(chunk_size as u64)?;
+        last_address(address, data.len() as u64)?;
+        for (index, chunk) in data.chunks(chunk_size).enumerate() {
+            let chunk_address = address + (index * chunk_size) as u64;
+            let parameters = format.encode(chunk_address, chunk.len() as u64)?;
+            let mut request = vec![WRITE_MEMORY_BY_ADDRESS];
+            request.extend_from_slice(&parameters);
+            request.extend_from_slice(chunk);
+            let response = self.request(&request).await?;
+            if response[1..] != parameters[..] {
+                return Err(Error::invalid_response(format!(
+                    "memory address 0x{chunk_address:X} not echoed"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Format fitting the last address of the range and the size of the largest chunk
+fn fitting_format(
+    address: u64,
+    size: u64,
+    chunk_size: u64,
+) -> Result<AddressAndLengthFormat, Error> {
+    let last_address = last_address(address, size)?;
+    let size = size.min(chunk_size);
+    Ok(AddressAndLengthFormat::fitting(last_address, size))
+}
+
+/// Address of the last byte of the range
+fn last_address(address: u64, size: u64) -> Result<u64, Error> {
+    address
+        .checked_add(size.saturating_sub(1))
+        .ok_or_else(|| Error::invalid_request(format!("0x{address:X} + {size} exceeds 64 bit")))
+}
+
+fn check_chunk_size(chunk_size: u64) -> Result<(), Error> {
+    if chunk_size == 0 {
+        return Err(Error::invalid_request("maximum PDU size too small"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::tests::MockTransport;
+
+    /// ECU with 256 bytes of memory at 0x1000, the value of each byte is its offset
+    fn ecu(max_pdu_size: usize) -> UdsClient<MockTransport> {
+        let transport = MockTransport::new(|request| match request {
+            [0x23, 0x12, high, low, length] => {
+                let start = usize::from(u16::from_be_bytes([*high, *low])) - 0x1000;
+                let mut response = vec![0x63];
+                response.extend((start..start + usize::from(*length)).map(|byte| byte as u8));
+                vec![response]
+            }
+            [0x3D, parameters @ ..] => {
+                let mut response = vec![0x7D];
+                response.extend_from_slice(&parameters[..4]);
+                vec![response]
+            }
+            _ => vec![vec![0x7F, request[0], 0x31]],
+        });
+        UdsClient::new(transport.with_max_pdu_size(max_pdu_size))
+    }
+
+    #[tokio::test]
+    async fn reads_in_chunks_of_the_pdu_size() {
+        let client = ecu(17);
+        let data = client.read_memory_by_address(0x1000, 40).await.unwrap();
+        assert_eq!(data, (0..40).collect::<Vec<u8>>());
+        assert_eq!(
+            client.transport().requests(),
+            [
+                vec![0x23, 0x12, 0x10, 0x00, 16],
+                vec![0x23, 0x12, 0x10, 0x10, 16],
+                vec![0x23, 0x12, 0x10, 0x20, 8],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_in_chunks_of_the_pdu_size() {
+        let client = ecu(10);
+        client
+            .write_memory_by_address(0x1000, &[0xAA; 9])
+            .await
+            .unwrap();
+        assert_eq!(
+            client.transport().requests(),
+            [
+                vec![0x3D, 0x12, 0x10, 0x00, 5, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA],
+                vec![0x3D, 0x12, 0x10, 0x05, 4, 0xAA, 0xAA, 0xAA, 0xAA],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_too_small_pdu_size() {
+        let client = ecu(4);
+        let error = client
+            .write_memory_by_address(0x1000, &[0xAA])
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidRequest { .. }), "{error:?}");
+        let error = client
+            .read_memory_by_address(u64::MAX, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidRequest { .. }), "{error:?}");
+    }
+
+    #[tokio::test]
+    async fn rejects_ranges_beyond_64_bit() {
+        let client = ecu(17);
+        let format = AddressAndLengthFormat::new(8, 1);
+        let error = client
+            .read_memory_by_address_with_format(format, u64::MAX - 1, 40)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidRequest { .. }), "{error:?}");
+        let error = client
+            .write_memory_by_address_with_format(format, u64::MAX - 1, &[0xAA; 40])
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidRequest { .. }), "{error:?}");
+        assert!(client.transport().requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn writes_up_to_the_last_address() {
+        let client = UdsClient::new(MockTransport::new(|request| {
+            let mut response = vec![0x7D];
+            response.extend_from_slice(&request[1..11]);
+            vec![response]
+        }));
+        let format = AddressAndLengthFormat::new(8, 1);
+        client
+            .write_memory_by_address_with_format(format, u64::MAX, &[0xAA])
+            .await
+            .unwrap();
+        assert_eq!(client.transport().requests()[0][2..10], [0xFF; 8]);
+    }
+}
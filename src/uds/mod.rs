@@ -0,0 +1,275 @@
+//! UDS (ISO 14229-1) client on top of ISO-TP
+//!
+//! [UdsClient] encodes the requests of the core diagnostic services, sends them over
+//! any [IsoTpTransport](crate::IsoTpTransport) and decodes the responses. Negative
+//! responses are returned as [Error::NegativeResponse] carrying the typed
+//! [NegativeResponseCode].
+//!
+//! Requests are executed with the P2/P2* timing of the active session: response
+//! pending (NRC 0x78) extends the wait to P2*, busyRepeatRequest (NRC 0x21) repeats
+//! the request with a backoff, see [ClientConfig].
+//!
+//! Non-default sessions are kept alive by a [SessionGuard], which sends TesterPresent
+//! in the background and returns to the default session when dropped.
+//!
+//! SecurityAccess takes the supplier specific [SeedKeyAlgorithm], with the
+//! `seed-key-library` feature it can be loaded from a shared library.
+//!
+//! Memory images are written to the ECU with the resumable download sequence of
+//! [flash], uploads and file transfers are streamed by [transfer].
+//!
+//! With the `scan` feature unknown ECUs are found by probing candidate ids, see `scan`,
+//! and their services, DIDs and routines are enumerated per session, see `enumerate`.
+//!
+//! With the `server` feature [UdsServer](server::UdsServer) answers requests like an
+//! ECU, for simulators and tests of tester software, see `server`. With the `ecu-sim`
+//! feature the ECUs are described in TOML or YAML files instead, see `sim`.
+//!
+//! With the `did` feature the data of DIDs is decoded by the codecs of a registry
+//! loaded from TOML or YAML, see `did`.
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::uds::{DiagnosticSession, UdsClient};
+//! use tokio_socketcan_isotp::{IsoTpSocket, StandardId};
+//!
+//! # async fn run() -> Result<(), tokio_socketcan_isotp::uds::Error> {
+//! let socket = IsoTpSocket::open(
+//!     "vcan0",
+//!     StandardId::new(0x7E8).unwrap(),
+//!     StandardId::new(0x7E0).unwrap(),
+//! )
+//! .unwrap();
+//! let client = UdsClient::new(socket);
+//! client
+//!     .diagnostic_session_control(DiagnosticSession::Extended)
+//!     .await?;
+//! let vin = client.read_data_by_identifier(0xF190).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+/// Enum over a protocol byte with named values and a fallback for all other values
+macro_rules! byte_enum {
+    (
+        $(#[$meta:meta])*
+        $name:ident {
+            $($variant:ident = $value:literal, $text:literal;)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $(
+                #[doc = $text]
+                $variant,
+            )*
+            /// Value without a name in this crate
+            Other(u8),
+        }
+
+        impl From<u8> for $name {
+            fn from(value: u8) -> Self {
+                match value {
+                    $($value => $name::$variant,)*
+                    other => $name::Other(other),
+                }
+            }
+        }
+
+        impl From<$name> for u8 {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => $value,)*
+                    $name::Other(other) => other,
+                }
+            }
+        }
+
+        impl $name {
+            /// Name of the value as used by the standard
+            pub fn description(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $text,)*
+                    $name::Other(_) => "reserved or manufacturer specific",
+                }
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{} (0x{:02X})", self.description(), u8::from(*self))
+            }
+        }
+    };
+}
+
+mod client;
+#[cfg(feature = "did")]
+pub mod did;
+mod dtc;
+#[cfg(feature = "scan")]
+pub mod enumerate;
+pub mod flash;
+mod memory;
+mod nrc;
+#[cfg(feature = "scan")]
+pub mod scan;
+mod security;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod service;
+mod session;
+#[cfg(feature = "ecu-sim")]
+pub mod sim;
+pub mod transfer;
+
+pub use client::{BusyRepeatPolicy, ClientConfig, SessionParameters, UdsClient};
+pub use dtc::{
+    Dtc, DtcCount, DtcExtendedData, DtcFormat, DtcList, DtcRecord, DtcSnapshot, DtcStatus,
+    ExtendedDataRecord, SnapshotData, SnapshotRecord,
+};
+pub use nrc::NegativeResponseCode;
+#[cfg(feature = "seed-key-library")]
+pub use security::SharedLibraryAlgorithm;
+pub use security::{SecurityAccessOptions, SecurityAccessOutcome, SeedKeyAlgorithm};
+pub use service::{
+    AddressAndLengthFormat, DataFormat, DiagnosticSession, DtcReportType, FileOperation,
+    ResetType, RoutineControlType,
+};
+pub use session::{SessionGuard, DEFAULT_TESTER_PRESENT_INTERVAL, S3_SERVER};
+
+use std::io;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+/// Possible errors of the UDS layer
+pub enum Error {
+    /// IO Error of the underlying transport
+    #[error("IO error: {source:?}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+
+    /// The ECU rejected the request
+    #[error("Negative response to service 0x{service:02X}: {code}")]
+    NegativeResponse {
+        service: u8,
+        code: NegativeResponseCode,
+    },
+
+    /// The response does not match the request
+    #[error("Invalid response: {reason}")]
+    InvalidResponse { reason: String },
+
+    /// No response within P2 or P2*
+    #[error("No response to service 0x{service:02X}")]
+    Timeout { service: u8 },
+
+    /// The ECU kept answering with response pending (NRC 0x78)
+    #[error("Too many response pending to service 0x{service:02X}")]
+    ResponsePendingLimit { service: u8 },
+
+    /// The request cannot be encoded
+    #[error("Invalid request: {reason}")]
+    InvalidRequest { reason: String },
+
+    /// The seed/key algorithm failed
+    #[error("Key computation failed: {reason}")]
+    KeyComputation { reason: String },
+}
+
+impl Error {
+    /// Negative response code if the ECU rejected the request
+    pub fn negative_response_code(&self) -> Option<NegativeResponseCode> {
+        match self {
+            Error::NegativeResponse { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn invalid_response(reason: impl Into<String>) -> Self {
+        Error::InvalidResponse {
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn invalid_request(reason: impl Into<String>) -> Self {
+        Error::InvalidRequest {
+            reason: reason.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::{Id, IsoTpTransport, StandardId};
+    use std::future::Future;
+    use std::io;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    type Handler = Box<dyn FnMut(&[u8]) -> Vec<Vec<u8>> + Send>;
+
+    /// Transport answering every sent request with the PDUs returned by a handler
+    ///
+    /// Without queued responses `recv` waits forever, so the client runs into its
+    /// P2 timeout.
+    pub(crate) struct MockTransport {
+        handler: Mutex<Handler>,
+        responses: mpsc::UnboundedSender<Vec<u8>>,
+        queue: tokio::sync::Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+        requests: Mutex<Vec<Vec<u8>>>,
+        max_pdu_size: usize,
+    }
+
+    impl MockTransport {
+        pub(crate) fn new(handler: impl FnMut(&[u8]) -> Vec<Vec<u8>> + Send + 'static) -> Self {
+            let (responses, queue) = mpsc::unbounded_channel();
+            Self {
+                handler: Mutex::new(Box::new(handler)),
+                responses,
+                queue: tokio::sync::Mutex::new(queue),
+                requests: Mutex::new(Vec::new()),
+                max_pdu_size: crate::RECV_BUFFER_SIZE,
+            }
+        }
+
+        pub(crate) fn with_max_pdu_size(mut self, max_pdu_size: usize) -> Self {
+            self.max_pdu_size = max_pdu_size;
+            self
+        }
+
+        /// All requests sent so far
+        pub(crate) fn requests(&self) -> Vec<Vec<u8>> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl IsoTpTransport for MockTransport {
+        fn rx_id(&self) -> Id {
+            Id::Standard(StandardId::new(0x7E8).unwrap())
+        }
+
+        fn tx_id(&self) -> Id {
+            Id::Standard(StandardId::new(0x7E0).unwrap())
+        }
+
+        fn max_pdu_size(&self) -> usize {
+            self.max_pdu_size
+        }
+
+        fn send<'a>(&'a self, pdu: &'a [u8]) -> impl Future<Output = io::Result<()>> + Send + 'a {
+            self.requests.lock().unwrap().push(pdu.to_vec());
+            for response in (self.handler.lock().unwrap())(pdu) {
+                self.responses.send(response).unwrap();
+            }
+            async { Ok(()) }
+        }
+
+        async fn recv(&self) -> io::Result<Vec<u8>> {
+            let response = self.queue.lock().await.recv().await;
+            response.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Negative response codes of ISO 14229-1 Annex A
+
+byte_enum! {
+    /// Negative response code (NRC) of a `7F <SID> <NRC>` response
+    NegativeResponseCode {
+        GeneralReject = 0x10, "generalReject";
+        ServiceNotSupported = 0x11, "serviceNotSupported";
+        SubFunctionNotSupported = 0x12, "subFunctionNotSupported";
+        IncorrectMessageLengthOrInvalidFormat = 0x13, "incorrectMessageLengthOrInvalidFormat";
+        ResponseTooLong = 0x14, "responseTooLong";
+        BusyRepeatRequest = 0x21, "busyRepeatRequest";
+        ConditionsNotCorrect = 0x22, "conditionsNotCorrect";
+        RequestSequenceError = 0x24, "requestSequenceError";
+        NoResponseFromSubnetComponent = 0x25, "noResponseFromSubnetComponent";
+        FailurePreventsExecutionOfRequestedAction = 0x26, "failurePreventsExecutionOfRequestedAction";
+        RequestOutOfRange = 0x31, "requestOutOfRange";
+        SecurityAccessDenied = 0x33, "securityAccessDenied";
+        AuthenticationRequired = 0x34, "authenticationRequired";
+        InvalidKey = 0x35, "invalidKey";
+        ExceededNumberOfAttempts = 0x36, "exceedNumberOfAttempts";
+        RequiredTimeDelayNotExpired = 0x37, "requiredTimeDelayNotExpired";
+        UploadDownloadNotAccepted = 0x70, "uploadDownloadNotAccepted";
+        TransferDataSuspended = 0x71, "transferDataSuspended";
+        GeneralProgrammingFailure = 0x72, "generalProgrammingFailure";
+        WrongBlockSequenceCounter = 0x73, "wrongBlockSequenceCounter";
+        RequestCorrectlyReceivedResponsePending = 0x78, "requestCorrectlyReceived-ResponsePending";
+        SubFunctionNotSupportedInActiveSession = 0x7E, "subFunctionNotSupportedInActiveSession";
+        ServiceNotSupportedInActiveSession = 0x7F, "serviceNotSupportedInActiveSession";
+        RpmTooHigh = 0x81, "rpmTooHigh";
+        RpmTooLow = 0x82, "rpmTooLow";
+        EngineIsRunning = 0x83, "engineIsRunning";
+        EngineIsNotRunning = 0x84, "engineIsNotRunning";
+        EngineRunTimeTooLow = 0x85, "engineRunTimeTooLow";
+        TemperatureTooHigh = 0x86, "temperatureTooHigh";
+        TemperatureTooLow = 0x87, "temperatureTooLow";
+        VehicleSpeedTooHigh = 0x88, "vehicleSpeedTooHigh";
+        VehicleSpeedTooLow = 0x89, "vehicleSpeedTooLow";
+        ThrottlePedalTooHigh = 0x8A, "throttle/PedalTooHigh";
+        ThrottlePedalTooLow = 0x8B, "throttle/PedalTooLow";
+        TransmissionRangeNotInNeutral = 0x8C, "transmissionRangeNotInNeutral";
+        TransmissionRangeNotInGear = 0x8D, "transmissionRangeNotInGear";
+        BrakeSwitchesNotClosed = 0x8F, "brakeSwitch(es)NotClosed";
+        ShifterLeverNotInPark = 0x90, "shifterLeverNotInPark";
+        TorqueConverterClutchLocked = 0x91, "torqueConverterClutchLocked";
+        VoltageTooHigh = 0x92, "voltageTooHigh";
+        VoltageTooLow = 0x93, "voltageTooLow";
+        ResourceTemporarilyNotAvailable = 0x94, "resourceTemporarilyNotAvailable";
+    }
+}
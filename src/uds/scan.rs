@@ -0,0 +1,586 @@
+//! Discovery of ECUs by probing candidate request/response id pairs
+//!
+//! A [Scanner] sends a harmless probe, TesterPresent or DiagnosticSessionControl
+//! default, to every [ScanTarget] and records the targets answering with a positive
+//! or negative response. Targets are probed concurrently, the probes leave the
+//! scanner at most once per [ScanConfig::interval]. Targets sharing a response id
+//! and extended address (e.g. all candidates of extended addressing) are probed one
+//! after another, as their responses cannot be told apart.
+//!
+//! The [ScanReport] is written as JSON, the ids in the hex notation of candump:
+//!
+//! ```json
+//! {"ecus":[{"request_id":"7E0","response_id":"7E8","response":"7E00","latency_ms":3}],"probed":248}
+//! ```
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::uds::scan::{ScanConfig, ScanTarget, Scanner};
+//!
+//! # async fn run() -> Result<(), tokio_socketcan_isotp::uds::Error> {
+//! let mut targets = ScanTarget::standard_range(0x700..=0x7F7, 8);
+//! targets.extend(ScanTarget::normal_fixed(0x00..=0xFF, 0xF1));
+//! let report = Scanner::new(ScanConfig::default()).run("can0", targets).await?;
+//! std::fs::write("ecus.json", report.to_json())?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::client::check_response;
+use super::service::{DIAGNOSTIC_SESSION_CONTROL, TESTER_PRESENT};
+use super::Error;
+use crate::text::{decode_hex, encode_hex, format_id, parse_id};
+use crate::{
+    ExtendedId, Id, IsoTpBehaviour, IsoTpOptions, IsoTpSocket, IsoTpTransport, StandardId, SFF_MASK,
+};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Request sent to every target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanProbe {
+    /// TesterPresent (0x3E 0x00)
+    #[default]
+    TesterPresent,
+    /// DiagnosticSessionControl default session (0x10 0x01)
+    DefaultSession,
+}
+
+impl ScanProbe {
+    pub fn request(self) -> [u8; 2] {
+        match self {
+            ScanProbe::TesterPresent => [TESTER_PRESENT, 0x00],
+            ScanProbe::DefaultSession => [DIAGNOSTIC_SESSION_CONTROL, 0x01],
+        }
+    }
+}
+
+/// Candidate addressing of an ECU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ScanTarget {
+    #[serde(with = "hex_id")]
+    pub request_id: Id,
+    #[serde(with = "hex_id")]
+    pub response_id: Id,
+    /// Target address in front of every request frame (extended addressing)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ext_address: Option<u8>,
+    /// Address in front of every response frame (extended addressing)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_ext_address: Option<u8>,
+}
+
+impl ScanTarget {
+    /// Normal addressing on a request/response id pair
+    pub fn new(request_id: impl Into<Id>, response_id: impl Into<Id>) -> Self {
+        Self {
+            request_id: request_id.into(),
+            response_id: response_id.into(),
+            ext_address: None,
+            rx_ext_address: None,
+        }
+    }
+
+    /// 11-bit request ids with the response id `offset` above, e.g. 0x7E0/0x7E8 for 8
+    pub fn standard_range(requests: RangeInclusive<u16>, offset: u16) -> Vec<Self> {
+        requests
+            .filter_map(|request| {
+                let response = request.checked_add(offset)?;
+                Some(Self::new(
+                    StandardId::new(request)?,
+                    StandardId::new(response)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// 29-bit NormalFixed addressing, request 0x18DA<target><tester>, response 0x18DA<tester><target>
+    pub fn normal_fixed(targets: RangeInclusive<u8>, tester: u8) -> Vec<Self> {
+        targets
+            .filter(|target| *target != tester)
+            .map(|target| {
+                let (target, tester) = (u32::from(target), u32::from(tester));
+                Self::new(
+                    ExtendedId::new(0x18DA_0000 | target << 8 | tester).unwrap(),
+                    ExtendedId::new(0x18DA_0000 | tester << 8 | target).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// Extended addressing on one id pair, the target address is the first byte of each frame
+    pub fn extended_addresses(
+        request_id: impl Into<Id>,
+        response_id: impl Into<Id>,
+        targets: RangeInclusive<u8>,
+        tester: u8,
+    ) -> Vec<Self> {
+        let (request_id, response_id) = (request_id.into(), response_id.into());
+        targets
+            .filter(|target| *target != tester)
+            .map(|target| Self {
+                request_id,
+                response_id,
+                ext_address: Some(target),
+                rx_ext_address: Some(tester),
+            })
+            .collect()
+    }
+
+    /// ISO-TP options of the addressing, frames padded with `padding`
+    pub fn isotp_options(&self, padding: Option<u8>) -> IsoTpOptions {
+        let mut options = IsoTpOptions::default();
+        let mut flags = IsoTpBehaviour::empty();
+        if let Some(address) = self.ext_address {
+            flags |= IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR;
+            options.set_ext_address(address);
+        }
+        if let Some(address) = self.rx_ext_address {
+            flags |= IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR | IsoTpBehaviour::CAN_ISOTP_RX_EXT_ADDR;
+            options.set_rx_ext_address(address);
+        }
+        if let Some(padding) = padding {
+            flags |= IsoTpBehaviour::CAN_ISOTP_TX_PADDING;
+            options.set_txpad_content(padding);
+        }
+        options.set_flags(flags);
+        options
+    }
+
+    /// Open a socket to the target on a named CAN device
+    pub fn open(&self, ifname: &str, padding: Option<u8>) -> Result<IsoTpSocket, crate::Error> {
+        IsoTpSocket::open_with_opts(
+            ifname,
+            self.response_id,
+            self.request_id,
+            Some(self.isotp_options(padding)),
+            None,
+            None,
+        )
+    }
+}
+
+/// ECU answering the probe
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscoveredEcu {
+    #[serde(flatten)]
+    pub target: ScanTarget,
+    /// Positive or negative response to the probe
+    #[serde(with = "hex_data")]
+    pub response: Vec<u8>,
+    /// Time from sending the probe to the response
+    pub latency_ms: u64,
+}
+
+/// Result of a scan
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub ecus: Vec<DiscoveredEcu>,
+    /// Number of targets probed
+    pub probed: usize,
+}
+
+impl ScanReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report serializes")
+    }
+
+    pub fn from_json(text: &str) -> io::Result<Self> {
+        serde_json::from_str(text)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// Timing of a [Scanner]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanConfig {
+    pub probe: ScanProbe,
+    /// Time to wait for the response of a target
+    pub timeout: Duration,
+    /// Minimum time between two probes
+    pub interval: Duration,
+    /// Number of targets probed at the same time
+    pub concurrency: usize,
+    /// Content of the padding bytes, `None` sends frames without padding
+    pub padding: Option<u8>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            probe: ScanProbe::default(),
+            timeout: Duration::from_millis(100),
+            interval: Duration::from_millis(10),
+            concurrency: 8,
+            padding: Some(0xCC),
+        }
+    }
+}
+
+/// Rate-limited, concurrent prober of [ScanTarget]s
+pub struct Scanner {
+    config: ScanConfig,
+}
+
+impl Scanner {
+    pub fn new(config: ScanConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> ScanConfig {
+        self.config
+    }
+
+    /// Probe the targets on a named CAN device
+    pub async fn run(&self, ifname: &str, targets: Vec<ScanTarget>) -> Result<ScanReport, Error> {
+        let padding = self.config.padding;
+        self.run_with(targets, |target| {
+            target.open(ifname, padding).map_err(|error| match error {
+                crate::Error::Lookup { source } => source.into(),
+                crate::Error::Io { source } => source,
+            })
+        })
+        .await
+    }
+
+    /// Probe the targets over transports opened by `open`
+    pub async fn run_with<T: IsoTpTransport>(
+        &self,
+        targets: Vec<ScanTarget>,
+        open: impl Fn(&ScanTarget) -> io::Result<T>,
+    ) -> Result<ScanReport, Error> {
+        let probed = targets.len();
+        let next_probe = tokio::sync::Mutex::new(Instant::now());
+        let mut receivers: HashMap<(Id, Option<u8>), Arc<tokio::sync::Mutex<()>>> = HashMap::new();
+        let probes: Vec<_> = targets
+            .into_iter()
+            .map(|target| {
+                let receiver = receivers
+                    .entry((target.response_id, target.rx_ext_address))
+                    .or_default()
+                    .clone();
+                (target, receiver)
+            })
+            .collect();
+        let results: Vec<Result<Option<DiscoveredEcu>, Error>> = stream::iter(probes)
+            .map(|(target, receiver)| {
+                let (open, next_probe) = (&open, &next_probe);
+                async move {
+                    let _receiver = receiver.lock().await;
+                    let transport = open(&target)?;
+                    {
+                        let mut next_probe = next_probe.lock().await;
+                        tokio::time::sleep_until(*next_probe).await;
+                        *next_probe = Instant::now() + self.config.interval;
+                    }
+                    self.probe(target, &transport).await
+                }
+            })
+            .buffer_unordered(self.config.concurrency.max(1))
+            .collect()
+            .await;
+        let mut ecus = results
+            .into_iter()
+            .filter_map(Result::transpose)
+            .collect::<Result<Vec<_>, _>>()?;
+        ecus.sort_by_key(|ecu| (raw_id(ecu.target.request_id), ecu.target.ext_address));
+        Ok(ScanReport { ecus, probed })
+    }
+
+    async fn probe(
+        &self,
+        target: ScanTarget,
+        transport: &impl IsoTpTransport,
+    ) -> Result<Option<DiscoveredEcu>, Error> {
+        let request = self.config.probe.request();
+        let sent = Instant::now();
+        transport.send(&request).await?;
+        let deadline = sent + self.config.timeout;
+        loop {
+            let Ok(response) = tokio::time::timeout_at(deadline, transport.recv()).await else {
+                return Ok(None);
+            };
+            let response = response?;
+            // PDUs of other testers or targets are no answer
+            if check_response(request[0], response.clone()).is_some() {
+                return Ok(Some(DiscoveredEcu {
+                    target,
+                    response,
+                    latency_ms: sent.elapsed().as_millis() as u64,
+                }));
+            }
+        }
+    }
+}
+
+/// Raw id for sorting, standard ids first
+fn raw_id(id: Id) -> u32 {
+    match id {
+        Id::Standard(id) => u32::from(id.as_raw()),
+        Id::Extended(id) => SFF_MASK + 1 + id.as_raw(),
+    }
+}
+
+mod hex_id {
+    use super::{format_id, parse_id};
+    use crate::Id;
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(id: &Id, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_id(*id))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Id, D::Error> {
+        parse_id(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+mod hex_data {
+    use super::{decode_hex, encode_hex};
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode_hex(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        decode_hex(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::tests::MockTransport;
+    use std::future::Future;
+    use std::sync::Mutex;
+
+    /// Time a probe was sent and its transport dropped
+    type Log = Arc<Mutex<Vec<(ScanTarget, Instant, Instant)>>>;
+
+    /// Transport of one target, logging the probe
+    struct Probe {
+        target: ScanTarget,
+        transport: MockTransport,
+        sent: Mutex<Option<Instant>>,
+        log: Log,
+    }
+
+    impl IsoTpTransport for Probe {
+        fn rx_id(&self) -> Id {
+            self.target.response_id
+        }
+
+        fn tx_id(&self) -> Id {
+            self.target.request_id
+        }
+
+        fn send<'a>(&'a self, pdu: &'a [u8]) -> impl Future<Output = io::Result<()>> + Send + 'a {
+            *self.sent.lock().unwrap() = Some(Instant::now());
+            self.transport.send(pdu)
+        }
+
+        fn recv(&self) -> impl Future<Output = io::Result<Vec<u8>>> + Send + '_ {
+            self.transport.recv()
+        }
+    }
+
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            if let Some(sent) = *self.sent.lock().unwrap() {
+                self.log
+                    .lock()
+                    .unwrap()
+                    .push((self.target, sent, Instant::now()));
+            }
+        }
+    }
+
+    /// Run a scan where the targets in `responding` answer the probe
+    async fn scan(
+        config: ScanConfig,
+        targets: Vec<ScanTarget>,
+        responding: &[ScanTarget],
+    ) -> (ScanReport, Vec<(ScanTarget, Instant, Instant)>) {
+        let log = Log::default();
+        let report = Scanner::new(config)
+            .run_with(targets, |target| {
+                let responds = responding.contains(target);
+                Ok(Probe {
+                    target: *target,
+                    transport: MockTransport::new(move |request| {
+                        if responds {
+                            vec![vec![request[0] + 0x40, request[1]]]
+                        } else {
+                            Vec::new()
+                        }
+                    }),
+                    sent: Mutex::new(None),
+                    log: log.clone(),
+                })
+            })
+            .await
+            .unwrap();
+        let mut log = log.lock().unwrap().clone();
+        log.sort_by_key(|(_, sent, _)| *sent);
+        (report, log)
+    }
+
+    fn config(timeout: u64, interval: u64) -> ScanConfig {
+        ScanConfig {
+            timeout: Duration::from_millis(timeout),
+            interval: Duration::from_millis(interval),
+            ..ScanConfig::default()
+        }
+    }
+
+    #[test]
+    fn standard_range() {
+        let targets = ScanTarget::standard_range(0x7E0..=0x7E2, 8);
+        assert_eq!(
+            targets,
+            [
+                ScanTarget::new(
+                    StandardId::new(0x7E0).unwrap(),
+                    StandardId::new(0x7E8).unwrap()
+                ),
+                ScanTarget::new(
+                    StandardId::new(0x7E1).unwrap(),
+                    StandardId::new(0x7E9).unwrap()
+                ),
+                ScanTarget::new(
+                    StandardId::new(0x7E2).unwrap(),
+                    StandardId::new(0x7EA).unwrap()
+                ),
+            ]
+        );
+        // responses beyond 0x7FF and requests beyond 11 bit are skipped
+        assert_eq!(ScanTarget::standard_range(0x7F6..=0x800, 8).len(), 2);
+        assert!(ScanTarget::standard_range(0x800..=0x900, 0).is_empty());
+    }
+
+    #[test]
+    fn normal_fixed() {
+        let targets = ScanTarget::normal_fixed(0xF0..=0xF2, 0xF1);
+        assert_eq!(
+            targets,
+            [
+                ScanTarget::new(
+                    ExtendedId::new(0x18DA_F0F1).unwrap(),
+                    ExtendedId::new(0x18DA_F1F0).unwrap(),
+                ),
+                ScanTarget::new(
+                    ExtendedId::new(0x18DA_F2F1).unwrap(),
+                    ExtendedId::new(0x18DA_F1F2).unwrap(),
+                ),
+            ]
+        );
+        assert_eq!(ScanTarget::normal_fixed(0x00..=0xFF, 0xF1).len(), 255);
+    }
+
+    #[tokio::test]
+    async fn reports_responding_targets() {
+        let targets = ScanTarget::standard_range(0x7E0..=0x7E7, 8);
+        let mut responding = vec![targets[5], targets[2]];
+        let (report, log) = scan(config(20, 0), targets, &responding).await;
+        responding.sort_by_key(|target| raw_id(target.request_id));
+        let found: Vec<ScanTarget> = report.ecus.iter().map(|ecu| ecu.target).collect();
+        assert_eq!(found, responding);
+        assert_eq!(report.ecus[0].response, [0x7E, 0x00]);
+        assert_eq!(report.probed, 8);
+        assert_eq!(log.len(), 8);
+    }
+
+    #[tokio::test]
+    async fn probes_are_rate_limited() {
+        let started = Instant::now();
+        let targets = ScanTarget::standard_range(0x700..=0x704, 8);
+        let (_, log) = scan(config(100, 20), targets, &[]).await;
+        let sent: Vec<Instant> = log.iter().map(|(_, sent, _)| *sent).collect();
+        assert_eq!(sent.len(), 5);
+        assert!(sent[4] - started >= Duration::from_millis(80));
+        for pair in sent.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(15));
+        }
+        // the probes wait for their responses concurrently
+        assert!(started.elapsed() < Duration::from_millis(5 * 100));
+    }
+
+    #[tokio::test]
+    async fn targets_sharing_a_response_id_are_serialized() {
+        let mut targets = ScanTarget::extended_addresses(
+            StandardId::new(0x6F1).unwrap(),
+            StandardId::new(0x6F8).unwrap(),
+            0x10..=0x13,
+            0xF1,
+        );
+        targets.extend(ScanTarget::standard_range(0x7E0..=0x7E1, 8));
+        let responding = [targets[1], targets[4]];
+        let (report, log) = scan(config(20, 0), targets.clone(), &responding).await;
+        assert_eq!(report.ecus.len(), 2);
+        let shared: Vec<_> = log
+            .iter()
+            .filter(|(target, _, _)| target.ext_address.is_some())
+            .collect();
+        assert_eq!(shared.len(), 4);
+        for pair in shared.windows(2) {
+            // the next probe is sent after the transport of the previous one is closed
+            assert!(pair[1].1 >= pair[0].2);
+        }
+        // other targets are probed at the same time
+        let (_, sent, closed) = log
+            .iter()
+            .find(|(target, _, _)| *target == targets[5])
+            .unwrap();
+        assert!(shared
+            .iter()
+            .any(|(_, shared_sent, shared_closed)| shared_sent < closed && sent < shared_closed));
+    }
+
+    #[test]
+    fn report_json_round_trip() {
+        let mut extended = ScanTarget::extended_addresses(
+            StandardId::new(0x6F1).unwrap(),
+            StandardId::new(0x6F8).unwrap(),
+            0x40..=0x40,
+            0xF1,
+        );
+        let report = ScanReport {
+            ecus: vec![
+                DiscoveredEcu {
+                    target: ScanTarget::standard_range(0x7E0..=0x7E0, 8)[0],
+                    response: vec![0x7E, 0x00],
+                    latency_ms: 3,
+                },
+                DiscoveredEcu {
+                    target: ScanTarget::normal_fixed(0x10..=0x10, 0xF1)[0],
+                    response: vec![0x7F, 0x3E, 0x11],
+                    latency_ms: 12,
+                },
+                DiscoveredEcu {
+                    target: extended.remove(0),
+                    response: vec![0x50, 0x01, 0x00, 0x32, 0x01, 0xF4],
+                    latency_ms: 0,
+                },
+            ],
+            probed: 248,
+        };
+        let json = report.to_json();
+        assert!(json.contains(r#""request_id": "7E0""#));
+        assert!(json.contains(r#""response_id": "18DAF110""#));
+        assert!(json.contains(r#""response": "7F3E11""#));
+        assert!(json.contains(r#""ext_address": 64"#));
+        assert_eq!(ScanReport::from_json(&json).unwrap(), report);
+
+        let json = r#"{"ecus":[{"request_id":"7E0","response_id":"7E8","response":"7E00","latency_ms":3}],"probed":248}"#;
+        let report = ScanReport::from_json(json).unwrap();
+        assert_eq!(report.ecus[0].target.ext_address, None);
+        let error = ScanReport::from_json(r#"{"ecus":[{"request_id":"800"}]}"#).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,322 @@
+use super::service::SECURITY_ACCESS;
+use super::{Error, NegativeResponseCode, UdsClient};
+use crate::IsoTpTransport;
+use std::time::Duration;
+
+/// Computes the key of SecurityAccess (0x27) from the seed sent by the ECU
+///
+/// The algorithm is supplier specific. Closures `Fn(level, seed) -> key` implement
+/// the trait as well.
+pub trait SeedKeyAlgorithm: Send + Sync {
+    /// Key for the seed of the given requestSeed level
+    fn compute_key(&self, level: u8, seed: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+impl<F> SeedKeyAlgorithm for F
+where
+    F: Fn(u8, &[u8]) -> Vec<u8> + Send + Sync,
+{
+    fn compute_key(&self, level: u8, seed: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(self(level, seed))
+    }
+}
+
+/// Result of a successful SecurityAccess
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityAccessOutcome {
+    /// The key was accepted
+    Unlocked,
+    /// The ECU sent a zero seed, the level was unlocked already
+    AlreadyUnlocked,
+}
+
+/// Handling of the lockout of SecurityAccess
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityAccessOptions {
+    /// Time to wait after exceededNumberOfAttempts (NRC 0x36) or
+    /// requiredTimeDelayNotExpired (NRC 0x37) before requesting a new seed
+    pub lockout_delay: Duration,
+    /// Number of lockout delays to wait before the NRC is returned, 0 disables waiting
+    pub max_lockout_waits: usize,
+}
+
+impl Default for SecurityAccessOptions {
+    fn default() -> Self {
+        Self {
+            lockout_delay: Duration::from_secs(10),
+            max_lockout_waits: 1,
+        }
+    }
+}
+
+impl<T: IsoTpTransport> UdsClient<T> {
+    /// SecurityAccess (0x27) of the given requestSeed level (odd number of 0x01..=0x7D)
+    ///
+    /// An invalid key is reported as the invalidKey (NRC 0x35) negative response, lockouts
+    /// are waited out according to the default [SecurityAccessOptions].
+    pub async fn security_access(
+        &self,
+        level: u8,
+        algorithm: &dyn SeedKeyAlgorithm,
+    ) -> Result<SecurityAccessOutcome, Error> {
+        self.security_access_with_options(level, algorithm, SecurityAccessOptions::default())
+            .await
+    }
+
+    /// SecurityAccess (0x27) with explicit lockout handling
+    pub async fn security_access_with_options(
+        &self,
+        level: u8,
+        algorithm: &dyn SeedKeyAlgorithm,
+        options: SecurityAccessOptions,
+    ) -> Result<SecurityAccessOutcome, Error> {
+        if !(0x01..=0x7D).contains(&level) || level.is_multiple_of(2) {
+            return Err(Error::invalid_request(format!(
+                "0x{level:02X} is not a requestSeed level"
+            )));
+        }
+        let mut waits = 0;
+        loop {
+            match self.unlock(level, algorithm).await {
+                Err(Error::NegativeResponse {
+                    code:
+                        NegativeResponseCode::ExceededNumberOfAttempts
+                        | NegativeResponseCode::RequiredTimeDelayNotExpired,
+                    ..
+                }) if waits < options.max_lockout_waits => {
+                    waits += 1;
+                    tokio::time::sleep(options.lockout_delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn unlock(
+        &self,
+        level: u8,
+        algorithm: &dyn SeedKeyAlgorithm,
+    ) -> Result<SecurityAccessOutcome, Error> {
+        let response = self.request(&[SECURITY_ACCESS, level]).await?;
+        if response.get(1) != Some(&level) {
+            return Err(Error::invalid_response(format!(
+                "level 0x{level:02X} not echoed"
+            )));
+        }
+        let seed = &response[2..];
+        if seed.is_empty() {
+            return Err(Error::invalid_response("empty seed"));
+        }
+        if seed.iter().all(|&byte| byte == 0) {
+            return Ok(SecurityAccessOutcome::AlreadyUnlocked);
+        }
+
+        let mut request = vec![SECURITY_ACCESS, level + 1];
+        request.extend(algorithm.compute_key(level, seed)?);
+        let response = self.request(&request).await?;
+        if response.get(1) != Some(&(level + 1)) {
+            return Err(Error::invalid_response(format!(
+                "level 0x{:02X} not echoed",
+                level + 1
+            )));
+        }
+        Ok(SecurityAccessOutcome::Unlocked)
+    }
+}
+
+#[cfg(feature = "seed-key-library")]
+pub use library::SharedLibraryAlgorithm;
+
+#[cfg(feature = "seed-key-library")]
+mod library {
+    use super::{Error, SeedKeyAlgorithm};
+    use std::ffi::{c_char, c_int, c_uint, CString, OsStr};
+
+    /// Largest key the library may return
+    const MAX_KEY_LEN: usize = 256;
+
+    /// `GenerateKeyEx` with the reference of the Vector interface replaced by a pointer
+    type GenerateKeyEx = unsafe extern "C" fn(
+        seed: *const u8,
+        seed_len: c_uint,
+        level: c_uint,
+        variant: *const c_char,
+        key: *mut u8,
+        max_key_len: c_uint,
+        key_len: *mut c_uint,
+    ) -> c_int;
+
+    /// Seed/key algorithm loaded from a Linux shared library
+    ///
+    /// The library has to export
+    ///
+    /// ```c
+    /// int GenerateKeyEx(const uint8_t *seed, unsigned int seed_len, unsigned int level,
+    ///                   const char *variant, uint8_t *key, unsigned int max_key_len,
+    ///                   unsigned int *key_len);
+    /// ```
+    ///
+    /// returning 0 on success, which is the interface key algorithms are usually
+    /// delivered with.
+    pub struct SharedLibraryAlgorithm {
+        generate_key: GenerateKeyEx,
+        variant: CString,
+        // keeps the function pointer valid
+        _library: libloading::Library,
+    }
+
+    impl SharedLibraryAlgorithm {
+        /// Load the library at `path`
+        ///
+        /// # Safety
+        ///
+        /// Loading runs the initialisation code of the library and the exported
+        /// function has to match the interface above.
+        pub unsafe fn load(path: impl AsRef<OsStr>) -> Result<Self, Error> {
+            let library = libloading::Library::new(path.as_ref()).map_err(key_error)?;
+            let generate_key = *library
+                .get::<GenerateKeyEx>(b"GenerateKeyEx\0")
+                .map_err(key_error)?;
+            Ok(Self {
+                generate_key,
+                variant: CString::default(),
+                _library: library,
+            })
+        }
+
+        /// Variant string passed to the library
+        pub fn with_variant(mut self, variant: &str) -> Result<Self, Error> {
+            self.variant = CString::new(variant).map_err(key_error)?;
+            Ok(self)
+        }
+    }
+
+    impl SeedKeyAlgorithm for SharedLibraryAlgorithm {
+        fn compute_key(&self, level: u8, seed: &[u8]) -> Result<Vec<u8>, Error> {
+            let mut key = vec![0u8; MAX_KEY_LEN];
+            let mut key_len: c_uint = 0;
+            // the pointers are valid for the duration of the call
+            let result = unsafe {
+                (self.generate_key)(
+                    seed.as_ptr(),
+                    seed.len() as c_uint,
+                    c_uint::from(level),
+                    self.variant.as_ptr(),
+                    key.as_mut_ptr(),
+                    MAX_KEY_LEN as c_uint,
+                    &mut key_len,
+                )
+            };
+            if result != 0 {
+                return Err(Error::KeyComputation {
+                    reason: format!("GenerateKeyEx returned {result}"),
+                });
+            }
+            key.truncate((key_len as usize).min(MAX_KEY_LEN));
+            Ok(key)
+        }
+    }
+
+    fn key_error(err: impl std::fmt::Display) -> Error {
+        Error::KeyComputation {
+            reason: err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::tests::MockTransport;
+
+    fn xor_key(_level: u8, seed: &[u8]) -> Vec<u8> {
+        seed.iter().map(|byte| byte ^ 0xFF).collect()
+    }
+
+    fn ecu(seed: &'static [u8]) -> UdsClient<MockTransport> {
+        UdsClient::new(MockTransport::new(move |request| match request {
+            [0x27, level] if level % 2 == 1 => {
+                let mut response = vec![0x67, *level];
+                response.extend_from_slice(seed);
+                vec![response]
+            }
+            [0x27, level, key @ ..] if key == xor_key(*level, seed) => vec![vec![0x67, *level]],
+            _ => vec![vec![0x7F, 0x27, 0x35]],
+        }))
+    }
+
+    #[tokio::test]
+    async fn unlocks() {
+        let client = ecu(&[0x12, 0x34]);
+        let outcome = client.security_access(0x03, &xor_key).await.unwrap();
+        assert_eq!(outcome, SecurityAccessOutcome::Unlocked);
+        assert_eq!(
+            client.transport().requests(),
+            [vec![0x27, 0x03], vec![0x27, 0x04, 0xED, 0xCB]]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_key() {
+        let client = ecu(&[0x12, 0x34]);
+        let error = client
+            .security_access(0x01, &|_: u8, _: &[u8]| vec![0x00, 0x00])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.negative_response_code(),
+            Some(NegativeResponseCode::InvalidKey)
+        );
+    }
+
+    #[tokio::test]
+    async fn zero_seed_is_already_unlocked() {
+        let client = ecu(&[0x00, 0x00]);
+        let outcome = client.security_access(0x01, &xor_key).await.unwrap();
+        assert_eq!(outcome, SecurityAccessOutcome::AlreadyUnlocked);
+        assert_eq!(client.transport().requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn empty_seed_is_invalid() {
+        let client = ecu(&[]);
+        let error = client.security_access(0x01, &xor_key).await.unwrap_err();
+        assert!(matches!(error, Error::InvalidResponse { .. }), "{error:?}");
+    }
+
+    #[tokio::test]
+    async fn rejects_levels_outside_request_seed_range() {
+        let client = ecu(&[0x12, 0x34]);
+        for level in [0x00, 0x02, 0x7E, 0x7F, 0x81, 0xC1, 0xFF] {
+            let error = client.security_access(level, &xor_key).await.unwrap_err();
+            assert!(matches!(error, Error::InvalidRequest { .. }), "{level:02X}");
+        }
+        assert!(client.transport().requests().is_empty());
+        client.security_access(0x7D, &xor_key).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn waits_out_lockout() {
+        let mut attempts = 0;
+        let client = UdsClient::new(MockTransport::new(move |request| match request {
+            [0x27, 0x01] => {
+                attempts += 1;
+                if attempts == 1 {
+                    vec![vec![0x7F, 0x27, 0x37]]
+                } else {
+                    vec![vec![0x67, 0x01, 0x55]]
+                }
+            }
+            _ => vec![vec![0x67, 0x02]],
+        }));
+        let options = SecurityAccessOptions {
+            lockout_delay: Duration::from_millis(1),
+            max_lockout_waits: 1,
+        };
+        let outcome = client
+            .security_access_with_options(0x01, &xor_key, options)
+            .await
+            .unwrap();
+        assert_eq!(outcome, SecurityAccessOutcome::Unlocked);
+    }
+}
@@ -0,0 +1,885 @@
+//! UDS server for ECU simulators
+//!
+//! [UdsServer] receives requests from an [IsoTpTransport] and answers them with the
+//! handlers registered per service or DID. The server itself implements
+//! DiagnosticSessionControl, ECUReset, SecurityAccess, TesterPresent and the
+//! Read/WriteDataByIdentifier dispatch, handlers registered for these services
+//! replace the built-in implementation.
+//!
+//! * Services without handler are answered with serviceNotSupported (0x11), services
+//!   not allowed in the active session with 0x7F, locked services and DIDs with
+//!   securityAccessDenied (0x33).
+//! * Handlers still running after 90% of P2 get response pending (0x78) sent for
+//!   them, repeated every 90% of P2*.
+//! * A non-default session without request for S3server ([S3_SERVER]) falls back to
+//!   the default session, which locks SecurityAccess again.
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::uds::server::{Access, UdsServer};
+//! use tokio_socketcan_isotp::uds::{DiagnosticSession, NegativeResponseCode};
+//! use tokio_socketcan_isotp::StandardId;
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut server = UdsServer::open(
+//!     "vcan0",
+//!     StandardId::new(0x7E0).unwrap(),
+//!     StandardId::new(0x7E8).unwrap(),
+//! )?;
+//! server.add_did(0xF190, b"WVWZZZ1JZXW000001".to_vec(), Access::default(), None);
+//! server.add_security_level(0x01, 4, |_level: u8, seed: &[u8]| {
+//!     seed.iter().map(|byte| byte ^ 0x5A).collect()
+//! });
+//! server.on_service(0x31, Access::session(DiagnosticSession::Extended), |request| async move {
+//!     match request.data.get(1..4) {
+//!         Some([0x01, 0xFF, 0x00]) => Ok(vec![0x01, 0xFF, 0x00]),
+//!         _ => Err(NegativeResponseCode::RequestOutOfRange),
+//!     }
+//! });
+//! server.serve().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::service::*;
+use super::{DiagnosticSession, NegativeResponseCode, SeedKeyAlgorithm, S3_SERVER};
+use crate::{Id, IsoTpSocket, IsoTpTransport};
+use futures::future::{BoxFuture, FutureExt};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Services whose second byte is a sub-function with the suppressPosRspMsgIndicationBit
+const SUB_FUNCTION_SERVICES: [u8; 9] = [
+    DIAGNOSTIC_SESSION_CONTROL,
+    ECU_RESET,
+    SECURITY_ACCESS,
+    COMMUNICATION_CONTROL,
+    ROUTINE_CONTROL,
+    TESTER_PRESENT,
+    CONTROL_DTC_SETTING,
+    0x86, // ResponseOnEvent
+    0x87, // LinkControl
+];
+
+/// Lower bound of the response pending interval, the resolution of P2* on the wire
+const MIN_RESPONSE_PENDING_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Result of a handler, the positive response without the response SID
+pub type HandlerResult = Result<Vec<u8>, NegativeResponseCode>;
+
+type ServiceHandler = Arc<dyn Fn(Request) -> BoxFuture<'static, HandlerResult> + Send + Sync>;
+type ReadHandler = Arc<dyn Fn() -> BoxFuture<'static, HandlerResult> + Send + Sync>;
+type WriteHandler =
+    Arc<dyn Fn(Vec<u8>) -> BoxFuture<'static, Result<(), NegativeResponseCode>> + Send + Sync>;
+
+/// Request passed to a service handler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// Whole request starting with the SID, the suppressPosRspMsgIndicationBit cleared
+    pub data: Vec<u8>,
+    /// The tester does not want a positive response, the server drops it
+    pub suppress_positive_response: bool,
+    pub session: DiagnosticSession,
+    /// Unlocked requestSeed level
+    pub security_level: Option<u8>,
+}
+
+/// Sessions and security level a service or DID is available in
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Access {
+    /// Sessions the service or DID is available in, empty for all sessions
+    pub sessions: Vec<DiagnosticSession>,
+    /// requestSeed level which has to be unlocked
+    pub security_level: Option<u8>,
+}
+
+impl Access {
+    /// Available in one session only
+    pub fn session(session: DiagnosticSession) -> Self {
+        Self {
+            sessions: vec![session],
+            security_level: None,
+        }
+    }
+
+    /// Require the requestSeed level to be unlocked
+    pub fn with_security_level(mut self, level: u8) -> Self {
+        self.security_level = Some(level);
+        self
+    }
+
+    fn allows_session(&self, session: DiagnosticSession) -> bool {
+        self.sessions.is_empty() || self.sessions.contains(&session)
+    }
+
+    fn allows_security(&self, unlocked: Option<u8>) -> bool {
+        self.security_level.is_none() || self.security_level == unlocked
+    }
+}
+
+/// Timing and SecurityAccess settings of a [UdsServer]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// P2server_max reported in the DiagnosticSessionControl response
+    pub p2: Duration,
+    /// P2*server_max reported in the DiagnosticSessionControl response, response
+    /// pending is repeated every 90% of it but at most every 10 ms
+    pub p2_star: Duration,
+    /// Time without request after which a non-default session ends
+    pub s3: Duration,
+    /// Sessions DiagnosticSessionControl accepts
+    pub sessions: Vec<DiagnosticSession>,
+    /// Invalid keys accepted before exceededNumberOfAttempts (NRC 0x36)
+    pub security_attempts: u8,
+    /// Time requestSeed is refused with NRC 0x37 after the attempts are exceeded
+    pub security_delay: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            p2: Duration::from_millis(50),
+            p2_star: Duration::from_millis(5000),
+            s3: S3_SERVER,
+            sessions: vec![
+                DiagnosticSession::Default,
+                DiagnosticSession::Programming,
+                DiagnosticSession::Extended,
+            ],
+            security_attempts: 3,
+            security_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Session and security state visible to the tester
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerState {
+    pub session: DiagnosticSession,
+    /// Unlocked requestSeed level
+    pub security_level: Option<u8>,
+}
+
+struct SecurityLevel {
+    seed_length: usize,
+    algorithm: Arc<dyn SeedKeyAlgorithm>,
+}
+
+struct DidEntry {
+    read_access: Option<Access>,
+    write_access: Option<Access>,
+    read: Option<ReadHandler>,
+    write: Option<WriteHandler>,
+}
+
+struct State {
+    session: DiagnosticSession,
+    security_level: Option<u8>,
+    /// requestSeed level and seed awaiting the key
+    seed: Option<(u8, Vec<u8>)>,
+    failed_attempts: u8,
+    locked_until: Option<Instant>,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            session: DiagnosticSession::Default,
+            security_level: None,
+            seed: None,
+            failed_attempts: 0,
+            locked_until: None,
+        }
+    }
+
+    fn enter_session(&mut self, session: DiagnosticSession) {
+        self.session = session;
+        self.security_level = None;
+        self.seed = None;
+    }
+}
+
+/// Simulated ECU answering UDS requests
+pub struct UdsServer<T = IsoTpSocket> {
+    transport: T,
+    config: ServerConfig,
+    services: HashMap<u8, (Access, ServiceHandler)>,
+    dids: HashMap<u16, DidEntry>,
+    values: Mutex<HashMap<u16, Vec<u8>>>,
+    security_levels: HashMap<u8, SecurityLevel>,
+    state: Mutex<State>,
+}
+
+impl UdsServer<IsoTpSocket> {
+    /// Open on a named CAN device, receiving requests on `request_id`
+    pub fn open(
+        ifname: &str,
+        request_id: impl Into<Id>,
+        response_id: impl Into<Id>,
+    ) -> Result<Self, crate::Error> {
+        Ok(Self::new(IsoTpSocket::open(
+            ifname,
+            request_id,
+            response_id,
+        )?))
+    }
+}
+
+impl<T: IsoTpTransport> UdsServer<T> {
+    pub fn new(transport: T) -> Self {
+        Self::with_config(transport, ServerConfig::default())
+    }
+
+    pub fn with_config(transport: T, config: ServerConfig) -> Self {
+        Self {
+            transport,
+            config,
+            services: HashMap::new(),
+            dids: HashMap::new(),
+            values: Mutex::new(HashMap::new()),
+            security_levels: HashMap::new(),
+            state: Mutex::new(State::new()),
+        }
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn state(&self) -> ServerState {
+        let state = self.state.lock().unwrap();
+        ServerState {
+            session: state.session,
+            security_level: state.security_level,
+        }
+    }
+
+    /// Handle a service, replacing the built-in implementation if there is one
+    pub fn on_service<F, Fut>(&mut self, sid: u8, access: Access, handler: F)
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let handler: ServiceHandler = Arc::new(move |request| handler(request).boxed());
+        self.services.insert(sid, (access, handler));
+    }
+
+    /// DID with a stored value, writable with WriteDataByIdentifier if `write` is given
+    pub fn add_did(&mut self, did: u16, value: Vec<u8>, read: Access, write: Option<Access>) {
+        self.values.lock().unwrap().insert(did, value);
+        self.dids.insert(
+            did,
+            DidEntry {
+                read_access: Some(read),
+                write_access: write,
+                read: None,
+                write: None,
+            },
+        );
+    }
+
+    /// DID read by a handler
+    pub fn on_read_did<F, Fut>(&mut self, did: u16, access: Access, handler: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let entry = self.did_entry(did);
+        entry.read_access = Some(access);
+        entry.read = Some(Arc::new(move || handler().boxed()));
+    }
+
+    /// DID written by a handler
+    pub fn on_write_did<F, Fut>(&mut self, did: u16, access: Access, handler: F)
+    where
+        F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), NegativeResponseCode>> + Send + 'static,
+    {
+        let entry = self.did_entry(did);
+        entry.write_access = Some(access);
+        entry.write = Some(Arc::new(move |data| handler(data).boxed()));
+    }
+
+    fn did_entry(&mut self, did: u16) -> &mut DidEntry {
+        self.dids.entry(did).or_insert(DidEntry {
+            read_access: None,
+            write_access: None,
+            read: None,
+            write: None,
+        })
+    }
+
+    /// Stored value of a DID added with [UdsServer::add_did]
+    pub fn did_value(&self, did: u16) -> Option<Vec<u8>> {
+        self.values.lock().unwrap().get(&did).cloned()
+    }
+
+    /// Change the stored value of a DID, e.g. while serving
+    pub fn set_did_value(&self, did: u16, value: Vec<u8>) {
+        self.values.lock().unwrap().insert(did, value);
+    }
+
+    /// Enable SecurityAccess for a requestSeed level (odd number) with seeds of `seed_length` bytes
+    ///
+    /// The key expected for a seed is computed with the same [SeedKeyAlgorithm] the
+    /// tester uses.
+    pub fn add_security_level(
+        &mut self,
+        level: u8,
+        seed_length: usize,
+        algorithm: impl SeedKeyAlgorithm + 'static,
+    ) {
+        self.security_levels.insert(
+            level,
+            SecurityLevel {
+                seed_length,
+                algorithm: Arc::new(algorithm),
+            },
+        );
+    }
+
+    /// Answer requests until the transport fails
+    pub async fn serve(&self) -> io::Result<()> {
+        loop {
+            let request = if self.state().session == DiagnosticSession::Default {
+                self.transport.recv().await?
+            } else {
+                match tokio::time::timeout(self.config.s3, self.transport.recv()).await {
+                    Ok(request) => request?,
+                    Err(_) => {
+                        self.state
+                            .lock()
+                            .unwrap()
+                            .enter_session(DiagnosticSession::Default);
+                        continue;
+                    }
+                }
+            };
+            if let Some(response) = self.handle(&request).await? {
+                self.transport.send(&response).await?;
+            }
+        }
+    }
+
+    /// Process one request, returns the final response unless it is suppressed
+    ///
+    /// Response pending is sent on the transport while the handler runs.
+    pub async fn handle(&self, request: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let Some(&sid) = request.first() else {
+            return Ok(None);
+        };
+        let mut data = request.to_vec();
+        let mut suppress_positive_response = false;
+        if SUB_FUNCTION_SERVICES.contains(&sid) && data.len() >= 2 {
+            suppress_positive_response = data[1] & SUPPRESS_POSITIVE_RESPONSE != 0;
+            data[1] &= !SUPPRESS_POSITIVE_RESPONSE;
+        }
+        let (session, security_level) = {
+            let state = self.state.lock().unwrap();
+            (state.session, state.security_level)
+        };
+        let request = Request {
+            data,
+            suppress_positive_response,
+            session,
+            security_level,
+        };
+
+        let mut pending_sent = false;
+        let result = if let Some((access, handler)) = self.services.get(&sid) {
+            if !access.allows_session(session) {
+                Err(NegativeResponseCode::ServiceNotSupportedInActiveSession)
+            } else if !access.allows_security(security_level) {
+                Err(NegativeResponseCode::SecurityAccessDenied)
+            } else {
+                self.with_response_pending(sid, handler(request.clone()), &mut pending_sent)
+                    .await?
+            }
+        } else {
+            match sid {
+                DIAGNOSTIC_SESSION_CONTROL => self.diagnostic_session_control(&request.data),
+                ECU_RESET => self.ecu_reset(&request.data),
+                SECURITY_ACCESS => self.security_access(&request.data),
+                TESTER_PRESENT => match request.data.as_slice() {
+                    [_, 0x00] => Ok(vec![0x00]),
+                    [_, _] => Err(NegativeResponseCode::SubFunctionNotSupported),
+                    _ => Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat),
+                },
+                READ_DATA_BY_IDENTIFIER => {
+                    let read = self.read_data_by_identifier(&request);
+                    self.with_response_pending(sid, read, &mut pending_sent)
+                        .await?
+                }
+                WRITE_DATA_BY_IDENTIFIER => {
+                    let write = self.write_data_by_identifier(&request);
+                    self.with_response_pending(sid, write, &mut pending_sent)
+                        .await?
+                }
+                _ => Err(NegativeResponseCode::ServiceNotSupported),
+            }
+        };
+        Ok(match result {
+            // a positive response announced by response pending cannot be suppressed
+            Ok(_) if suppress_positive_response && !pending_sent => None,
+            Ok(payload) => {
+                let mut response = vec![sid.wrapping_add(POSITIVE_RESPONSE_OFFSET)];
+                response.extend_from_slice(&payload);
+                Some(response)
+            }
+            Err(code) => Some(vec![NEGATIVE_RESPONSE, sid, code.into()]),
+        })
+    }
+
+    /// Await a handler, sending response pending whenever P2 or P2* is about to expire
+    async fn with_response_pending(
+        &self,
+        sid: u8,
+        handler: impl Future<Output = HandlerResult>,
+        pending_sent: &mut bool,
+    ) -> io::Result<HandlerResult> {
+        tokio::pin!(handler);
+        let mut wait = self.config.p2 * 9 / 10;
+        loop {
+            match tokio::time::timeout(wait, &mut handler).await {
+                Ok(result) => return Ok(result),
+                Err(_) => {
+                    let pending: u8 =
+                        NegativeResponseCode::RequestCorrectlyReceivedResponsePending.into();
+                    self.transport
+                        .send(&[NEGATIVE_RESPONSE, sid, pending])
+                        .await?;
+                    *pending_sent = true;
+                    wait = (self.config.p2_star * 9 / 10).max(MIN_RESPONSE_PENDING_INTERVAL);
+                }
+            }
+        }
+    }
+
+    fn diagnostic_session_control(&self, request: &[u8]) -> HandlerResult {
+        let [_, session] = request else {
+            return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat);
+        };
+        let session = DiagnosticSession::from(*session);
+        if !self.config.sessions.contains(&session) {
+            return Err(NegativeResponseCode::SubFunctionNotSupported);
+        }
+        self.state.lock().unwrap().enter_session(session);
+        let p2 = self.config.p2.as_millis().min(0xFFFF) as u16;
+        let p2_star = (self.config.p2_star.as_millis() / 10).min(0xFFFF) as u16;
+        let mut response = vec![session.into()];
+        response.extend_from_slice(&p2.to_be_bytes());
+        response.extend_from_slice(&p2_star.to_be_bytes());
+        Ok(response)
+    }
+
+    /// Hard, key off on and soft reset return to the default session
+    fn ecu_reset(&self, request: &[u8]) -> HandlerResult {
+        let [_, reset_type] = request else {
+            return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat);
+        };
+        if !(0x01..=0x03).contains(reset_type) {
+            return Err(NegativeResponseCode::SubFunctionNotSupported);
+        }
+        self.state
+            .lock()
+            .unwrap()
+            .enter_session(DiagnosticSession::Default);
+        Ok(vec![*reset_type])
+    }
+
+    fn security_access(&self, request: &[u8]) -> HandlerResult {
+        let Some(&sub_function) = request.get(1) else {
+            return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat);
+        };
+        let level = if sub_function % 2 == 1 {
+            sub_function
+        } else {
+            sub_function.wrapping_sub(1)
+        };
+        let Some(security_level) = self.security_levels.get(&level) else {
+            return Err(NegativeResponseCode::SubFunctionNotSupported);
+        };
+        let mut state = self.state.lock().unwrap();
+
+        // requestSeed
+        if sub_function == level {
+            if state
+                .locked_until
+                .is_some_and(|locked_until| Instant::now() < locked_until)
+            {
+                return Err(NegativeResponseCode::RequiredTimeDelayNotExpired);
+            }
+            let seed = if state.security_level == Some(level) {
+                vec![0; security_level.seed_length]
+            } else {
+                let seed = random_seed(security_level.seed_length);
+                state.seed = Some((level, seed.clone()));
+                seed
+            };
+            let mut response = vec![level];
+            response.extend_from_slice(&seed);
+            return Ok(response);
+        }
+
+        // sendKey
+        let Some((seed_level, seed)) = state.seed.take() else {
+            return Err(NegativeResponseCode::RequestSequenceError);
+        };
+        if seed_level != level {
+            return Err(NegativeResponseCode::RequestSequenceError);
+        }
+        let expected = security_level
+            .algorithm
+            .compute_key(level, &seed)
+            .map_err(|_| NegativeResponseCode::ConditionsNotCorrect)?;
+        if request[2..] == expected[..] {
+            state.security_level = Some(level);
+            state.failed_attempts = 0;
+            return Ok(vec![sub_function]);
+        }
+        state.failed_attempts += 1;
+        if state.failed_attempts >= self.config.security_attempts {
+            state.failed_attempts = 0;
+            state.locked_until = Some(Instant::now() + self.config.security_delay);
+            return Err(NegativeResponseCode::ExceededNumberOfAttempts);
+        }
+        Err(NegativeResponseCode::InvalidKey)
+    }
+
+    fn read_data_by_identifier(&self, request: &Request) -> BoxFuture<'static, HandlerResult> {
+        let data = &request.data[1..];
+        if data.is_empty() || !data.len().is_multiple_of(2) {
+            return futures::future::ready(Err(
+                NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat,
+            ))
+            .boxed();
+        }
+        // resolve the DIDs before awaiting, the handlers are owned by the future
+        let mut reads = Vec::new();
+        for did in data
+            .chunks(2)
+            .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]))
+        {
+            let Some(entry) = self.dids.get(&did) else {
+                continue;
+            };
+            let Some(access) = &entry.read_access else {
+                continue;
+            };
+            if !access.allows_session(request.session) {
+                continue;
+            }
+            if !access.allows_security(request.security_level) {
+                return futures::future::ready(Err(NegativeResponseCode::SecurityAccessDenied))
+                    .boxed();
+            }
+            match &entry.read {
+                Some(handler) => reads.push((did, Err(Arc::clone(handler)))),
+                None => reads.push((did, Ok(self.did_value(did).unwrap_or_default()))),
+            }
+        }
+        async move {
+            if reads.is_empty() {
+                return Err(NegativeResponseCode::RequestOutOfRange);
+            }
+            let mut response = Vec::new();
+            for (did, read) in reads {
+                response.extend_from_slice(&did.to_be_bytes());
+                match read {
+                    Ok(value) => response.extend_from_slice(&value),
+                    Err(handler) => response.extend_from_slice(&handler().await?),
+                }
+            }
+            Ok(response)
+        }
+        .boxed()
+    }
+
+    fn write_data_by_identifier(&self, request: &Request) -> BoxFuture<'static, HandlerResult> {
+        let ready = |result| futures::future::ready(result).boxed();
+        let [_, high, low, value @ ..] = request.data.as_slice() else {
+            return ready(Err(
+                NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat,
+            ));
+        };
+        if value.is_empty() {
+            return ready(Err(
+                NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat,
+            ));
+        }
+        let did = u16::from_be_bytes([*high, *low]);
+        let Some(entry) = self.dids.get(&did) else {
+            return ready(Err(NegativeResponseCode::RequestOutOfRange));
+        };
+        let Some(access) = &entry.write_access else {
+            return ready(Err(NegativeResponseCode::RequestOutOfRange));
+        };
+        if !access.allows_session(request.session) {
+            return ready(Err(NegativeResponseCode::RequestOutOfRange));
+        }
+        if !access.allows_security(request.security_level) {
+            return ready(Err(NegativeResponseCode::SecurityAccessDenied));
+        }
+        let echo = did.to_be_bytes().to_vec();
+        match &entry.write {
+            Some(handler) => {
+                let write = handler(value.to_vec());
+                async move { write.await.map(|()| echo) }.boxed()
+            }
+            None => {
+                self.set_did_value(did, value.to_vec());
+                ready(Ok(echo))
+            }
+        }
+    }
+}
+
+/// Seed of random bytes, never all zero as that means "already unlocked"
+fn random_seed(length: usize) -> Vec<u8> {
+    loop {
+        let mut seed = Vec::with_capacity(length);
+        while seed.len() < length {
+            let random = RandomState::new().build_hasher().finish();
+            seed.extend(random.to_be_bytes().into_iter().take(length - seed.len()));
+        }
+        if length == 0 || seed.iter().any(|byte| *byte != 0) {
+            return seed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::tests::MockTransport;
+
+    /// Server whose responses pending end up in the requests of the transport
+    fn server(config: ServerConfig) -> UdsServer<MockTransport> {
+        let mut server = UdsServer::with_config(MockTransport::new(|_| vec![]), config);
+        server.add_security_level(0x01, 4, |_level: u8, seed: &[u8]| {
+            seed.iter().map(|byte| byte ^ 0x5A).collect()
+        });
+        server
+    }
+
+    async fn handle(server: &UdsServer<MockTransport>, request: &[u8]) -> Option<Vec<u8>> {
+        server.handle(request).await.unwrap()
+    }
+
+    /// requestSeed and sendKey of level 1, the key inverted if `valid` is false
+    async fn unlock(server: &UdsServer<MockTransport>, valid: bool) -> Option<Vec<u8>> {
+        let response = handle(server, &[0x27, 0x01]).await.unwrap();
+        assert_eq!(response[..2], [0x67, 0x01]);
+        let mut request = vec![0x27, 0x02];
+        request.extend(response[2..].iter().map(|byte| match valid {
+            true => byte ^ 0x5A,
+            false => !(byte ^ 0x5A),
+        }));
+        handle(server, &request).await
+    }
+
+    #[tokio::test]
+    async fn session_control_reports_timing() {
+        let server = server(ServerConfig {
+            p2: Duration::from_millis(25),
+            p2_star: Duration::from_millis(2000),
+            ..ServerConfig::default()
+        });
+        assert_eq!(
+            handle(&server, &[0x10, 0x03]).await,
+            Some(vec![0x50, 0x03, 0x00, 0x19, 0x00, 0xC8])
+        );
+        assert_eq!(server.state().session, DiagnosticSession::Extended);
+        assert_eq!(
+            handle(&server, &[0x10, 0x04]).await,
+            Some(vec![0x7F, 0x10, 0x12])
+        );
+        assert_eq!(handle(&server, &[0x10]).await, Some(vec![0x7F, 0x10, 0x13]));
+        assert_eq!(server.state().session, DiagnosticSession::Extended);
+        assert_eq!(handle(&server, &[0x11, 0x01]).await, Some(vec![0x51, 0x01]));
+        assert_eq!(server.state().session, DiagnosticSession::Default);
+        assert_eq!(handle(&server, &[]).await, None);
+    }
+
+    #[tokio::test]
+    async fn session_falls_back_after_s3() {
+        let server = server(ServerConfig {
+            s3: Duration::from_millis(50),
+            ..ServerConfig::default()
+        });
+        handle(&server, &[0x10, 0x03]).await.unwrap();
+        assert_eq!(unlock(&server, true).await, Some(vec![0x67, 0x02]));
+        assert_eq!(server.state().security_level, Some(0x01));
+
+        // the mock never receives a request, serve runs until the timeout
+        tokio::time::timeout(Duration::from_millis(30), server.serve())
+            .await
+            .unwrap_err();
+        assert_eq!(server.state().session, DiagnosticSession::Extended);
+        tokio::time::timeout(Duration::from_millis(100), server.serve())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            server.state(),
+            ServerState {
+                session: DiagnosticSession::Default,
+                security_level: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn seed_and_key() {
+        let server = server(ServerConfig::default());
+        // sendKey without requestSeed
+        assert_eq!(
+            handle(&server, &[0x27, 0x02, 0x00]).await,
+            Some(vec![0x7F, 0x27, 0x24])
+        );
+        assert_eq!(
+            handle(&server, &[0x27, 0x03]).await,
+            Some(vec![0x7F, 0x27, 0x12])
+        );
+        assert_eq!(handle(&server, &[0x27]).await, Some(vec![0x7F, 0x27, 0x13]));
+
+        let seed = handle(&server, &[0x27, 0x01]).await.unwrap();
+        assert_eq!(seed.len(), 6);
+        assert!(seed[2..].iter().any(|byte| *byte != 0));
+        assert_eq!(unlock(&server, true).await, Some(vec![0x67, 0x02]));
+        assert_eq!(server.state().security_level, Some(0x01));
+        // an unlocked level answers with a zero seed
+        assert_eq!(
+            handle(&server, &[0x27, 0x01]).await,
+            Some(vec![0x67, 0x01, 0x00, 0x00, 0x00, 0x00])
+        );
+        // entering a session locks again
+        handle(&server, &[0x10, 0x01]).await.unwrap();
+        assert_eq!(server.state().security_level, None);
+    }
+
+    #[tokio::test]
+    async fn invalid_keys_delay_request_seed() {
+        let server = server(ServerConfig {
+            security_attempts: 2,
+            security_delay: Duration::from_millis(50),
+            ..ServerConfig::default()
+        });
+        assert_eq!(unlock(&server, false).await, Some(vec![0x7F, 0x27, 0x35]));
+        // the seed is used up by a sendKey
+        assert_eq!(
+            handle(&server, &[0x27, 0x02, 0x00]).await,
+            Some(vec![0x7F, 0x27, 0x24])
+        );
+        assert_eq!(unlock(&server, false).await, Some(vec![0x7F, 0x27, 0x36]));
+        assert_eq!(
+            handle(&server, &[0x27, 0x01]).await,
+            Some(vec![0x7F, 0x27, 0x37])
+        );
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(unlock(&server, true).await, Some(vec![0x67, 0x02]));
+        // a successful key resets the attempts
+        handle(&server, &[0x10, 0x01]).await.unwrap();
+        assert_eq!(unlock(&server, false).await, Some(vec![0x7F, 0x27, 0x35]));
+        assert_eq!(server.state().security_level, None);
+    }
+
+    #[tokio::test]
+    async fn suppress_positive_response() {
+        let mut server = server(ServerConfig {
+            p2: Duration::from_millis(20),
+            ..ServerConfig::default()
+        });
+        server.on_service(0x31, Access::default(), |request| async move {
+            assert!(request.suppress_positive_response);
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            Ok(request.data[1..].to_vec())
+        });
+        assert_eq!(handle(&server, &[0x3E, 0x80]).await, None);
+        assert_eq!(handle(&server, &[0x3E, 0x00]).await, Some(vec![0x7E, 0x00]));
+        assert_eq!(handle(&server, &[0x10, 0x83]).await, None);
+        assert_eq!(server.state().session, DiagnosticSession::Extended);
+        // negative responses are sent anyway
+        assert_eq!(
+            handle(&server, &[0x3E, 0x81]).await,
+            Some(vec![0x7F, 0x3E, 0x12])
+        );
+        // as is a positive response announced by response pending
+        assert_eq!(
+            handle(&server, &[0x31, 0x81, 0xFF, 0x00]).await,
+            Some(vec![0x71, 0x01, 0xFF, 0x00])
+        );
+        assert_eq!(server.transport().requests(), [[0x7F, 0x31, 0x78]]);
+    }
+
+    #[tokio::test]
+    async fn negative_response_codes() {
+        let mut server = server(ServerConfig::default());
+        server.on_service(
+            0x31,
+            Access::session(DiagnosticSession::Extended).with_security_level(0x01),
+            |_| async { Ok(vec![0x01, 0xFF, 0x00]) },
+        );
+        server.add_did(
+            0xF190,
+            b"WVW".to_vec(),
+            Access::default().with_security_level(0x01),
+            None,
+        );
+        assert_eq!(
+            handle(&server, &[0x85, 0x01]).await,
+            Some(vec![0x7F, 0x85, 0x11])
+        );
+        assert_eq!(
+            handle(&server, &[0x31, 0x01, 0xFF, 0x00]).await,
+            Some(vec![0x7F, 0x31, 0x7F])
+        );
+        handle(&server, &[0x10, 0x03]).await.unwrap();
+        assert_eq!(
+            handle(&server, &[0x31, 0x01, 0xFF, 0x00]).await,
+            Some(vec![0x7F, 0x31, 0x33])
+        );
+        assert_eq!(
+            handle(&server, &[0x22, 0xF1, 0x90]).await,
+            Some(vec![0x7F, 0x22, 0x33])
+        );
+        unlock(&server, true).await.unwrap();
+        assert_eq!(
+            handle(&server, &[0x31, 0x01, 0xFF, 0x00]).await,
+            Some(vec![0x71, 0x01, 0xFF, 0x00])
+        );
+        assert_eq!(
+            handle(&server, &[0x22, 0xF1, 0x90]).await,
+            Some(b"\x62\xF1\x90WVW".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn response_pending_interval() {
+        let mut server = server(ServerConfig {
+            p2: Duration::from_millis(20),
+            p2_star: Duration::ZERO,
+            ..ServerConfig::default()
+        });
+        server.on_service(0x34, Access::default(), |_| async {
+            tokio::time::sleep(Duration::from_millis(65)).await;
+            Ok(vec![0x20, 0x0F, 0xFF])
+        });
+        assert_eq!(
+            handle(&server, &[0x34, 0x00, 0x44]).await,
+            Some(vec![0x74, 0x20, 0x0F, 0xFF])
+        );
+        // after 18 ms and then every 10 ms despite a P2* of zero
+        let pending = server.transport().requests();
+        assert!((3..=6).contains(&pending.len()), "{pending:02X?}");
+        assert!(pending.iter().all(|pdu| pdu == &[0x7F, 0x34, 0x78]));
+    }
+}
@@ -0,0 +1,227 @@
+//! Service identifiers and sub-function values
+
+use super::Error;
+
+/// DiagnosticSessionControl
+pub const DIAGNOSTIC_SESSION_CONTROL: u8 = 0x10;
+/// ECUReset
+pub const ECU_RESET: u8 = 0x11;
+/// ClearDiagnosticInformation
+pub const CLEAR_DIAGNOSTIC_INFORMATION: u8 = 0x14;
+/// ReadDTCInformation
+pub const READ_DTC_INFORMATION: u8 = 0x19;
+/// ReadDataByIdentifier
+pub const READ_DATA_BY_IDENTIFIER: u8 = 0x22;
+/// ReadMemoryByAddress
+pub const READ_MEMORY_BY_ADDRESS: u8 = 0x23;
+/// SecurityAccess
+pub const SECURITY_ACCESS: u8 = 0x27;
+/// CommunicationControl
+pub const COMMUNICATION_CONTROL: u8 = 0x28;
+/// WriteDataByIdentifier
+pub const WRITE_DATA_BY_IDENTIFIER: u8 = 0x2E;
+/// InputOutputControlByIdentifier
+pub const INPUT_OUTPUT_CONTROL_BY_IDENTIFIER: u8 = 0x2F;
+/// RoutineControl
+pub const ROUTINE_CONTROL: u8 = 0x31;
+/// RequestDownload
+pub const REQUEST_DOWNLOAD: u8 = 0x34;
+/// RequestUpload
+pub const REQUEST_UPLOAD: u8 = 0x35;
+/// TransferData
+pub const TRANSFER_DATA: u8 = 0x36;
+/// RequestTransferExit
+pub const REQUEST_TRANSFER_EXIT: u8 = 0x37;
+/// RequestFileTransfer
+pub const REQUEST_FILE_TRANSFER: u8 = 0x38;
+/// WriteMemoryByAddress
+pub const WRITE_MEMORY_BY_ADDRESS: u8 = 0x3D;
+/// TesterPresent
+pub const TESTER_PRESENT: u8 = 0x3E;
+/// ControlDTCSetting
+pub const CONTROL_DTC_SETTING: u8 = 0x85;
+
+/// First byte of a negative response
+pub const NEGATIVE_RESPONSE: u8 = 0x7F;
+
+/// Added to the SID in a positive response
+pub const POSITIVE_RESPONSE_OFFSET: u8 = 0x40;
+
+/// suppressPosRspMsgIndicationBit of the sub-function byte
+pub const SUPPRESS_POSITIVE_RESPONSE: u8 = 0x80;
+
+/// Name of a service identifier
+pub fn service_name(sid: u8) -> Option<&'static str> {
+    Some(match sid {
+        DIAGNOSTIC_SESSION_CONTROL => "DiagnosticSessionControl",
+        ECU_RESET => "ECUReset",
+        CLEAR_DIAGNOSTIC_INFORMATION => "ClearDiagnosticInformation",
+        READ_DTC_INFORMATION => "ReadDTCInformation",
+        READ_DATA_BY_IDENTIFIER => "ReadDataByIdentifier",
+        READ_MEMORY_BY_ADDRESS => "ReadMemoryByAddress",
+        SECURITY_ACCESS => "SecurityAccess",
+        COMMUNICATION_CONTROL => "CommunicationControl",
+        WRITE_DATA_BY_IDENTIFIER => "WriteDataByIdentifier",
+        INPUT_OUTPUT_CONTROL_BY_IDENTIFIER => "InputOutputControlByIdentifier",
+        ROUTINE_CONTROL => "RoutineControl",
+        REQUEST_DOWNLOAD => "RequestDownload",
+        REQUEST_UPLOAD => "RequestUpload",
+        TRANSFER_DATA => "TransferData",
+        REQUEST_TRANSFER_EXIT => "RequestTransferExit",
+        REQUEST_FILE_TRANSFER => "RequestFileTransfer",
+        WRITE_MEMORY_BY_ADDRESS => "WriteMemoryByAddress",
+        TESTER_PRESENT => "TesterPresent",
+        CONTROL_DTC_SETTING => "ControlDTCSetting",
+        _ => return None,
+    })
+}
+
+byte_enum! {
+    /// Diagnostic session of DiagnosticSessionControl
+    DiagnosticSession {
+        Default = 0x01, "defaultSession";
+        Programming = 0x02, "programmingSession";
+        Extended = 0x03, "extendedDiagnosticSession";
+        SafetySystem = 0x04, "safetySystemDiagnosticSession";
+    }
+}
+
+byte_enum! {
+    /// Reset type of ECUReset
+    ResetType {
+        Hard = 0x01, "hardReset";
+        KeyOffOn = 0x02, "keyOffOnReset";
+        Soft = 0x03, "softReset";
+        EnableRapidPowerShutDown = 0x04, "enableRapidPowerShutDown";
+        DisableRapidPowerShutDown = 0x05, "disableRapidPowerShutDown";
+    }
+}
+
+byte_enum! {
+    /// Sub-function of RoutineControl
+    RoutineControlType {
+        Start = 0x01, "startRoutine";
+        Stop = 0x02, "stopRoutine";
+        RequestResults = 0x03, "requestRoutineResults";
+    }
+}
+
+/// addressAndLengthFormatIdentifier, number of bytes of memoryAddress and memorySize
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AddressAndLengthFormat {
+    /// Bytes of memoryAddress, 1 to 8
+    pub address_bytes: u8,
+    /// Bytes of memorySize, 1 to 8
+    pub size_bytes: u8,
+}
+
+impl AddressAndLengthFormat {
+    pub fn new(address_bytes: u8, size_bytes: u8) -> Self {
+        Self {
+            address_bytes,
+            size_bytes,
+        }
+    }
+
+    /// Smallest format able to hold the address and size
+    pub fn fitting(address: u64, size: u64) -> Self {
+        Self::new(min_bytes(address), min_bytes(size))
+    }
+
+    /// The identifier byte, memorySize length in the high nibble
+    pub fn identifier(&self) -> u8 {
+        (self.size_bytes << 4) | (self.address_bytes & 0x0F)
+    }
+
+    /// Identifier followed by memoryAddress and memorySize
+    pub(crate) fn encode(&self, address: u64, size: u64) -> Result<Vec<u8>, Error> {
+        let valid = 1..=8;
+        if !valid.contains(&self.address_bytes) || !valid.contains(&self.size_bytes) {
+            return Err(Error::invalid_request(format!(
+                "invalid addressAndLengthFormatIdentifier 0x{:02X}",
+                self.identifier()
+            )));
+        }
+        if min_bytes(address) > self.address_bytes || min_bytes(size) > self.size_bytes {
+            return Err(Error::invalid_request(format!(
+                "address 0x{address:X} or size 0x{size:X} exceeds format 0x{:02X}",
+                self.identifier()
+            )));
+        }
+        let mut encoded = vec![self.identifier()];
+        encoded.extend_from_slice(&address.to_be_bytes()[8 - usize::from(self.address_bytes)..]);
+        encoded.extend_from_slice(&size.to_be_bytes()[8 - usize::from(self.size_bytes)..]);
+        Ok(encoded)
+    }
+}
+
+/// Number of bytes needed to hold the value, at least one
+fn min_bytes(value: u64) -> u8 {
+    (8 - (value.leading_zeros() / 8) as u8).max(1)
+}
+
+byte_enum! {
+    /// Sub-function (reportType) of ReadDTCInformation
+    DtcReportType {
+        NumberOfDtcByStatusMask = 0x01, "reportNumberOfDTCByStatusMask";
+        DtcByStatusMask = 0x02, "reportDTCByStatusMask";
+        DtcSnapshotIdentification = 0x03, "reportDTCSnapshotIdentification";
+        DtcSnapshotRecordByDtcNumber = 0x04, "reportDTCSnapshotRecordByDTCNumber";
+        DtcStoredDataByRecordNumber = 0x05, "reportDTCStoredDataByRecordNumber";
+        DtcExtDataRecordByDtcNumber = 0x06, "reportDTCExtDataRecordByDTCNumber";
+        NumberOfDtcBySeverityMaskRecord = 0x07, "reportNumberOfDTCBySeverityMaskRecord";
+        DtcBySeverityMaskRecord = 0x08, "reportDTCBySeverityMaskRecord";
+        SeverityInformationOfDtc = 0x09, "reportSeverityInformationOfDTC";
+        SupportedDtc = 0x0A, "reportSupportedDTC";
+        FirstTestFailedDtc = 0x0B, "reportFirstTestFailedDTC";
+        FirstConfirmedDtc = 0x0C, "reportFirstConfirmedDTC";
+        MostRecentTestFailedDtc = 0x0D, "reportMostRecentTestFailedDTC";
+        MostRecentConfirmedDtc = 0x0E, "reportMostRecentConfirmedDTC";
+        DtcFaultDetectionCounter = 0x14, "reportDTCFaultDetectionCounter";
+        DtcWithPermanentStatus = 0x15, "reportDTCWithPermanentStatus";
+    }
+}
+
+/// dataFormatIdentifier of the transfer services
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DataFormat {
+    /// Compression method, 0 for none
+    pub compression: u8,
+    /// Encryption method, 0 for none
+    pub encryption: u8,
+}
+
+impl DataFormat {
+    /// Neither compressed nor encrypted
+    pub const PLAIN: DataFormat = DataFormat::new(0, 0);
+
+    pub const fn new(compression: u8, encryption: u8) -> Self {
+        Self {
+            compression: compression & 0x0F,
+            encryption: encryption & 0x0F,
+        }
+    }
+
+    /// The identifier byte, compression method in the high nibble
+    pub fn identifier(&self) -> u8 {
+        (self.compression << 4) | self.encryption
+    }
+}
+
+impl From<u8> for DataFormat {
+    fn from(identifier: u8) -> Self {
+        Self::new(identifier >> 4, identifier & 0x0F)
+    }
+}
+
+byte_enum! {
+    /// modeOfOperation of RequestFileTransfer
+    FileOperation {
+        AddFile = 0x01, "AddFile";
+        DeleteFile = 0x02, "DeleteFile";
+        ReplaceFile = 0x03, "ReplaceFile";
+        ReadFile = 0x04, "ReadFile";
+        ReadDir = 0x05, "ReadDir";
+        ResumeFile = 0x06, "ResumeFile";
+    }
+}
@@ -0,0 +1,217 @@
+use super::service::{SUPPRESS_POSITIVE_RESPONSE, TESTER_PRESENT};
+use super::{DiagnosticSession, Error, SessionParameters, UdsClient};
+use crate::IsoTpTransport;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// S3server, a non-default session ends after this time without a request
+pub const S3_SERVER: Duration = Duration::from_secs(5);
+
+/// Default TesterPresent interval, S3client of ISO 14229-2
+pub const DEFAULT_TESTER_PRESENT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Keeps a non-default diagnostic session alive
+///
+/// A background task sends TesterPresent with suppressed positive response (`3E 80`)
+/// every keepalive interval, see [UdsClient::keep_alive].
+///
+/// Dropping the guard stops the task and returns the ECU to the default session in
+/// the background, use [SessionGuard::close] to wait for that.
+pub struct SessionGuard<T: IsoTpTransport + 'static> {
+    client: Arc<UdsClient<T>>,
+    parameters: SessionParameters,
+    keepalive: JoinHandle<Result<(), Error>>,
+    closed: bool,
+}
+
+impl<T: IsoTpTransport> UdsClient<T> {
+    /// Send TesterPresent with suppressed positive response (`3E 80`) every `interval`
+    ///
+    /// Ticks while `active` returns false are skipped, as are ticks falling into a
+    /// running request, the request itself keeps the session alive. So the ECU sees
+    /// a request at least every `interval`, which must be shorter than [S3_SERVER].
+    /// Runs until the transport fails.
+    pub async fn keep_alive(
+        &self,
+        interval: Duration,
+        mut active: impl FnMut() -> bool,
+    ) -> Result<(), Error> {
+        check_keepalive_interval(interval)?;
+        let start = tokio::time::Instant::now() + interval;
+        let mut ticks = tokio::time::interval_at(start, interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            if active() {
+                self.try_send_only(&[TESTER_PRESENT, SUPPRESS_POSITIVE_RESPONSE])
+                    .await?;
+            }
+        }
+    }
+}
+
+impl<T: IsoTpTransport + 'static> UdsClient<T> {
+    /// Enter a diagnostic session and keep it alive until the guard is dropped
+    ///
+    /// The interval must be shorter than S3server ([S3_SERVER]), usually
+    /// [DEFAULT_TESTER_PRESENT_INTERVAL] is used.
+    pub async fn enter_session(
+        self: &Arc<Self>,
+        session: DiagnosticSession,
+        interval: Duration,
+    ) -> Result<SessionGuard<T>, Error> {
+        check_keepalive_interval(interval)?;
+        let parameters = self.diagnostic_session_control(session).await?;
+        let client = Arc::clone(self);
+        let keepalive = tokio::spawn(async move { client.keep_alive(interval, || true).await });
+        Ok(SessionGuard {
+            client: Arc::clone(self),
+            parameters,
+            keepalive,
+            closed: false,
+        })
+    }
+}
+
+fn check_keepalive_interval(interval: Duration) -> Result<(), Error> {
+    if interval.is_zero() || interval >= S3_SERVER {
+        return Err(Error::invalid_request(format!(
+            "TesterPresent interval {interval:?} not between zero and S3server"
+        )));
+    }
+    Ok(())
+}
+
+impl<T: IsoTpTransport + 'static> SessionGuard<T> {
+    /// Timing reported by the ECU when entering the session
+    pub fn parameters(&self) -> SessionParameters {
+        self.parameters
+    }
+
+    pub fn client(&self) -> &Arc<UdsClient<T>> {
+        &self.client
+    }
+
+    /// Whether the keepalive task is still running
+    ///
+    /// The task ends when the transport fails, [SessionGuard::close] reports the error.
+    pub fn is_alive(&self) -> bool {
+        !self.keepalive.is_finished()
+    }
+
+    /// Stop the keepalive and return to the default session
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.closed = true;
+        self.keepalive.abort();
+        let keepalive = (&mut self.keepalive).await;
+        self.client
+            .diagnostic_session_control(DiagnosticSession::Default)
+            .await?;
+        match keepalive {
+            Ok(Err(err)) => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<T: IsoTpTransport + 'static> Drop for SessionGuard<T> {
+    fn drop(&mut self) {
+        self.keepalive.abort();
+        if self.closed {
+            return;
+        }
+        let client = Arc::clone(&self.client);
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                // ignore result, the session ends with S3 anyway
+                client
+                    .diagnostic_session_control(DiagnosticSession::Default)
+                    .await
+                    .ok();
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::tests::MockTransport;
+
+    const KEEPALIVE: [u8; 2] = [TESTER_PRESENT, SUPPRESS_POSITIVE_RESPONSE];
+
+    fn client() -> Arc<UdsClient<MockTransport>> {
+        Arc::new(UdsClient::new(MockTransport::new(
+            |request| match request {
+                [0x10, session] => vec![vec![0x50, *session]],
+                _ => vec![],
+            },
+        )))
+    }
+
+    fn keepalives(client: &UdsClient<MockTransport>) -> usize {
+        let requests = client.transport().requests();
+        requests
+            .iter()
+            .filter(|request| **request == KEEPALIVE)
+            .count()
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_intervals() {
+        let client = client();
+        for interval in [Duration::ZERO, S3_SERVER] {
+            let error = client
+                .enter_session(DiagnosticSession::Extended, interval)
+                .await
+                .err()
+                .unwrap();
+            assert!(matches!(error, Error::InvalidRequest { .. }), "{error:?}");
+        }
+        assert!(client.transport().requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sends_tester_present_every_interval() {
+        let client = client();
+        let guard = client
+            .enter_session(DiagnosticSession::Extended, Duration::from_millis(20))
+            .await
+            .unwrap();
+        assert_eq!(guard.parameters().session, DiagnosticSession::Extended);
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        assert!(guard.is_alive());
+        let sent = keepalives(&client);
+        assert!((3..=6).contains(&sent), "{sent} TesterPresent");
+
+        guard.close().await.unwrap();
+        let requests = client.transport().requests();
+        assert_eq!(requests.last().unwrap(), &[0x10, 0x01]);
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(client.transport().requests().len(), requests.len());
+    }
+
+    #[tokio::test]
+    async fn drop_returns_to_default_session() {
+        let client = client();
+        let guard = client
+            .enter_session(
+                DiagnosticSession::Programming,
+                DEFAULT_TESTER_PRESENT_INTERVAL,
+            )
+            .await
+            .unwrap();
+        drop(guard);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(client.transport().requests(), [[0x10, 0x02], [0x10, 0x01]]);
+    }
+
+    #[tokio::test]
+    async fn inactive_ticks_are_skipped() {
+        let client = client();
+        let keepalive = client.keep_alive(Duration::from_millis(10), || false);
+        let _ = tokio::time::timeout(Duration::from_millis(50), keepalive).await;
+        assert_eq!(keepalives(&client), 0);
+    }
+}
@@ -0,0 +1,832 @@
+//! ECU simulation described by a TOML or YAML model
+//!
+//! A [SimulationModel] lists ECUs with their ids, ISO-TP options, DIDs, DTCs,
+//! SecurityAccess levels and canned responses. Each ECU becomes a
+//! [UdsServer], all of them are served at the same time.
+//! The `isotp-ecu-sim` binary runs a model file:
+//!
+//! ```toml
+//! interface = "vcan0"
+//!
+//! [[ecu]]
+//! name = "engine"
+//! request_id = "7E0"
+//! response_id = "7E8"
+//! isotp = { padding = 0xCC, block_size = 8, st_min = 5 }
+//! s3_ms = 5000
+//!
+//! [[ecu.did]]
+//! did = 0xF190
+//! ascii = "WVWZZZ1JZXW000001"
+//!
+//! [[ecu.did]]
+//! did = 0x0D01
+//! hex = "00 64"
+//! writable = true
+//! write_sessions = [3]
+//! write_security_level = 1
+//!
+//! [[ecu.dtc]]
+//! code = "P0301-00"
+//! status = 0x2F
+//!
+//! [[ecu.security]]
+//! level = 1
+//! key_xor = "5A5A5A5A"
+//!
+//! [[ecu.response]]
+//! request = "31 01 FF 00"
+//! response = "71 01 FF 00 00"
+//! delay_ms = 200
+//! sessions = [3]
+//!
+//! [[ecu]]
+//! name = "gateway"
+//! interface = "vcan1"
+//! request_id = "18DA10F1"
+//! response_id = "18DAF110"
+//! isotp = { fd = true, tx_dl = 64 }
+//! ```
+//!
+//! * DIDs are given as `hex` or `ascii`, they are readable in `sessions` (all if
+//!   empty) and written to with WriteDataByIdentifier if `writable`.
+//! * DTCs are reported by ReadDTCInformation 0x01, 0x02 and 0x0A and removed by
+//!   ClearDiagnosticInformation.
+//! * The key of a SecurityAccess level is the seed XOR the repeated `key_xor` mask.
+//! * A canned response answers every request starting with its `request` bytes, the
+//!   first matching one in the file wins. Negative responses are given as
+//!   `7F <SID> <NRC>`. Canned responses take over the whole service of their SID,
+//!   including services the server implements itself.
+
+use super::server::{Access, HandlerResult, ServerConfig, UdsServer};
+use super::service::{
+    CLEAR_DIAGNOSTIC_INFORMATION, NEGATIVE_RESPONSE, POSITIVE_RESPONSE_OFFSET, READ_DTC_INFORMATION,
+};
+use super::{
+    DiagnosticSession, Dtc, DtcFormat, DtcRecord, DtcReportType, DtcStatus, NegativeResponseCode,
+};
+use crate::frame::CANFD_DATA_LENGTHS;
+use crate::text::{parse_hex, parse_id};
+use crate::{
+    FlowControlOptions, Id, IsoTpBehaviour, IsoTpOptions, IsoTpSocket, IsoTpTransport,
+    LinkLayerOptions, TxFlags,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// ECUs served together
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimulationModel {
+    /// CAN device of ECUs without their own
+    #[serde(default = "default_interface")]
+    pub interface: String,
+    #[serde(default, rename = "ecu")]
+    pub ecus: Vec<EcuModel>,
+}
+
+/// One simulated ECU
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EcuModel {
+    pub name: String,
+    /// CAN device, the one of the model if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// Id the tester sends requests on, 3 hex digits for 11 bit and 8 for 29 bit ids
+    pub request_id: String,
+    /// Id the responses are sent on
+    pub response_id: String,
+    #[serde(default)]
+    pub isotp: IsoTpModel,
+    /// Session types DiagnosticSessionControl accepts, 1, 2 and 3 if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sessions: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p2_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p2_star_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_ms: Option<u64>,
+    #[serde(default, rename = "did")]
+    pub dids: Vec<DidModel>,
+    /// DTCStatusAvailabilityMask reported with the DTCs
+    #[serde(default = "default_status_availability")]
+    pub dtc_status_availability: u8,
+    #[serde(default, rename = "dtc")]
+    pub dtcs: Vec<DtcModel>,
+    #[serde(default, rename = "security")]
+    pub security_levels: Vec<SecurityModel>,
+    #[serde(default, rename = "response")]
+    pub responses: Vec<CannedResponse>,
+}
+
+/// Addressing, padding, flow control and link layer of an ECU
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IsoTpModel {
+    /// Address in front of every frame sent (extended addressing)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ext_address: Option<u8>,
+    /// Address expected in front of every frame received
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_ext_address: Option<u8>,
+    /// Padding byte of frames sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<u8>,
+    /// Block size sent in flow control frames
+    #[serde(default)]
+    pub block_size: u8,
+    /// STmin sent in flow control frames, as encoded in the frame
+    #[serde(default)]
+    pub st_min: u8,
+    /// Send CAN FD frames
+    #[serde(default)]
+    pub fd: bool,
+    /// Payload length of CAN FD frames sent, one of 8, 12, 16, 20, 24, 32, 48 and 64,
+    /// 64 if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_dl: Option<u8>,
+    /// Bit rate switch of CAN FD frames sent
+    #[serde(default)]
+    pub brs: bool,
+}
+
+/// DID with a stored value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DidModel {
+    pub did: u16,
+    /// Value in hex, spaces are allowed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hex: Option<String>,
+    /// Value as ASCII text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ascii: Option<String>,
+    /// Sessions the DID is readable in, all if empty
+    #[serde(default)]
+    pub sessions: Vec<u8>,
+    /// Level which has to be unlocked for reading
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security_level: Option<u8>,
+    #[serde(default)]
+    pub writable: bool,
+    /// Sessions the DID is writable in, all if empty
+    #[serde(default)]
+    pub write_sessions: Vec<u8>,
+    /// Level which has to be unlocked for writing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_security_level: Option<u8>,
+}
+
+/// Stored DTC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DtcModel {
+    /// Code like `P0301` or `P0301-1A` with failure type
+    pub code: String,
+    pub status: u8,
+}
+
+/// SecurityAccess level
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecurityModel {
+    /// requestSeed level (odd number)
+    pub level: u8,
+    #[serde(default = "default_seed_length")]
+    pub seed_length: usize,
+    /// Hex mask XORed with the seed to get the key, the key is the seed if empty
+    #[serde(default)]
+    pub key_xor: String,
+}
+
+/// Fixed response to requests starting with given bytes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CannedResponse {
+    /// Leading bytes of the request in hex
+    pub request: String,
+    /// Whole response in hex, `7F <SID> <NRC>` for a negative response
+    pub response: String,
+    /// Time before the response is sent, response pending is sent meanwhile
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// Sessions the response is given in, all if empty
+    #[serde(default)]
+    pub sessions: Vec<u8>,
+}
+
+impl SimulationModel {
+    /// Parse a model in TOML
+    pub fn from_toml(text: &str) -> io::Result<Self> {
+        let model: Self = toml::from_str(text).map_err(|err| invalid_data(err.to_string()))?;
+        model.validate()?;
+        Ok(model)
+    }
+
+    /// Parse a model in YAML
+    pub fn from_yaml(text: &str) -> io::Result<Self> {
+        let model: Self =
+            serde_yaml::from_str(text).map_err(|err| invalid_data(err.to_string()))?;
+        model.validate()?;
+        Ok(model)
+    }
+
+    /// Load a model file, `.yaml`/`.yml` are read as YAML and all others as TOML
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&text),
+            _ => Self::from_toml(&text),
+        }
+    }
+
+    /// Check everything that can be checked without opening sockets
+    pub fn validate(&self) -> io::Result<()> {
+        if self.ecus.is_empty() {
+            return Err(invalid_data("no ECU in the model".to_string()));
+        }
+        for ecu in &self.ecus {
+            ecu.server(NoTransport)
+                .map_err(|err| invalid_data(format!("ECU {}: {err}", ecu.name)))?;
+        }
+        Ok(())
+    }
+
+    /// Open the sockets of all ECUs, `interface` replaces the CAN devices of the model
+    pub fn open(&self, interface: Option<&str>) -> io::Result<Vec<UdsServer<IsoTpSocket>>> {
+        self.ecus
+            .iter()
+            .map(|ecu| {
+                let interface = interface
+                    .or(ecu.interface.as_deref())
+                    .unwrap_or(&self.interface);
+                ecu.open(interface)
+                    .map_err(|err| io::Error::new(err.kind(), format!("ECU {}: {err}", ecu.name)))
+            })
+            .collect()
+    }
+
+    /// Serve all ECUs until a socket fails
+    pub async fn serve(&self, interface: Option<&str>) -> io::Result<()> {
+        let servers = self.open(interface)?;
+        futures::future::try_join_all(servers.iter().map(UdsServer::serve)).await?;
+        Ok(())
+    }
+}
+
+impl EcuModel {
+    pub fn request_id(&self) -> io::Result<Id> {
+        parse_id(&self.request_id)
+    }
+
+    pub fn response_id(&self) -> io::Result<Id> {
+        parse_id(&self.response_id)
+    }
+
+    /// Open the socket of the ECU on a named CAN device and set up its server
+    pub fn open(&self, interface: &str) -> io::Result<UdsServer<IsoTpSocket>> {
+        let socket = IsoTpSocket::open_with_opts(
+            interface,
+            self.request_id()?,
+            self.response_id()?,
+            Some(self.isotp.isotp_options()),
+            Some(self.isotp.flow_control_options()),
+            Some(self.isotp.link_layer_options()),
+        )
+        .map_err(|err| io::Error::other(format!("{interface}: {err}")))?;
+        self.server(socket)
+    }
+
+    /// Server answering on `transport` as described by the model
+    pub fn server<T: IsoTpTransport>(&self, transport: T) -> io::Result<UdsServer<T>> {
+        self.request_id()?;
+        self.response_id()?;
+        self.isotp.validate()?;
+        let mut server = UdsServer::with_config(transport, self.server_config());
+
+        for did in &self.dids {
+            let value = match (&did.hex, &did.ascii) {
+                (Some(hex), None) => parse_hex(hex)?,
+                (None, Some(ascii)) => ascii.as_bytes().to_vec(),
+                _ => {
+                    return Err(invalid_data(format!(
+                        "DID 0x{:04X} needs either hex or ascii",
+                        did.did
+                    )))
+                }
+            };
+            let read = access(&did.sessions, did.security_level);
+            let write = did
+                .writable
+                .then(|| access(&did.write_sessions, did.write_security_level));
+            server.add_did(did.did, value, read, write);
+        }
+
+        let mut records = Vec::new();
+        for dtc in &self.dtcs {
+            let code: Dtc = dtc
+                .code
+                .parse()
+                .map_err(|err| invalid_data(format!("DTC {}: {err}", dtc.code)))?;
+            records.push(DtcRecord {
+                dtc: code,
+                status: DtcStatus(dtc.status),
+            });
+        }
+        self.add_dtc_services(&mut server, records);
+
+        for security in &self.security_levels {
+            if !(0x01..=0x7D).contains(&security.level) || security.level.is_multiple_of(2) {
+                return Err(invalid_data(format!(
+                    "0x{:02X} is not a requestSeed level",
+                    security.level
+                )));
+            }
+            let mask = parse_hex(&security.key_xor)?;
+            server.add_security_level(
+                security.level,
+                security.seed_length,
+                move |_level: u8, seed: &[u8]| {
+                    seed.iter()
+                        .zip(mask.iter().cycle().chain(std::iter::repeat(&0)))
+                        .map(|(byte, mask)| byte ^ mask)
+                        .collect()
+                },
+            );
+        }
+
+        let mut canned: Vec<(u8, Vec<Canned>)> = Vec::new();
+        for response in &self.responses {
+            let canned_response = Canned::parse(response)?;
+            let sid = canned_response.request[0];
+            match canned.iter_mut().find(|(other, _)| *other == sid) {
+                Some((_, responses)) => responses.push(canned_response),
+                None => canned.push((sid, vec![canned_response])),
+            }
+        }
+        for (sid, responses) in canned {
+            server.on_service(sid, Access::default(), move |request| {
+                let (delay, response) = canned_response(&responses, &request.data, request.session);
+                async move {
+                    tokio::time::sleep(delay).await;
+                    response
+                }
+            });
+        }
+        Ok(server)
+    }
+
+    fn server_config(&self) -> ServerConfig {
+        let mut config = ServerConfig::default();
+        if let Some(sessions) = &self.sessions {
+            config.sessions = sessions.iter().map(|session| (*session).into()).collect();
+        }
+        if let Some(p2) = self.p2_ms {
+            config.p2 = Duration::from_millis(p2);
+        }
+        if let Some(p2_star) = self.p2_star_ms {
+            config.p2_star = Duration::from_millis(p2_star);
+        }
+        if let Some(s3) = self.s3_ms {
+            config.s3 = Duration::from_millis(s3);
+        }
+        config
+    }
+
+    /// ReadDTCInformation and ClearDiagnosticInformation on a shared DTC list
+    fn add_dtc_services<T: IsoTpTransport>(
+        &self,
+        server: &mut UdsServer<T>,
+        records: Vec<DtcRecord>,
+    ) {
+        let availability = self.dtc_status_availability;
+        let records = Arc::new(Mutex::new(records));
+        let dtcs = Arc::clone(&records);
+        server.on_service(READ_DTC_INFORMATION, Access::default(), move |request| {
+            let result = read_dtc_information(&dtcs.lock().unwrap(), availability, &request.data);
+            std::future::ready(result)
+        });
+        server.on_service(
+            CLEAR_DIAGNOSTIC_INFORMATION,
+            Access::default(),
+            move |request| {
+                let result =
+                    clear_diagnostic_information(&mut records.lock().unwrap(), &request.data);
+                std::future::ready(result)
+            },
+        );
+    }
+}
+
+impl IsoTpModel {
+    /// Addressing and padding options
+    pub fn isotp_options(&self) -> IsoTpOptions {
+        let mut options = IsoTpOptions::default();
+        let mut flags = IsoTpBehaviour::empty();
+        if let Some(address) = self.ext_address {
+            flags |= IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR;
+            options.set_ext_address(address);
+        }
+        if let Some(address) = self.rx_ext_address {
+            flags |= IsoTpBehaviour::CAN_ISOTP_EXTEND_ADDR | IsoTpBehaviour::CAN_ISOTP_RX_EXT_ADDR;
+            options.set_rx_ext_address(address);
+        }
+        if let Some(padding) = self.padding {
+            flags |= IsoTpBehaviour::CAN_ISOTP_TX_PADDING;
+            options.set_txpad_content(padding);
+        }
+        options.set_flags(flags);
+        options
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        match self.tx_dl {
+            Some(tx_dl) if !self.fd => Err(invalid_data(format!(
+                "tx_dl {tx_dl} needs CAN FD, set fd = true"
+            ))),
+            Some(tx_dl) if !CANFD_DATA_LENGTHS.contains(&usize::from(tx_dl)) => Err(invalid_data(
+                format!("tx_dl {tx_dl} is not a CAN FD data length"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn flow_control_options(&self) -> FlowControlOptions {
+        FlowControlOptions::new(self.block_size, self.st_min, 0)
+    }
+
+    /// CAN FD link layer if `fd` is set, kernel defaults otherwise
+    pub fn link_layer_options(&self) -> LinkLayerOptions {
+        if !self.fd {
+            return LinkLayerOptions::default();
+        }
+        let flags = if self.brs {
+            TxFlags::CANFD_BRS
+        } else {
+            TxFlags::empty()
+        };
+        LinkLayerOptions::can_fd(self.tx_dl.unwrap_or(64), flags)
+    }
+}
+
+/// Parsed [CannedResponse]
+struct Canned {
+    request: Vec<u8>,
+    response: HandlerResult,
+    delay: Duration,
+    sessions: Vec<DiagnosticSession>,
+}
+
+impl Canned {
+    fn parse(model: &CannedResponse) -> io::Result<Self> {
+        let request = parse_hex(&model.request)?;
+        let Some(&sid) = request.first() else {
+            return Err(invalid_data(
+                "canned response with empty request".to_string(),
+            ));
+        };
+        let response = match parse_hex(&model.response)?.as_slice() {
+            [NEGATIVE_RESPONSE, service, code] if *service == sid => Err((*code).into()),
+            [response_sid, data @ ..]
+                if *response_sid == sid.wrapping_add(POSITIVE_RESPONSE_OFFSET) =>
+            {
+                Ok(data.to_vec())
+            }
+            _ => {
+                return Err(invalid_data(format!(
+                    "response {:?} does not answer service 0x{sid:02X}",
+                    model.response
+                )))
+            }
+        };
+        Ok(Self {
+            request,
+            response,
+            delay: Duration::from_millis(model.delay_ms),
+            sessions: model
+                .sessions
+                .iter()
+                .map(|session| (*session).into())
+                .collect(),
+        })
+    }
+}
+
+/// First canned response matching the request in the session and its delay
+fn canned_response(
+    responses: &[Canned],
+    request: &[u8],
+    session: DiagnosticSession,
+) -> (Duration, HandlerResult) {
+    let matching: Vec<&Canned> = responses
+        .iter()
+        .filter(|canned| request.starts_with(&canned.request))
+        .collect();
+    if matching.is_empty() {
+        return (Duration::ZERO, Err(NegativeResponseCode::RequestOutOfRange));
+    }
+    match matching
+        .into_iter()
+        .find(|canned| canned.sessions.is_empty() || canned.sessions.contains(&session))
+    {
+        Some(canned) => (canned.delay, canned.response.clone()),
+        None => (
+            Duration::ZERO,
+            Err(NegativeResponseCode::ServiceNotSupportedInActiveSession),
+        ),
+    }
+}
+
+/// reportNumberOfDTCByStatusMask, reportDTCByStatusMask and reportSupportedDTC
+fn read_dtc_information(records: &[DtcRecord], availability: u8, request: &[u8]) -> HandlerResult {
+    let Some(&report_type) = request.get(1) else {
+        return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat);
+    };
+    let mut response = vec![report_type, availability];
+    match (DtcReportType::from(report_type), &request[2..]) {
+        (DtcReportType::NumberOfDtcByStatusMask, [mask]) => {
+            let count = matching_dtcs(records, *mask & availability).count() as u16;
+            response.push(DtcFormat::Iso14229_1.into());
+            response.extend_from_slice(&count.to_be_bytes());
+        }
+        (DtcReportType::DtcByStatusMask, [mask]) => {
+            for record in matching_dtcs(records, *mask & availability) {
+                response.extend_from_slice(&record.dtc.to_bytes());
+                response.push(record.status.0 & availability);
+            }
+        }
+        (DtcReportType::SupportedDtc, []) => {
+            for record in records {
+                response.extend_from_slice(&record.dtc.to_bytes());
+                response.push(record.status.0 & availability);
+            }
+        }
+        (
+            DtcReportType::NumberOfDtcByStatusMask
+            | DtcReportType::DtcByStatusMask
+            | DtcReportType::SupportedDtc,
+            _,
+        ) => return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat),
+        _ => return Err(NegativeResponseCode::SubFunctionNotSupported),
+    }
+    Ok(response)
+}
+
+fn matching_dtcs(records: &[DtcRecord], mask: u8) -> impl Iterator<Item = &DtcRecord> {
+    records
+        .iter()
+        .filter(move |record| record.status.0 & mask != 0)
+}
+
+/// Clear all DTCs or the one given
+fn clear_diagnostic_information(records: &mut Vec<DtcRecord>, request: &[u8]) -> HandlerResult {
+    let [_, high, middle, low] = request else {
+        return Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat);
+    };
+    let group = Dtc::from_bytes([*high, *middle, *low]);
+    if group == Dtc::ALL {
+        records.clear();
+    } else if records.iter().any(|record| record.dtc == group) {
+        records.retain(|record| record.dtc != group);
+    } else {
+        return Err(NegativeResponseCode::RequestOutOfRange);
+    }
+    Ok(Vec::new())
+}
+
+fn access(sessions: &[u8], security_level: Option<u8>) -> Access {
+    Access {
+        sessions: sessions.iter().map(|session| (*session).into()).collect(),
+        security_level,
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn default_interface() -> String {
+    "vcan0".to_string()
+}
+
+fn default_status_availability() -> u8 {
+    0xFF
+}
+
+fn default_seed_length() -> usize {
+    4
+}
+
+/// Transport of [SimulationModel::validate], servers built on it are never served
+struct NoTransport;
+
+impl IsoTpTransport for NoTransport {
+    fn rx_id(&self) -> Id {
+        crate::id_from_raw(0)
+    }
+
+    fn tx_id(&self) -> Id {
+        crate::id_from_raw(0)
+    }
+
+    async fn send(&self, _pdu: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    async fn recv(&self) -> io::Result<Vec<u8>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// TOML example of the module documentation
+    fn documented_example() -> String {
+        include_str!("sim.rs")
+            .lines()
+            .filter_map(|line| line.strip_prefix("//!"))
+            .map(|line| line.strip_prefix(' ').unwrap_or(line))
+            .skip_while(|line| *line != "```toml")
+            .skip(1)
+            .take_while(|line| *line != "```")
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn ecu(isotp: &str, security: &str) -> String {
+        format!(
+            "[[ecu]]\nname = \"engine\"\nrequest_id = \"7E0\"\nresponse_id = \"7E8\"\n\
+             isotp = {{ {isotp} }}\n{security}"
+        )
+    }
+
+    #[test]
+    fn documented_example_loads() {
+        let model = SimulationModel::from_toml(&documented_example()).unwrap();
+        assert_eq!(model.interface, "vcan0");
+        let names: Vec<&str> = model.ecus.iter().map(|ecu| ecu.name.as_str()).collect();
+        assert_eq!(names, ["engine", "gateway"]);
+        let engine = &model.ecus[0];
+        assert_eq!(engine.request_id().unwrap(), crate::id_from_raw(0x7E0));
+        assert_eq!(engine.isotp.padding, Some(0xCC));
+        assert_eq!(engine.s3_ms, Some(5000));
+        assert_eq!(engine.dids.len(), 2);
+        assert!(engine.dids[1].writable);
+        assert_eq!(engine.dtcs[0].status, 0x2F);
+        assert_eq!(engine.security_levels[0].seed_length, 4);
+        assert_eq!(engine.responses[0].delay_ms, 200);
+        let gateway = &model.ecus[1];
+        assert_eq!(gateway.interface.as_deref(), Some("vcan1"));
+        assert_eq!(
+            gateway.response_id().unwrap(),
+            crate::id_from_raw(0x18DA_F110 | crate::EFF_FLAG)
+        );
+        assert!(gateway.isotp.fd);
+        assert_eq!(gateway.isotp.tx_dl, Some(64));
+
+        let yaml = serde_yaml::to_string(&model).unwrap();
+        assert_eq!(SimulationModel::from_yaml(&yaml).unwrap(), model);
+    }
+
+    #[test]
+    fn security_levels() {
+        for level in [0x01, 0x03, 0x41, 0x7D] {
+            let text = ecu("", &format!("[[ecu.security]]\nlevel = {level}\n"));
+            assert!(SimulationModel::from_toml(&text).is_ok(), "{level}");
+        }
+        for level in [0x00, 0x02, 0x7E, 0x7F, 0x81, 0xFF] {
+            let text = ecu("", &format!("[[ecu.security]]\nlevel = {level}\n"));
+            assert!(SimulationModel::from_toml(&text).is_err(), "{level}");
+        }
+    }
+
+    #[test]
+    fn can_fd_data_lengths() {
+        for tx_dl in CANFD_DATA_LENGTHS {
+            let text = ecu(&format!("fd = true, tx_dl = {tx_dl}"), "");
+            assert!(SimulationModel::from_toml(&text).is_ok(), "{tx_dl}");
+        }
+        assert!(SimulationModel::from_toml(&ecu("fd = true", "")).is_ok());
+        for isotp in [
+            "fd = true, tx_dl = 0",
+            "fd = true, tx_dl = 13",
+            "fd = true, tx_dl = 65",
+            "fd = true, tx_dl = 255",
+            "tx_dl = 64",
+        ] {
+            assert!(
+                SimulationModel::from_toml(&ecu(isotp, "")).is_err(),
+                "{isotp}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_models() {
+        assert!(SimulationModel::from_toml("interface = \"vcan0\"").is_err());
+        let invalid = [
+            ("request_id = \"7E0\"", "request_id = \"7G0\""),
+            ("isotp = {  }", "isotp = {  }\n[[ecu.did]]\ndid = 1\n"),
+            (
+                "isotp = {  }",
+                "isotp = {  }\n[[ecu.dtc]]\ncode = \"X0301\"\nstatus = 1\n",
+            ),
+            (
+                "isotp = {  }",
+                "isotp = {  }\n[[ecu.response]]\nrequest = \"22\"\nresponse = \"63\"\n",
+            ),
+        ];
+        for (from, to) in invalid {
+            let text = ecu("", "").replace(from, to);
+            assert!(SimulationModel::from_toml(&text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn canned_responses() {
+        let model = |request: &str, response: &str, sessions: Vec<u8>| CannedResponse {
+            request: request.to_string(),
+            response: response.to_string(),
+            delay_ms: 0,
+            sessions,
+        };
+        let responses = [
+            model("31 01 FF 00", "71 01 FF 00 00", vec![3]),
+            model("31 01", "7F 31 22", vec![]),
+        ]
+        .iter()
+        .map(Canned::parse)
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+        let extended = DiagnosticSession::from(3);
+        let default = DiagnosticSession::from(1);
+        let cases = [
+            (
+                &[0x31, 0x01, 0xFF, 0x00][..],
+                extended,
+                Ok(vec![0x01, 0xFF, 0x00, 0x00]),
+            ),
+            (
+                &[0x31, 0x01, 0xFF, 0x00],
+                default,
+                Err(NegativeResponseCode::ConditionsNotCorrect),
+            ),
+            (
+                &[0x31, 0x02],
+                default,
+                Err(NegativeResponseCode::RequestOutOfRange),
+            ),
+        ];
+        for (request, session, response) in cases {
+            assert_eq!(canned_response(&responses, request, session).1, response);
+        }
+    }
+
+    #[test]
+    fn dtc_services() {
+        let mut records = vec![
+            DtcRecord {
+                dtc: "P0301-00".parse().unwrap(),
+                status: DtcStatus(0x2F),
+            },
+            DtcRecord {
+                dtc: "U0100-00".parse().unwrap(),
+                status: DtcStatus(0x08),
+            },
+        ];
+        assert_eq!(
+            read_dtc_information(&records, 0x7F, &[0x19, 0x01, 0x01]),
+            Ok(vec![0x01, 0x7F, 0x01, 0x00, 0x01])
+        );
+        assert_eq!(
+            read_dtc_information(&records, 0xFF, &[0x19, 0x02, 0x08]),
+            Ok(vec![
+                0x02, 0xFF, 0x03, 0x01, 0x00, 0x2F, 0xC1, 0x00, 0x00, 0x08
+            ])
+        );
+        assert_eq!(
+            read_dtc_information(&records, 0xFF, &[0x19, 0x02]),
+            Err(NegativeResponseCode::IncorrectMessageLengthOrInvalidFormat)
+        );
+        assert_eq!(
+            read_dtc_information(&records, 0xFF, &[0x19, 0x42]),
+            Err(NegativeResponseCode::SubFunctionNotSupported)
+        );
+        assert_eq!(
+            clear_diagnostic_information(&mut records, &[0x14, 0x03, 0x01, 0x00]),
+            Ok(vec![])
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            clear_diagnostic_information(&mut records, &[0x14, 0x03, 0x01, 0x00]),
+            Err(NegativeResponseCode::RequestOutOfRange)
+        );
+        assert_eq!(
+            clear_diagnostic_information(&mut records, &[0x14, 0xFF, 0xFF, 0xFF]),
+            Ok(vec![])
+        );
+        assert!(records.is_empty());
+    }
+}
@@ -0,0 +1,545 @@
+//! RequestUpload (0x35) and RequestFileTransfer (0x38)
+//!
+//! The data is streamed block by block between TransferData and any [AsyncRead] or
+//! [AsyncWrite], so that only one block is held in memory at a time. Compressed or
+//! encrypted data according to the [DataFormat] is passed through as it is.
+//!
+//! ```rust,no_run
+//! use tokio_socketcan_isotp::uds::{DataFormat, UdsClient};
+//! # async fn run(client: UdsClient) -> Result<(), tokio_socketcan_isotp::uds::Error> {
+//! let mut dump = Vec::new();
+//! client
+//!     .upload(0x0800_0000, 0x4_0000, DataFormat::PLAIN, &mut dump)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::flash::{block_data_size, parse_max_block_length, parse_unsigned};
+use super::service::{
+    AddressAndLengthFormat, DataFormat, FileOperation, REQUEST_FILE_TRANSFER, REQUEST_UPLOAD,
+};
+use super::{Error, NegativeResponseCode, UdsClient};
+use crate::IsoTpTransport;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of a file, the compressed size is the number of bytes transferred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSize {
+    pub uncompressed: u64,
+    pub compressed: u64,
+}
+
+impl FileSize {
+    /// Size of an uncompressed file
+    pub fn plain(size: u64) -> Self {
+        Self {
+            uncompressed: size,
+            compressed: size,
+        }
+    }
+}
+
+/// Positive response of RequestFileTransfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileTransferResponse {
+    pub operation: FileOperation,
+    /// maxNumberOfBlockLength of the following TransferData
+    pub max_block_length: Option<usize>,
+    pub data_format: Option<DataFormat>,
+    /// Size of the file for ReadFile, for ReadDir both sizes hold the length of the
+    /// directory information
+    pub file_size: Option<FileSize>,
+    /// filePosition to continue at for ResumeFile
+    pub file_position: Option<u64>,
+}
+
+impl<T: IsoTpTransport> UdsClient<T> {
+    /// RequestUpload (0x35), returns the maxNumberOfBlockLength
+    pub async fn request_upload(
+        &self,
+        data_format: DataFormat,
+        format: AddressAndLengthFormat,
+        address: u64,
+        size: u64,
+    ) -> Result<usize, Error> {
+        let mut request = vec![REQUEST_UPLOAD, data_format.identifier()];
+        request.extend(format.encode(address, size)?);
+        let response = self.request(&request).await?;
+        let (max_block_length, _) = parse_max_block_length(&response[1..])?;
+        Ok(max_block_length)
+    }
+
+    /// Upload `size` bytes of memory to the writer, returns the number of bytes written
+    ///
+    /// Compressed data is received until a block shorter than the maximum block length
+    /// or a requestSequenceError (NRC 0x24) ends the transfer.
+    pub async fn upload(
+        &self,
+        address: u64,
+        size: u64,
+        data_format: DataFormat,
+        writer: impl AsyncWrite + Unpin,
+    ) -> Result<u64, Error> {
+        let format = AddressAndLengthFormat::fitting(address, size);
+        let max_block_length = self
+            .request_upload(data_format, format, address, size)
+            .await?;
+        let expected = (data_format.compression == 0).then_some(size);
+        self.receive_stream(writer, max_block_length, expected)
+            .await
+    }
+
+    /// RequestFileTransfer (0x38)
+    ///
+    /// The dataFormatIdentifier and the file sizes are only encoded if given, as
+    /// required by the operation.
+    pub async fn request_file_transfer(
+        &self,
+        operation: FileOperation,
+        path: &str,
+        data_format: Option<DataFormat>,
+        file_size: Option<FileSize>,
+    ) -> Result<FileTransferResponse, Error> {
+        let path_length = u16::try_from(path.len())
+            .map_err(|_| Error::invalid_request("file path longer than 65535 bytes"))?;
+        let mut request = vec![REQUEST_FILE_TRANSFER, operation.into()];
+        request.extend_from_slice(&path_length.to_be_bytes());
+        request.extend_from_slice(path.as_bytes());
+        if let Some(data_format) = data_format {
+            request.push(data_format.identifier());
+        }
+        if let Some(size) = file_size {
+            let format = AddressAndLengthFormat::fitting(0, size.uncompressed.max(size.compressed));
+            let bytes = usize::from(format.size_bytes);
+            request.push(format.size_bytes);
+            request.extend_from_slice(&size.uncompressed.to_be_bytes()[8 - bytes..]);
+            request.extend_from_slice(&size.compressed.to_be_bytes()[8 - bytes..]);
+        }
+        let response = self.request(&request).await?;
+        if response.get(1) != Some(&u8::from(operation)) {
+            return Err(Error::invalid_response(format!(
+                "modeOfOperation {operation} not echoed"
+            )));
+        }
+        parse_file_transfer_response(operation, &response[2..])
+    }
+
+    /// AddFile, the reader provides the `size.compressed` bytes to be transferred
+    pub async fn add_file(
+        &self,
+        path: &str,
+        data_format: DataFormat,
+        size: FileSize,
+        reader: impl AsyncRead + Unpin,
+    ) -> Result<(), Error> {
+        self.write_file(FileOperation::AddFile, path, data_format, size, reader)
+            .await?;
+        Ok(())
+    }
+
+    /// ReplaceFile, the reader provides the `size.compressed` bytes to be transferred
+    pub async fn replace_file(
+        &self,
+        path: &str,
+        data_format: DataFormat,
+        size: FileSize,
+        reader: impl AsyncRead + Unpin,
+    ) -> Result<(), Error> {
+        self.write_file(FileOperation::ReplaceFile, path, data_format, size, reader)
+            .await?;
+        Ok(())
+    }
+
+    /// ResumeFile, returns the filePosition the transfer continued at
+    ///
+    /// The reader provides the whole file from its start, the bytes already stored by
+    /// the ECU are skipped.
+    pub async fn resume_file(
+        &self,
+        path: &str,
+        data_format: DataFormat,
+        size: FileSize,
+        reader: impl AsyncRead + Unpin,
+    ) -> Result<u64, Error> {
+        self.write_file(FileOperation::ResumeFile, path, data_format, size, reader)
+            .await
+    }
+
+    /// DeleteFile
+    pub async fn delete_file(&self, path: &str) -> Result<(), Error> {
+        self.request_file_transfer(FileOperation::DeleteFile, path, None, None)
+            .await?;
+        Ok(())
+    }
+
+    /// ReadFile into the writer, returns the size reported by the ECU
+    pub async fn read_file(
+        &self,
+        path: &str,
+        data_format: DataFormat,
+        writer: impl AsyncWrite + Unpin,
+    ) -> Result<FileSize, Error> {
+        let response = self
+            .request_file_transfer(FileOperation::ReadFile, path, Some(data_format), None)
+            .await?;
+        let (Some(max_block_length), Some(size)) = (response.max_block_length, response.file_size)
+        else {
+            return Err(Error::invalid_response("missing file size"));
+        };
+        self.receive_stream(writer, max_block_length, Some(size.compressed))
+            .await?;
+        Ok(size)
+    }
+
+    /// ReadDir into the writer, returns the length of the directory information
+    pub async fn read_directory(
+        &self,
+        path: &str,
+        writer: impl AsyncWrite + Unpin,
+    ) -> Result<u64, Error> {
+        let response = self
+            .request_file_transfer(FileOperation::ReadDir, path, None, None)
+            .await?;
+        let (Some(max_block_length), Some(size)) = (response.max_block_length, response.file_size)
+        else {
+            return Err(Error::invalid_response(
+                "missing directory information length",
+            ));
+        };
+        self.receive_stream(writer, max_block_length, Some(size.uncompressed))
+            .await
+    }
+
+    /// AddFile, ReplaceFile or ResumeFile followed by the transfer, returns the file position
+    async fn write_file(
+        &self,
+        operation: FileOperation,
+        path: &str,
+        data_format: DataFormat,
+        size: FileSize,
+        mut reader: impl AsyncRead + Unpin,
+    ) -> Result<u64, Error> {
+        let response = self
+            .request_file_transfer(operation, path, Some(data_format), Some(size))
+            .await?;
+        let max_block_length = response
+            .max_block_length
+            .ok_or_else(|| Error::invalid_response("missing maxNumberOfBlockLength"))?;
+        let position = response.file_position.unwrap_or(0);
+        let skipped =
+            tokio::io::copy(&mut (&mut reader).take(position), &mut tokio::io::sink()).await?;
+        if skipped != position || position > size.compressed {
+            return Err(Error::invalid_response(format!(
+                "filePosition {position} beyond the file"
+            )));
+        }
+        self.send_stream(reader, max_block_length, size.compressed - position)
+            .await?;
+        Ok(position)
+    }
+
+    /// TransferData of `length` bytes from the reader followed by RequestTransferExit
+    async fn send_stream(
+        &self,
+        mut reader: impl AsyncRead + Unpin,
+        max_block_length: usize,
+        length: u64,
+    ) -> Result<(), Error> {
+        let mut block = vec![0; block_data_size(max_block_length)?];
+        let mut remaining = length;
+        let mut counter: u8 = 1;
+        while remaining > 0 {
+            let length = remaining.min(block.len() as u64) as usize;
+            let chunk = &mut block[..length];
+            reader.read_exact(chunk).await?;
+            self.transfer_data(counter, chunk).await?;
+            remaining -= chunk.len() as u64;
+            counter = counter.wrapping_add(1);
+        }
+        self.request_transfer_exit(&[]).await?;
+        Ok(())
+    }
+
+    /// TransferData into the writer followed by RequestTransferExit, returns the number
+    /// of bytes written
+    ///
+    /// Without an expected length the transfer ends with a short block or a
+    /// requestSequenceError (NRC 0x24).
+    async fn receive_stream(
+        &self,
+        mut writer: impl AsyncWrite + Unpin,
+        max_block_length: usize,
+        expected: Option<u64>,
+    ) -> Result<u64, Error> {
+        let block_size = block_data_size(max_block_length)?;
+        let mut received = 0;
+        let mut counter: u8 = 1;
+        while expected.is_none_or(|expected| received < expected) {
+            let data = match self.transfer_data(counter, &[]).await {
+                Ok(data) => data,
+                Err(Error::NegativeResponse {
+                    code: NegativeResponseCode::RequestSequenceError,
+                    ..
+                }) if expected.is_none() && received > 0 => break,
+                Err(err) => return Err(err),
+            };
+            if data.is_empty() || data.len() > block_size {
+                return Err(Error::invalid_response(format!(
+                    "TransferData block of {} bytes",
+                    data.len()
+                )));
+            }
+            writer.write_all(&data).await?;
+            received += data.len() as u64;
+            counter = counter.wrapping_add(1);
+            if expected.is_none() && data.len() < block_size {
+                break;
+            }
+        }
+        if expected.is_some_and(|expected| received != expected) {
+            return Err(Error::invalid_response(format!(
+                "received {received} bytes instead of {}",
+                expected.unwrap_or_default()
+            )));
+        }
+        writer.flush().await?;
+        self.request_transfer_exit(&[]).await?;
+        Ok(received)
+    }
+}
+
+fn parse_file_transfer_response(
+    operation: FileOperation,
+    record: &[u8],
+) -> Result<FileTransferResponse, Error> {
+    let mut response = FileTransferResponse {
+        operation,
+        max_block_length: None,
+        data_format: None,
+        file_size: None,
+        file_position: None,
+    };
+    if operation == FileOperation::DeleteFile {
+        return Ok(response);
+    }
+    let (max_block_length, rest) = parse_max_block_length(record)?;
+    response.max_block_length = Some(max_block_length);
+    let Some((&data_format, rest)) = rest.split_first() else {
+        return Err(Error::invalid_response("missing dataFormatIdentifier"));
+    };
+    response.data_format = Some(DataFormat::from(data_format));
+
+    match operation {
+        FileOperation::ReadFile | FileOperation::ReadDir => {
+            let [length_high, length_low, ref sizes @ ..] = rest[..] else {
+                return Err(Error::invalid_response(
+                    "missing fileSizeOrDirInfoParameterLength",
+                ));
+            };
+            let length = usize::from(u16::from_be_bytes([length_high, length_low]));
+            let count = if operation == FileOperation::ReadFile {
+                2
+            } else {
+                1
+            };
+            if !(1..=8).contains(&length) || sizes.len() != count * length {
+                return Err(Error::invalid_response("invalid file size"));
+            }
+            let uncompressed = parse_unsigned(&sizes[..length]);
+            let compressed = match operation {
+                FileOperation::ReadFile => parse_unsigned(&sizes[length..2 * length]),
+                _ => uncompressed,
+            };
+            response.file_size = Some(FileSize {
+                uncompressed,
+                compressed,
+            });
+        }
+        FileOperation::ResumeFile => {
+            let position = rest
+                .get(..8)
+                .ok_or_else(|| Error::invalid_response("missing filePosition"))?;
+            response.file_position = Some(parse_unsigned(position));
+        }
+        _ => {}
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uds::tests::MockTransport;
+
+    /// Content of the files and memory of the ECU, the value of each byte is its offset
+    fn content(range: std::ops::Range<usize>) -> Vec<u8> {
+        range.map(|byte| byte as u8).collect()
+    }
+
+    /// ECU serving a 300 byte file and a 10 byte directory listing in blocks of 256
+    /// bytes and memory in blocks of 16 bytes
+    fn ecu() -> UdsClient<MockTransport> {
+        let mut block_size = 0;
+        let mut total = 0;
+        UdsClient::new(MockTransport::new(move |request| match request {
+            [0x35, .., size] => {
+                block_size = 16;
+                total = usize::from(*size);
+                vec![vec![0x75, 0x10, 0x12]]
+            }
+            [0x38, 0x04, ..] => {
+                block_size = 256;
+                total = 300;
+                vec![vec![
+                    0x78, 0x04, 0x20, 0x01, 0x02, 0x00, 0x00, 0x02, 0x01, 0x2C, 0x01, 0x2C,
+                ]]
+            }
+            [0x38, 0x05, ..] => {
+                block_size = 256;
+                total = 10;
+                vec![vec![0x78, 0x05, 0x20, 0x01, 0x02, 0x00, 0x00, 0x01, 0x0A]]
+            }
+            [0x38, 0x01 | 0x03, ..] => vec![vec![0x78, request[1], 0x10, 0x0A, 0x00]],
+            [0x38, 0x06, ..] => vec![vec![
+                0x78, 0x06, 0x10, 0x0A, 0x00, 0, 0, 0, 0, 0, 0, 0, 0x05,
+            ]],
+            [0x38, 0x02, ..] => vec![vec![0x78, 0x02]],
+            [0x36, counter] => {
+                let start = usize::from(counter - 1) * block_size;
+                let mut response = vec![0x76, *counter];
+                response.extend(content(start..(start + block_size).min(total)));
+                vec![response]
+            }
+            [0x36, counter, ..] => vec![vec![0x76, *counter]],
+            [0x37] => vec![vec![0x77]],
+            _ => vec![vec![0x7F, request[0], 0x31]],
+        }))
+    }
+
+    #[test]
+    fn parses_read_file_response() {
+        // 0x78 0x04, maxNumberOfBlockLength 0x0102, compressed with method 1,
+        // fileSizeOrDirInfoParameterLength 0x0002, 0x1000 bytes compressed to 0x0400
+        let response = [
+            0x78, 0x04, 0x20, 0x01, 0x02, 0x10, 0x00, 0x02, 0x10, 0x00, 0x04, 0x00,
+        ];
+        let parsed = parse_file_transfer_response(FileOperation::ReadFile, &response[2..]);
+        assert_eq!(
+            parsed.unwrap(),
+            FileTransferResponse {
+                operation: FileOperation::ReadFile,
+                max_block_length: Some(0x0102),
+                data_format: Some(DataFormat::new(1, 0)),
+                file_size: Some(FileSize {
+                    uncompressed: 0x1000,
+                    compressed: 0x0400,
+                }),
+                file_position: None,
+            }
+        );
+
+        for record in [
+            &[0x20, 0x01, 0x02, 0x00, 0x02, 0x10, 0x00, 0x04, 0x00][..],
+            &[0x20, 0x01, 0x02, 0x00, 0x00, 0x02, 0x10, 0x00, 0x04][..],
+            &[
+                0x20, 0x01, 0x02, 0x00, 0x00, 0x02, 0x10, 0x00, 0x04, 0x00, 0x00,
+            ][..],
+            &[0x20, 0x01, 0x02, 0x00, 0x00, 0x00][..],
+            &[
+                0x20, 0x01, 0x02, 0x00, 0x00, 0x09, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ][..],
+            &[0x20, 0x01, 0x02][..],
+        ] {
+            let parsed = parse_file_transfer_response(FileOperation::ReadFile, record);
+            assert!(parsed.is_err(), "{record:02X?}");
+        }
+    }
+
+    #[test]
+    fn parses_other_responses() {
+        let read_dir = [0x20, 0x01, 0x02, 0x00, 0x00, 0x01, 0x40];
+        let parsed = parse_file_transfer_response(FileOperation::ReadDir, &read_dir).unwrap();
+        assert_eq!(parsed.file_size, Some(FileSize::plain(0x40)));
+
+        let resume = [0x10, 0x0A, 0x00, 0, 0, 0, 0, 0, 0, 0x01, 0x00];
+        let parsed = parse_file_transfer_response(FileOperation::ResumeFile, &resume).unwrap();
+        assert_eq!(parsed.file_position, Some(0x100));
+        assert!(parse_file_transfer_response(FileOperation::ResumeFile, &resume[..10]).is_err());
+
+        let parsed = parse_file_transfer_response(FileOperation::DeleteFile, &[]).unwrap();
+        assert_eq!(parsed.max_block_length, None);
+    }
+
+    #[tokio::test]
+    async fn reads_file() {
+        let client = ecu();
+        let mut file = Vec::new();
+        let size = client
+            .read_file("/log.txt", DataFormat::PLAIN, &mut file)
+            .await
+            .unwrap();
+        assert_eq!(size, FileSize::plain(300));
+        assert_eq!(file, content(0..300));
+        let requests = client.transport().requests();
+        assert_eq!(
+            requests[0],
+            [&[0x38, 0x04, 0x00, 0x08][..], b"/log.txt", &[0x00]].concat()
+        );
+        assert_eq!(requests[1..], [vec![0x36, 1], vec![0x36, 2], vec![0x37]]);
+
+        let mut directory = Vec::new();
+        let length = client.read_directory("/", &mut directory).await.unwrap();
+        assert_eq!(length, 10);
+        assert_eq!(directory, content(0..10));
+    }
+
+    #[tokio::test]
+    async fn uploads_memory() {
+        let client = ecu();
+        let mut dump = Vec::new();
+        let length = client
+            .upload(0x1000, 40, DataFormat::PLAIN, &mut dump)
+            .await
+            .unwrap();
+        assert_eq!(length, 40);
+        assert_eq!(dump, content(0..40));
+        assert_eq!(
+            client.transport().requests()[0],
+            [0x35, 0x00, 0x12, 0x10, 0x00, 40]
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_files() {
+        let client = ecu();
+        let file = content(0..20);
+        client
+            .add_file("a", DataFormat::PLAIN, FileSize::plain(20), &file[..])
+            .await
+            .unwrap();
+        let requests = client.transport().requests();
+        assert_eq!(
+            requests[0],
+            [0x38, 0x01, 0x00, 0x01, b'a', 0x00, 0x01, 20, 20]
+        );
+        assert_eq!(requests[1], [&[0x36, 1][..], &file[..8]].concat());
+        assert_eq!(requests[3], [&[0x36, 3][..], &file[16..]].concat());
+        assert_eq!(requests[4], [0x37]);
+
+        let position = client
+            .resume_file("a", DataFormat::PLAIN, FileSize::plain(20), &file[..])
+            .await
+            .unwrap();
+        assert_eq!(position, 5);
+        let requests = client.transport().requests();
+        assert_eq!(requests[6], [&[0x36, 1][..], &file[5..13]].concat());
+
+        client.delete_file("a").await.unwrap();
+        let error = client
+            .add_file("a", DataFormat::PLAIN, FileSize::plain(20), &file[..10])
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::Io { .. }), "{error:?}");
+    }
+}